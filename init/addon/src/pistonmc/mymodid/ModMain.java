@@ -0,0 +1,18 @@
+package pistonmc.mymodid;
+
+import cpw.mods.fml.common.Loader;
+import cpw.mods.fml.common.Mod;
+import cpw.mods.fml.common.Mod.EventHandler;
+import cpw.mods.fml.common.event.FMLInitializationEvent;
+
+@Mod(modid = ModInfo.ID, version = ModInfo.VERSION, dependencies = "required-after:parentmodid")
+public class ModMain
+{
+
+    @EventHandler
+    public void init(FMLInitializationEvent event)
+    {
+		// some example code
+        System.out.println("PARENT MOD >> "+Loader.instance().getIndexedModList().get("parentmodid"));
+    }
+}
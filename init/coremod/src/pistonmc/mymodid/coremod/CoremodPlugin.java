@@ -0,0 +1,40 @@
+package pistonmc.mymodid.coremod;
+
+import java.util.Map;
+
+import cpw.mods.fml.relauncher.IFMLLoadingPlugin;
+
+@IFMLLoadingPlugin.MCVersion("1.7.10")
+@IFMLLoadingPlugin.TransformerExclusions("pistonmc.mymodid.coremod")
+public class CoremodPlugin implements IFMLLoadingPlugin
+{
+    @Override
+    public String[] getASMTransformerClass()
+    {
+        // add the class names of your IClassTransformers here
+        return new String[0];
+    }
+
+    @Override
+    public String getModContainerClass()
+    {
+        return null;
+    }
+
+    @Override
+    public String getSetupClass()
+    {
+        return null;
+    }
+
+    @Override
+    public void injectData(Map<String, Object> data)
+    {
+    }
+
+    @Override
+    public String getAccessTransformerClass()
+    {
+        return null;
+    }
+}
@@ -0,0 +1,19 @@
+package pistonmc.mymodid.mixins.client;
+
+import net.minecraft.client.Minecraft;
+
+import org.spongepowered.asm.mixin.Mixin;
+import org.spongepowered.asm.mixin.injection.At;
+import org.spongepowered.asm.mixin.injection.Inject;
+import org.spongepowered.asm.mixin.injection.callback.CallbackInfo;
+
+@Mixin(Minecraft.class)
+public class MixinMinecraft
+{
+    @Inject(method = "startGame", at = @At("TAIL"))
+    private void onStartGame(CallbackInfo ci)
+    {
+		// some example code
+        System.out.println("MIXIN >> game started");
+    }
+}
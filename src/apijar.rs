@@ -0,0 +1,293 @@
+//! Extracting the API of a mod jar into a slim jar to compile against, for `libs` entries with `api-of`
+//!
+//! The full jar is downloaded into target/api-cache so it's not on the classpath, and only the
+//! API packages are copied into `<name>-api.jar` in libs. The packages are `api` in mcmod.yaml,
+//! or the packages with an `@API` annotation owned by the mod
+
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::mcmod::ApiLib;
+use crate::remap::ClassReader;
+use crate::sync;
+use crate::util::{self, IoResult, Project};
+
+/// Descriptors of the FML @API annotation, which is put on package-info classes
+const API_ANNOTATIONS: &[&str] = &[
+    "Lcpw/mods/fml/common/API;",
+    "Lnet/minecraftforge/fml/common/API;",
+];
+
+/// Directory the full jars of API libs are downloaded to
+pub fn cache_dir(project: &Project) -> PathBuf {
+    project.target_root().join("api-cache")
+}
+
+/// File name of the slim jar in libs
+pub fn jar_name(lib: &ApiLib) -> Option<String> {
+    let name = sync::download_file_name(&lib.source)?;
+    let stem = name.strip_suffix(".jar").unwrap_or(name);
+    Some(format!("{stem}-api.jar"))
+}
+
+/// Extract the API of the downloaded jar into the slim jar in libs.
+/// Returns if the slim jar changed
+pub async fn sync_jar(project: &Project, libs_dir: &Path, lib: &ApiLib) -> IoResult<bool> {
+    let (name, jar_name) = match (sync::download_file_name(&lib.source), jar_name(lib)) {
+        (Some(name), Some(jar_name)) => (name, jar_name),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot find file name in '{}'", lib.source),
+        ))?,
+    };
+    let input = cache_dir(project).join(name);
+    let result = {
+        let lib = lib.clone();
+        tokio::task::spawn_blocking(move || extract(&input, &lib)).await
+    };
+    let (content, packages) = match result {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    let output = libs_dir.join(jar_name);
+    if fs::read(&output).await.ok().as_deref() == Some(content.as_slice()) {
+        return Ok(false);
+    }
+    println!(
+        "extracting API of '{}' ({}) into '{}'",
+        lib.api_of,
+        packages.join(", "),
+        output.display()
+    );
+    util::write_atomic(&output, &content).await?;
+    Ok(true)
+}
+
+/// Copy the API packages (and their subpackages) of the jar into a new jar.
+/// Returns the jar and the packages
+fn extract(input: &Path, lib: &ApiLib) -> IoResult<(Vec<u8>, Vec<String>)> {
+    let mut archive = match ZipArchive::new(std::fs::File::open(input)?) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot read '{}' as a jar: {e}", input.display()),
+        ))?,
+    };
+    let packages = if lib.api.is_empty() {
+        let annotations = find_api_annotations(&mut archive)?;
+        let packages = annotations
+            .iter()
+            .filter(|x| {
+                x.owner.eq_ignore_ascii_case(&lib.api_of)
+                    || x.provides.eq_ignore_ascii_case(&lib.api_of)
+            })
+            .map(|x| x.package.clone())
+            .collect::<Vec<_>>();
+        if packages.is_empty() {
+            let found = annotations
+                .iter()
+                .map(|x| format!("{} (owner '{}')", x.package.replace('/', "."), x.owner))
+                .collect::<Vec<_>>();
+            let message = if found.is_empty() {
+                format!(
+                    "No @API packages in '{}'. Set `api` to the API package of '{}' in mcmod.yaml",
+                    input.display(),
+                    lib.api_of
+                )
+            } else {
+                format!(
+                    "No @API packages of '{}' in '{}'. Found: {}. Set `api` to the package to use in mcmod.yaml",
+                    lib.api_of,
+                    input.display(),
+                    found.join(", ")
+                )
+            };
+            Err(io::Error::new(io::ErrorKind::NotFound, message))?;
+        }
+        packages
+    } else {
+        vec![lib.api.replace('.', "/")]
+    };
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_owned();
+        let in_api = packages.iter().any(|x| {
+            name.strip_prefix(x.as_str())
+                .is_some_and(|x| x.starts_with('/'))
+        });
+        if !in_api {
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        writer.start_file(name, options).map_err(io::Error::other)?;
+        writer.write_all(&content)?;
+        count += 1;
+    }
+    let packages = packages
+        .iter()
+        .map(|x| x.replace('/', "."))
+        .collect::<Vec<_>>();
+    if count == 0 {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No files in the API package(s) {} of '{}'",
+                packages.join(", "),
+                input.display()
+            ),
+        ))?;
+    }
+    let content = writer.finish().map_err(io::Error::other)?.into_inner();
+    Ok((content, packages))
+}
+
+/// An @API annotation on a package
+struct ApiAnnotation {
+    /// The package, with `/` as separator
+    package: String,
+    owner: String,
+    provides: String,
+}
+
+fn find_api_annotations(archive: &mut ZipArchive<std::fs::File>) -> IoResult<Vec<ApiAnnotation>> {
+    let mut annotations = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let package = match entry.name().strip_suffix("/package-info.class") {
+            Some(x) => x.to_owned(),
+            None => continue,
+        };
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        if let Some((owner, provides)) = parse_api_annotation(&content) {
+            annotations.push(ApiAnnotation {
+                package,
+                owner,
+                provides,
+            });
+        }
+    }
+    Ok(annotations)
+}
+
+/// Get the owner and provides of the @API annotation of a package-info class.
+/// Returns None if the class doesn't have one or can't be parsed
+fn parse_api_annotation(class: &[u8]) -> Option<(String, String)> {
+    if class.get(..4)? != [0xCA, 0xFE, 0xBA, 0xBE] {
+        return None;
+    }
+    let mut reader = ClassReader {
+        data: class,
+        pos: 8,
+    };
+    let count = reader.u2()? as usize;
+    let mut utf8 = BTreeMap::new();
+    let mut i = 1;
+    while i < count {
+        let tag = reader.u1()?;
+        let size = match tag {
+            1 => reader.u2()? as usize,
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => 4,
+            5 | 6 => 8,
+            7 | 8 | 16 | 19 | 20 => 2,
+            15 => 3,
+            _ => return None,
+        };
+        let start = reader.pos;
+        reader.skip(size)?;
+        if tag == 1 {
+            utf8.insert(i as u16, String::from_utf8_lossy(&class[start..reader.pos]));
+        }
+        i += if tag == 5 || tag == 6 { 2 } else { 1 };
+    }
+    // access, this, super
+    reader.skip(6)?;
+    let interfaces = reader.u2()? as usize;
+    reader.skip(interfaces * 2)?;
+    // fields, then methods
+    for _ in 0..2 {
+        for _ in 0..reader.u2()? {
+            reader.skip(6)?;
+            for _ in 0..reader.u2()? {
+                reader.skip(2)?;
+                let len = reader.u4()? as usize;
+                reader.skip(len)?;
+            }
+        }
+    }
+    for _ in 0..reader.u2()? {
+        let name = utf8.get(&reader.u2()?)?;
+        let len = reader.u4()? as usize;
+        let end = reader.pos + len;
+        if name == "RuntimeVisibleAnnotations" || name == "RuntimeInvisibleAnnotations" {
+            for _ in 0..reader.u2()? {
+                let descriptor = utf8.get(&reader.u2()?)?;
+                let mut values = BTreeMap::new();
+                for _ in 0..reader.u2()? {
+                    let name = utf8.get(&reader.u2()?)?;
+                    if let Some(value) = read_element_value(&mut reader, &utf8)? {
+                        values.insert(name.as_ref(), value);
+                    }
+                }
+                if API_ANNOTATIONS.contains(&descriptor.as_ref()) {
+                    let owner = values.remove("owner").unwrap_or_default();
+                    let provides = values.remove("provides").unwrap_or_default();
+                    return Some((owner, provides));
+                }
+            }
+        }
+        reader.pos = end;
+    }
+    None
+}
+
+/// Read an annotation element value. Returns the string if it's a string constant
+fn read_element_value(
+    reader: &mut ClassReader,
+    utf8: &BTreeMap<u16, std::borrow::Cow<str>>,
+) -> Option<Option<String>> {
+    match reader.u1()? {
+        b's' => Some(Some(utf8.get(&reader.u2()?)?.to_string())),
+        b'e' => {
+            reader.skip(4)?;
+            Some(None)
+        }
+        b'@' => {
+            reader.skip(2)?;
+            for _ in 0..reader.u2()? {
+                reader.skip(2)?;
+                read_element_value(reader, utf8)?;
+            }
+            Some(None)
+        }
+        b'[' => {
+            for _ in 0..reader.u2()? {
+                read_element_value(reader, utf8)?;
+            }
+            Some(None)
+        }
+        _ => {
+            reader.skip(2)?;
+            Some(None)
+        }
+    }
+}
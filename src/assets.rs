@@ -0,0 +1,673 @@
+//! Checking and generating assets
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use walkdir::WalkDir;
+
+use crate::mcmod::{Language, Mcmod};
+use crate::png::{self, Image};
+use crate::state;
+use crate::util::{self, mkdir, write_file, IoResult, Project, ProjectArgs};
+
+/// Target of the copy paths for the resources
+const RESOURCES_TARGET: &str = "src/main/resources";
+/// Target of the copy paths for the assets
+const ASSETS_TARGET: &str = "src/main/resources/assets";
+/// Namespace of vanilla assets, which mods can override
+const MINECRAFT_NAMESPACE: &str = "minecraft";
+const SOUNDS_JSON: &str = "sounds.json";
+/// Larger logos are scaled down to fit in this size
+const LOGO_MAX_SIZE: u32 = 512;
+/// The size of the box the mods list shows the logo in
+const LOGO_BOX: (u32, u32) = (200, 65);
+/// Logos with aspect ratios outside of this range are shown small in the mods list
+const LOGO_MIN_RATIO: f64 = 2.0;
+const LOGO_MAX_RATIO: f64 = 5.0;
+
+#[derive(Debug, Parser)]
+pub struct AssetsCommand {
+    #[clap(subcommand)]
+    pub command: AssetsSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AssetsSubcommand {
+    /// Check the assets like a full sync does, without syncing
+    Check(AssetsCheck),
+    /// Generate the .mcmeta of an animated texture, which is a vertical strip of square frames
+    Animate(AssetsAnimate),
+    /// Add the .ogg files in assets/<modid>/sounds to sounds.json, and check that the
+    /// sounds in it exist
+    Sounds(AssetsSounds),
+}
+
+#[derive(Debug, Parser)]
+pub struct AssetsCheck {
+    /// Fix the assets directories and resource locations that don't match the mod id
+    #[arg(long)]
+    pub fix: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct AssetsAnimate {
+    /// The texture PNG
+    pub texture: PathBuf,
+
+    /// Number of ticks to show each frame
+    #[arg(long, default_value = "1")]
+    pub frametime: u32,
+
+    /// Blend between frames
+    #[arg(long)]
+    pub interpolate: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct AssetsSounds {
+    /// Only check sounds.json without adding new sounds
+    #[arg(long)]
+    pub check: bool,
+
+    /// Category of the new sound events, like `block`, `neutral` or `master`
+    #[arg(long)]
+    pub category: Option<String>,
+
+    /// Add `<modid>.subtitle.<event>` as the subtitle of the new sound events (MC 1.9+)
+    #[arg(long)]
+    pub subtitle: bool,
+}
+
+impl AssetsCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        match self.command {
+            AssetsSubcommand::Check(check) => {
+                let project = Project::new_in(args)?;
+                check_assets(&project, check.fix).await?;
+                println!("assets checked");
+                Ok(())
+            }
+            AssetsSubcommand::Animate(animate) => animate.run().await,
+            AssetsSubcommand::Sounds(sounds) => sounds.run(args).await,
+        }
+    }
+}
+
+impl AssetsAnimate {
+    pub async fn run(self) -> IoResult<()> {
+        if self.frametime == 0 {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--frametime must be at least 1",
+            ))?;
+        }
+        let (width, height) = read_png_size(&self.texture).await?;
+        let frames = match animation_frames(width, height) {
+            Some(x) => x,
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is {width}x{height}. An animated texture is a vertical strip of square frames, so the height must be a multiple of the width", self.texture.display()),
+            ))?,
+        };
+        let mut animation = json!({ "frametime": self.frametime });
+        if self.interpolate {
+            animation["interpolate"] = json!(true);
+        }
+        let mcmeta = json!({ "animation": animation });
+        let path = mcmeta_path(&self.texture);
+        let mut content = serde_json::to_string_pretty(&mcmeta).map_err(io::Error::other)?;
+        content.push('\n');
+        write_file!(&path, content).await?;
+        println!(
+            "written '{}' for {frames} frames of {width}x{width}",
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+impl AssetsSounds {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let sources = copy_sources(&project, mcmod);
+        let namespace_dirs = sources
+            .iter()
+            .filter(|(_, t)| *t == ASSETS_TARGET)
+            .map(|(source, _)| source.join(&mcmod.modid))
+            .filter(|x| x.join("sounds").is_dir() || x.join(SOUNDS_JSON).exists())
+            .collect::<Vec<_>>();
+        if namespace_dirs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No sounds in assets/{}/sounds", mcmod.modid),
+            ))?;
+        }
+        let mut problems = Vec::new();
+        for dir in namespace_dirs {
+            let path = dir.join(SOUNDS_JSON);
+            let mut sounds = read_sounds_json(&path).await?;
+            if !self.check {
+                let added = self.add_sounds(&mut sounds, &dir, &mcmod.modid)?;
+                if added > 0 {
+                    state::write_json(&path, &sounds).await?;
+                    println!("added {added} sound event(s) to '{}'", path.display());
+                }
+            }
+            problems.extend(check_sounds(&sounds, &dir, &path));
+        }
+        if !problems.is_empty() {
+            for problem in &problems {
+                println!("  {problem}");
+            }
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "sounds.json has sounds that don't exist",
+            ))?;
+        }
+        println!("sounds ok");
+        Ok(())
+    }
+
+    /// Add the .ogg files that are not in sounds.json. Files with the same name except the number
+    /// at the end, like `step1.ogg` and `step2.ogg`, are variants of one event.
+    ///
+    /// Returns the number of new or updated events
+    fn add_sounds(
+        &self,
+        sounds: &mut serde_json::Map<String, Value>,
+        namespace_dir: &Path,
+        modid: &str,
+    ) -> IoResult<usize> {
+        let sounds_dir = namespace_dir.join("sounds");
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&sounds_dir).into_iter().filter_map(Result::ok) {
+            let path = entry.path();
+            if !entry.file_type().is_file() || path.extension().is_none_or(|x| x != "ogg") {
+                continue;
+            }
+            let name = match path.strip_prefix(&sounds_dir) {
+                Ok(x) => x.with_extension("").to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            files.push(name);
+        }
+        files.sort();
+
+        let referenced = sounds
+            .values()
+            .flat_map(sound_names)
+            .map(|x| x.split_once(':').map_or(x, |(_, name)| name).to_owned())
+            .collect::<BTreeSet<_>>();
+        let mut events: Vec<(String, Vec<String>)> = Vec::new();
+        for name in &files {
+            let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+            let has_variants = base != name
+                && !base.ends_with('/')
+                && files
+                    .iter()
+                    .filter(|x| x.trim_end_matches(|c: char| c.is_ascii_digit()) == base)
+                    .count()
+                    > 1;
+            let event = if has_variants { base } else { name.as_str() }.replace('/', ".");
+            if referenced.contains(name) {
+                continue;
+            }
+            match events.iter_mut().find(|(e, _)| *e == event) {
+                Some((_, names)) => names.push(format!("{modid}:{name}")),
+                None => events.push((event, vec![format!("{modid}:{name}")])),
+            }
+        }
+
+        for (event, names) in &events {
+            match sounds.get_mut(event) {
+                Some(Value::Object(entry)) => {
+                    let list = entry
+                        .entry("sounds")
+                        .or_insert_with(|| Value::Array(Vec::new()));
+                    match list {
+                        Value::Array(list) => list.extend(names.iter().map(|x| json!(x))),
+                        _ => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("`sounds` of '{event}' in sounds.json is not a list"),
+                        ))?,
+                    }
+                }
+                _ => {
+                    let mut entry = serde_json::Map::new();
+                    if let Some(category) = &self.category {
+                        entry.insert("category".to_owned(), json!(category));
+                    }
+                    if self.subtitle {
+                        entry.insert(
+                            "subtitle".to_owned(),
+                            json!(format!("{modid}.subtitle.{event}")),
+                        );
+                    }
+                    entry.insert("sounds".to_owned(), json!(names));
+                    sounds.insert(event.clone(), Value::Object(entry));
+                }
+            }
+            println!("  {event}: {}", names.join(", "));
+        }
+        Ok(events.len())
+    }
+}
+
+/// Check that the sounds in sounds.json of the namespace exist
+fn check_sounds(
+    sounds: &serde_json::Map<String, Value>,
+    namespace_dir: &Path,
+    path: &Path,
+) -> Vec<String> {
+    let namespace = namespace_dir
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut problems = Vec::new();
+    for (event, entry) in sounds {
+        for name in sound_names(entry) {
+            let name = match name.split_once(':') {
+                Some((x, name)) if x == namespace => name,
+                // sounds of other mods or minecraft
+                Some(_) => continue,
+                None => name,
+            };
+            let file = namespace_dir.join("sounds").join(format!("{name}.ogg"));
+            if !file.is_file() {
+                problems.push(format!(
+                    "'{event}' in '{}' uses '{name}', but '{}' does not exist",
+                    path.display(),
+                    file.display()
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Get the names of the sound files of an event in sounds.json.
+/// Sounds can be a name or `{"name": ..., "stream": true}`, and events with `"type": "event"` are skipped
+fn sound_names(entry: &Value) -> Vec<&str> {
+    let list = match entry.get("sounds").and_then(Value::as_array) {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    list.iter()
+        .filter(|x| x.get("type").and_then(Value::as_str) != Some("event"))
+        .filter_map(|x| match x {
+            Value::String(name) => Some(name.as_str()),
+            x => x.get("name").and_then(Value::as_str),
+        })
+        .collect()
+}
+
+async fn read_sounds_json(path: &Path) -> IoResult<serde_json::Map<String, Value>> {
+    if !path.exists() {
+        return Ok(serde_json::Map::new());
+    }
+    let content = fs::read_to_string(path).await?;
+    match serde_json::from_str(&content) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid '{}': {e}", path.display()),
+        ))?,
+    }
+}
+
+/// Check that the directories under assets match the mod id, and that resource locations
+/// like `"mymodid:textures/..."` in the sources and assets use the same namespace.
+///
+/// With `fix`, the directories are renamed and the resource locations are replaced.
+/// `logo` and `screenshots` in mcmod.yaml must also be in the copied resources, which can't be fixed
+pub async fn check_assets(project: &Project, fix: bool) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let mut modids = vec![mcmod.modid.as_str()];
+    modids.extend(mcmod.child_mods.iter().map(|x| x.modid.as_str()));
+
+    let sources = copy_sources(project, mcmod);
+    let mut problems = Vec::new();
+    for image in std::iter::once(&mcmod.logo).chain(&mcmod.screenshots) {
+        if !image.is_empty() && find_resource(&sources, image).is_none() {
+            problems.push(format!("'{image}' in mcmod.yaml is not in the copied resources. It should be relative to src/main/resources, like `assets/{}/logo.png`", mcmod.modid));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("  {problem}");
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Files in mcmod.yaml are missing from the resources",
+        ))?;
+    }
+
+    check_animations(&sources).await?;
+    for (source, _) in sources.iter().filter(|(_, t)| *t == ASSETS_TARGET) {
+        let namespace_dir = source.join(&mcmod.modid);
+        let path = namespace_dir.join(SOUNDS_JSON);
+        if path.exists() {
+            let sounds = read_sounds_json(&path).await?;
+            for problem in check_sounds(&sounds, &namespace_dir, &path) {
+                println!("warning: {problem}");
+            }
+        }
+    }
+
+    // packs change the assets of other mods, so their namespaces don't need to match
+    if mcmod.is_pack() {
+        return Ok(());
+    }
+
+    // (assets dir, old namespace, new namespace)
+    let mut renames: Vec<(PathBuf, String, String)> = Vec::new();
+    let mut unfixable = false;
+    for (source, _) in sources.iter().filter(|(_, t)| *t == ASSETS_TARGET) {
+        let namespaces = read_namespaces(source).await?;
+        let mut unknown = Vec::new();
+        for namespace in &namespaces {
+            if modids.contains(&namespace.as_str()) || namespace == MINECRAFT_NAMESPACE {
+                continue;
+            }
+            match modids.iter().find(|x| x.eq_ignore_ascii_case(namespace)) {
+                Some(modid) if !namespaces.contains(*modid) => {
+                    renames.push((source.clone(), namespace.clone(), modid.to_string()))
+                }
+                _ => unknown.push(namespace.clone()),
+            }
+        }
+        let has_modid =
+            namespaces.contains(&mcmod.modid) || renames.iter().any(|(_, _, x)| *x == mcmod.modid);
+        match unknown.as_slice() {
+            [namespace] if !has_modid => {
+                renames.push((source.clone(), namespace.clone(), mcmod.modid.clone()))
+            }
+            _ if !has_modid && !unknown.is_empty() => {
+                unfixable = true;
+                problems.push(format!(
+                    "'{}' has no directory for the mod id '{}', but has {}",
+                    source.display(),
+                    mcmod.modid,
+                    unknown.join(", ")
+                ));
+            }
+            _ => {}
+        }
+    }
+    for (source, old, new) in &renames {
+        problems.push(format!(
+            "'{}' should be named '{new}' to match the mod id",
+            source.join(old).display()
+        ));
+    }
+
+    // resource locations with the wrong namespace
+    let mut references: Vec<(PathBuf, Vec<(String, String)>)> = Vec::new();
+    for (source, _) in &sources {
+        for entry in WalkDir::new(source).into_iter().filter_map(Result::ok) {
+            let path = entry.path();
+            let is_text =
+                Language::of(path).is_some() || path.extension().is_some_and(|x| x == "json");
+            if !entry.file_type().is_file() || !is_text {
+                continue;
+            }
+            let content = match fs::read_to_string(path).await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let mut found = BTreeSet::new();
+            for namespace in resource_namespaces(&content) {
+                let new = renames
+                    .iter()
+                    .find(|(_, old, _)| *old == namespace)
+                    .map(|(_, _, new)| new.as_str())
+                    .or_else(|| {
+                        modids
+                            .iter()
+                            .find(|x| **x != namespace && x.eq_ignore_ascii_case(namespace))
+                            .copied()
+                    });
+                if let Some(new) = new {
+                    found.insert((namespace.to_owned(), new.to_owned()));
+                }
+            }
+            for (old, new) in &found {
+                problems.push(format!(
+                    "'{}' uses the namespace '{old}' instead of '{new}'",
+                    path.display()
+                ));
+            }
+            if !found.is_empty() {
+                references.push((path.to_path_buf(), found.into_iter().collect()));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if !fix || unfixable {
+        for problem in &problems {
+            println!("  {problem}");
+        }
+        let message = if unfixable {
+            "Assets don't match the mod id"
+        } else {
+            "Assets don't match the mod id. Run `mcmod sync --fix` to fix them"
+        };
+        Err(io::Error::new(io::ErrorKind::InvalidData, message))?;
+    }
+
+    // references first, since renaming moves the files
+    for (path, namespaces) in references {
+        let mut content = fs::read_to_string(&path).await?;
+        for (old, new) in namespaces {
+            content = content.replace(&format!("\"{old}:"), &format!("\"{new}:"));
+        }
+        println!("fixing namespaces in '{}'", path.display());
+        write_file!(&path, content).await?;
+    }
+    for (source, old, new) in renames {
+        let old_path = source.join(&old);
+        let new_path = source.join(&new);
+        println!(
+            "renaming '{}' to '{}'",
+            old_path.display(),
+            new_path.display()
+        );
+        fs::rename(&old_path, &new_path).await?;
+    }
+    Ok(())
+}
+
+/// Resize the logo to a power of two size that is not too large, if it isn't already.
+///
+/// The processed logo replaces the copied one in the target resources
+pub async fn process_logo(project: &Project) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    if mcmod.logo.is_empty() || !mcmod.logo.ends_with(".png") {
+        return Ok(());
+    }
+    let sources = copy_sources(project, mcmod);
+    let source = match find_resource(&sources, &mcmod.logo) {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let data = fs::read(&source).await?;
+    let image = match Image::decode(&data) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Cannot read logo '{}': {e}", source.display()),
+        ))?,
+    };
+    let (width, height) = (image.width, image.height);
+    let ratio = width as f64 / height.max(1) as f64;
+    if !(LOGO_MIN_RATIO..=LOGO_MAX_RATIO).contains(&ratio) {
+        println!(
+            "warning: the logo is {width}x{height}. The mods list shows it in a {}x{} box, so it will look small. Use an aspect ratio around 3:1",
+            LOGO_BOX.0, LOGO_BOX.1
+        );
+    }
+    let scale = (LOGO_MAX_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let scaled_width = ((width as f64 * scale).round() as u32).max(1);
+    let scaled_height = ((height as f64 * scale).round() as u32).max(1);
+    let (pot_width, pot_height) = (
+        scaled_width.next_power_of_two(),
+        scaled_height.next_power_of_two(),
+    );
+    if (pot_width, pot_height) == (width, height) {
+        return Ok(());
+    }
+    println!("resizing logo from {width}x{height} to {scaled_width}x{scaled_height} in a {pot_width}x{pot_height} image");
+    let output = image
+        .resize(scaled_width, scaled_height)
+        .pad(pot_width, pot_height)
+        .encode()?;
+    let path = project
+        .target_root()
+        .join(RESOURCES_TARGET)
+        .join(mcmod.logo.trim_start_matches('/'));
+    if let Some(parent) = path.parent() {
+        mkdir!(parent).await?;
+    }
+    util::write_atomic(&path, &output).await?;
+    Ok(())
+}
+
+/// Warn about textures that look like animation strips without a .mcmeta,
+/// and animated textures with a .mcmeta that are not strips of square frames
+async fn check_animations(sources: &[(PathBuf, &str)]) -> IoResult<()> {
+    for (source, _) in sources.iter().filter(|(_, t)| *t == ASSETS_TARGET) {
+        for entry in WalkDir::new(source).into_iter().filter_map(Result::ok) {
+            let path = entry.path();
+            let is_texture = path.extension().is_some_and(|x| x == "png")
+                && path.components().any(|x| x.as_os_str() == "textures");
+            if !entry.file_type().is_file() || !is_texture {
+                continue;
+            }
+            let (width, height) = match read_png_size(path).await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let frames = animation_frames(width, height);
+            let has_mcmeta = mcmeta_path(path).exists();
+            match frames {
+                Some(frames) if !has_mcmeta => println!(
+                    "warning: '{}' looks like an animation of {frames} frames but has no .mcmeta. Generate it with `mcmod assets animate`",
+                    path.display()
+                ),
+                None if has_mcmeta && height > width => println!(
+                    "warning: '{}' has a .mcmeta but is {width}x{height}. The height of an animation must be a multiple of the width",
+                    path.display()
+                ),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Number of frames if the size is a vertical strip of more than one square frame
+fn animation_frames(width: u32, height: u32) -> Option<u32> {
+    (width > 0 && height > width && height.is_multiple_of(width)).then(|| height / width)
+}
+
+fn mcmeta_path(texture: &Path) -> PathBuf {
+    let mut path = texture.as_os_str().to_owned();
+    path.push(".mcmeta");
+    PathBuf::from(path)
+}
+
+/// Read the size of a PNG without reading the whole file
+async fn read_png_size(path: &Path) -> IoResult<(u32, u32)> {
+    let mut header = Vec::new();
+    fs::File::open(path)
+        .await?
+        .take(png::HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .await?;
+    match png::size(&header) {
+        Some(x) => Ok(x),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}' is not a PNG file", path.display()),
+        ))?,
+    }
+}
+
+/// The existing source directories of the copy paths that apply to the project,
+/// with their targets
+fn copy_sources<'a>(project: &Project, mcmod: &'a Mcmod) -> Vec<(PathBuf, &'a str)> {
+    let mut sources = Vec::new();
+    for copy_path in &mcmod.copy_paths {
+        if !copy_path.applies_to(project, &mcmod.template) {
+            continue;
+        }
+        let (source, target) = match copy_path.source_target() {
+            Some(x) => x,
+            None => continue,
+        };
+        let source_path = project.root.join(source);
+        if source_path.is_dir() {
+            sources.push((source_path, target.trim_end_matches('/')));
+        }
+    }
+    sources
+}
+
+/// Find the source file of a path in the resources
+fn find_resource(sources: &[(PathBuf, &str)], path: &str) -> Option<PathBuf> {
+    let path = path.trim_start_matches('/');
+    sources.iter().find_map(|(source, target)| {
+        let prefix = target
+            .strip_prefix(RESOURCES_TARGET)?
+            .trim_start_matches('/');
+        let rel_path = match prefix {
+            "" => path,
+            _ => path.strip_prefix(prefix)?.strip_prefix('/')?,
+        };
+        let file = source.join(rel_path);
+        file.is_file().then_some(file)
+    })
+}
+
+async fn read_namespaces(assets_dir: &Path) -> IoResult<BTreeSet<String>> {
+    let mut namespaces = BTreeSet::new();
+    let mut entries = fs::read_dir(assets_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            namespaces.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(namespaces)
+}
+
+/// Get the namespaces of string literals that look like resource locations, like `"mymodid:textures/a"`
+fn resource_namespaces(content: &str) -> Vec<&str> {
+    let mut namespaces = Vec::new();
+    // every other part is inside quotes. Escaped quotes are rare enough in resource locations
+    for literal in content.split('"').skip(1).step_by(2) {
+        let (namespace, path) = match literal.split_once(':') {
+            Some(x) => x,
+            None => continue,
+        };
+        let valid_namespace = !namespace.is_empty()
+            && namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        // urls have `//` after the scheme
+        let valid_path =
+            !path.is_empty() && !path.starts_with("//") && !path.contains(char::is_whitespace);
+        if valid_namespace && valid_path {
+            namespaces.push(namespace);
+        }
+    }
+    namespaces
+}
@@ -0,0 +1,341 @@
+//! Benchmarks for the dev environment
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use tokio::fs;
+
+use crate::run::{self, Side};
+use crate::state::write_json;
+use crate::sync;
+use crate::template::TemplateHandler;
+use crate::util::{
+    self, cd, kill_process_tree, mkdir, new_process_group, timestamp, write_file, IoResult,
+    Project, ProjectArgs,
+};
+
+/// Log4j config for the benchmark runs, with time since JVM start in milliseconds
+/// and FML's trace messages (which has the events sent to each mod)
+const LOG4J_CONFIG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated by mcmod -->
+<Configuration status="WARN">
+    <Appenders>
+        <Console name="Console" target="SYSTEM_OUT">
+            <PatternLayout pattern="[%d{HH:mm:ss}] [%t/%level] [%logger]: %msg%n"/>
+            <ThresholdFilter level="INFO"/>
+        </Console>
+        <File name="Bench" fileName="${sys:mcmod.bench.log}" append="false">
+            <PatternLayout pattern="%r [%logger]: %msg%n"/>
+        </File>
+    </Appenders>
+    <Loggers>
+        <Root level="all">
+            <AppenderRef ref="Console"/>
+            <AppenderRef ref="Bench"/>
+        </Root>
+    </Loggers>
+</Configuration>
+"#;
+
+/// Message FML logs when loading is done
+const LOADED_MESSAGE: &str = "Forge Mod Loader has successfully loaded";
+
+#[derive(Debug, Parser)]
+pub struct BenchCommand {
+    #[clap(subcommand)]
+    pub command: BenchSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BenchSubcommand {
+    /// Measure the startup time of the game, per mod and loading phase
+    Startup(StartupBench),
+}
+
+#[derive(Debug, Parser)]
+pub struct StartupBench {
+    /// Number of times to start the game
+    #[arg(short, long, default_value = "3")]
+    pub runs: usize,
+
+    /// The side to start. The server doesn't need a display
+    #[arg(long, value_enum, default_value = "server")]
+    pub side: Side,
+
+    /// Seconds to wait for each run to finish loading
+    #[arg(long, default_value = "600")]
+    pub timeout: u64,
+}
+
+impl BenchCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        match self.command {
+            BenchSubcommand::Startup(bench) => bench.run(args).await,
+        }
+    }
+}
+
+/// Load times of one run in milliseconds
+#[derive(Debug, Default)]
+struct StartupRun {
+    /// Time until FML finished loading
+    total: f64,
+    /// (mod, phase) to the time spent
+    phases: BTreeMap<(String, String), f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Stats {
+    mean: f64,
+    stddev: f64,
+}
+
+/// Summary of the benchmark, saved to target/bench
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct StartupReport {
+    side: String,
+    runs: usize,
+    total: Stats,
+    /// Mod to total time
+    mods: BTreeMap<String, Stats>,
+    /// Mod to phase to time
+    phases: BTreeMap<String, BTreeMap<String, Stats>>,
+}
+
+impl StartupBench {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        if self.runs == 0 {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Need at least 1 run",
+            ))?;
+        }
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let template_handler = mcmod.template.new_handler();
+        let bench_dir = cd!(project.target_root(), "bench");
+        mkdir!(&bench_dir).await?;
+        let log4j_config = bench_dir.join("log4j2-bench.xml");
+        write_file!(&log4j_config, LOG4J_CONFIG).await?;
+        let task = match self.side {
+            Side::Client => "runClient",
+            Side::Server => {
+                run::agree_to_eula(template_handler.as_ref(), &project, false).await?;
+                "runServer"
+            }
+        };
+
+        sync::link_mods(template_handler.as_ref(), &project, Some(&self.side), None).await?;
+
+        let mut runs = Vec::new();
+        for i in 0..self.runs {
+            println!("startup run {}/{}", i + 1, self.runs);
+            let log_path = bench_dir.join(format!("startup-{i}.log"));
+            if log_path.exists() {
+                fs::remove_file(&log_path).await?;
+            }
+            let mut jvm_args = mcmod.jvm_args.clone();
+            jvm_args.push(format!(
+                "-Dlog4j.configurationFile={}",
+                util::gradle_path(&log4j_config)
+            ));
+            jvm_args.push(format!(
+                "-Dmcmod.bench.log={}",
+                util::gradle_path(&log_path)
+            ));
+            let init_script = run::write_init_script(&project, &jvm_args, &[]).await?;
+            let init_script = util::gradle_path(&init_script);
+            let run = self
+                .run_once(
+                    template_handler.as_ref(),
+                    &project,
+                    task,
+                    &init_script,
+                    &log_path,
+                )
+                .await?;
+            println!("loaded in {:.0}ms", run.total);
+            runs.push(run);
+        }
+
+        let report = summarize(&format!("{:?}", self.side).to_lowercase(), &runs);
+        print_report(&report, &mcmod.modid);
+        let report_path = bench_dir.join(format!("startup-{}.json", timestamp()));
+        write_json(&report_path, &report).await?;
+        println!("report saved to '{}'", report_path.display());
+        Ok(())
+    }
+
+    /// Start the game, wait for FML to finish loading, then stop the game and parse the log
+    async fn run_once(
+        &self,
+        handler: &dyn TemplateHandler,
+        project: &Project,
+        task: &str,
+        init_script: &str,
+        log_path: &Path,
+    ) -> IoResult<StartupRun> {
+        let mut command =
+            handler.gradlew_command(project, &[task, "--init-script", init_script])?;
+        command.stdin(Stdio::null()).stdout(Stdio::null());
+        new_process_group(&mut command);
+        let mut child = command.spawn()?;
+        let start = Instant::now();
+        let timeout = Duration::from_secs(self.timeout);
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if let Ok(log) = fs::read_to_string(log_path).await {
+                if log.contains(LOADED_MESSAGE) {
+                    stop(&mut child)?;
+                    return Ok(parse_log(&log));
+                }
+            }
+            if let Some(status) = child.try_wait()? {
+                Err(io::Error::other(format!(
+                    "Game exited before loading finished ({status}). See '{}'",
+                    log_path.display()
+                )))?;
+            }
+            if start.elapsed() > timeout {
+                stop(&mut child)?;
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Game did not finish loading in {} seconds", self.timeout),
+                ))?;
+            }
+        }
+    }
+}
+
+/// Stop gradle and the game it started
+fn stop(child: &mut Child) -> IoResult<()> {
+    kill_process_tree(child.id());
+    let _ = child.kill();
+    child.wait()?;
+    Ok(())
+}
+
+/// Parse the time spent in each mod and phase from FML's
+/// `Sending event X to mod Y` and `Sent event X to mod Y` messages
+fn parse_log(log: &str) -> StartupRun {
+    let mut run = StartupRun::default();
+    let mut sending = BTreeMap::new();
+    for line in log.lines() {
+        let (time, message) = match line.split_once(' ') {
+            Some((time, rest)) => match time.parse::<f64>() {
+                Ok(time) => (time, rest.split_once("]: ").map(|x| x.1).unwrap_or(rest)),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        if message.contains(LOADED_MESSAGE) {
+            run.total = time;
+            break;
+        }
+        if let Some(rest) = message.strip_prefix("Sending event ") {
+            if let Some((phase, modid)) = rest.split_once(" to mod ") {
+                sending.insert((modid.trim().to_owned(), phase.to_owned()), time);
+            }
+        } else if let Some(rest) = message.strip_prefix("Sent event ") {
+            if let Some((phase, modid)) = rest.split_once(" to mod ") {
+                let key = (modid.trim().to_owned(), phase.to_owned());
+                if let Some(start) = sending.remove(&key) {
+                    *run.phases.entry(key).or_default() += time - start;
+                }
+            }
+        }
+    }
+    run
+}
+
+fn stats(values: &[f64]) -> Stats {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    Stats {
+        mean,
+        stddev: variance.sqrt(),
+    }
+}
+
+fn summarize(side: &str, runs: &[StartupRun]) -> StartupReport {
+    let totals = runs.iter().map(|x| x.total).collect::<Vec<_>>();
+    let mut mod_times: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut phase_times: BTreeMap<String, BTreeMap<String, Vec<f64>>> = BTreeMap::new();
+    for (i, run) in runs.iter().enumerate() {
+        for ((modid, phase), time) in &run.phases {
+            let times = mod_times.entry(modid.clone()).or_default();
+            times.resize(runs.len(), 0.0);
+            times[i] += time;
+            let times = phase_times
+                .entry(modid.clone())
+                .or_default()
+                .entry(phase.clone())
+                .or_default();
+            times.resize(runs.len(), 0.0);
+            times[i] += time;
+        }
+    }
+    StartupReport {
+        side: side.to_owned(),
+        runs: runs.len(),
+        total: stats(&totals),
+        mods: mod_times
+            .into_iter()
+            .map(|(modid, times)| (modid, stats(&times)))
+            .collect(),
+        phases: phase_times
+            .into_iter()
+            .map(|(modid, phases)| {
+                let phases = phases
+                    .into_iter()
+                    .map(|(phase, times)| (phase, stats(&times)))
+                    .collect();
+                (modid, phases)
+            })
+            .collect(),
+    }
+}
+
+fn print_report(report: &StartupReport, modid: &str) {
+    println!();
+    println!(
+        "startup ({}, {} runs): {:.0}ms ± {:.0}ms",
+        report.side, report.runs, report.total.mean, report.total.stddev
+    );
+    let mut mods = report.mods.iter().collect::<Vec<_>>();
+    mods.sort_by(|a, b| b.1.mean.total_cmp(&a.1.mean));
+    println!();
+    println!("{:<32} {:>10} {:>10}", "mod", "mean(ms)", "stddev");
+    for (name, stats) in mods.iter().take(20) {
+        println!("{:<32} {:>10.0} {:>10.0}", name, stats.mean, stats.stddev);
+    }
+    println!();
+    match (report.mods.get(modid), report.phases.get(modid)) {
+        (Some(stats), Some(phases)) => {
+            let percent = if report.total.mean > 0.0 {
+                stats.mean / report.total.mean * 100.0
+            } else {
+                0.0
+            };
+            println!(
+                "'{modid}' took {:.0}ms ± {:.0}ms ({percent:.1}% of startup)",
+                stats.mean, stats.stddev
+            );
+            for (phase, stats) in phases {
+                println!(
+                    "  {:<30} {:>10.0} {:>10.0}",
+                    phase, stats.mean, stats.stddev
+                );
+            }
+        }
+        _ => println!("no load times found for '{modid}'"),
+    }
+}
@@ -1,19 +1,388 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::hash;
+use crate::jar::{self, JarInfo};
+use crate::mcmod::VerifyOptions;
+use crate::pack;
+use crate::state::{BuildState, WorkspaceState};
 use crate::sync::SyncCommand;
-use crate::util::{IoResult, Project};
+use crate::template::TemplateHandler;
+use crate::timings;
+use crate::util::{git_output, mkdir, write_file, IoResult, Project, ProjectArgs};
+use crate::workspace::Workspace;
 
-pub async fn run_build(dir: &str) -> IoResult<()> {
-    let sync = SyncCommand {
-        incremental: false,
-        eclipse: true,
-    };
-    sync.run(dir).await?;
-    let project = Project::new_in(dir)?;
-    let template_handler = project.mcmod().await?.template.new_handler();
-    template_handler.build(&project).await?;
-    let output = template_handler.output_dir(&project)?;
+#[derive(Debug, Clone, Parser)]
+pub struct BuildCommand {
+    /// Run gradle even if nothing changed since the last successful build
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// Also generate javadoc with the template's javadoc task
+    #[arg(long)]
+    pub javadoc: bool,
+
+    /// Copy the javadoc into <DIR>/<version>, for publishing to a site like gh-pages.
+    /// Implies --javadoc
+    #[arg(long, value_name = "DIR")]
+    pub javadoc_publish: Option<PathBuf>,
+
+    /// Build every project in `workspace-members`, after the projects they depend on
+    #[arg(long)]
+    pub workspace: bool,
+
+    /// Only build the workspace members whose sources changed since they were last built,
+    /// and the members that depend on them. Implies --workspace
+    #[arg(long)]
+    pub changed: bool,
+}
+
+impl BuildCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        if self.workspace || self.changed {
+            return self.run_workspace(args).await;
+        }
+        let sync = SyncCommand {
+            incremental: false,
+            eclipse: true,
+            pull_back: false,
+            overwrite: false,
+            ninja_args: Vec::new(),
+            no_prune: false,
+            show_properties_diff: false,
+            fix: false,
+            keep_going: false,
+        };
+        let project = Project::new_in(args)?;
+        let _lock = project.lock_target().await?;
+        sync.run(args).await?;
+        let template_handler = project.mcmod().await?.template.new_handler();
+        let output = template_handler.output_dir(&project)?;
+        if project.mcmod().await?.is_pack() {
+            timings::time("build: pack", pack::build_pack(&project, &output)).await?;
+            println!();
+            println!("the output directory is: {}", output.display());
+            return Ok(());
+        }
+
+        let state = BuildState {
+            inputs: timings::time(
+                "build: hash inputs",
+                hash_build_inputs(template_handler.as_ref(), &project),
+            )
+            .await?,
+            git_version: git_version(template_handler.as_ref(), &project),
+        };
+        let up_to_date = !self.force
+            && output.exists()
+            && BuildState::load(&project).await.as_ref() == Some(&state);
+        if up_to_date {
+            println!("nothing changed since the last build, skipping gradle (use --force to build anyway)");
+        } else {
+            BuildState::remove(&project).await?;
+            timings::time("build: gradle", template_handler.build(&project)).await?;
+            state.save(&project).await?;
+        }
 
-    println!();
-    println!("the output directory is: {}", output.display());
+        if let Some(keep) = project.mcmod().await?.keep_artifacts {
+            prune_artifacts(&output, keep).await?;
+        }
+        if let Some(options) = &project.mcmod().await?.verify {
+            verify_release_jars(&project, &output, options).await?;
+        }
+
+        let javadoc_dir = if self.javadoc || self.javadoc_publish.is_some() {
+            timings::time("build: javadoc", template_handler.javadoc(&project)).await?;
+            Some(template_handler.javadoc_dir(&project)?)
+        } else {
+            None
+        };
+        if let (Some(javadoc_dir), Some(publish_dir)) = (&javadoc_dir, &self.javadoc_publish) {
+            let version = &project.mcmod().await?.artifact_version;
+            publish_javadoc(javadoc_dir, publish_dir, version).await?;
+        }
+
+        println!();
+        println!("the output directory is: {}", output.display());
+        if let Some(javadoc_dir) = javadoc_dir {
+            println!("the javadoc directory is: {}", javadoc_dir.display());
+        }
+
+        Ok(())
+    }
+
+    async fn run_workspace(self, args: &ProjectArgs) -> IoResult<()> {
+        let workspace = Workspace::load(args).await?;
+        let root = &workspace.members[0].project;
+        let mut state = WorkspaceState::load(root).await;
+        let mut selected = vec![false; workspace.members.len()];
+        // (index, hash of the sources) of the members to build
+        let mut to_build = Vec::new();
+        for i in workspace.build_order()? {
+            let member = &workspace.members[i];
+            let hash = workspace.hash_sources(i).await?;
+            let changed = state.members.get(&member.name) != Some(&hash);
+            let dependency_built = member.depends.iter().any(|x| selected[*x]);
+            if !self.changed || changed || dependency_built {
+                selected[i] = true;
+                to_build.push((i, hash));
+                if self.changed {
+                    let reason = if changed {
+                        "changed"
+                    } else {
+                        "depends on a changed member"
+                    };
+                    println!("'{}': {reason}", member.name);
+                }
+            }
+        }
+        if to_build.is_empty() {
+            println!("no workspace members changed since they were last built");
+            return Ok(());
+        }
+
+        let build = Self {
+            workspace: false,
+            changed: false,
+            ..self
+        };
+        let count = to_build.len();
+        for (n, (i, hash)) in to_build.into_iter().enumerate() {
+            let member = &workspace.members[i];
+            println!();
+            println!(
+                "building workspace member '{}' ({}/{count})",
+                member.name,
+                n + 1
+            );
+            let member_args = ProjectArgs {
+                project_root: Some(member.project.root.clone()),
+                ..args.clone()
+            };
+            Box::pin(build.clone().run(&member_args)).await?;
+            state.members.insert(member.name.clone(), hash);
+            mkdir!(root.target_root()).await?;
+            state.save(root).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Classifiers of the jars built by the templates, besides the release jar
+const CLASSIFIERS: &[&str] = &["dev", "sources", "api", "javadoc", "deobf"];
+
+/// The classifier of the jar from its name without `.jar`. Empty for the release jar
+pub fn jar_classifier(stem: &str) -> &'static str {
+    CLASSIFIERS
+        .iter()
+        .find(|x| stem.ends_with(&format!("-{x}")))
+        .copied()
+        .unwrap_or_default()
+}
 
+/// The name of the jar without `.jar` and the version parts, which are the parts
+/// between dashes starting with a digit, or `v` and a digit.
+/// `mymod-1.7.10-1.2.0-dev` and `mymod-1.7.10-1.3.0-dev` are both `mymod-dev`
+pub fn jar_artifact(stem: &str) -> String {
+    stem.split('-')
+        .filter(|part| {
+            let part = part.strip_prefix('v').unwrap_or(part);
+            !part.starts_with(|c: char| c.is_ascii_digit())
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Remove the jars in the output directory except the latest `keep` of each artifact,
+/// which groups the jars by [`jar_artifact`]. Returns the names of the removed jars
+pub async fn prune_artifacts(output: &Path, keep: usize) -> IoResult<Vec<String>> {
+    if keep == 0 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Refusing to remove every artifact, keep at least 1",
+        ))?;
+    }
+    // artifact -> (modified time, name)
+    let mut jars = BTreeMap::<String, Vec<_>>::new();
+    if !output.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut dir = fs::read_dir(output).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let artifact = match name.strip_suffix(".jar") {
+            Some(stem) => jar_artifact(stem),
+            None => continue,
+        };
+        let modified = entry.metadata().await?.modified()?;
+        jars.entry(artifact).or_default().push((modified, name));
+    }
+    let mut removed = Vec::new();
+    for (_, mut jars) in jars {
+        jars.sort_by(|a, b| b.cmp(a));
+        for (_, name) in jars.into_iter().skip(keep) {
+            fs::remove_file(output.join(&name)).await?;
+            println!("removed old artifact '{name}'");
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+/// Check the release jar of the version being built in the output directory.
+/// Jars of other versions and jars with classifiers are not checked
+async fn verify_release_jars(
+    project: &Project,
+    output: &Path,
+    options: &VerifyOptions,
+) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let name = if mcmod.artifact_version.is_empty() {
+        format!("{}.jar", mcmod.archives_base_name)
+    } else {
+        format!(
+            "{}-{}.jar",
+            mcmod.archives_base_name, mcmod.artifact_version
+        )
+    };
+    let path = output.join(&name);
+    if !path.exists() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The release jar '{name}' is not in the output directory"),
+        ))?;
+    }
+    println!("verifying '{name}'");
+    let info = JarInfo::read(&path).await?;
+    let mut failed = false;
+    for problem in jar::check_release(&info, mcmod, options) {
+        eprintln!("  {problem}");
+        failed = true;
+    }
+    if failed {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The release jar failed the checks in the verify section of mcmod.yaml",
+        ))?;
+    }
+    Ok(())
+}
+
+/// Copy the javadoc into a version directory of the publish directory,
+/// and point index.html of the publish directory to it
+async fn publish_javadoc(javadoc_dir: &Path, publish_dir: &Path, version: &str) -> IoResult<()> {
+    if !javadoc_dir.join("index.html").exists() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No javadoc found in '{}'", javadoc_dir.display()),
+        ))?;
+    }
+    let version = if version.is_empty() {
+        "latest"
+    } else {
+        version
+    };
+    let version_dir = publish_dir.join(version);
+    if version_dir.exists() {
+        fs::remove_dir_all(&version_dir).await?;
+    }
+    mkdir!(publish_dir).await?;
+    let errors = copy_dir::copy_dir(javadoc_dir, &version_dir)?;
+    if !errors.is_empty() {
+        for e in errors {
+            eprintln!("  {}", e);
+        }
+        Err(io::Error::other("Failed to copy javadoc"))?;
+    }
+    let index = format!(
+        "<!DOCTYPE html>\n<!-- generated by mcmod -->\n<meta http-equiv=\"refresh\" content=\"0; url={version}/index.html\">\n"
+    );
+    write_file!(publish_dir.join("index.html"), index).await?;
+    println!("published javadoc to '{}'", version_dir.display());
     Ok(())
 }
+
+/// Hash everything in the target that can affect the build output
+async fn hash_build_inputs(handler: &dyn TemplateHandler, project: &Project) -> IoResult<String> {
+    let mut inputs = [
+        "src",
+        "gradle.properties",
+        "build.gradle",
+        "settings.gradle",
+        "dependencies.gradle",
+        "repositories.gradle",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect::<Vec<_>>();
+    inputs.push(handler.libs_dir(project)?);
+    for copy_path in &project.mcmod().await?.copy_paths {
+        let target = PathBuf::from(copy_path.target());
+        if !inputs.contains(&target) {
+            inputs.push(target);
+        }
+    }
+    hash::hash_paths(project, &project.target_root(), &inputs).await
+}
+
+/// Get the version from git for templates that use it, since it changes without any file changes
+pub fn git_version(handler: &dyn TemplateHandler, project: &Project) -> String {
+    if !handler.capabilities().git_version {
+        return String::new();
+    }
+    git_output(
+        &project.root,
+        &["describe", "--always", "--dirty", "--tags"],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn test_jar_artifact() {
+        assert_eq!(jar_artifact("mymod-1.7.10-1.2.0"), "mymod");
+        assert_eq!(jar_artifact("mymod-1.7.10-1.2.0-dev"), "mymod-dev");
+        assert_eq!(jar_artifact("mymod-v1.2.0-universal"), "mymod-universal");
+        assert_eq!(jar_artifact("my-mod-1.2.0+5-all"), "my-mod-all");
+    }
+
+    #[tokio::test]
+    async fn test_prune_artifacts() {
+        let dir = std::env::temp_dir().join(format!("mcmod-test-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        for (i, version) in ["1.0.0", "1.1.0", "1.2.0"].iter().enumerate() {
+            for suffix in ["", "-dev", "-shadow"] {
+                let path = dir.join(format!("mymod-{version}{suffix}.jar"));
+                let file = std::fs::File::create(&path).unwrap();
+                file.set_modified(now - Duration::from_secs(100 - i as u64))
+                    .unwrap();
+            }
+        }
+        assert!(prune_artifacts(&dir, 0).await.is_err());
+        let mut removed = prune_artifacts(&dir, 1).await.unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                "mymod-1.0.0-dev.jar",
+                "mymod-1.0.0-shadow.jar",
+                "mymod-1.0.0.jar",
+                "mymod-1.1.0-dev.jar",
+                "mymod-1.1.0-shadow.jar",
+                "mymod-1.1.0.jar",
+            ]
+        );
+        assert!(dir.join("mymod-1.2.0-shadow.jar").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
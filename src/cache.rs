@@ -0,0 +1,376 @@
+//! Looking into, checking and clearing the caches of the tool in the cache directory
+//!
+//! The index in the cache directory records when each cache was last used, and the checksum
+//! of caches that don't change after they are written, so `mcmod cache gc` can find
+//! unused and corrupted caches
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::paths;
+use crate::util::{self, format_size, IoResult};
+
+/// Directories in the cache that have one entry per template or key,
+/// which are shown and cleared separately
+const GROUPED_DIRS: &[&str] = &["gradle", "decomp"];
+
+/// Name of the index in the cache directory
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    #[serde(default)]
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IndexEntry {
+    /// Seconds since the epoch
+    #[serde(default)]
+    last_used: u64,
+    /// Checksum of the cache when it was written, for caches that don't change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+impl CacheIndex {
+    fn load(cache_dir: &Path) -> Self {
+        std::fs::read_to_string(cache_dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|x| serde_json::from_str(&x).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, cache_dir: &Path) -> IoResult<()> {
+        let content = match serde_json::to_string_pretty(self) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        std::fs::create_dir_all(cache_dir)?;
+        util::write_atomic_blocking(&cache_dir.join(INDEX_FILE), content.as_bytes())
+    }
+}
+
+/// Record that the cache entry (like `gradle/ntmc-1.7.10`) is used, and the checksum of it
+/// if it's just written. Not being able to record it only affects `mcmod cache gc`
+pub fn record_use(entry: &str, checksum: Option<String>) {
+    let cache_dir = match paths::cache_dir_location() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let mut index = CacheIndex::load(&cache_dir);
+    let record = index.entries.entry(entry.to_owned()).or_default();
+    record.last_used = now_secs();
+    if checksum.is_some() {
+        record.checksum = checksum;
+    }
+    let _ = index.save(&cache_dir);
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// Checksum of the files in the path and their relative paths
+pub fn checksum_path(path: &Path) -> IoResult<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = match entry {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::other(e))?,
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update(&[0]);
+        let mut file = std::fs::File::open(entry.path())?;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[derive(Debug, Parser)]
+pub struct CacheCommand {
+    #[clap(subcommand)]
+    pub command: CacheSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheSubcommand {
+    /// Show the caches and their sizes
+    Info,
+    /// Remove caches, like `gradle/ntmc-1.7.10` or `decomp`, as shown by `mcmod cache info`
+    Clear(ClearCache),
+    /// Check the caches, and remove the ones that are corrupted or unused for a while.
+    /// Only the caches in the cache directory are checked. The caches in the target of a
+    /// project, like target/mods-cache, target/pack-mods and the remapped dev jars, are pruned
+    /// by `mcmod sync` and removed with the target
+    Gc(GcCache),
+}
+
+#[derive(Debug, Parser)]
+pub struct GcCache {
+    /// Remove caches not used for this many days
+    #[arg(long, default_value_t = 30)]
+    pub days: u64,
+
+    /// Only show what would be removed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ClearCache {
+    /// The caches to remove
+    pub entries: Vec<String>,
+
+    /// Remove all caches
+    #[arg(long, conflicts_with = "entries")]
+    pub all: bool,
+}
+
+impl CacheCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let cache_dir = paths::cache_dir().await?;
+        match self.command {
+            CacheSubcommand::Info => info(&cache_dir).await,
+            CacheSubcommand::Clear(clear) => clear.run(&cache_dir).await,
+            CacheSubcommand::Gc(gc) => gc.run(&cache_dir).await,
+        }
+    }
+}
+
+/// The entries in the cache, as paths relative to the cache directory
+async fn cache_entries(cache_dir: &Path) -> IoResult<Vec<String>> {
+    let mut entries = Vec::new();
+    for name in file_names(cache_dir).await? {
+        if name == INDEX_FILE {
+            continue;
+        }
+        if GROUPED_DIRS.contains(&name.as_str()) {
+            for sub_name in file_names(&cache_dir.join(&name)).await? {
+                entries.push(format!("{name}/{sub_name}"));
+            }
+        } else {
+            entries.push(name);
+        }
+    }
+    Ok(entries)
+}
+
+async fn file_names(dir: &Path) -> IoResult<Vec<String>> {
+    let mut names = Vec::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+async fn info(cache_dir: &Path) -> IoResult<()> {
+    println!("cache directory: '{}'", cache_dir.display());
+    let entries = cache_entries(cache_dir).await?;
+    if entries.is_empty() {
+        println!("  empty");
+        return Ok(());
+    }
+    let width = entries.iter().map(|x| x.len()).max().unwrap_or_default();
+    let mut total = 0;
+    for entry in entries {
+        let path = cache_dir.join(&entry);
+        let size = tokio::task::spawn_blocking(move || disk_size(&path))
+            .await
+            .unwrap_or_default();
+        total += size;
+        println!("  {entry:<width$}  {}", format_size(size));
+    }
+    println!("total: {}", format_size(total));
+    Ok(())
+}
+
+/// Size of the files in the path
+fn disk_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|x| x.file_type().is_file())
+        .filter_map(|x| x.metadata().ok())
+        .map(|x| x.len())
+        .sum()
+}
+
+impl ClearCache {
+    async fn run(self, cache_dir: &Path) -> IoResult<()> {
+        let entries = if self.all {
+            cache_entries(cache_dir).await?
+        } else {
+            if self.entries.is_empty() {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Specify the caches to remove, or --all. The caches are: {}",
+                        cache_entries(cache_dir).await?.join(", ")
+                    ),
+                ))?;
+            }
+            self.entries
+        };
+        for entry in entries {
+            let path = entry_path(cache_dir, &entry)?;
+            if path.is_dir() {
+                fs::remove_dir_all(&path).await?;
+            } else {
+                fs::remove_file(&path).await?;
+            }
+            println!("removed '{entry}'");
+        }
+        forget_removed(cache_dir).await
+    }
+}
+
+/// Remove the records of caches that no longer exist from the index
+async fn forget_removed(cache_dir: &Path) -> IoResult<()> {
+    let mut index = CacheIndex::load(cache_dir);
+    let before = index.entries.len();
+    index
+        .entries
+        .retain(|entry, _| cache_dir.join(entry).exists());
+    if index.entries.len() != before {
+        index.save(cache_dir)?;
+    }
+    Ok(())
+}
+
+/// What kind of cache the entry is, from where it is in the cache directory
+fn entry_kind(entry: &str) -> &'static str {
+    match entry.split('/').next() {
+        Some("gradle") => "gradle home",
+        Some("decomp") => "decompiled workspace",
+        _ => "download",
+    }
+}
+
+impl GcCache {
+    async fn run(self, cache_dir: &Path) -> IoResult<()> {
+        let index = CacheIndex::load(cache_dir);
+        let now = now_secs();
+        let mut to_remove = Vec::new();
+        println!("cache directory: '{}'", cache_dir.display());
+        for entry in cache_entries(cache_dir).await? {
+            let path = cache_dir.join(&entry);
+            let record = index.entries.get(&entry);
+            // caches from before the index was added use the modified time
+            let last_used = match record {
+                Some(x) if x.last_used > 0 => x.last_used,
+                _ => std::fs::metadata(&path)
+                    .and_then(|x| x.modified())
+                    .ok()
+                    .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                    .map(|x| x.as_secs())
+                    .unwrap_or(now),
+            };
+            let days_unused = now.saturating_sub(last_used) / 86400;
+            let expected = record.and_then(|x| x.checksum.clone());
+            let (size, checksum) = {
+                let path = path.clone();
+                let check = expected.is_some();
+                let result = tokio::task::spawn_blocking(move || {
+                    let checksum = if check {
+                        Some(checksum_path(&path))
+                    } else {
+                        None
+                    };
+                    (disk_size(&path), checksum)
+                })
+                .await;
+                match result {
+                    Ok(x) => x,
+                    Err(e) => Err(io::Error::from(e))?,
+                }
+            };
+            let integrity = match (&expected, checksum) {
+                (Some(expected), Some(Ok(actual))) if *expected == actual => "ok",
+                (Some(_), Some(_)) => "corrupted",
+                _ => "not checked",
+            };
+            println!(
+                "  {entry}: {}, {}, last used {days_unused} day(s) ago, {integrity}",
+                entry_kind(&entry),
+                format_size(size)
+            );
+            if integrity == "corrupted" || days_unused >= self.days {
+                to_remove.push((entry, size));
+            }
+        }
+        if to_remove.is_empty() {
+            println!("nothing to remove");
+            return Ok(());
+        }
+        let freed = to_remove.iter().map(|(_, size)| size).sum::<u64>();
+        if self.dry_run {
+            for (entry, _) in &to_remove {
+                println!("would remove '{entry}'");
+            }
+            println!("would free {}", format_size(freed));
+            return Ok(());
+        }
+        for (entry, _) in &to_remove {
+            let path = cache_dir.join(entry);
+            if path.is_dir() {
+                fs::remove_dir_all(&path).await?;
+            } else {
+                fs::remove_file(&path).await?;
+            }
+            println!("removed '{entry}'");
+        }
+        forget_removed(cache_dir).await?;
+        println!("freed {}", format_size(freed));
+        Ok(())
+    }
+}
+
+/// Resolve the entry in the cache directory, refusing paths outside of it
+fn entry_path(cache_dir: &Path, entry: &str) -> IoResult<PathBuf> {
+    let relative = Path::new(entry);
+    let is_inside = relative
+        .components()
+        .all(|x| matches!(x, Component::Normal(_)));
+    if !is_inside || entry.is_empty() {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{entry}' is not a cache. Use the names shown by `mcmod cache info`"),
+        ))?;
+    }
+    let path = cache_dir.join(relative);
+    if !path.exists() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("There is no cache '{entry}'. Use the names shown by `mcmod cache info`"),
+        ))?;
+    }
+    Ok(path)
+}
@@ -0,0 +1,44 @@
+//! Removing outputs the tool and templates leave behind in the target directory
+
+use std::num::NonZeroUsize;
+
+use clap::{ArgGroup, Parser};
+
+use crate::build;
+use crate::util::{IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+#[clap(group(ArgGroup::new("what").required(true).args(["artifacts"])))]
+pub struct CleanCommand {
+    /// Remove the jars of previous builds from the output directory
+    #[arg(long)]
+    pub artifacts: bool,
+
+    /// Number of the latest jars of each artifact to keep with --artifacts, at least 1.
+    /// Defaults to `keep-artifacts` in mcmod.yaml, or 1
+    #[arg(long, requires = "artifacts")]
+    pub keep: Option<NonZeroUsize>,
+}
+
+impl CleanCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let _lock = project.lock_target().await?;
+        let mcmod = project.mcmod().await?;
+        if self.artifacts {
+            let keep = self
+                .keep
+                .map(NonZeroUsize::get)
+                .or(mcmod.keep_artifacts)
+                .unwrap_or(1);
+            let output = mcmod.template.new_handler().output_dir(&project)?;
+            let removed = build::prune_artifacts(&output, keep).await?;
+            println!(
+                "removed {} artifact(s) from '{}', keeping the latest {keep} of each artifact",
+                removed.len(),
+                output.display()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Global config of the tool, in config.json in the config directory
+
+use std::io;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::explain::ErrorCode;
+use crate::paths;
+use crate::util::IoResult;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct GlobalConfig {
+    /// Registry to get templates.json from with `mcmod template sync-registry`
+    pub template_registry: Option<RegistryConfig>,
+    /// Run gradle with a `GRADLE_USER_HOME` in the cache directory shared by the projects
+    /// of the same template, instead of the default gradle home
+    pub shared_gradle_home: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryConfig {
+    /// Url of the templates.json of the registry
+    pub url: String,
+    /// Expected blake3 checksum of the templates.json, which is required since the templates
+    /// in the registry can run commands
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+static GLOBAL_CONFIG: OnceLock<GlobalConfig> = OnceLock::new();
+
+impl GlobalConfig {
+    /// Get config.json in the config directory, loaded once. Uses the defaults if it doesn't exist
+    pub fn get() -> IoResult<&'static Self> {
+        if let Some(x) = GLOBAL_CONFIG.get() {
+            return Ok(x);
+        }
+        let config = Self::load()?;
+        Ok(GLOBAL_CONFIG.get_or_init(|| config))
+    }
+
+    fn load() -> IoResult<Self> {
+        let path = paths::config_dir()?.join("config.json");
+        let content = match std::fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => Err(e)?,
+        };
+        match serde_json::from_str(&content) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse '{}': {e}", path.display()),
+            )))?,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Persistent daemon for instant incremental syncs
+//!
+//! The daemon keeps the project config and the state of copied files in memory,
+//! and copies changed files itself instead of running ninja. Editors can connect to
+//! the socket (unix socket, or named pipe on Windows) and send one JSON request per line,
+//! like `{"command":"sync"}`. Each request gets one JSON response line like
+//! `{"ok":true,"message":"copied 2 file(s)"}`.
+//!
+//! When mcmod.yaml changes, the mods are synced again with the HTTP client the daemon keeps,
+//! so its connections are reused. Other changes to the config still need `mcmod sync`.
+//!
+//! Commands are `sync`, `ping` and `stop`
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::mcmod::is_preprocessed;
+use crate::preprocess;
+use crate::sync::{self, Downloader, Prune};
+use crate::util::{mkdir, write_file, IoResult, Project, ProjectArgs};
+use crate::verify;
+
+#[derive(Debug, Parser)]
+pub struct DaemonCommand {
+    /// Send a request (sync, ping or stop) to the running daemon instead of starting one
+    #[arg(long)]
+    pub send: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub command: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl DaemonCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let address = socket_address(&project);
+        match self.send {
+            Some(command) => {
+                let response = send(&address, &Request { command }).await?;
+                println!("{}", response.message);
+                if !response.ok {
+                    Err(io::Error::other("daemon request failed"))?;
+                }
+                Ok(())
+            }
+            None => {
+                if send(
+                    &address,
+                    &Request {
+                        command: "ping".to_owned(),
+                    },
+                )
+                .await
+                .is_ok()
+                {
+                    Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        "A daemon is already running for this project",
+                    ))?;
+                }
+                let downloader = Downloader::new(&project.mcmod().await?.downloads)?;
+                let mut daemon = Daemon {
+                    args: args.clone(),
+                    project,
+                    downloader,
+                    mcmod_modified: None,
+                    copied: HashMap::new(),
+                };
+                // load everything upfront so the first request is fast
+                let message = daemon.sync().await?;
+                println!("{message}");
+                println!("daemon listening on '{address}'");
+                serve(&mut daemon, &address).await
+            }
+        }
+    }
+}
+
+struct Daemon {
+    args: ProjectArgs,
+    project: Project,
+    /// Kept warm for syncing the mods when the config changes
+    downloader: Downloader,
+    /// Modified time of mcmod.yaml when the config was loaded
+    mcmod_modified: Option<SystemTime>,
+    /// Modified time and size of the source of each target file when it was copied
+    copied: HashMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl Daemon {
+    async fn handle(&mut self, request: &Request) -> (Response, bool) {
+        match request.command.as_str() {
+            "ping" => (ok("pong".to_owned()), false),
+            "stop" => (ok("stopping".to_owned()), true),
+            "sync" => match self.sync().await {
+                Ok(message) => (ok(message), false),
+                Err(e) => (
+                    Response {
+                        ok: false,
+                        message: format!("{e:?}"),
+                    },
+                    false,
+                ),
+            },
+            command => (
+                Response {
+                    ok: false,
+                    message: format!("unknown command '{command}'"),
+                },
+                false,
+            ),
+        }
+    }
+
+    /// Copy the source files that changed since they were last copied
+    async fn sync(&mut self) -> IoResult<String> {
+        let start = Instant::now();
+        let modified = fs::metadata(self.project.root.join("mcmod.yaml"))
+            .await?
+            .modified()
+            .ok();
+        let reload = modified != self.mcmod_modified;
+        if reload {
+            self.project = Project::new_in(&self.args)?;
+            self.mcmod_modified = modified;
+            self.copied.clear();
+        }
+        let _lock = self.project.lock_target().await?;
+        let mcmod = self.project.mcmod().await?;
+        if reload && !mcmod.is_pack() {
+            let handler = mcmod.template.new_handler();
+            let prune = Prune {
+                enabled: true,
+                keep: &mcmod.keep,
+                generated: &[],
+            };
+            sync::sync_mods(
+                handler.as_ref(),
+                &self.project,
+                &self.downloader,
+                &prune,
+                false,
+            )
+            .await?;
+        }
+        let mut vars = std::collections::BTreeMap::new();
+        if mcmod.preprocess {
+            let handler = mcmod.template.new_handler();
+            vars.insert(
+                "MC".to_owned(),
+                preprocess::mc_version_number(handler.mc_version()),
+            );
+        }
+
+        let mut copied = Vec::new();
+        for (target, source) in mcmod.collect_copy_edges(&self.project).await? {
+            let metadata = fs::metadata(&source).await?;
+            let stamp = (metadata.modified()?, metadata.len());
+            let up_to_date = match self.copied.get(&target) {
+                Some(x) => *x == stamp && target.exists(),
+                // first time seeing the file, compare with the target like ninja
+                None => match fs::metadata(&target).await {
+                    Ok(x) => x.modified()? >= stamp.0,
+                    Err(_) => false,
+                },
+            };
+            if !up_to_date {
+                if let Some(parent) = target.parent() {
+                    mkdir!(parent).await?;
+                }
+                if mcmod.preprocess && is_preprocessed(&source) {
+                    let content = fs::read_to_string(&source).await?;
+                    let content = match preprocess::preprocess(&content, &vars) {
+                        Ok(x) => x,
+                        Err(e) => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{}: {e}", source.display()),
+                        ))?,
+                    };
+                    write_file!(&target, content).await?;
+                } else {
+                    fs::copy(&source, &target).await?;
+                }
+                copied.push(target.clone());
+            }
+            self.copied.insert(target, stamp);
+        }
+        let count = copied.len();
+        if count > 0 {
+            verify::update_manifest(&self.project, copied).await?;
+        }
+        Ok(format!(
+            "copied {count} file(s) in {}ms",
+            start.elapsed().as_millis()
+        ))
+    }
+}
+
+fn ok(message: String) -> Response {
+    Response { ok: true, message }
+}
+
+/// Handle requests on a connection. Returns true if the daemon should stop
+async fn handle_connection<S>(daemon: &mut Daemon, stream: S) -> IoResult<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, stop) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => daemon.handle(&request).await,
+            Err(e) => (
+                Response {
+                    ok: false,
+                    message: format!("invalid request: {e}"),
+                },
+                false,
+            ),
+        };
+        let mut response = match serde_json::to_string(&response) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+        writer.flush().await?;
+        if stop {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Send a request to the running daemon and wait for the response
+async fn send(address: &str, request: &Request) -> IoResult<Response> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(address).await?;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(address)?;
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut request = match serde_json::to_string(request) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+    writer.flush().await?;
+    let line = match BufReader::new(reader).lines().next_line().await? {
+        Some(x) => x,
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Daemon closed the connection",
+        ))?,
+    };
+    match serde_json::from_str(&line) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+    }
+}
+
+/// Get the socket path (or pipe name on Windows) for the project
+fn socket_address(project: &Project) -> String {
+    let hash = blake3::hash(project.target_root().to_string_lossy().as_bytes()).to_hex();
+    let hash = &hash[..16];
+    if cfg!(windows) {
+        return format!(r"\\.\pipe\mcmod-{hash}");
+    }
+    let path = project.target_root().join(".mcmod-daemon.sock");
+    let path = path.display().to_string();
+    // unix socket paths are limited to around 100 bytes
+    if path.len() < 100 {
+        return path;
+    }
+    std::env::temp_dir()
+        .join(format!("mcmod-{hash}.sock"))
+        .display()
+        .to_string()
+}
+
+#[cfg(unix)]
+async fn serve(daemon: &mut Daemon, address: &str) -> IoResult<()> {
+    // a socket file left over from a daemon that didn't stop cleanly
+    if fs::metadata(address).await.is_ok() {
+        fs::remove_file(address).await?;
+    }
+    let listener = tokio::net::UnixListener::bind(address)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        match handle_connection(daemon, stream).await {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => eprintln!("error: {e:?}"),
+        }
+    }
+    fs::remove_file(address).await?;
+    println!("daemon stopped");
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn serve(daemon: &mut Daemon, address: &str) -> IoResult<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(address)?;
+    loop {
+        server.connect().await?;
+        let stream = server;
+        server = ServerOptions::new().create(address)?;
+        match handle_connection(daemon, stream).await {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => eprintln!("error: {e:?}"),
+        }
+    }
+    println!("daemon stopped");
+    Ok(())
+}
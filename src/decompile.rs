@@ -0,0 +1,325 @@
+//! Decompiling dependencies to read their code
+
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::Parser;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::QName;
+use quick_xml::{Reader, Writer};
+use reqwest::Client;
+use tokio::fs;
+use zip::ZipArchive;
+
+use crate::cache;
+use crate::gradle;
+use crate::paths;
+use crate::sync;
+use crate::util::{self, cd, mkdir, IoResult, Project, ProjectArgs};
+
+/// Version of Vineflower to download
+const VINEFLOWER_VERSION: &str = "1.10.1";
+/// Java version to run the decompiler with
+const DECOMPILER_JAVA_VERSION: u32 = 17;
+
+#[derive(Debug, Parser)]
+pub struct DecompileCommand {
+    /// Name of the jar in libs or run/mods to decompile.
+    ///
+    /// Can be the file name or part of it
+    pub name: String,
+
+    /// Add the decompiled sources to .classpath as the source attachment of the jar
+    #[arg(long)]
+    pub attach: bool,
+}
+
+impl DecompileCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let jar = find_jar(&project, &self.name).await?;
+        let output = decompiled_dir(&project, &jar)?;
+        let decompiler = decompiler_jar().await?;
+
+        println!("decompiling '{}'", jar.display());
+        if output.exists() {
+            fs::remove_dir_all(&output).await?;
+        }
+        let temp_dir = output.with_extension("tmp");
+        if temp_dir.exists() {
+            fs::remove_dir_all(&temp_dir).await?;
+        }
+        mkdir!(&temp_dir).await?;
+        let java = gradle::java_home(DECOMPILER_JAVA_VERSION)?
+            .join("bin")
+            .join(if cfg!(windows) { "java.exe" } else { "java" });
+        let status = Command::new(java)
+            .arg("-jar")
+            .arg(&decompiler)
+            .arg(&jar)
+            .arg(&temp_dir)
+            .status()?;
+        if !status.success() {
+            Err(io::Error::other(format!(
+                "Decompiler failed with status: {status}"
+            )))?;
+        }
+        extract_output(&temp_dir, &output).await?;
+        fs::remove_dir_all(&temp_dir).await?;
+        println!("decompiled into '{}'", output.display());
+
+        if self.attach {
+            let stem = jar_stem(&jar)?;
+            let attached = attach_sources(&project).await?;
+            if attached.contains(&stem) {
+                println!("attached sources in .classpath");
+            } else {
+                println!("'{}' is not in .classpath, sources not attached. Sync with --eclipse first if it's a lib", jar.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the jar by name in the libs and mods directories.
+/// The mods cache has the mods in mod sets that are not being used
+async fn find_jar(project: &Project, name: &str) -> IoResult<PathBuf> {
+    let handler = project.mcmod().await?.template.new_handler();
+    let dirs = [
+        handler.libs_dir(project)?,
+        cd!(handler.run_dir(project)?, "mods"),
+        sync::mods_cache_dir(project),
+    ];
+    let name_lower = name.to_lowercase();
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.ends_with(".jar") {
+                continue;
+            }
+            if file_name == name || path.file_stem().is_some_and(|x| x == name) {
+                return Ok(path);
+            }
+            // the same mod can be in the mods directory and the cache
+            let seen = candidates.iter().any(|x| x.file_name() == path.file_name());
+            if file_name.to_lowercase().contains(&name_lower) && !seen {
+                candidates.push(path);
+            }
+        }
+    }
+    match candidates.len() {
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No jar matching '{name}' in libs or run/mods. Run `mcmod sync` to download them first"),
+        ))?,
+        1 => Ok(candidates.remove(0)),
+        _ => {
+            let names = candidates
+                .iter()
+                .filter_map(|x| x.file_name())
+                .map(|x| x.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Multiple jars match '{name}': {names}"),
+            ))?
+        }
+    }
+}
+
+fn jar_stem(jar: &Path) -> IoResult<String> {
+    match jar.file_stem().and_then(|x| x.to_str()) {
+        Some(x) => Ok(x.to_owned()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid jar name '{}'", jar.display()),
+        ))?,
+    }
+}
+
+/// The directory to decompile the jar into
+pub fn decompiled_dir(project: &Project, jar: &Path) -> IoResult<PathBuf> {
+    Ok(cd!(project.target_root(), "decompiled", jar_stem(jar)?))
+}
+
+/// Get the decompiler jar, downloading it if needed.
+///
+/// MCMOD_DECOMPILER_JAR can be set to use another Vineflower or Fernflower jar
+async fn decompiler_jar() -> IoResult<PathBuf> {
+    if let Ok(path) = std::env::var("MCMOD_DECOMPILER_JAR") {
+        return Ok(PathBuf::from(path));
+    }
+    let cache_dir = paths::cache_dir().await?;
+    let file_name = format!("vineflower-{VINEFLOWER_VERSION}.jar");
+    let path = cache_dir.join(&file_name);
+    if path.exists() {
+        cache::record_use(&file_name, None);
+        return Ok(path);
+    }
+    mkdir!(&cache_dir).await?;
+    let url = format!(
+        "https://repo1.maven.org/maven2/org/vineflower/vineflower/{VINEFLOWER_VERSION}/vineflower-{VINEFLOWER_VERSION}.jar"
+    );
+    println!("downloading '{url}'");
+    let bytes_result = async {
+        Client::new()
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    }
+    .await;
+    let bytes = match bytes_result {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::other(e))?,
+    };
+    // written in one step, so the recorded checksum is of the complete jar
+    util::write_atomic(&path, &bytes).await?;
+    cache::record_use(&file_name, Some(cache::checksum_path(&path)?));
+    Ok(path)
+}
+
+/// Move the decompiler output into the output directory.
+///
+/// Depending on the version, the decompiler writes either a folder or
+/// a jar of sources, which is extracted
+async fn extract_output(temp_dir: &Path, output: &Path) -> IoResult<()> {
+    let mut entries = fs::read_dir(temp_dir).await?;
+    let mut archive = None;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".jar") || name.ends_with(".zip") {
+            archive = Some(entry.path());
+        }
+    }
+    let archive = match archive {
+        Some(x) => x,
+        None => {
+            fs::rename(temp_dir, output).await?;
+            mkdir!(temp_dir).await?;
+            return Ok(());
+        }
+    };
+    let output = output.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&archive)?;
+        let mut archive = ZipArchive::new(file).map_err(io::Error::other)?;
+        archive.extract(&output).map_err(io::Error::other)
+    })
+    .await;
+    match result {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    }
+    Ok(())
+}
+
+/// Set the decompiled sources as the source attachment of the libs in .classpath,
+/// for libs that are decompiled and don't have sources already.
+///
+/// Returns the names of the jars with sources attached
+pub async fn attach_sources(project: &Project) -> IoResult<Vec<String>> {
+    let classpath = project.root.join(".classpath");
+    let decompiled_root = cd!(project.target_root(), "decompiled");
+    if !classpath.exists() || !decompiled_root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let input = fs::read_to_string(&classpath).await?;
+    let mut attached = Vec::new();
+    let mut output = Vec::new();
+    let result = (|| {
+        let mut reader = Reader::from_str(&input);
+        let mut writer = Writer::new(&mut output);
+        loop {
+            let event = reader.read_event()?;
+            let (e, empty) = match event {
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                Event::Eof => break,
+                e => {
+                    writer.write_event(e)?;
+                    continue;
+                }
+            };
+            let e = match attach_to_entry(&e, &decompiled_root)? {
+                Some((e, name)) => {
+                    attached.push(name);
+                    e
+                }
+                None => e,
+            };
+            if empty {
+                writer.write_event(Event::Empty(e))?;
+            } else {
+                writer.write_event(Event::Start(e))?;
+            }
+        }
+        Ok::<(), quick_xml::Error>(())
+    })();
+    if let Err(e) = result {
+        Err(io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+    if !attached.is_empty() {
+        util::write_atomic(&classpath, &output).await?;
+    }
+    Ok(attached)
+}
+
+/// Add sourcepath to the lib classpathentry if it's decompiled
+fn attach_to_entry<'a>(
+    e: &BytesStart<'a>,
+    decompiled_root: &Path,
+) -> Result<Option<(BytesStart<'a>, String)>, quick_xml::Error> {
+    if e.name().as_ref() != b"classpathentry" {
+        return Ok(None);
+    }
+    let mut is_lib = false;
+    let mut path = None;
+    let mut source_path = None;
+    for attr in e.attributes() {
+        let attr = attr?;
+        match attr.key.as_ref() {
+            b"kind" => is_lib = attr.value.as_ref() == b"lib",
+            b"path" => path = Some(String::from_utf8_lossy(&attr.value).to_string()),
+            b"sourcepath" => source_path = Some(String::from_utf8_lossy(&attr.value).to_string()),
+            _ => {}
+        }
+    }
+    let stem = match (is_lib, path.as_deref().map(Path::new)) {
+        (true, Some(path)) => match path.file_stem() {
+            Some(x) => x.to_string_lossy().to_string(),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let source_dir = decompiled_root.join(&stem);
+    if !source_dir.is_dir() {
+        return Ok(None);
+    }
+    let source_dir = util::gradle_path(&source_dir);
+    match source_path {
+        Some(x) if x == source_dir => return Ok(Some((e.clone(), stem))),
+        // has other sources
+        Some(_) => return Ok(None),
+        None => {}
+    }
+    let mut new_e = e.clone();
+    new_e.push_attribute(Attribute {
+        key: QName(b"sourcepath"),
+        value: Cow::Owned(source_dir.into_bytes()),
+    });
+    Ok(Some((new_e, stem)))
+}
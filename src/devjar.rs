@@ -0,0 +1,267 @@
+//! Picking the dev flavor of Maven artifacts in `libs`
+//!
+//! Mods publish their jars with SRG names, which don't match the dev environment.
+//! For a lib url like `.../name/1.0/name-1.0.jar`, the `-dev` and `-deobf` classifiers
+//! are used instead when the repository has them. Otherwise the jar is downloaded into
+//! target/srg-cache and remapped into `name-1.0-dev.jar` in libs with the template's mappings.
+//!
+//! The decision for each url is recorded in mcmod.lock in the project, so later syncs
+//! and other machines use the same artifact without asking the repository again
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::mcmod::DownloadOptions;
+use crate::remap;
+use crate::state::{read_json, write_json};
+use crate::sync;
+use crate::util::{IoResult, Project};
+
+/// Classifiers with dev names, in the order they are preferred
+const CLASSIFIERS: &[(&str, Flavor)] = &[("dev", Flavor::Dev), ("deobf", Flavor::Deobf)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flavor {
+    /// The `-dev` classifier
+    Dev,
+    /// The `-deobf` classifier
+    Deobf,
+    /// No dev classifier, the jar is remapped from SRG names
+    Remap,
+}
+
+/// The artifact picked for a lib
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockedLib {
+    pub flavor: Flavor,
+    /// The url that is downloaded
+    pub url: String,
+}
+
+/// Decisions made when resolving the libs, kept in the project
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lockfile {
+    /// The artifact of each Maven lib, keyed by the url in mcmod.yaml
+    #[serde(default)]
+    pub dev_jars: BTreeMap<String, LockedLib>,
+}
+
+/// The libs to download after picking the flavors
+#[derive(Debug, Default)]
+pub struct Resolved {
+    /// Sources to download into libs
+    pub libs: Vec<String>,
+    /// Urls of jars with SRG names, downloaded into the SRG cache and remapped into libs
+    pub remap: Vec<String>,
+}
+
+impl Lockfile {
+    pub fn path(project: &Project) -> PathBuf {
+        project.root.join("mcmod.lock")
+    }
+
+    pub async fn load(project: &Project) -> Self {
+        read_json(&Self::path(project)).await.unwrap_or_default()
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+
+    /// Get the libs to download with the recorded flavors.
+    /// Libs that are not recorded are downloaded as they are
+    pub fn apply(&self, libs: &[String]) -> Resolved {
+        let mut resolved = Resolved::default();
+        for lib in libs {
+            match self.dev_jars.get(lib) {
+                Some(locked) if locked.flavor == Flavor::Remap => {
+                    resolved.remap.push(locked.url.clone())
+                }
+                Some(locked) => resolved.libs.push(locked.url.clone()),
+                None => resolved.libs.push(lib.clone()),
+            }
+        }
+        resolved
+    }
+}
+
+/// Directory the jars with SRG names are downloaded to before remapping
+pub fn cache_dir(project: &Project) -> PathBuf {
+    project.target_root().join("srg-cache")
+}
+
+/// File name of the remapped jar in libs
+pub fn jar_name(url: &str) -> Option<String> {
+    let name = sync::download_file_name(url)?;
+    let stem = name.strip_suffix(".jar").unwrap_or(name);
+    Some(format!("{stem}-dev.jar"))
+}
+
+/// Pick the flavor of the Maven libs that are not in the lockfile yet, and get the libs to download
+pub async fn resolve(
+    project: &Project,
+    libs: &[String],
+    options: &DownloadOptions,
+) -> IoResult<Resolved> {
+    let mut lockfile = Lockfile::load(project).await;
+    let mut changed = false;
+    let new_libs = libs
+        .iter()
+        .filter(|x| !lockfile.dev_jars.contains_key(*x))
+        .filter_map(|x| Some((x, maven_base(x)?)))
+        .collect::<Vec<_>>();
+    let client = match Client::builder()
+        .connect_timeout(options.connect_timeout())
+        .timeout(options.read_timeout())
+        .build()
+    {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::other(e))?,
+    };
+    for (lib, base) in new_libs {
+        match probe(&client, lib, base).await {
+            Some(locked) => {
+                match locked.flavor {
+                    Flavor::Remap => println!("no dev jar for '{lib}', it will be remapped"),
+                    _ => println!("using '{}' for '{lib}'", locked.url),
+                }
+                lockfile.dev_jars.insert(lib.clone(), locked);
+                changed = true;
+            }
+            None => {
+                println!("warning: cannot check for a dev jar of '{lib}', using it as is");
+            }
+        }
+    }
+    // forget the libs that were removed from mcmod.yaml
+    let count = lockfile.dev_jars.len();
+    lockfile.dev_jars.retain(|url, _| libs.contains(url));
+    if lockfile.dev_jars.is_empty() && count > 0 {
+        fs::remove_file(Lockfile::path(project)).await?;
+    } else if changed || lockfile.dev_jars.len() != count {
+        lockfile.save(project).await?;
+    }
+    Ok(lockfile.apply(libs))
+}
+
+/// Find the dev classifier of the artifact in the repository.
+/// Returns None if the repository can't be reached
+async fn probe(client: &Client, url: &str, base: &str) -> Option<LockedLib> {
+    for (classifier, flavor) in CLASSIFIERS {
+        let candidate = format!("{base}-{classifier}.jar");
+        let status = client.head(&candidate).send().await.ok()?.status();
+        if status.is_success() {
+            return Some(LockedLib {
+                flavor: *flavor,
+                url: candidate,
+            });
+        }
+        if !status.is_client_error() {
+            return None;
+        }
+    }
+    Some(LockedLib {
+        flavor: Flavor::Remap,
+        url: url.to_owned(),
+    })
+}
+
+/// Get the url without `.jar` if it's a Maven artifact without a classifier,
+/// like `https://repo/group/name/1.0/name-1.0.jar`
+fn maven_base(url: &str) -> Option<&str> {
+    if !url.starts_with("http") {
+        return None;
+    }
+    let base = url.strip_suffix(".jar")?;
+    let mut parts = base.rsplit('/');
+    let (file, version, artifact) = (parts.next()?, parts.next()?, parts.next()?);
+    if file == format!("{artifact}-{version}") {
+        Some(base)
+    } else {
+        None
+    }
+}
+
+/// Remap the downloaded jars with SRG names into libs, if they changed since the last remap.
+/// Returns if any jar was remapped
+pub async fn remap_jars(project: &Project, libs_dir: &Path) -> IoResult<bool> {
+    let mcmod = project.mcmod().await?;
+    let resolved = Lockfile::load(project).await.apply(&mcmod.full_libs());
+    let mut names = None;
+    let mut changed = false;
+    for url in &resolved.remap {
+        let (name, jar_name) = match (sync::download_file_name(url), jar_name(url)) {
+            (Some(name), Some(jar_name)) => (name, jar_name),
+            _ => continue,
+        };
+        let input = cache_dir(project).join(name);
+        let output = libs_dir.join(jar_name);
+        let modified = |path: &Path| path.metadata().and_then(|x| x.modified()).ok();
+        if modified(&output).is_some() && modified(&output) >= modified(&input) {
+            continue;
+        }
+        let names = match names.clone() {
+            Some(x) => x,
+            None => match remap::find_mappings_dir() {
+                Ok(dir) => {
+                    let x = Arc::new(remap::read_mappings(&dir).await?);
+                    names = Some(Arc::clone(&x));
+                    x
+                }
+                Err(_) => {
+                    println!("warning: cannot find the MCP mappings to remap '{url}'. Set up the template with `mcmod sync` first");
+                    return Ok(changed);
+                }
+            },
+        };
+        let (input, output_path) = (input.clone(), output.clone());
+        let result =
+            tokio::task::spawn_blocking(move || remap::remap_jar(&input, &output_path, &names))
+                .await;
+        let remapped = match result {
+            Ok(x) => x?,
+            Err(e) => Err(io::Error::from(e))?,
+        };
+        println!(
+            "remapped {remapped} names of '{name}' into '{}'",
+            output.display()
+        );
+        changed = true;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maven_base() {
+        assert_eq!(
+            maven_base("https://repo.example.com/com/example/name/1.0/name-1.0.jar"),
+            Some("https://repo.example.com/com/example/name/1.0/name-1.0")
+        );
+        // classifiers like -dev and -sources are other artifacts
+        assert_eq!(
+            maven_base("https://repo.example.com/com/example/name/1.0/name-1.0-dev.jar"),
+            None
+        );
+        assert_eq!(maven_base("https://example.com/name.jar"), None);
+        assert_eq!(
+            maven_base("https://repo.example.com/name/1.0/name-1.0.zip"),
+            None
+        );
+        // not a url
+        assert_eq!(maven_base("name/1.0/name-1.0.jar"), None);
+        assert_eq!(maven_base("./libs/name/1.0/name-1.0.jar"), None);
+    }
+}
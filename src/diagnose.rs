@@ -0,0 +1,210 @@
+//! Finding well-known problems in FML logs
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::mcmod::Mcmod;
+use crate::run::Side;
+use crate::template::TemplateHandler;
+use crate::util::{cd, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct DiagnoseCommand {
+    /// The side to read the log of
+    #[arg(long, value_enum, default_value = "client")]
+    pub side: Side,
+
+    /// Read this log file instead of the latest FML log of the side
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    /// Keep watching the log and report problems as they appear
+    #[arg(short, long)]
+    pub follow: bool,
+}
+
+impl DiagnoseCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let path = match self.file {
+            Some(x) => x,
+            None => fml_log_path(mcmod.template.new_handler().as_ref(), &project, &self.side)?,
+        };
+        if !self.follow {
+            let content = read_log(&path).await?;
+            print_problems(&find_problems(&content, mcmod));
+            return Ok(());
+        }
+
+        println!("watching '{}'", path.display());
+        let mut reported = BTreeSet::new();
+        let mut last_len = 0;
+        loop {
+            if let Ok(content) = fs::read_to_string(&path).await {
+                // the log was recreated by a new run
+                if content.len() < last_len {
+                    reported.clear();
+                }
+                last_len = content.len();
+                for problem in find_problems(&content, mcmod) {
+                    if reported.insert(problem.line.clone()) {
+                        print_problem(&problem);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// Path of the latest FML log for the side
+pub fn fml_log_path(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    side: &Side,
+) -> IoResult<PathBuf> {
+    let name = match side {
+        Side::Client => "fml-client-latest.log",
+        Side::Server => "fml-server-latest.log",
+    };
+    Ok(cd!(handler.run_dir(project)?, "logs", name))
+}
+
+pub async fn read_log(path: &Path) -> IoResult<String> {
+    match fs::read(path).await {
+        // logs can have invalid UTF-8 from mods printing binary data
+        Ok(x) => Ok(String::from_utf8_lossy(&x).into_owned()),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Cannot read log '{}': {e}", path.display()),
+        ))?,
+    }
+}
+
+/// A problem found in the log
+pub struct Problem {
+    /// What the problem is
+    pub title: &'static str,
+    /// 1-based line number in the log
+    pub line_no: usize,
+    /// The line in the log
+    pub line: String,
+    /// What in mcmod.yaml likely causes it
+    pub hint: String,
+}
+
+/// Find well-known problems in the content of an FML log
+pub fn find_problems(log: &str, mcmod: &Mcmod) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut seen = BTreeSet::new();
+    for (i, line) in log.lines().enumerate() {
+        let (title, hint) = if line.contains("requires mods") && line.contains("to be available")
+            || line.contains("MissingModsException")
+        {
+            (
+                "missing dependency mod",
+                "Add the required mod to `mods` in mcmod.yaml (or `libs` if you also compile against it)".to_owned(),
+            )
+        } else if line.contains("Found a duplicate mod")
+            || line.contains("DuplicateModsFoundException")
+        {
+            (
+                "duplicate mod",
+                "The same mod is loaded twice. Check for the same mod in both `mods` and `libs`, or different versions of it in `mods`".to_owned(),
+            )
+        } else if line.contains("Mixin apply failed")
+            || line.contains("Mixin prepare failed")
+            || line.contains("InvalidMixinException")
+            || line.contains("MixinApplyError")
+        {
+            let hint = if mcmod.mixins.is_empty() {
+                "A mixin from another mod failed. Check the versions of the mods in `mods`"
+                    .to_owned()
+            } else {
+                format!(
+                    "Check the mixins in the `mixins` package ({}), and that the refmap is generated by a full `mcmod sync`",
+                    mcmod.mixins
+                )
+            };
+            ("mixin failed", hint)
+        } else if line.contains("Invalid config file line") {
+            let hint = if mcmod.access_transformers.is_empty() {
+                "An access transformer of another mod is invalid".to_owned()
+            } else {
+                format!(
+                    "Check the syntax of the files in `access-transformers`: {}",
+                    mcmod.access_transformers.join(", ")
+                )
+            };
+            ("access transformer syntax error", hint)
+        } else if line.contains("Unable to class load the plugin")
+            || line.contains("Coremod") && line.contains("Unable to")
+        {
+            (
+                "coremod failed to load",
+                format!(
+                    "Check that `coremod` in mcmod.yaml ({}) is the fully qualified name of the plugin class",
+                    if mcmod.coremod.is_empty() { "not set" } else { &mcmod.coremod }
+                ),
+            )
+        } else {
+            continue;
+        };
+        let line = line.trim().to_owned();
+        if !seen.insert(line.clone()) {
+            continue;
+        }
+        problems.push(Problem {
+            title,
+            line_no: i + 1,
+            line,
+            hint,
+        });
+    }
+    problems
+}
+
+fn print_problem(problem: &Problem) {
+    println!("{} (line {}):", problem.title, problem.line_no);
+    println!("  {}", problem.line);
+    println!("  hint: {}", problem.hint);
+}
+
+/// Print the problems found in the log
+pub fn print_problems(problems: &[Problem]) {
+    if problems.is_empty() {
+        println!("no known problems found in the log");
+        return;
+    }
+    println!("found {} known problem(s) in the log:", problems.len());
+    for problem in problems {
+        println!();
+        print_problem(problem);
+    }
+}
+
+/// Print the problems in the latest FML log of the side, if there are any.
+/// Used after a run fails
+pub async fn report_run(handler: &dyn TemplateHandler, project: &Project, side: &Side) {
+    let path = match fml_log_path(handler, project, side) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let (content, mcmod) = match (read_log(&path).await, project.mcmod().await) {
+        (Ok(content), Ok(mcmod)) => (content, mcmod),
+        _ => return,
+    };
+    let problems = find_problems(&content, mcmod);
+    if !problems.is_empty() {
+        println!();
+        print_problems(&problems);
+        println!();
+        println!("see '{}' for the full log", path.display());
+    }
+}
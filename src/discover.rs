@@ -0,0 +1,117 @@
+//! Finding the project root from the command line arguments
+//!
+//! The root is, in order:
+//! 1. `--project-root`, used as is
+//! 2. MCMOD_PROJECT_ROOT, used as is
+//! 3. The closest directory with a mcmod.yaml, starting from `-C` (the current directory by default)
+//!
+//! Since the closest mcmod.yaml wins, running in a project nested in another project
+//! (like a workspace member) uses the nested project. Use `-C` or `--project-root` to
+//! select the outer project. `init` creates the project in the root override, or in `-C`
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::explain::ErrorCode;
+use crate::util::{IoResult, Project};
+
+/// Environment variable to set the project root, like `--project-root`
+pub const PROJECT_ROOT_ENV: &str = "MCMOD_PROJECT_ROOT";
+
+/// Arguments for locating and loading the project, shared by all commands
+#[derive(Debug, Clone, Args)]
+pub struct ProjectArgs {
+    /// Directory to find the project from. The closest parent with a mcmod.yaml is the project root
+    #[arg(short = 'C', long, default_value = ".", global = true)]
+    pub dir: String,
+
+    /// The project root, without searching the parents. Can also be set with MCMOD_PROJECT_ROOT
+    #[arg(long, global = true)]
+    pub project_root: Option<PathBuf>,
+
+    /// Profile in mcmod.yaml to apply on top of the top-level config
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Target in mcmod.yaml to use instead of the top-level template.
+    ///
+    /// Use "all" to run the command for the top-level template and every target
+    #[arg(long, global = true)]
+    pub target: Option<String>,
+}
+
+impl ProjectArgs {
+    /// Expand `--target all` into the arguments for each target
+    pub async fn expand_targets(&self) -> IoResult<Vec<Self>> {
+        if self.target.as_deref() != Some("all") {
+            return Ok(vec![self.clone()]);
+        }
+        let args = Self {
+            target: None,
+            ..self.clone()
+        };
+        let project = Project::new_in(&args)?;
+        let mut expanded = Vec::new();
+        for name in project.mcmod().await?.targets.keys() {
+            expanded.push(Self {
+                target: Some(name.clone()),
+                ..self.clone()
+            });
+        }
+        expanded.insert(0, args);
+        Ok(expanded)
+    }
+
+    /// The project root set with `--project-root` or MCMOD_PROJECT_ROOT
+    fn root_override(&self) -> Option<PathBuf> {
+        if let Some(root) = &self.project_root {
+            return Some(root.clone());
+        }
+        match std::env::var(PROJECT_ROOT_ENV) {
+            Ok(x) if !x.is_empty() => Some(PathBuf::from(x)),
+            _ => None,
+        }
+    }
+
+    /// Find the root of an existing project
+    pub fn find_root(&self) -> IoResult<PathBuf> {
+        if let Some(root) = self.root_override() {
+            let root = match dunce::canonicalize(&root) {
+                Ok(x) => x,
+                Err(e) => Err(io::Error::new(
+                    e.kind(),
+                    format!("Cannot find the project root '{}': {e}", root.display()),
+                ))?,
+            };
+            if !root.join("mcmod.yaml").exists() {
+                Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "No mcmod.yaml in the project root '{}' set with --project-root or {PROJECT_ROOT_ENV}",
+                        root.display()
+                    ),
+                )))?;
+            }
+            return Ok(root);
+        }
+        let dir = dunce::canonicalize(Path::new(&self.dir))?;
+        match dir.ancestors().find(|x| x.join("mcmod.yaml").exists()) {
+            Some(x) => Ok(x.to_path_buf()),
+            None => Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Could not find mcmod.yaml in '{}' or its parents. Run `mcmod init` to create a project",
+                    dir.display()
+                ),
+            )))?,
+        }
+    }
+
+    /// The directory to create a new project in
+    pub fn init_dir(&self) -> PathBuf {
+        self.root_override()
+            .unwrap_or_else(|| PathBuf::from(&self.dir))
+    }
+}
@@ -0,0 +1,149 @@
+//! Collecting the registry dumps written during a dev run into target/dumps
+//!
+//! The dumps are made by a dump mod the project runs with, like NEI. mcmod doesn't inject
+//! a helper mod of its own, it only warns when no known dump mod is in the run directory
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::template::TemplateHandler;
+use crate::util::{self, cd, mkdir, timestamp, IoResult, Project};
+
+/// Directory in the run directory that dump mods write to, like NEI's data dumps
+const RUN_DUMPS_DIR: &str = "dumps";
+
+/// File systems with coarse timestamps can give files written right after the run started
+/// an earlier modified time
+const MTIME_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Start of the lowercase jar names of mods that can dump the registries
+const DUMP_MODS: &[&str] = &["notenoughitems", "nei-", "nei_"];
+
+/// Extensions of dumps that are sorted so they can be diffed between versions
+const SORTED_EXTENSIONS: &[&str] = &["csv", "txt"];
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Dump {
+    /// Item, block and recipe ID dumps, written by a dump mod like NEI
+    Registries,
+}
+
+impl Dump {
+    /// Print how to make the dumps while the game runs
+    pub fn print_instructions(
+        self,
+        handler: &dyn TemplateHandler,
+        project: &Project,
+    ) -> IoResult<()> {
+        match self {
+            Self::Registries => {
+                let mods_dir = handler.run_dir(project)?.join("mods");
+                if !has_dump_mod(&mods_dir) {
+                    println!(
+                        "warning: no dump mod like NEI is in '{}'. mcmod doesn't provide one, add it to `mods` in mcmod.yaml",
+                        mods_dir.display()
+                    );
+                }
+                println!(
+                    "dumps written to '{}' during this run will be collected",
+                    dump_dir(handler, project)?.display()
+                );
+                println!(
+                    "with NEI, dump the registries from Options > Tools > Data Dumps, then close the game"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// If a known dump mod is in the mods directory
+fn has_dump_mod(mods_dir: &Path) -> bool {
+    let entries = match std::fs::read_dir(mods_dir) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    entries.filter_map(Result::ok).any(|x| {
+        let name = x.file_name().to_string_lossy().to_lowercase();
+        DUMP_MODS.iter().any(|prefix| name.starts_with(prefix))
+    })
+}
+
+fn dump_dir(handler: &dyn TemplateHandler, project: &Project) -> IoResult<PathBuf> {
+    Ok(handler.run_dir(project)?.join(RUN_DUMPS_DIR))
+}
+
+/// Copy the dumps modified since the run started into target/dumps/<timestamp>.
+/// Lines in text dumps are sorted after the header, so the order mods register
+/// things in doesn't show up as changes
+pub async fn collect(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    since: SystemTime,
+) -> IoResult<()> {
+    let dump_dir = dump_dir(handler, project)?;
+    let since = since - MTIME_TOLERANCE;
+    let paths = WalkDir::new(&dump_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|x| x.file_type().is_file())
+        .filter(|x| {
+            x.metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|modified| modified >= since)
+        })
+        .map(|x| x.into_path())
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        println!(
+            "no dumps were written to '{}' during the run",
+            dump_dir.display()
+        );
+        return Ok(());
+    }
+    let output_dir = cd!(project.target_root(), "dumps", timestamp());
+    for path in &paths {
+        let rel_path = path.strip_prefix(&dump_dir).unwrap_or(path);
+        let output = output_dir.join(rel_path);
+        if let Some(parent) = output.parent() {
+            mkdir!(parent).await?;
+        }
+        if is_sorted_dump(path) {
+            let content = fs::read_to_string(path).await?;
+            util::write_atomic(&output, sort_lines(&content).as_bytes()).await?;
+        } else {
+            fs::copy(path, &output).await?;
+        }
+    }
+    println!(
+        "collected {} dump(s) into '{}'",
+        paths.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
+
+fn is_sorted_dump(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| SORTED_EXTENSIONS.contains(&x))
+}
+
+/// Sort the lines after the first one, which is the header in CSV dumps
+fn sort_lines(content: &str) -> String {
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut rest = lines.filter(|x| !x.is_empty()).collect::<Vec<_>>();
+    rest.sort_unstable();
+    let mut output = format!("{header}\n");
+    for line in rest {
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
@@ -0,0 +1,195 @@
+//! Stable codes for the major failure modes, and `mcmod explain` to print their causes and fixes
+//!
+//! The code is attached to the error, printed with it as `error[E0001]`, and written
+//! in the `--timings json` output so scripts can tell failures apart
+//!
+//! - E00xx: the project and its template
+//! - E01xx: the environment, like the JDK and the network
+//! - E02xx: syncing the target directory
+//! - E03xx: gradle builds and runs
+
+use std::fmt;
+use std::io;
+
+use clap::Parser;
+use error_stack::Report;
+
+use crate::util::IoResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    TemplateNotFound,
+    TemplateFetchFailed,
+    ProjectNotFound,
+    InvalidConfig,
+    TargetLocked,
+    JdkMissing,
+    DownloadFailed,
+    TargetEdited,
+    NinjaFailed,
+    GeneratorFailed,
+    GradleFailed,
+}
+
+impl ErrorCode {
+    pub const ALL: &'static [Self] = &[
+        Self::TemplateNotFound,
+        Self::TemplateFetchFailed,
+        Self::ProjectNotFound,
+        Self::InvalidConfig,
+        Self::TargetLocked,
+        Self::JdkMissing,
+        Self::DownloadFailed,
+        Self::TargetEdited,
+        Self::NinjaFailed,
+        Self::GeneratorFailed,
+        Self::GradleFailed,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::TemplateNotFound => "E0001",
+            Self::TemplateFetchFailed => "E0002",
+            Self::ProjectNotFound => "E0003",
+            Self::InvalidConfig => "E0004",
+            Self::TargetLocked => "E0101",
+            Self::JdkMissing => "E0102",
+            Self::DownloadFailed => "E0103",
+            Self::TargetEdited => "E0201",
+            Self::NinjaFailed => "E0202",
+            Self::GeneratorFailed => "E0203",
+            Self::GradleFailed => "E0301",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::TemplateNotFound => "Template not found",
+            Self::TemplateFetchFailed => "Template could not be fetched",
+            Self::ProjectNotFound => "Project not found",
+            Self::InvalidConfig => "Invalid mcmod.yaml",
+            Self::TargetLocked => "Target directory is locked",
+            Self::JdkMissing => "JDK missing",
+            Self::DownloadFailed => "Download failed",
+            Self::TargetEdited => "Synced files edited in the target directory",
+            Self::NinjaFailed => "ninja failed",
+            Self::GeneratorFailed => "Generator command failed",
+            Self::GradleFailed => "gradle failed",
+        }
+    }
+
+    /// The causes and fixes, one per line
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Self::TemplateNotFound => "\
+The template in mcmod.yaml or given to `mcmod init` is not a known template.
+- Check the spelling of `template` in mcmod.yaml. `mcmod init` without a template lists them
+- If it's a user template, check that it's defined in templates.json in the config directory
+- If the template was removed in this version of the tool, switch to another template",
+            Self::TemplateFetchFailed => "\
+Cloning or downloading the template into the target directory failed.
+- Check the network connection and that the url of the template in templates.json is reachable
+- If the template uses `clone-command`, run the command by hand to see the error
+- Check that git is installed and on PATH",
+            Self::ProjectNotFound => "\
+No mcmod.yaml was found in the directory or its parents.
+- Run the command in the project, or point to it with -C or --project-root
+- Check that MCMOD_PROJECT_ROOT is not set to another directory
+- Run `mcmod init` to create a project",
+            Self::InvalidConfig => "\
+mcmod.yaml could not be parsed, or a value in it is invalid.
+- The error above has the line and column of the problem
+- Check the indentation and that the keys are spelled as in the README
+- Check that the profile and target given with --profile and --target exist",
+            Self::TargetLocked => "\
+Another mcmod command is using the target directory.
+- Wait for the other command to finish, or stop it
+- If no other command is running, the lock file could not be opened. Check the permissions of the target directory",
+            Self::JdkMissing => "\
+The JDK for the java version of the template is not configured.
+- Set JDK<version>_HOME (for example JDK8_HOME or JDK17_HOME) to the JDK directory
+- The environment variable needs to be set where mcmod runs, so restart the terminal after setting it",
+            Self::DownloadFailed => "\
+A lib or mod in mcmod.yaml could not be downloaded.
+- Check the network connection and that the url opens in a browser
+- A web page instead of a file usually means the url is a download page, not the file itself
+- For slow connections, increase the timeouts in `downloads` in mcmod.yaml",
+            Self::TargetEdited => "\
+Files synced from the project were edited in the target directory, and syncing would overwrite them.
+- Run `mcmod sync --pull-back` to copy the changes into the project
+- Run `mcmod sync --overwrite` to discard the changes
+- Edit the files in the project instead, the target directory is generated",
+            Self::NinjaFailed => "\
+A step in build.ninja failed while syncing the sources.
+- The ninja output above has the step that failed
+- Run with --ninja-arg=-v to see the commands
+- If build.ninja is outdated, run `mcmod sync` without --incremental to regenerate it",
+            Self::GeneratorFailed => "\
+A `generate` command in `copy-paths` exited with an error while syncing.
+- The output of the command above has the error
+- The command runs with the shell in the project root. Run it there by hand to check it works
+- The command must write its files into the directory in MCMOD_OUTPUT, not into the target directory",
+            Self::GradleFailed => "\
+gradlew exited with an error while building or running.
+- The gradle output above has the error. The output of runs is also in target/mcmod-logs
+- Compile errors are in the project sources, with the paths in the target directory
+- If the gradle setup is broken, delete the target directory and run `mcmod sync`",
+        }
+    }
+
+    /// Attach the code to the error
+    #[track_caller]
+    pub fn error(self, error: io::Error) -> Report<io::Error> {
+        Report::new(error).attach_printable(self)
+    }
+
+    /// Get the code attached to the error, if any
+    pub fn of<C>(report: &Report<C>) -> Option<Self> {
+        report.downcast_ref::<Self>().copied()
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "error code {0}, run `mcmod explain {0}` for the causes and fixes",
+            self.code()
+        )
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ExplainCommand {
+    /// The error code, like E0001. Lists all codes if not given
+    pub code: Option<String>,
+}
+
+impl ExplainCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let code = match self.code {
+            Some(x) => x,
+            None => {
+                for code in ErrorCode::ALL {
+                    println!("{}  {}", code.code(), code.title());
+                }
+                return Ok(());
+            }
+        };
+        let found = ErrorCode::ALL
+            .iter()
+            .find(|x| x.code().eq_ignore_ascii_case(&code));
+        match found {
+            Some(x) => {
+                println!("{}: {}", x.code(), x.title());
+                println!();
+                println!("{}", x.explanation());
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown error code '{code}'. Run `mcmod explain` to list the codes"),
+            ))?,
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! Addons that extend a parent mod
+//!
+//! With `extends` in mcmod.yaml, the dev jar of the parent mod is added to the libs, its release
+//! jar to the mods, and its access transformers are applied to the decompiled workspace of the
+//! addon. They are not put into the jar of the addon, since the parent applies them itself. The
+//! parent is another project found by its mod id or path, a git repository that is cloned and
+//! built, or jars downloaded like libs and mods.
+//!
+//! The sync copies the jars of the parent into target/extends with names that don't change
+//! with the version, so the libs and mods can point to them before the parent is built
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use zip::ZipArchive;
+
+use crate::build::{self, BuildCommand};
+use crate::explain::ErrorCode;
+use crate::jar::JarInfo;
+use crate::mcmod::{LibSpec, Mcmod, ModSpec};
+use crate::sync::{self, Downloader, Prune};
+use crate::template::TemplateHandler;
+use crate::util::{self, git_output, mkdir, write_file, IoResult, Project, ProjectArgs};
+
+/// The gradle script in the target that applies the access transformers of the parent
+pub const GRADLE_SCRIPT: &str = "mcmod-extends.gradle";
+
+/// The parent mod of an addon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExtendsDef {
+    /// Mod id of another project in the directories above, or in their workspace members
+    ModId(String),
+    Source(ExtendsSource),
+}
+
+/// Where to get the parent mod from. Exactly one of `path`, `git` and `dev-jar` is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExtendsSource {
+    pub modid: String,
+    /// Directory of the parent project, relative to the project root
+    #[serde(default)]
+    pub path: String,
+    /// Git url of the parent project, cloned into target/extends/<modid>/repo and built
+    #[serde(default)]
+    pub git: String,
+    /// Branch to clone with `git`. Default is the default branch
+    #[serde(default)]
+    pub branch: String,
+    /// Dev jar of the parent, same as the entries in `libs`
+    #[serde(default)]
+    pub dev_jar: String,
+    /// Release jar of the parent with `dev-jar`, same as the entries in `mods`
+    #[serde(default)]
+    pub jar: String,
+}
+
+/// Where the parent mod was found
+enum ParentLocation {
+    /// Root of the parent project
+    Project(PathBuf),
+    Git {
+        url: String,
+        branch: String,
+    },
+    Download {
+        dev_jar: String,
+        jar: String,
+    },
+}
+
+impl ExtendsDef {
+    pub fn modid(&self) -> &str {
+        match self {
+            Self::ModId(x) => x,
+            Self::Source(x) => &x.modid,
+        }
+    }
+
+    /// If the release jar of the parent is installed in the run directory.
+    /// Only parents downloaded without `jar` don't have one
+    fn has_release_jar(&self) -> bool {
+        match self {
+            Self::ModId(_) => true,
+            Self::Source(x) => x.dev_jar.is_empty() || !x.jar.is_empty(),
+        }
+    }
+
+    /// A name for the parent when it's not a project, like the git url
+    pub fn source_label(&self) -> &str {
+        match self {
+            Self::ModId(x) => x,
+            Self::Source(x) if !x.git.is_empty() => &x.git,
+            Self::Source(x) if !x.dev_jar.is_empty() => &x.dev_jar,
+            Self::Source(x) => &x.modid,
+        }
+    }
+
+    async fn locate(&self, project: &Project) -> IoResult<ParentLocation> {
+        let source = match self {
+            Self::ModId(modid) => {
+                return Ok(ParentLocation::Project(find_project(project, modid).await?))
+            }
+            Self::Source(x) => x,
+        };
+        let count = [&source.path, &source.git, &source.dev_jar]
+            .iter()
+            .filter(|x| !x.is_empty())
+            .count();
+        if count != 1 || (!source.jar.is_empty() && source.dev_jar.is_empty()) {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "`extends` for '{}' needs exactly one of `path`, `git` or `dev-jar` (with an optional `jar`)",
+                    source.modid
+                ),
+            )))?;
+        }
+        if !source.git.is_empty() {
+            return Ok(ParentLocation::Git {
+                url: source.git.clone(),
+                branch: source.branch.clone(),
+            });
+        }
+        if !source.dev_jar.is_empty() {
+            return Ok(ParentLocation::Download {
+                dev_jar: source.dev_jar.clone(),
+                jar: source.jar.clone(),
+            });
+        }
+        let path = project.root.join(&source.path);
+        if !path.join("mcmod.yaml").exists() {
+            Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Parent mod '{}' in `extends` has no mcmod.yaml at '{}'",
+                    source.modid,
+                    path.display()
+                ),
+            )))?;
+        }
+        Ok(ParentLocation::Project(dunce::canonicalize(&path)?))
+    }
+}
+
+/// Directory in the target the parent is fetched into
+pub fn dir(project: &Project) -> PathBuf {
+    project.target_root().join("extends")
+}
+
+fn dev_jar_path(project: &Project, modid: &str) -> PathBuf {
+    dir(project).join(format!("{modid}-dev.jar"))
+}
+
+fn jar_path(project: &Project, modid: &str) -> PathBuf {
+    dir(project).join(format!("{modid}.jar"))
+}
+
+/// Name of the access transformer file with the access transformers of the parent,
+/// in target/extends
+pub fn at_file_name(modid: &str) -> String {
+    format!("{modid}_parent_at.cfg")
+}
+
+/// The path of a file in the project as a local lib or mod (`./...`)
+pub fn local_source(project: &Project, path: &Path) -> String {
+    let relative = path.strip_prefix(&project.root).unwrap_or(path);
+    format!("./{}", relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Add the jars of the parent to the config. Called when loading mcmod.yaml
+pub fn apply(mcmod: &mut Mcmod, project: &Project) {
+    let parent = match &mcmod.extends {
+        Some(x) => x,
+        None => return,
+    };
+    let modid = parent.modid();
+    let dev_jar = local_source(project, &dev_jar_path(project, modid));
+    mcmod.libs.push(LibSpec::Simple(dev_jar));
+    if parent.has_release_jar() {
+        let jar = local_source(project, &jar_path(project, modid));
+        mcmod.mods.push(ModSpec::Simple(jar));
+    }
+}
+
+/// The path of the access transformer file of the parent in the target, if the project has a parent
+pub fn at_file_path(project: &Project, mcmod: &Mcmod) -> Option<PathBuf> {
+    let parent = mcmod.extends.as_ref()?;
+    Some(dir(project).join(at_file_name(parent.modid())))
+}
+
+/// The content of [`GRADLE_SCRIPT`] for the template. Applies nothing if the project
+/// has no parent or the template can't apply access transformers to the workspace only
+pub fn dev_gradle_script(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    mcmod: &Mcmod,
+) -> String {
+    let mut script = String::from("// generated by mcmod\n");
+    let at_file = match at_file_path(project, mcmod) {
+        Some(x) => util::gradle_path(&x).replace('\\', "/"),
+        None => return script,
+    };
+    if let Some(x) = handler.dev_access_transformer_gradle(&at_file) {
+        script.push_str(
+            "// applies the access transformers of the parent mod to the decompiled workspace\n",
+        );
+        script.push_str(&x);
+    }
+    script
+}
+
+/// The root of the parent project, for parents that are another project in the repository
+pub async fn project_dir(project: &Project, mcmod: &Mcmod) -> IoResult<Option<PathBuf>> {
+    let parent = match &mcmod.extends {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    match parent.locate(project).await? {
+        ParentLocation::Project(x) => Ok(Some(x)),
+        _ => Ok(None),
+    }
+}
+
+/// Find the project with the mod id in the directories above the project, and in their workspace members
+async fn find_project(project: &Project, modid: &str) -> IoResult<PathBuf> {
+    for dir in project.root.ancestors().skip(1) {
+        let config = match read_config(dir).await {
+            Some(x) => x,
+            None => continue,
+        };
+        if config_modid(&config) == Some(modid) {
+            return Ok(dir.to_path_buf());
+        }
+        let members = config
+            .get("workspace-members")
+            .and_then(|x| x.as_sequence())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_str());
+        for member in members {
+            let path = match dunce::canonicalize(dir.join(member)) {
+                Ok(x) if x != project.root => x,
+                _ => continue,
+            };
+            let found = read_config(&path)
+                .await
+                .is_some_and(|x| config_modid(&x) == Some(modid));
+            if found {
+                return Ok(path);
+            }
+        }
+    }
+    Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Parent mod '{modid}' in `extends` is not a project in the directories above the project or their workspace members. Use `path`, `git` or `dev-jar` in `extends` to say where it is"),
+    )))?
+}
+
+/// Read mcmod.yaml in the directory without applying anything, to find the mod id
+async fn read_config(dir: &Path) -> Option<serde_yaml::Value> {
+    let content = fs::read_to_string(dir.join("mcmod.yaml")).await.ok()?;
+    serde_yaml::from_str(&content).ok()
+}
+
+fn config_modid(config: &serde_yaml::Value) -> Option<&str> {
+    config.get("modid").and_then(|x| x.as_str())
+}
+
+/// Fetch the jars of the parent into target/extends, and write its access transformers
+/// next to them
+pub async fn sync_parent(project: &Project) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let parent = match &mcmod.extends {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let modid = parent.modid();
+    let dir = dir(project);
+    mkdir!(&dir).await?;
+    let (dev_jar, jar) = match parent.locate(project).await? {
+        ParentLocation::Project(root) => built_jars(&root, modid).await?,
+        ParentLocation::Git { url, branch } => {
+            let root = dir.join(modid).join("repo");
+            fetch_git(&root, &url, &branch).await?;
+            // built again only when the pull changed the checkout
+            let head = git_output(&root, &["rev-parse", "HEAD"]);
+            let built_head_path = dir.join(modid).join("built-head");
+            let built_head = fs::read_to_string(&built_head_path)
+                .await
+                .unwrap_or_default();
+            match built_jars(&root, modid).await {
+                Ok(jars) if !head.is_empty() && built_head == head => {
+                    println!("parent mod is up to date at {head}, skipping the build");
+                    jars
+                }
+                _ => {
+                    build_parent(&root).await?;
+                    let jars = built_jars(&root, modid).await?;
+                    write_file!(&built_head_path, head).await?;
+                    jars
+                }
+            }
+        }
+        ParentLocation::Download { dev_jar, jar } => {
+            let downloads = dir.join(modid);
+            let dev_jar = download(
+                project,
+                &downloads.join("dev"),
+                &dev_jar,
+                sync::LIBS_CDN_URL,
+                mcmod,
+            )
+            .await?;
+            let jar = if jar.is_empty() {
+                None
+            } else {
+                Some(
+                    download(
+                        project,
+                        &downloads.join("release"),
+                        &jar,
+                        sync::MODS_CDN_URL,
+                        mcmod,
+                    )
+                    .await?,
+                )
+            };
+            (dev_jar, jar)
+        }
+    };
+    copy_if_changed(&dev_jar, &dev_jar_path(project, modid)).await?;
+    match &jar {
+        Some(jar) => copy_if_changed(jar, &jar_path(project, modid)).await?,
+        None => {
+            let path = jar_path(project, modid);
+            if path.exists() {
+                fs::remove_file(path).await?;
+            }
+        }
+    }
+
+    let access_transformers = {
+        let dev_jar = dev_jar.clone();
+        tokio::task::spawn_blocking(move || read_access_transformers(&dev_jar)).await
+    };
+    let access_transformers = match access_transformers {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    let mut content =
+        format!("# access transformers of the parent mod '{modid}', generated by mcmod\n");
+    for (name, at) in &access_transformers {
+        content.push_str(&format!("\n# {name}\n{at}\n"));
+    }
+    if let Some(path) = at_file_path(project, mcmod) {
+        write_file!(&path, content).await?;
+    }
+    println!(
+        "using parent mod '{modid}' with {} access transformer file(s)",
+        access_transformers.len()
+    );
+    let handler = mcmod.template.new_handler();
+    if !access_transformers.is_empty() && handler.dev_access_transformer_gradle("").is_none() {
+        println!(
+            "warning: template '{}' can't apply the access transformers of '{modid}' to the workspace",
+            mcmod.template
+        );
+    }
+    let mixin_configs = JarInfo::read(&dev_jar).await?.mixin_configs;
+    if !mixin_configs.is_empty() {
+        match jar {
+            Some(_) => println!(
+                "the mixin configs of '{modid}' ({}) are applied by its jar in the run directory",
+                mixin_configs.join(", ")
+            ),
+            None => println!(
+                "warning: '{modid}' has mixin configs ({}), but no `jar` in `extends` to run with",
+                mixin_configs.join(", ")
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// The latest dev jar and release jar in the output directory of the parent project
+async fn built_jars(root: &Path, modid: &str) -> IoResult<(PathBuf, Option<PathBuf>)> {
+    let parent = Project::new_root(root.to_path_buf());
+    let output = parent
+        .mcmod()
+        .await?
+        .template
+        .new_handler()
+        .output_dir(&parent)?;
+    // (modified time, path) of the latest jar of each kind
+    let mut dev_jar = None;
+    let mut jar = None;
+    if output.is_dir() {
+        let mut dir = fs::read_dir(&output).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let latest = match name.strip_suffix(".jar").map(build::jar_classifier) {
+                Some("dev" | "deobf") => &mut dev_jar,
+                Some("") => &mut jar,
+                _ => continue,
+            };
+            let modified = entry.metadata().await?.modified()?;
+            if latest.as_ref().is_none_or(|(x, _)| modified > *x) {
+                *latest = Some((modified, entry.path()));
+            }
+        }
+    }
+    let (dev_jar, jar) = match (dev_jar, jar) {
+        (Some((_, dev_jar)), Some((_, jar))) => (dev_jar, jar),
+        _ => Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Parent mod '{modid}' has no dev jar and release jar in '{}'. Build it with `mcmod build` in '{}', or build the workspace with `mcmod build --workspace`",
+                output.display(),
+                root.display()
+            ),
+        )))?,
+    };
+    Ok((dev_jar, Some(jar)))
+}
+
+/// Clone the parent, or pull it if it's cloned already
+async fn fetch_git(root: &Path, url: &str, branch: &str) -> IoResult<()> {
+    // cloned again when the url in mcmod.yaml changed
+    let cloned =
+        root.join(".git").exists() && git_output(root, &["remote", "get-url", "origin"]) == url;
+    if root.exists() && !cloned {
+        fs::remove_dir_all(root).await?;
+    }
+    let status = if root.exists() {
+        println!("pulling parent mod from '{url}'");
+        Command::new("git")
+            .args(["pull", "--ff-only"])
+            .current_dir(root)
+            .status()?
+    } else {
+        println!("cloning parent mod from '{url}'");
+        let mut command = Command::new("git");
+        command.arg("clone");
+        if !branch.is_empty() {
+            command.args(["--branch", branch]);
+        }
+        command
+            .args(["--depth", "1", "--recurse-submodules", "--", url])
+            .arg(root)
+            .status()?
+    };
+    if !status.success() {
+        Err(ErrorCode::DownloadFailed.error(io::Error::other(format!(
+            "Failed to fetch the parent mod from '{url}'"
+        ))))?;
+    }
+    Ok(())
+}
+
+/// Build the cloned parent like running `mcmod build` in it
+async fn build_parent(root: &Path) -> IoResult<()> {
+    println!("building parent mod in '{}'", root.display());
+    let args = ProjectArgs {
+        dir: ".".to_owned(),
+        project_root: Some(root.to_path_buf()),
+        profile: None,
+        target: None,
+    };
+    let build = BuildCommand {
+        force: false,
+        javadoc: false,
+        javadoc_publish: None,
+        workspace: false,
+        changed: false,
+    };
+    Box::pin(build.run(&args)).await
+}
+
+/// Download a jar of the parent into its own directory and return the path
+async fn download(
+    project: &Project,
+    dir: &Path,
+    source: &str,
+    cdn_url_prefix: &str,
+    mcmod: &Mcmod,
+) -> IoResult<PathBuf> {
+    let name = match sync::download_file_name(source) {
+        Some(x) => x,
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot find file name in '{source}'"),
+        ))?,
+    };
+    let prune = Prune {
+        enabled: true,
+        keep: &[],
+        generated: &[],
+    };
+    sync::sync_downloads(
+        dir,
+        &[source.to_owned()],
+        &project.root,
+        cdn_url_prefix,
+        &Downloader::new(&mcmod.downloads)?,
+        &prune,
+        false,
+    )
+    .await?;
+    Ok(dir.join(name))
+}
+
+/// Copy the file if the copy is different, so the copies in libs and mods are only updated
+/// when the parent changed
+async fn copy_if_changed(from: &Path, to: &Path) -> IoResult<()> {
+    let content = fs::read(from).await?;
+    if fs::read(to).await.ok().as_deref() == Some(content.as_slice()) {
+        return Ok(());
+    }
+    util::write_atomic(to, &content).await
+}
+
+/// Read the access transformer files in the jar, as (path, content) pairs
+fn read_access_transformers(path: &Path) -> IoResult<Vec<(String, String)>> {
+    let mut archive = match ZipArchive::new(std::fs::File::open(path)?) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot read '{}' as a jar: {e}", path.display()),
+        ))?,
+    };
+    let mut access_transformers = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let name = entry.name().to_owned();
+        if entry.is_dir() || !name.ends_with("_at.cfg") {
+            continue;
+        }
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        access_transformers.push((name, content));
+    }
+    Ok(access_transformers)
+}
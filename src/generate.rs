@@ -0,0 +1,132 @@
+//! Running the commands of `generate` entries in `copy-paths`
+//!
+//! Each command writes into its own directory in target/generated, given in `MCMOD_OUTPUT`,
+//! which is then copied to the target like a source directory. The hash of the inputs
+//! is kept in target/.mcmod-generate-state, so the command only runs again when they change
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::explain::ErrorCode;
+use crate::hash;
+use crate::mcmod::CopyGenerate;
+use crate::state::{read_json, write_json};
+use crate::util::{self, mkdir, IoResult, Project};
+
+/// Hash of the inputs of each generator when it last ran, keyed by the name of its output directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GenerateState {
+    hashes: BTreeMap<String, String>,
+}
+
+fn state_path(project: &Project) -> PathBuf {
+    project.target_root().join(".mcmod-generate-state")
+}
+
+fn generated_root(project: &Project) -> PathBuf {
+    project.target_root().join("generated")
+}
+
+/// Name of the output directory, which changes when the command or target changes
+fn output_name(generator: &CopyGenerate) -> String {
+    let key = format!("{}\n{}", generator.generate, generator.target);
+    blake3::hash(key.as_bytes()).to_hex()[..16].to_owned()
+}
+
+/// Directory the generator writes its files to
+pub fn output_dir(project: &Project, generator: &CopyGenerate) -> PathBuf {
+    generated_root(project).join(output_name(generator))
+}
+
+/// Run the generators whose inputs changed since they last ran,
+/// and remove the output of generators that were removed from mcmod.yaml
+pub async fn run_generators(project: &Project) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let mut state = read_json::<GenerateState>(&state_path(project))
+        .await
+        .unwrap_or_default();
+    let mut names = BTreeSet::new();
+    let mut changed = false;
+    for copy_path in &mcmod.copy_paths {
+        if !copy_path.applies_to(project, &mcmod.template) {
+            continue;
+        }
+        let generator = match copy_path.generator() {
+            Some(x) => x,
+            None => continue,
+        };
+        let name = output_name(generator);
+        let output = output_dir(project, generator);
+        names.insert(name.clone());
+        let hash = if generator.inputs.is_empty() {
+            None
+        } else {
+            let inputs = generator
+                .inputs
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>();
+            Some(hash::hash_paths(project, &project.root, &inputs).await?)
+        };
+        if output.exists() && hash.is_some() && state.hashes.get(&name) == hash.as_ref() {
+            continue;
+        }
+        println!(
+            "generating '{}' with `{}`",
+            generator.target, generator.generate
+        );
+        if output.exists() {
+            fs::remove_dir_all(&output).await?;
+        }
+        mkdir!(&output).await?;
+        changed = true;
+        let status = util::shell_command(&generator.generate)
+            .current_dir(&project.root)
+            .env("MCMOD_OUTPUT", &output)
+            .status()?;
+        if !status.success() {
+            state.hashes.remove(&name);
+            write_json(&state_path(project), &state).await?;
+            Err(ErrorCode::GeneratorFailed.error(io::Error::other(format!(
+                "Generator `{}` for '{}' failed with {status}. See its output above",
+                generator.generate, generator.target
+            ))))?;
+        }
+        match hash {
+            Some(hash) => state.hashes.insert(name, hash),
+            None => state.hashes.remove(&name),
+        };
+    }
+
+    let generated_root = generated_root(project);
+    if generated_root.exists() {
+        let mut entries = fs::read_dir(&generated_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !names.contains(&name) {
+                println!(
+                    "removing the output of a removed generator '{}'",
+                    entry.path().display()
+                );
+                fs::remove_dir_all(entry.path()).await?;
+            }
+        }
+        if names.is_empty() {
+            fs::remove_dir(&generated_root).await?;
+        }
+    }
+    let count = state.hashes.len();
+    state.hashes.retain(|name, _| names.contains(name));
+    if names.is_empty() {
+        if state_path(project).exists() {
+            fs::remove_file(state_path(project)).await?;
+        }
+    } else if changed || state.hashes.len() != count {
+        write_json(&state_path(project), &state).await?;
+    }
+    Ok(())
+}
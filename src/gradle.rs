@@ -1,66 +1,252 @@
 //! Gradle stuff
 
 use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{io, path::Path};
 
 use tokio::fs;
 
-use crate::util::{write_file, IoResult};
+use crate::cache;
+use crate::config::GlobalConfig;
+use crate::explain::ErrorCode;
+use crate::paths;
+use crate::util::{self, cd, write_file, IoResult};
 
 /// Merge properties into a gradle.properties file without destroying comments
 /// and existing properties
 pub async fn merge_properties(
     gradle_properties: &Path,
-    mut to_merge: BTreeMap<String, String>,
+    to_merge: BTreeMap<String, String>,
 ) -> IoResult<()> {
+    let old = if gradle_properties.exists() {
+        fs::read_to_string(gradle_properties).await?
+    } else {
+        String::new()
+    };
+    write_file!(gradle_properties, merge_properties_content(&old, to_merge)).await?;
+    Ok(())
+}
+
+/// Merge properties into the content of a gradle.properties file.
+/// Existing lines stay in place, and new properties are added at the end
+pub fn merge_properties_content(old: &str, mut to_merge: BTreeMap<String, String>) -> String {
     let mut new_gradle_properties = String::new();
-    if gradle_properties.exists() {
-        for line in fs::read_to_string(gradle_properties).await?.lines() {
-            let mut parts = line.splitn(2, '=');
-            if let Some(key) = parts.next() {
-                let mut key = key.trim();
-                if key.starts_with("# ") {
-                    key = &key[2..];
-                }
-                if let Some(value) = to_merge.remove(key) {
-                    new_gradle_properties.push_str(&format!("{key} = {value}\n"));
-                    continue;
-                }
+    for line in old.lines() {
+        let mut parts = line.splitn(2, '=');
+        if let Some(key) = parts.next() {
+            let mut key = key.trim();
+            if key.starts_with("# ") {
+                key = &key[2..];
+            }
+            if let Some(value) = to_merge.remove(key) {
+                new_gradle_properties.push_str(&format!("{key} = {value}\n"));
+                continue;
             }
-            new_gradle_properties.push_str(&format!("{line}\n"));
         }
+        new_gradle_properties.push_str(&format!("{line}\n"));
     }
     for (k, v) in to_merge {
         new_gradle_properties.push_str(&format!("{k}={v}\n"));
     }
-    write_file!(gradle_properties, new_gradle_properties).await?;
-    Ok(())
+    new_gradle_properties
+}
+
+/// Print the lines changed by merge_properties_content, with where the property comes from.
+/// Since merging keeps the lines in place, lines are compared by position
+pub fn print_properties_diff(
+    old: &str,
+    new: &str,
+    source_of: impl Fn(&str) -> &'static str,
+) -> usize {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let mut changed = 0;
+    println!("--- a/gradle.properties");
+    println!("+++ b/gradle.properties");
+    for (i, new_line) in new.lines().enumerate() {
+        let old_line = old_lines.get(i).copied();
+        if old_line == Some(new_line) {
+            continue;
+        }
+        changed += 1;
+        let key = new_line.split('=').next().unwrap_or_default().trim();
+        println!("@@ line {} @@", i + 1);
+        if let Some(old_line) = old_line {
+            println!("-{old_line}");
+        }
+        println!("+{new_line}    ({})", source_of(key));
+    }
+    changed
+}
+
+/// Create a gradle init script that adds the JVM arguments to every JavaExec task,
+/// which includes the run tasks of all templates.
+///
+/// `module_args` are only added when the task runs on Java 9 or newer. That's the java of the
+/// task's launcher on gradle with toolchains, which can be older than the java gradle runs on.
+/// Older gradle runs the tasks on its own java. If `working_dir` is set, the run tasks run in it instead of the
+/// template's run directory
+pub fn create_run_init_script(
+    jvm_args: &[String],
+    module_args: &[String],
+    working_dir: Option<&Path>,
+    client_args: &[String],
+) -> String {
+    let mut script =
+        String::from("// generated by mcmod\nallprojects {\n    tasks.withType(JavaExec) {\n");
+    for arg in jvm_args {
+        let arg = escape_groovy(arg);
+        script.push_str(&format!("        jvmArgs '{arg}'\n"));
+    }
+    if !module_args.is_empty() {
+        script.push_str("        doFirst { task ->\n");
+        script.push_str("            def version = JavaVersion.current()\n");
+        script.push_str(
+            "            if (task.hasProperty('javaLauncher') && task.javaLauncher.isPresent()) {\n",
+        );
+        script.push_str("                def launcher = task.javaLauncher.get().metadata\n");
+        script.push_str(
+            "                version = JavaVersion.toVersion(launcher.languageVersion.asInt())\n",
+        );
+        script.push_str("            }\n");
+        script.push_str("            if (version.isJava9Compatible()) {\n");
+        for arg in module_args {
+            let arg = escape_groovy(arg);
+            script.push_str(&format!("                task.jvmArgs '{arg}'\n"));
+        }
+        script.push_str("            }\n");
+        script.push_str("        }\n");
+    }
+    script.push_str("    }\n");
+    if let Some(dir) = working_dir {
+        let dir = escape_groovy(&util::gradle_path(dir));
+        script.push_str(
+            "    tasks.withType(JavaExec).matching { it.name.startsWith('run') }.all {\n",
+        );
+        script.push_str(&format!("        workingDir = file('{dir}')\n"));
+        script.push_str("    }\n");
+    }
+    if !client_args.is_empty() {
+        let args = client_args
+            .iter()
+            .map(|x| format!("'{}'", escape_groovy(x)))
+            .collect::<Vec<_>>();
+        script.push_str(
+            "    tasks.withType(JavaExec).matching { it.name.startsWith('runClient') }.all {\n",
+        );
+        script.push_str(&format!("        args {}\n", args.join(", ")));
+        script.push_str("    }\n");
+    }
+    script.push_str("}\n");
+    script
+}
+
+fn escape_groovy(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
 }
 
-pub async fn run_gradlew(dir: &Path, java_version: u32, args: &[&str]) -> IoResult<()> {
+/// Get the JDK home for the java version from the JDK<version>_HOME environment variable
+pub fn java_home(java_version: u32) -> IoResult<PathBuf> {
     let jdk_home = format!("JDK{java_version}_HOME");
-    let jdk_home = match std::env::var(&jdk_home) {
-        Ok(x) => x,
-        Err(_) => Err(io::Error::new(
+    match std::env::var(&jdk_home) {
+        Ok(x) => Ok(PathBuf::from(x)),
+        Err(_) => Err(ErrorCode::JdkMissing.error(io::Error::new(
             io::ErrorKind::NotFound,
             format!("Could not find {jdk_home} environment variable"),
-        ))?,
-    };
-    let java_home = Path::new(&jdk_home);
+        )))?,
+    }
+}
+
+/// Create the command to run gradlew in the directory with the java version
+pub fn gradlew_command(
+    dir: &Path,
+    java_version: u32,
+    gradle_home: Option<&Path>,
+    args: &[&str],
+) -> IoResult<Command> {
+    let java_home = java_home(java_version)?;
+    if util::wsl_interop() {
+        return wsl_gradlew_command(dir, &java_home, gradle_home, args);
+    }
     let gradlew = if cfg!(windows) {
         dir.join("gradlew.bat")
     } else {
         dir.join("gradlew")
     };
 
-    let status = Command::new(gradlew)
+    let mut command = Command::new(gradlew);
+    command
         .args(args)
         .current_dir(dir)
-        .env("JAVA_HOME", java_home)
-        .status()?;
-    if !status.success() {
-        Err(io::Error::new(io::ErrorKind::Other, "gradlew failed"))?;
+        .env("JAVA_HOME", java_home);
+    if let Some(gradle_home) = gradle_home {
+        command.env("GRADLE_USER_HOME", gradle_home);
+    }
+    Ok(command)
+}
+
+/// The `GRADLE_USER_HOME` in the cache directory shared by the projects of the template,
+/// if `shared-gradle-home` is set in the global config. A `GRADLE_USER_HOME` in the
+/// environment takes priority
+pub fn shared_gradle_home(template: &str) -> IoResult<Option<PathBuf>> {
+    if !GlobalConfig::get()?.shared_gradle_home || std::env::var_os("GRADLE_USER_HOME").is_some() {
+        return Ok(None);
+    }
+    cache::record_use(&format!("gradle/{template}"), None);
+    Ok(Some(cd!(paths::cache_dir_location()?, "gradle", template)))
+}
+
+/// Run gradlew.bat on the Windows side from WSL. The JDK homes are WSL paths to Windows JDKs,
+/// which WSLENV translates when passing JAVA_HOME to Windows
+fn wsl_gradlew_command(
+    dir: &Path,
+    java_home: &Path,
+    gradle_home: Option<&Path>,
+    args: &[&str],
+) -> IoResult<Command> {
+    if util::to_windows_path(dir).is_none() {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "'{}' is not on a Windows drive. The project must be in /mnt/<drive> to run gradle on Windows with MCMOD_WSL_INTEROP",
+                dir.display()
+            ),
+        ))?;
+    }
+    let mut wslenv = std::env::var("WSLENV").unwrap_or_default();
+    if !wslenv.is_empty() {
+        wslenv.push(':');
+    }
+    wslenv.push_str("JAVA_HOME/p");
+    let mut command = Command::new("cmd.exe");
+    command
+        .args(["/C", "gradlew.bat"])
+        .args(args)
+        .current_dir(dir)
+        .env("JAVA_HOME", java_home);
+    if let Some(gradle_home) = gradle_home {
+        wslenv.push_str(":GRADLE_USER_HOME/p");
+        command.env("GRADLE_USER_HOME", gradle_home);
+    }
+    command.env("WSLENV", wslenv);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_args_depend_on_the_java_of_the_task() {
+        let script = create_run_init_script(
+            &["-Xmx2G".to_owned()],
+            &["--add-opens=java.base/java.lang=ALL-UNNAMED".to_owned()],
+            None,
+            &[],
+        );
+        assert!(script.contains("        jvmArgs '-Xmx2G'\n"));
+        assert!(script.contains("task.javaLauncher.get()"));
+        assert!(!script.contains("if (JavaVersion.current().isJava9Compatible())"));
+        assert!(script.contains("task.jvmArgs '--add-opens=java.base/java.lang=ALL-UNNAMED'"));
     }
-    Ok(())
 }
@@ -0,0 +1,241 @@
+//! Graph of the projects in the workspace, their template, and the libs and mods they use
+
+use std::collections::BTreeMap;
+
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::devjar::Lockfile;
+use crate::extends;
+use crate::mcmod::{ModSide, ModSpec};
+use crate::util::{IoResult, ProjectArgs};
+use crate::workspace::Workspace;
+
+#[derive(Debug, Parser)]
+pub struct GraphCommand {
+    /// Format to print the graph in
+    #[arg(long, value_enum, default_value = "dot")]
+    pub format: GraphFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, like `mcmod graph | dot -Tsvg > graph.svg`
+    Dot,
+    /// Nodes, edges and the build order of the workspace as JSON
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Node {
+    id: String,
+    /// project, template, lib or mod
+    kind: &'static str,
+    label: String,
+    /// What is downloaded for a lib, when mcmod.lock picked another artifact
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Edge {
+    from: String,
+    to: String,
+    /// member, template, extends, compile, compile-api, runtime, runtime-client or runtime-server
+    scope: &'static str,
+    /// The mod set of a mod only used with `mcmod run --mods`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mod_set: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Graph {
+    nodes: BTreeMap<String, Node>,
+    edges: Vec<Edge>,
+}
+
+impl Graph {
+    fn add_node(&mut self, id: String, kind: &'static str, label: String) {
+        self.nodes.entry(id.clone()).or_insert(Node {
+            id,
+            kind,
+            label,
+            resolved: None,
+        });
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, scope: &'static str, mod_set: Option<&str>) {
+        self.edges.push(Edge {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            scope,
+            mod_set: mod_set.map(str::to_owned),
+        });
+    }
+}
+
+impl GraphCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let workspace = Workspace::load(args).await?;
+        let mut graph = Graph::default();
+        let project_id = |i: usize| format!("project:{}", workspace.members[i].name);
+        for (i, member) in workspace.members.iter().enumerate() {
+            let mcmod = member.project.loaded_mcmod()?;
+            let id = project_id(i);
+            graph.add_node(
+                id.clone(),
+                "project",
+                format!("{} ({})", mcmod.modid, member.name),
+            );
+            if i != 0 {
+                graph.add_edge(&project_id(0), &id, "member", None);
+            }
+            let template_id = format!("template:{}", mcmod.template);
+            graph.add_node(template_id.clone(), "template", mcmod.template.to_string());
+            graph.add_edge(&id, &template_id, "template", None);
+
+            // local jars of other members are edges to the member, like in the build order
+            let member_of = |source: &str| {
+                if !source.starts_with("./") {
+                    return None;
+                }
+                workspace
+                    .member_of(&member.project.root.join(source))
+                    .filter(|x| *x != i)
+            };
+            // the jars of the parent are copied into the target, and shown as the parent instead
+            let parent_jars =
+                extends::local_source(&member.project, &extends::dir(&member.project));
+            let is_parent_jar = |source: &str| source.starts_with(&parent_jars);
+            if let Some(parent) = &mcmod.extends {
+                let parent_member = extends::project_dir(&member.project, mcmod)
+                    .await?
+                    .and_then(|dir| workspace.member_of(&dir))
+                    .filter(|x| *x != i);
+                match parent_member {
+                    Some(x) => graph.add_edge(&id, &project_id(x), "extends", None),
+                    None => {
+                        let mod_id = format!("mod:{}", parent.source_label());
+                        graph.add_node(mod_id.clone(), "mod", parent.source_label().to_owned());
+                        graph.add_edge(&id, &mod_id, "extends", None);
+                    }
+                }
+            }
+            let lockfile = Lockfile::load(&member.project).await;
+            for lib in &mcmod.libs {
+                let source = lib.source();
+                if is_parent_jar(source) {
+                    continue;
+                }
+                let scope = match lib.api_of() {
+                    Some(_) => "compile-api",
+                    None => "compile",
+                };
+                if let Some(x) = member_of(source) {
+                    graph.add_edge(&id, &project_id(x), scope, None);
+                    continue;
+                }
+                let lib_id = format!("lib:{source}");
+                graph.add_node(lib_id.clone(), "lib", source.to_owned());
+                if lib.api_of().is_none() {
+                    let resolved = lockfile.apply(&[source.to_owned()]);
+                    let resolved = resolved.libs.into_iter().chain(resolved.remap).next();
+                    if let Some(node) = graph.nodes.get_mut(&lib_id) {
+                        node.resolved = resolved.filter(|x| x != source);
+                    }
+                }
+                graph.add_edge(&id, &lib_id, scope, None);
+            }
+            let mods = mcmod.mods.iter().map(|x| (x, None)).chain(
+                mcmod
+                    .mod_sets
+                    .iter()
+                    .flat_map(|(name, mods)| mods.iter().map(move |x| (x, Some(name.as_str())))),
+            );
+            for (spec, mod_set) in mods {
+                if is_parent_jar(spec.source()) {
+                    continue;
+                }
+                let scope = mod_scope(spec);
+                if let Some(x) = member_of(spec.source()) {
+                    graph.add_edge(&id, &project_id(x), scope, mod_set);
+                    continue;
+                }
+                let mod_id = format!("mod:{}", spec.source());
+                graph.add_node(mod_id.clone(), "mod", spec.source().to_owned());
+                graph.add_edge(&id, &mod_id, scope, mod_set);
+            }
+        }
+
+        match self.format {
+            GraphFormat::Dot => print!("{}", to_dot(&graph)),
+            GraphFormat::Json => {
+                let build_order = workspace
+                    .build_order()?
+                    .into_iter()
+                    .map(|x| workspace.members[x].name.clone())
+                    .collect::<Vec<_>>();
+                let value = json!({
+                    "nodes": graph.nodes.values().collect::<Vec<_>>(),
+                    "edges": graph.edges,
+                    "build-order": build_order,
+                });
+                println!("{value:#}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mod_scope(spec: &ModSpec) -> &'static str {
+    match spec.side() {
+        None => "runtime",
+        Some(ModSide::ClientOnly) => "runtime-client",
+        Some(ModSide::ServerOnly) => "runtime-server",
+    }
+}
+
+fn to_dot(graph: &Graph) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let quote = |s: &str| format!("\"{}\"", escape(s));
+    let mut dot = String::from("digraph mcmod {\n    rankdir=LR;\n");
+    for node in graph.nodes.values() {
+        let shape = match node.kind {
+            "project" => "box",
+            "template" => "component",
+            "lib" => "note",
+            _ => "ellipse",
+        };
+        // the line break is for dot, so it is added after escaping
+        let label = match &node.resolved {
+            Some(x) => format!("{}\\n-> {}", escape(&node.label), escape(x)),
+            None => escape(&node.label),
+        };
+        dot.push_str(&format!(
+            "    {} [label=\"{label}\", shape={shape}];\n",
+            quote(&node.id)
+        ));
+    }
+    for edge in &graph.edges {
+        let label = match &edge.mod_set {
+            Some(x) => format!("{} ({x})", edge.scope),
+            None => edge.scope.to_owned(),
+        };
+        let style = if edge.mod_set.is_some() || edge.scope == "member" {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        dot.push_str(&format!(
+            "    {} -> {} [label={}{style}];\n",
+            quote(&edge.from),
+            quote(&edge.to),
+            quote(&label)
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
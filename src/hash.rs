@@ -0,0 +1,224 @@
+//! Hashing files for change detection
+//!
+//! Files are hashed on all cores, and hashes are cached in the target directory
+//! by size and modified time, so only files that changed are read again
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use walkdir::WalkDir;
+
+use crate::state::{CachedHash, HashCache};
+use crate::util::{IoResult, Project};
+
+/// Files modified this recently are not cached, since a change right after hashing
+/// could keep the same modified time on file systems with coarse timestamps
+const RACY_DURATION: Duration = Duration::from_secs(2);
+
+/// Hash the files under the paths (relative to root), including their relative paths.
+/// Missing paths are hashed as missing
+pub async fn hash_paths(project: &Project, root: &Path, paths: &[PathBuf]) -> IoResult<String> {
+    let root = root.to_path_buf();
+    let paths = paths.to_vec();
+    let mut cache = HashCache::load(project).await;
+    let result = tokio::task::spawn_blocking(move || {
+        let hash = hash_paths_blocking(&root, &paths, &mut cache)?;
+        Ok::<_, error_stack::Report<io::Error>>((hash, cache))
+    })
+    .await;
+    let (hash, cache) = match result {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    save_cache(project, &cache).await?;
+    Ok(hash)
+}
+
+fn hash_paths_blocking(root: &Path, paths: &[PathBuf], cache: &mut HashCache) -> IoResult<String> {
+    // (relative path, file) in the order they are hashed. A None file marks a missing path
+    let mut entries = Vec::new();
+    for path in paths {
+        let full_path = root.join(path);
+        if !full_path.exists() {
+            entries.push((path.to_string_lossy().to_string(), None));
+            continue;
+        }
+        for entry in WalkDir::new(&full_path).sort_by_file_name() {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(e) => Err(io::Error::other(e))?,
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            entries.push((
+                relative.to_string_lossy().to_string(),
+                Some(entry.into_path()),
+            ));
+        }
+    }
+    let files = entries
+        .iter()
+        .filter_map(|(_, file)| file.clone())
+        .collect::<Vec<_>>();
+    let mut hashes = hash_all(&files, cache)?.into_iter();
+    // remove files that were deleted from the walked directories
+    let walked = files
+        .iter()
+        .map(|x| x.to_string_lossy().to_string())
+        .collect::<BTreeSet<_>>();
+    let walked_dirs = paths.iter().map(|x| root.join(x)).collect::<Vec<_>>();
+    let count = cache.files.len();
+    cache.files.retain(|key, _| {
+        walked.contains(key) || !walked_dirs.iter().any(|x| Path::new(key).starts_with(x))
+    });
+    cache.changed |= cache.files.len() != count;
+
+    let mut hasher = blake3::Hasher::new();
+    for (relative, file) in &entries {
+        hasher.update(relative.as_bytes());
+        match file {
+            None => {
+                hasher.update(b"\0missing\0");
+            }
+            Some(_) => {
+                let hash = hashes.next().flatten().unwrap_or_default();
+                hasher.update(b"\0");
+                hasher.update(hash.as_bytes());
+                hasher.update(b"\0");
+            }
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash each file (absolute, under root) individually, keyed by the path relative to root
+/// with `/` as separator. Missing files are hashed as None
+pub async fn hash_files(
+    project: &Project,
+    root: &Path,
+    files: Vec<PathBuf>,
+) -> IoResult<BTreeMap<String, Option<String>>> {
+    let root = root.to_path_buf();
+    let mut cache = HashCache::load(project).await;
+    let result = tokio::task::spawn_blocking(move || {
+        let hashes = hash_all(&files, &mut cache)?;
+        let hashes = files
+            .iter()
+            .map(|file| relative_key(&root, file))
+            .zip(hashes)
+            .collect::<BTreeMap<_, _>>();
+        Ok::<_, error_stack::Report<io::Error>>((hashes, cache))
+    })
+    .await;
+    let (hashes, cache) = match result {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    save_cache(project, &cache).await?;
+    Ok(hashes)
+}
+
+async fn save_cache(project: &Project, cache: &HashCache) -> IoResult<()> {
+    // nothing to cache in before the first sync
+    if !project.target_root().exists() {
+        return Ok(());
+    }
+    cache.save(project).await
+}
+
+/// Hash the files in parallel, using the cached hashes of unchanged files.
+/// Returns the hashes in the same order, with None for missing files
+fn hash_all(files: &[PathBuf], cache: &mut HashCache) -> IoResult<Vec<Option<String>>> {
+    let threads = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1);
+    let chunk_size = files.len().div_ceil(threads).max(1);
+    let cache_ref = &*cache;
+    let results = std::thread::scope(|scope| {
+        let handles = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|file| hash_file(file, cache_ref))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|x| match x.join() {
+                Ok(x) => x,
+                Err(_) => Err(io::Error::other("Hashing thread panicked")),
+            })
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    let racy_time = SystemTime::now() - RACY_DURATION;
+    let mut hashes = Vec::with_capacity(files.len());
+    for (file, result) in files.iter().zip(results.into_iter().flatten()) {
+        let key = file.to_string_lossy().to_string();
+        match result {
+            Some((cached, modified)) => {
+                hashes.push(Some(cached.hash.clone()));
+                if modified >= racy_time {
+                    cache.changed |= cache.files.remove(&key).is_some();
+                } else if cache.files.get(&key) != Some(&cached) {
+                    cache.files.insert(key, cached);
+                    cache.changed = true;
+                }
+            }
+            None => {
+                hashes.push(None);
+                cache.changed |= cache.files.remove(&key).is_some();
+            }
+        }
+    }
+    // the cache is written again anyway, so also drop the files deleted since
+    if cache.changed {
+        cache.files.retain(|key, _| Path::new(key).is_file());
+    }
+    Ok(hashes)
+}
+
+/// Hash a file, or reuse the cached hash if the size and modified time are the same.
+/// Returns None if the file doesn't exist
+fn hash_file(file: &Path, cache: &HashCache) -> io::Result<Option<(CachedHash, SystemTime)>> {
+    let metadata = match std::fs::metadata(file) {
+        Ok(x) if x.is_file() => x,
+        _ => return Ok(None),
+    };
+    let modified = metadata.modified()?;
+    let modified_nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default();
+    if let Some(cached) = cache.files.get(file.to_string_lossy().as_ref()) {
+        if cached.size == metadata.len() && cached.modified == modified_nanos {
+            return Ok(Some((cached.clone(), modified)));
+        }
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(file)?)?;
+    let cached = CachedHash {
+        size: metadata.len(),
+        modified: modified_nanos,
+        hash: hasher.finalize().to_hex().to_string(),
+    };
+    Ok(Some((cached, modified)))
+}
+
+/// Get the path relative to root with `/` as separator, used as keys in manifests
+pub fn relative_key(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -1,30 +1,95 @@
+use std::ffi::OsString;
 use std::io;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 
 use clap::Parser;
 use tokio::fs;
+use walkdir::WalkDir;
 
-use crate::template;
+use crate::explain::ErrorCode;
+use crate::template::{self, Template};
 use crate::util::{cd, confirm_yn, mkdir, tool_root, write_file, IoResult};
 
 #[derive(Debug, Parser)]
 pub struct InitCommand {
     /// The template to use
     pub template: Option<String>,
+
+    /// The scaffold to start from, like `coremod`, `mixin` or `addon`.
+    /// Scaffolds add their files on top of `basic`
+    #[arg(long, default_value = BASE_SCAFFOLD)]
+    pub scaffold: String,
 }
 
+/// The scaffold with the files every scaffold starts from
+const BASE_SCAFFOLD: &str = "basic";
+
+/// Lines of the generated .gitignore, before the template-specific ones
+pub const GITIGNORE: &[&str] = &[
+    "# generated by mcmod",
+    "# -----",
+    "/target",
+    "/target-*",
+    "/target.lock",
+    "/target-*.lock",
+    "/build",
+    "/.gradle",
+    "/.settings",
+    "/.vscode",
+    "# eclipse",
+    "/bin",
+    "/.classpath",
+    "/.project",
+    "# ninja",
+    "/build.ninja",
+    "/build-*.ninja",
+    "/.ninja_log",
+    "/.ninja_deps",
+    "# run artifacts, when run-dir is in the project",
+    "/run",
+    "hs_err_pid*.log",
+    "replay_pid*.log",
+];
+
+/// The generated .gitattributes. Text files use LF so the sync hashes
+/// are the same on Windows
+pub const GITATTRIBUTES: &str = r#"# generated by mcmod
+# -----
+* text=auto
+*.java text eol=lf
+*.yaml text eol=lf
+*.yml text eol=lf
+*.json text eol=lf
+*.mcmeta text eol=lf
+*.lang text eol=lf
+*.cfg text eol=lf
+*.md text eol=lf
+*.png binary
+*.ogg binary
+*.nbt binary
+*.jar binary
+"#;
+
 impl InitCommand {
-    pub async fn run(self, dir: &str) -> IoResult<()> {
-        let dir_str = dir;
-        let dir = PathBuf::from(dir);
+    pub async fn run(self, dir: &Path) -> IoResult<()> {
+        let dir_str = dir.display().to_string();
+        let dir = dir.to_path_buf();
+        let init_dir = cd!(tool_root()?, "init");
+        let scaffolds = list_scaffolds(&init_dir).await?;
+        if !scaffolds.contains(&self.scaffold) {
+            println!("Unknown scaffold '{}'", self.scaffold);
+            println!("available scaffolds: {}", scaffolds.join(", "));
+            Err(io::Error::other("Unknown scaffold"))?;
+        }
+
         if dir.exists() {
             if fs::read_dir(&dir).await?.next_entry().await?.is_some() {
                 println!("Directory '{}' is not empty!", dir_str);
                 println!("You will be prompted for each file that would be overwritten.");
                 println!("Continue?");
                 if !confirm_yn()? {
-                    return Err(io::Error::new(io::ErrorKind::Other, "Operation cancelled"))?;
+                    return Err(io::Error::other("Operation cancelled"))?;
                 }
             }
         } else {
@@ -32,12 +97,11 @@ impl InitCommand {
         }
 
         if !dir.join(".git").exists() {
-            let status = Command::new("git").args(["-C", dir_str, "init"]).status()?;
+            let status = Command::new("git")
+                .args(["-C", &dir_str, "init"])
+                .status()?;
             if !status.success() {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to initialize git repository",
-                ))?;
+                Err(io::Error::other("Failed to initialize git repository"))?;
             }
         }
 
@@ -48,25 +112,23 @@ impl InitCommand {
             None => {
                 println!("Please specify a template!");
                 template::list_templates(&templates);
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "No template specified",
-                ))?;
+                return Err(io::Error::other("No template specified"))?;
             }
         };
 
         templates.remove(&template).ok_or_else(|| {
             println!("Unknown template '{template}'");
-            io::Error::new(io::ErrorKind::Other, "Unknown template")
+            ErrorCode::TemplateNotFound.error(io::Error::other("Unknown template"))
         })?;
 
-        let init_dir = cd!(tool_root()?, "init");
-        let mut init_dir_iter = fs::read_dir(&init_dir).await?;
+        let mut skipped = Vec::new();
+        let mut init_dir_iter = fs::read_dir(init_dir.join(BASE_SCAFFOLD)).await?;
         while let Some(entry) = init_dir_iter.next_entry().await? {
             let target_path = dir.join(entry.file_name());
             if target_path.exists() {
                 println!("overwrite '{}'?", target_path.display());
                 if !confirm_yn()? {
+                    skipped.push(entry.file_name());
                     continue;
                 }
                 if target_path.is_dir() {
@@ -85,18 +147,37 @@ impl InitCommand {
                     for e in r {
                         eprintln!("  {}", e);
                     }
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Failed to copy all files",
-                    ))?;
+                    Err(io::Error::other("Failed to copy all files"))?;
                 }
             } else {
                 fs::copy(&source_dir, &target_path).await?;
             }
         }
 
+        let template_enum = template.parse::<Template>().ok();
+        let gitignore_entries = template_enum
+            .map(|x| x.new_handler().gitignore_entries())
+            .unwrap_or_default();
+        let mut gitignore = GITIGNORE
+            .iter()
+            .chain(gitignore_entries)
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n");
+        gitignore.push('\n');
+        write_generated(&dir, ".gitignore", &gitignore).await?;
+        write_generated(&dir, ".gitattributes", GITATTRIBUTES).await?;
+
+        let mut fragment = None;
+        if self.scaffold != BASE_SCAFFOLD {
+            fragment = copy_scaffold(&init_dir.join(&self.scaffold), &dir, &skipped).await?;
+        }
+
         let mcmod_path = dir.join("mcmod.yaml");
-        let mcmod = fs::read_to_string(&mcmod_path).await?;
+        let mut mcmod = fs::read_to_string(&mcmod_path).await?;
+        if let Some(fragment) = fragment {
+            mcmod = merge_yaml_fragment(&mcmod, &fragment);
+        }
         let mcmod = mcmod.replace("INIT_TEMPLATE", &template);
         write_file!(&mcmod_path, mcmod).await?;
 
@@ -110,3 +191,152 @@ impl InitCommand {
         Ok(())
     }
 }
+
+/// Write a file generated by init, asking before overwriting
+async fn write_generated(dir: &Path, name: &str, content: &str) -> IoResult<()> {
+    let path = dir.join(name);
+    if path.exists() {
+        println!("overwrite '{}'?", path.display());
+        if !confirm_yn()? {
+            return Ok(());
+        }
+    }
+    println!("writing '{}'", path.display());
+    write_file!(&path, content).await?;
+    Ok(())
+}
+
+/// Get the names of the scaffolds in the init directory
+async fn list_scaffolds(init_dir: &Path) -> IoResult<Vec<String>> {
+    let mut scaffolds = Vec::new();
+    let mut entries = fs::read_dir(init_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().is_dir() {
+            scaffolds.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    scaffolds.sort();
+    Ok(scaffolds)
+}
+
+/// Copy the files of a scaffold on top of the base scaffold, except in the
+/// top-level entries the user chose not to overwrite.
+///
+/// Returns the mcmod.yaml of the scaffold, which is merged instead of copied
+async fn copy_scaffold(
+    scaffold_dir: &Path,
+    dir: &Path,
+    skipped: &[OsString],
+) -> IoResult<Option<String>> {
+    let mut fragment = None;
+    for entry in WalkDir::new(scaffold_dir) {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = match entry.path().strip_prefix(scaffold_dir) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let is_skipped = rel_path
+            .components()
+            .next()
+            .is_some_and(|x| skipped.iter().any(|s| s == x.as_os_str()));
+        if is_skipped {
+            continue;
+        }
+        if rel_path == Path::new("mcmod.yaml") {
+            fragment = Some(fs::read_to_string(entry.path()).await?);
+            continue;
+        }
+        let target_path = dir.join(rel_path);
+        if let Some(parent) = target_path.parent() {
+            mkdir!(parent).await?;
+        }
+        println!(
+            "copying '{}' to '{}'",
+            rel_path.display(),
+            target_path.display()
+        );
+        fs::copy(entry.path(), &target_path).await?;
+    }
+    Ok(fragment)
+}
+
+/// Merge the top-level keys in the fragment into mcmod.yaml.
+///
+/// Each key replaces the same key in mcmod.yaml, or the commented example of it
+/// so the documentation above stays. Keys not found are added at the end.
+/// The line endings of mcmod.yaml are kept
+fn merge_yaml_fragment(mcmod: &str, fragment: &str) -> String {
+    let mut lines = mcmod.lines().map(str::to_owned).collect::<Vec<_>>();
+    let mut entries: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in fragment.lines() {
+        let is_key = !line.starts_with([' ', '#', '-']) && line.contains(':');
+        match line.split_once(':') {
+            Some((key, _)) if is_key => entries.push((key, vec![line])),
+            _ => match entries.last_mut() {
+                Some((_, entry_lines)) if !line.trim().is_empty() => entry_lines.push(line),
+                _ => {}
+            },
+        }
+    }
+    for (key, entry_lines) in entries {
+        let key_prefix = format!("{key}:");
+        let comment_prefix = format!("# {key}:");
+        let range = if let Some(i) = lines.iter().position(|x| x.starts_with(&key_prefix)) {
+            let len = lines[i + 1..]
+                .iter()
+                .take_while(|x| x.starts_with([' ', '-']))
+                .count();
+            Some(i..i + 1 + len)
+        } else if let Some(i) = lines.iter().position(|x| x.starts_with(&comment_prefix)) {
+            let len = lines[i + 1..]
+                .iter()
+                .take_while(|x| x.starts_with("#  ") || x.starts_with("# -"))
+                .count();
+            Some(i..i + 1 + len)
+        } else {
+            None
+        };
+        let entry_lines = entry_lines.into_iter().map(str::to_owned);
+        match range {
+            Some(range) => {
+                lines.splice(range, entry_lines);
+            }
+            None => {
+                lines.push(String::new());
+                lines.extend(entry_lines);
+            }
+        }
+    }
+    let line_ending = if mcmod.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut output = lines.join(line_ending);
+    output.push_str(line_ending);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_yaml_fragment() {
+        let mcmod = "modid: mymodid\n# Uncomment to use mixins\n# mixins: com.example.mixins\ncoremod: \"\"\n";
+        let fragment = "mixins: pistonmc.mymodid.mixins\ncoremod: pistonmc.mymodid.Coremod\n";
+        assert_eq!(
+            merge_yaml_fragment(mcmod, fragment),
+            "modid: mymodid\n# Uncomment to use mixins\nmixins: pistonmc.mymodid.mixins\ncoremod: pistonmc.mymodid.Coremod\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_yaml_fragment_keeps_crlf() {
+        let mcmod = "modid: mymodid\r\n# mixins: com.example.mixins\r\n";
+        let fragment = "mixins: pistonmc.mymodid.mixins\nsources:\n- root: src\n";
+        assert_eq!(
+            merge_yaml_fragment(mcmod, fragment),
+            "modid: mymodid\r\nmixins: pistonmc.mymodid.mixins\r\n\r\nsources:\r\n- root: src\r\n"
+        );
+    }
+}
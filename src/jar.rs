@@ -0,0 +1,401 @@
+//! Reading built or downloaded jars
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use zip::ZipArchive;
+
+use crate::mcmod::{Language, Mcmod, VerifyOptions};
+use crate::util::{format_size, IoResult};
+
+/// Manifest attributes related to coremods and mixins
+const MANIFEST_KEYS: &[&str] = &[
+    "FMLCorePlugin",
+    "FMLCorePluginContainsFMLMod",
+    "ForceLoadAsMod",
+    "TweakClass",
+    "FMLAT",
+    "MixinConfigs",
+];
+
+#[derive(Debug, Parser)]
+pub struct InspectCommand {
+    /// The jar to inspect
+    pub jar: PathBuf,
+}
+
+impl InspectCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let jar = JarInfo::read(&self.jar).await?;
+        println!("{}", self.jar.display());
+        println!();
+
+        match &jar.mod_metadata {
+            Some((name, content)) => {
+                println!("{name}:");
+                for line in content.lines() {
+                    println!("  {line}");
+                }
+            }
+            None => println!("no mod metadata (mcmod.info or mods.toml)"),
+        }
+        println!();
+
+        println!("manifest:");
+        let mut any = false;
+        for key in MANIFEST_KEYS {
+            if let Some(value) = jar.manifest.get(*key) {
+                println!("  {key}: {value}");
+                any = true;
+            }
+        }
+        if !any {
+            println!("  no coremod or mixin attributes");
+        }
+        println!();
+
+        print_list("mixin configs", &jar.mixin_configs);
+        let refmaps = jar
+            .refmaps
+            .iter()
+            .map(|(name, count)| format!("{name} ({count} mappings)"))
+            .collect::<Vec<_>>();
+        print_list("refmaps", &refmaps);
+        print_list("access transformers", &jar.access_transformers);
+
+        println!("classes:");
+        if jar.class_versions.is_empty() {
+            println!("  none");
+        }
+        for (major, count) in &jar.class_versions {
+            println!(
+                "  {count} class(es) with major version {major} (Java {})",
+                java_version(*major)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffJarCommand {
+    /// The old jar
+    pub old: PathBuf,
+    /// The new jar
+    pub new: PathBuf,
+}
+
+impl DiffJarCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let (old, new) = tokio::join!(JarInfo::read(&self.old), JarInfo::read(&self.new));
+        let (old, new) = (old?, new?);
+
+        match (&old.mod_metadata, &new.mod_metadata) {
+            (Some((_, a)), Some((_, b))) if a == b => println!("mod metadata: unchanged"),
+            (None, None) => println!("mod metadata: none"),
+            (a, b) => {
+                println!("mod metadata: changed");
+                let a = a.as_ref().map(|x| x.1.as_str()).unwrap_or_default();
+                let b = b.as_ref().map(|x| x.1.as_str()).unwrap_or_default();
+                let a_lines = a.lines().map(str::trim).collect::<BTreeSet<_>>();
+                let b_lines = b.lines().map(str::trim).collect::<BTreeSet<_>>();
+                for line in a_lines.difference(&b_lines) {
+                    println!("  - {line}");
+                }
+                for line in b_lines.difference(&a_lines) {
+                    println!("  + {line}");
+                }
+            }
+        }
+        println!();
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        let mut changed = Vec::new();
+        for (name, entry) in &new.files {
+            match old.files.get(name) {
+                None => added.push((name, entry.size as i64)),
+                Some(x) if x != entry => changed.push((name, entry.size as i64 - x.size as i64)),
+                _ => {}
+            }
+        }
+        for (name, entry) in &old.files {
+            if !new.files.contains_key(name) {
+                removed.push((name, -(entry.size as i64)));
+            }
+        }
+
+        for (title, is_class) in [("classes", true), ("resources", false)] {
+            let filter = |list: &[(&String, i64)]| {
+                list.iter()
+                    .filter(|(name, _)| name.ends_with(".class") == is_class)
+                    .map(|(name, size)| (name.to_string(), *size))
+                    .collect::<Vec<_>>()
+            };
+            let (added, removed, changed) = (filter(&added), filter(&removed), filter(&changed));
+            println!(
+                "{title}: {} added, {} removed, {} changed",
+                added.len(),
+                removed.len(),
+                changed.len()
+            );
+            for (prefix, list) in [("+", &added), ("-", &removed), ("~", &changed)] {
+                for (name, size) in list {
+                    println!("  {prefix} {name} ({})", format_size_delta(*size));
+                }
+            }
+            println!();
+        }
+
+        let old_size = old.files.values().map(|x| x.size).sum::<u64>();
+        let new_size = new.files.values().map(|x| x.size).sum::<u64>();
+        println!(
+            "uncompressed size: {} -> {} ({})",
+            format_size(old_size),
+            format_size(new_size),
+            format_size_delta(new_size as i64 - old_size as i64)
+        );
+        let jar_sizes = (
+            std::fs::metadata(&self.old).map(|x| x.len()),
+            std::fs::metadata(&self.new).map(|x| x.len()),
+        );
+        if let (Ok(old_size), Ok(new_size)) = jar_sizes {
+            println!(
+                "jar size: {} -> {} ({})",
+                format_size(old_size),
+                format_size(new_size),
+                format_size_delta(new_size as i64 - old_size as i64)
+            );
+        }
+        Ok(())
+    }
+}
+
+fn format_size_delta(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{sign}{}", format_size(delta.unsigned_abs()))
+}
+
+fn print_list(title: &str, items: &[String]) {
+    println!("{title}:");
+    if items.is_empty() {
+        println!("  none");
+    }
+    for item in items {
+        println!("  {item}");
+    }
+    println!();
+}
+
+/// Java version from class file major version
+pub fn java_version(major: u16) -> u16 {
+    major.saturating_sub(44)
+}
+
+/// Information about a jar
+#[derive(Debug, Default)]
+pub struct JarInfo {
+    /// Name and content of mcmod.info or mods.toml
+    pub mod_metadata: Option<(String, String)>,
+    /// Main attributes in META-INF/MANIFEST.MF
+    pub manifest: BTreeMap<String, String>,
+    /// Paths of mixin configs
+    pub mixin_configs: Vec<String>,
+    /// Paths of refmaps, and the number of mappings in each
+    pub refmaps: Vec<(String, usize)>,
+    /// Paths of access transformer configs
+    pub access_transformers: Vec<String>,
+    /// Class file major version to number of classes
+    pub class_versions: BTreeMap<u16, usize>,
+    /// Paths of all files
+    pub files: BTreeMap<String, JarEntry>,
+}
+
+/// A file in a jar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JarEntry {
+    /// Uncompressed size
+    pub size: u64,
+    pub crc32: u32,
+}
+
+impl JarInfo {
+    pub async fn read(path: &Path) -> IoResult<Self> {
+        let path = path.to_path_buf();
+        let result = tokio::task::spawn_blocking(move || Self::read_blocking(&path)).await;
+        match result {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::from(e))?,
+        }
+    }
+
+    fn read_blocking(path: &Path) -> IoResult<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut archive = match ZipArchive::new(file) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cannot read '{}' as a jar: {e}", path.display()),
+            ))?,
+        };
+        let mut info = Self::default();
+        for i in 0..archive.len() {
+            let mut entry = match archive.by_index(i) {
+                Ok(x) => x,
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            let name = entry.name().to_owned();
+            info.files.insert(
+                name.clone(),
+                JarEntry {
+                    size: entry.size(),
+                    crc32: entry.crc32(),
+                },
+            );
+            let file_name = name.rsplit('/').next().unwrap_or(&name);
+
+            if name.ends_with(".class") {
+                let mut header = [0u8; 8];
+                if entry.read_exact(&mut header).is_ok() && header[..4] == [0xCA, 0xFE, 0xBA, 0xBE]
+                {
+                    let major = u16::from_be_bytes([header[6], header[7]]);
+                    *info.class_versions.entry(major).or_default() += 1;
+                }
+            } else if name == "mcmod.info" || name == "META-INF/mods.toml" {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                info.mod_metadata = Some((name, content));
+            } else if name == "META-INF/MANIFEST.MF" {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                info.manifest = parse_manifest(&content);
+            } else if file_name.ends_with(".json") && file_name.contains("refmap") {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                info.refmaps.push((name, count_refmap_mappings(&content)));
+            } else if file_name.ends_with(".json")
+                && (file_name.starts_with("mixins.") || file_name.ends_with(".mixins.json"))
+            {
+                info.mixin_configs.push(name);
+            } else if file_name.ends_with("_at.cfg") {
+                info.access_transformers.push(name);
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// Check a release jar against the verify options, returning the problems found
+pub fn check_release(info: &JarInfo, mcmod: &Mcmod, options: &VerifyOptions) -> Vec<String> {
+    let mut problems = Vec::new();
+    for package in &options.forbidden_packages {
+        let prefix = format!("{}/", package.replace('.', "/").trim_end_matches('/'));
+        let count = info.files.keys().filter(|x| x.starts_with(&prefix)).count();
+        if count > 0 {
+            problems.push(format!(
+                "{count} file(s) from forbidden package '{package}' are in the jar"
+            ));
+        }
+    }
+    if !options.allow_sources {
+        let count = info
+            .files
+            .keys()
+            .filter(|x| Language::of(Path::new(x)).is_some())
+            .count();
+        if count > 0 {
+            problems.push(format!(
+                "{count} source file(s) are in the jar. Set `allow-sources: true` if this is intended"
+            ));
+        }
+    }
+    if !mcmod.mixins.is_empty() {
+        if info.refmaps.is_empty() {
+            problems.push("mixins are configured, but there is no refmap in the jar".to_owned());
+        } else if info.refmaps.iter().all(|(_, count)| *count == 0) {
+            problems.push("mixins are configured, but the refmap is empty".to_owned());
+        }
+        if info.mixin_configs.is_empty() {
+            problems
+                .push("mixins are configured, but there is no mixin config in the jar".to_owned());
+        }
+    }
+    if !mcmod.coremod.is_empty() {
+        match info.manifest.get("FMLCorePlugin") {
+            Some(x) if x == &mcmod.coremod => {}
+            Some(x) => problems.push(format!(
+                "FMLCorePlugin in the manifest is '{x}', but the coremod is '{}'",
+                mcmod.coremod
+            )),
+            None => problems
+                .push("coremod is configured, but FMLCorePlugin is not in the manifest".to_owned()),
+        }
+    }
+    let max_java_version = options.max_java_version.unwrap_or_else(|| {
+        let capabilities = mcmod.template.new_handler().capabilities();
+        capabilities
+            .java_versions
+            .iter()
+            .min()
+            .map(|x| *x as u16)
+            .unwrap_or(8)
+    });
+    for (major, count) in &info.class_versions {
+        if java_version(*major) > max_java_version {
+            problems.push(format!(
+                "{count} class(es) target Java {}, but the highest allowed is Java {max_java_version}",
+                java_version(*major)
+            ));
+        }
+    }
+    problems
+}
+
+/// Parse the main section of a jar manifest, joining continuation lines
+pub fn parse_manifest(content: &str) -> BTreeMap<String, String> {
+    let mut attributes: BTreeMap<String, String> = BTreeMap::new();
+    let mut last_key: Option<String> = None;
+    for line in content.lines() {
+        if line.is_empty() {
+            // end of the main section
+            break;
+        }
+        if let Some(rest) = line.strip_prefix(' ') {
+            if let Some(key) = &last_key {
+                if let Some(value) = attributes.get_mut(key) {
+                    value.push_str(rest);
+                }
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_owned();
+            attributes.insert(key.clone(), value.trim().to_owned());
+            last_key = Some(key);
+        }
+    }
+    attributes
+}
+
+/// Count the mappings in a refmap
+fn count_refmap_mappings(content: &str) -> usize {
+    let value: serde_json::Value = match serde_json::from_str(content) {
+        Ok(x) => x,
+        Err(_) => return 0,
+    };
+    value
+        .get("mappings")
+        .and_then(|x| x.as_object())
+        .map(|x| {
+            x.values()
+                .filter_map(|x| x.as_object())
+                .map(|x| x.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
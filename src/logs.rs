@@ -0,0 +1,209 @@
+//! Reading and filtering the logs of the game
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use tokio::fs;
+
+use crate::diagnose;
+use crate::run::Side;
+use crate::util::{write_file, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct LogsCommand {
+    /// The side to read the log of
+    #[arg(long, value_enum, default_value = "client")]
+    pub side: Side,
+
+    /// Read this log file instead of the latest FML log of the side
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    /// Keep printing new lines as they are logged
+    #[arg(short, long, conflicts_with = "output")]
+    pub follow: bool,
+
+    /// Only show lines logged at or after this time, as `HH:MM` or `HH:MM:SS`
+    #[arg(long, value_name = "TIME")]
+    pub since: Option<String>,
+
+    /// Only show lines at this level or above
+    #[arg(long, value_enum)]
+    pub level: Option<LogLevel>,
+
+    /// Only show lines from loggers starting with this name, like `FML` or a mod id.
+    /// Can be specified multiple times
+    #[arg(long)]
+    pub logger: Vec<String>,
+
+    /// Write the lines to this file instead of printing them, to attach to bug reports
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, ValueEnum)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "TRACE" => Some(Self::Trace),
+            "DEBUG" => Some(Self::Debug),
+            "INFO" => Some(Self::Info),
+            "WARN" => Some(Self::Warn),
+            "ERROR" => Some(Self::Error),
+            "FATAL" => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+}
+
+impl LogsCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let path = match &self.file {
+            Some(x) => x.clone(),
+            None => {
+                let project = Project::new_in(args)?;
+                let handler = project.mcmod().await?.template.new_handler();
+                diagnose::fml_log_path(handler.as_ref(), &project, &self.side)?
+            }
+        };
+        let since = match &self.since {
+            Some(x) => Some(parse_time(x)?),
+            None => None,
+        };
+        let mut filter = LogFilter {
+            since,
+            level: self.level,
+            loggers: &self.logger,
+            // lines before the first entry are shown if nothing is filtered
+            showing: since.is_none() && self.level.is_none() && self.logger.is_empty(),
+        };
+
+        if !self.follow {
+            let content = diagnose::read_log(&path).await?;
+            let lines = content
+                .lines()
+                .filter(|x| filter.accept(x))
+                .collect::<Vec<_>>();
+            match &self.output {
+                Some(output) => {
+                    let mut content = lines.join("\n");
+                    content.push('\n');
+                    write_file!(output, content).await?;
+                    println!("written {} lines to '{}'", lines.len(), output.display());
+                }
+                None => {
+                    for line in lines {
+                        println!("{line}");
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        println!("following '{}'", path.display());
+        let mut last_len = 0;
+        loop {
+            if let Ok(content) = fs::read(&path).await {
+                // the log was recreated by a new run
+                if content.len() < last_len {
+                    last_len = 0;
+                }
+                // only print complete lines
+                let end = match content[last_len..].iter().rposition(|x| *x == b'\n') {
+                    Some(x) => last_len + x + 1,
+                    None => last_len,
+                };
+                let new_content = String::from_utf8_lossy(&content[last_len..end]);
+                for line in new_content.lines() {
+                    if filter.accept(line) {
+                        println!("{line}");
+                    }
+                }
+                last_len = end;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
+
+/// Parse `HH:MM` or `HH:MM:SS` into seconds since midnight
+fn parse_time(time: &str) -> IoResult<u32> {
+    let parts = time
+        .split(':')
+        .map(|x| x.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>();
+    match parts.as_deref() {
+        Some([h, m]) if *h < 24 && *m < 60 => Ok(h * 3600 + m * 60),
+        Some([h, m, s]) if *h < 24 && *m < 60 && *s < 60 => Ok(h * 3600 + m * 60 + s),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid time '{time}', expected HH:MM or HH:MM:SS"),
+        ))?,
+    }
+}
+
+/// The header of a log line, like `[12:34:56] [Client thread/INFO] [FML]: ...`.
+/// The logger is empty for latest.log, which doesn't have it
+struct LogHeader<'a> {
+    /// Seconds since midnight
+    time: u32,
+    level: Option<LogLevel>,
+    logger: &'a str,
+}
+
+impl<'a> LogHeader<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let rest = line.strip_prefix('[')?;
+        let (time, rest) = rest.split_once("] [")?;
+        let time = parse_time(time).ok()?;
+        let (thread_level, rest) = rest.split_once(']')?;
+        let level = LogLevel::parse(thread_level.rsplit_once('/')?.1);
+        let logger = rest
+            .strip_prefix(" [")
+            .and_then(|x| x.split_once("]:"))
+            .map(|(x, _)| x.trim_end_matches('/'))
+            .unwrap_or_default();
+        Some(Self {
+            time,
+            level,
+            logger,
+        })
+    }
+}
+
+/// Filters log entries. Lines without a header, like stack traces,
+/// belong to the entry before them
+struct LogFilter<'a> {
+    since: Option<u32>,
+    level: Option<LogLevel>,
+    loggers: &'a [String],
+    /// If the current entry is shown
+    showing: bool,
+}
+
+impl LogFilter<'_> {
+    fn accept(&mut self, line: &str) -> bool {
+        let header = match LogHeader::parse(line) {
+            Some(x) => x,
+            None => return self.showing,
+        };
+        self.showing = self.since.is_none_or(|x| header.time >= x)
+            && self
+                .level
+                .is_none_or(|x| header.level.is_some_and(|l| l >= x))
+            && (self.loggers.is_empty()
+                || self.loggers.iter().any(|x| header.logger.starts_with(x)));
+        self.showing
+    }
+}
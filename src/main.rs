@@ -1,25 +1,105 @@
+use std::ffi::OsString;
+use std::time::Instant;
+
 use clap::{Parser, Subcommand};
 
+mod apijar;
+mod assets;
+mod bench;
 mod build;
+mod cache;
+mod clean;
+mod config;
+mod daemon;
+mod decompile;
+mod devjar;
+mod diagnose;
+mod discover;
+mod dump;
+mod explain;
+mod extends;
+mod generate;
 mod gradle;
+mod graph;
+mod hash;
 mod init;
+mod jar;
+mod logs;
 mod mcmod;
+mod modpack;
+mod nbt;
+mod ninja;
+mod pack;
+mod paths;
+mod plugin;
+mod png;
+mod preprocess;
+mod rcon;
+mod remap;
+mod rename;
+mod report;
 mod run;
+mod run_env;
+mod sbom;
+mod smoketest;
+mod state;
+mod status;
 mod sync;
+mod task;
 mod template;
+mod template_kit;
+mod test;
+mod timings;
+mod upgrade;
 mod util;
+mod verify;
+mod why;
+mod window;
+mod workspace;
 
+use assets::AssetsCommand;
+use bench::BenchCommand;
+use build::BuildCommand;
+use cache::CacheCommand;
+use clean::CleanCommand;
+use daemon::DaemonCommand;
+use decompile::DecompileCommand;
+use diagnose::DiagnoseCommand;
+use explain::{ErrorCode, ExplainCommand};
+use graph::GraphCommand;
 use init::InitCommand;
+use jar::{DiffJarCommand, InspectCommand};
+use logs::LogsCommand;
+use nbt::NbtCommand;
+use ninja::RegenNinjaCommand;
+use preprocess::PreprocessCommand;
+use rcon::RconCommand;
+use remap::RemapCommand;
+use rename::RenameCommand;
+use report::ReportCommand;
 use run::RunCommand;
+use sbom::SbomCommand;
+use smoketest::SmoketestCommand;
+use status::StatusCommand;
 use sync::SyncCommand;
-use util::IoResult;
+use task::TaskCommand;
+use template_kit::TemplateCommand;
+use test::TestCommand;
+use timings::TimingsOutput;
+use upgrade::UpgradeProjectCommand;
+use util::{IoResult, Project, ProjectArgs};
+use verify::VerifyCommand;
+use why::WhyCommand;
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
 
     if let Err(e) = cli.run().await {
-        eprintln!("error: {:?}", e);
+        match ErrorCode::of(&e) {
+            Some(code) => eprintln!("error[{}]: {:?}", code.code(), e),
+            None => eprintln!("error: {:?}", e),
+        }
         std::process::exit(1);
     }
 }
@@ -27,9 +107,12 @@ async fn main() {
 /// MC modding tool
 #[derive(Debug, Parser)]
 pub struct Cli {
-    /// Directory to run the command in
-    #[arg(short = 'C', long, default_value = ".")]
-    pub dir: String,
+    #[clap(flatten)]
+    pub args: ProjectArgs,
+
+    /// Print how long each phase of sync, build and run took. `json` also writes it into target/mcmod-logs
+    #[arg(long, global = true, value_enum, num_args = 0..=1, default_missing_value = "print")]
+    pub timings: Option<TimingsOutput>,
 
     /// Command to run
     #[clap(subcommand)]
@@ -38,11 +121,71 @@ pub struct Cli {
 
 impl Cli {
     pub async fn run(self) -> IoResult<()> {
+        let timings = self.timings;
+        if timings.is_some() {
+            timings::enable();
+        }
+        let start = Instant::now();
+        let args = self.args.clone();
+        let result = self.run_command().await;
+        if let Some(output) = timings {
+            let project = Project::new_in(&args).ok();
+            let error_code = result.as_ref().err().and_then(ErrorCode::of);
+            timings::report(output, start.elapsed(), project.as_ref(), error_code).await?;
+        }
+        result
+    }
+
+    async fn run_command(self) -> IoResult<()> {
         match self.command {
-            CliCommand::Sync(sync) => sync.run(&self.dir).await,
-            CliCommand::Init(init) => init.run(&self.dir).await,
-            CliCommand::Build => crate::build::run_build(&self.dir).await,
-            CliCommand::Run(run) => run.run(&self.dir).await,
+            CliCommand::Sync(sync) => {
+                for args in self.args.expand_targets().await? {
+                    sync.clone().run(&args).await?;
+                }
+                Ok(())
+            }
+            CliCommand::Init(init) => init.run(&self.args.init_dir()).await,
+            CliCommand::Build(build) => {
+                for args in self.args.expand_targets().await? {
+                    build.clone().run(&args).await?;
+                }
+                Ok(())
+            }
+            CliCommand::Clean(clean) => clean.run(&self.args).await,
+            CliCommand::Verify(verify) => {
+                for args in self.args.expand_targets().await? {
+                    verify.clone().run(&args).await?;
+                }
+                Ok(())
+            }
+            CliCommand::Status(status) => status.run(&self.args).await,
+            CliCommand::Run(run) => run.run(&self.args).await,
+            CliCommand::Test(test) => test.run(&self.args).await,
+            CliCommand::Task(task) => task.run(&self.args).await,
+            CliCommand::Inspect(inspect) => inspect.run().await,
+            CliCommand::DiffJar(diff) => diff.run().await,
+            CliCommand::Decompile(decompile) => decompile.run(&self.args).await,
+            CliCommand::Remap(remap) => remap.run().await,
+            CliCommand::UpgradeProject(upgrade) => upgrade.run(&self.args).await,
+            CliCommand::Rename(rename) => rename.run(&self.args).await,
+            CliCommand::Assets(assets) => assets.run(&self.args).await,
+            CliCommand::Nbt(nbt) => nbt.run().await,
+            CliCommand::Sbom(sbom) => sbom.run(&self.args).await,
+            CliCommand::Diagnose(diagnose) => diagnose.run(&self.args).await,
+            CliCommand::Logs(logs) => logs.run(&self.args).await,
+            CliCommand::Report(report) => report.run(&self.args).await,
+            CliCommand::Smoketest(smoketest) => smoketest.run(&self.args).await,
+            CliCommand::Rcon(rcon) => rcon.run(&self.args).await,
+            CliCommand::Bench(bench) => bench.run(&self.args).await,
+            CliCommand::Daemon(daemon) => daemon.run(&self.args).await,
+            CliCommand::Explain(explain) => explain.run().await,
+            CliCommand::Template(template) => template.run(&self.args).await,
+            CliCommand::Cache(cache) => cache.run().await,
+            CliCommand::Graph(graph) => graph.run(&self.args).await,
+            CliCommand::Why(why) => why.run(&self.args).await,
+            CliCommand::Preprocess(preprocess) => preprocess.run().await,
+            CliCommand::RegenNinja(regen) => regen.run(&self.args).await,
+            CliCommand::Plugin(command_line) => plugin::run_plugin(&self.args, command_line).await,
         }
     }
 }
@@ -52,9 +195,70 @@ pub enum CliCommand {
     /// Syncs the project state
     Sync(SyncCommand),
     /// Build the project
-    Build,
+    Build(BuildCommand),
+    /// Remove outputs of previous builds, like old jars
+    Clean(CleanCommand),
+    /// Check that files synced to the target directory have not been edited
+    Verify(VerifyCommand),
+    /// Show the state of the target directory and what syncing would do
+    Status(StatusCommand),
     /// Run the project
     Run(RunCommand),
-    /// Initialize a new project in the current directory
+    /// Compile and run the tests in the test sources
+    Test(TestCommand),
+    /// Run a task from `tasks` in mcmod.yaml, after the tasks it depends on
+    Task(TaskCommand),
+    /// Initialize a new project in the current directory, or the one given with -C
     Init(InitCommand),
+    /// Print the mod metadata, mixins, access transformers and class versions in a jar
+    Inspect(InspectCommand),
+    /// Compare the classes, resources, sizes and mod metadata of two jars
+    DiffJar(DiffJarCommand),
+    /// Decompile a jar from libs or run/mods into target/decompiled
+    Decompile(DecompileCommand),
+    /// Remap a jar between SRG and dev names, like a mod jar to use in the dev environment
+    Remap(RemapCommand),
+    /// Regenerate the files the tool owns in the project after updating the tool, and report what changed
+    UpgradeProject(UpgradeProjectCommand),
+    /// Rename the mod id and/or group, moving and updating the sources, assets and mcmod.yaml
+    Rename(RenameCommand),
+    /// Check assets and generate animation metadata
+    Assets(AssetsCommand),
+    /// Print or compare NBT files, like structures and level.dat
+    Nbt(NbtCommand),
+    /// Write a CycloneDX SBOM of the libs and mods used by the project
+    Sbom(SbomCommand),
+    /// Find well-known problems in the FML log of the last run
+    Diagnose(DiagnoseCommand),
+    /// Print the log of the last run, optionally filtered
+    Logs(LogsCommand),
+    /// Bundle the latest crash report, log, mcmod.yaml and environment info into a zip for bug reports
+    Report(ReportCommand),
+    /// Start the game and check that it boots without crashing
+    Smoketest(SmoketestCommand),
+    /// Send commands to the running dev server with RCON
+    Rcon(RconCommand),
+    /// Run benchmarks on the dev environment
+    Bench(BenchCommand),
+    /// Run a daemon that keeps the project in memory for instant incremental syncs
+    Daemon(DaemonCommand),
+    /// Scaffold and test templates, and sync the template registry
+    Template(TemplateCommand),
+    /// Show or clear the caches of the tool, like the shared gradle homes
+    Cache(CacheCommand),
+    /// Print the causes and fixes of an error code, like E0001
+    Explain(ExplainCommand),
+    /// Print the projects of the workspace, their template, libs and mods as a graph
+    Graph(GraphCommand),
+    /// Show where a file in the target directory came from
+    Why(WhyCommand),
+    /// Preprocess a source file. Used by build.ninja
+    #[clap(hide = true)]
+    Preprocess(PreprocessCommand),
+    /// Regenerate build.ninja for changed directories. Used by build.ninja
+    #[clap(hide = true)]
+    RegenNinja(RegenNinjaCommand),
+    /// Run `mcmod-<name>` on PATH for other commands
+    #[clap(external_subcommand)]
+    Plugin(Vec<OsString>),
 }
@@ -1,23 +1,30 @@
 //! The mcmod.yaml front end properties
 
-use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_recursion::async_recursion;
-use ninja_writer::*;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use tokio::task::JoinSet;
 use tokio::{fs, io};
 
-use crate::template::Template;
-use crate::util::{join_join_set, IoResult, Project};
+use crate::explain::ErrorCode;
+use crate::extends::{self, ExtendsDef};
+use crate::generate;
+use crate::template::{GtnhOptions, Template};
+use crate::util::{self, join_join_set, IoResult, Project};
+use crate::window::WindowSize;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Mcmod {
-    /// Template being used
+    /// What the project builds
+    #[serde(default, rename = "type")]
+    pub project_type: ProjectType,
+    /// Template being used. For packs, only the MC version of the template is used
     pub template: Template,
     /// Name of the mod
     pub name: String,
@@ -51,6 +58,13 @@ pub struct Mcmod {
     /// The group
     #[serde(default)]
     pub group: String,
+    /// Sources of the source sets, copied into the directories the template uses for them
+    #[serde(default)]
+    pub sources: Vec<SourceEntry>,
+    /// Languages of the sources besides Java, like `[java, scala]`. Their files in the
+    /// Java source directories are copied into the directories of the language instead
+    #[serde(default)]
+    pub languages: Vec<Language>,
     /// The archive base name
     #[serde(default)]
     pub archives_base_name: String,
@@ -60,27 +74,485 @@ pub struct Mcmod {
     /// The coremod class
     #[serde(default)]
     pub coremod: String,
+    /// The jar only has a coremod and/or mixins, and no @Mod. mcmod.info is not generated
+    #[serde(default)]
+    pub coremod_only: bool,
     /// The access transformer file
     #[serde(default)]
     pub access_transformers: Vec<String>,
     /// The mixin package
     #[serde(default)]
     pub mixins: String,
+    /// Fully qualified name of a class to generate with the version, mod id
+    /// and build metadata as constants
+    #[serde(default)]
+    pub tags_class: String,
     /// Libraries to download
     #[serde(default)]
-    pub libs: Vec<String>,
+    pub libs: Vec<LibSpec>,
     /// Mods to download
     #[serde(default)]
-    pub mods: Vec<String>,
+    pub mods: Vec<ModSpec>,
+    /// The parent mod of an addon. Its dev jar is added to the libs, its release jar to the mods
+    /// and its access transformers to the access transformers
+    #[serde(default)]
+    pub extends: Option<ExtendsDef>,
+    /// Named sets of mods added to `mods` when selected with `mcmod run --mods`
+    #[serde(default)]
+    pub mod_sets: BTreeMap<String, Vec<ModSpec>>,
+    /// File name patterns (with `*` and `?`) of files in libs and mods that are not removed
+    /// when they are not in the lists above, like jars added manually for testing
+    #[serde(default)]
+    pub keep: Vec<String>,
     /// Gradle properties overrides
     #[serde(default)]
     pub gradle_overrides: BTreeMap<String, String>,
+    /// Java version to run gradle with for tasks, overriding the template's mapping
+    #[serde(default)]
+    pub task_java_versions: BTreeMap<String, u32>,
+    /// Options for the GTNH templates. Ignored by other templates
+    #[serde(default)]
+    pub gtnh: GtnhOptions,
     /// Paths to copy to the template
     #[serde(default)]
     pub copy_paths: Vec<CopySpec>,
     /// Paths suffixes to exclude from copying
     #[serde(default)]
     pub copy_exclude: Vec<String>,
+    /// Run java, scala and kotlin sources through the preprocessor when copying,
+    /// for `//#if MC >= 11202` directives
+    #[serde(default)]
+    pub preprocess: bool,
+    /// Extra arguments for ninja when copying
+    #[serde(default)]
+    pub ninja_args: Vec<String>,
+    /// Extra JVM arguments when running the game
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+    /// Directory to run the game in instead of the template's run directory,
+    /// relative to the project root. Can be shared by related projects
+    #[serde(default)]
+    pub run_dir: String,
+    /// Run the game in target/run-<profile> when a profile is selected,
+    /// so experiments don't change the main run directory
+    #[serde(default)]
+    pub isolated_run_dir: bool,
+    /// Move the run directory to a short path and link to it, for Windows where
+    /// deep paths in the run directory can be longer than MAX_PATH
+    #[serde(default)]
+    pub short_run_dir: bool,
+    /// Java agents, module arguments and output recording for the game
+    #[serde(default)]
+    pub run: RunOptions,
+    /// Named profiles that override the fields above, selected with --profile
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Additional targets built from the same source, selected with --target.
+    /// Each target is synced to its own target-<name> directory
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetDef>,
+    /// Dependency and version fields of the generated mcmod.info
+    #[serde(default)]
+    pub mcmod_info: McmodInfoOptions,
+    /// Other @Mod containers in the same jar, added to mcmod.info as children of this mod
+    #[serde(default)]
+    pub child_mods: Vec<ChildMod>,
+    /// Overrides for the generated pack.mcmeta
+    #[serde(default)]
+    pub pack_mcmeta: PackMcmetaOptions,
+    /// Timeouts for downloading libs and mods
+    #[serde(default)]
+    pub downloads: DownloadOptions,
+    /// Checks on the output jar after building. Not checked if not set
+    #[serde(default)]
+    pub verify: Option<VerifyOptions>,
+    /// Number of the latest built jars of each artifact to keep in the output directory
+    /// after building, at least 1. Keeps all if not set
+    #[serde(default)]
+    pub keep_artifacts: Option<usize>,
+    /// Other projects in the repository built together with this one by `mcmod build --workspace`,
+    /// as directories relative to the project root
+    #[serde(default)]
+    pub workspace_members: Vec<String>,
+    /// Named tasks run with `mcmod task <name>`
+    #[serde(default)]
+    pub tasks: BTreeMap<String, TaskDef>,
+}
+
+/// A directory of sources in a source set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceEntry {
+    pub set: SourceSet,
+    /// The directory, relative to the project root
+    pub root: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceSet {
+    /// Sources of the mod
+    Main,
+    /// Sources of the `api` package, in the mod jar and the API jar
+    Api,
+    /// Tests compiled and run by `mcmod test`, which are not in the mod jar
+    Test,
+}
+
+/// A language the sources are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Language {
+    Java,
+    Scala,
+    Kotlin,
+}
+
+impl Language {
+    /// Name of the language, which is also the name of its source directory in gradle
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Java => "java",
+            Self::Scala => "scala",
+            Self::Kotlin => "kotlin",
+        }
+    }
+
+    /// Extension of the source files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Java => "java",
+            Self::Scala => "scala",
+            Self::Kotlin => "kt",
+        }
+    }
+
+    /// Get the language of a source file from its extension
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+        [Self::Java, Self::Scala, Self::Kotlin]
+            .into_iter()
+            .find(|x| extension == x.extension())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProjectType {
+    /// A mod built with the gradle template
+    #[default]
+    Mod,
+    /// A resource pack with only assets, zipped without gradle
+    Pack,
+}
+
+/// Checks on the output jar after building
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VerifyOptions {
+    /// Packages that should not be in the jar, like dev-only code
+    #[serde(default)]
+    pub forbidden_packages: Vec<String>,
+    /// Allow .java, .scala and .kt sources in the jar
+    #[serde(default)]
+    pub allow_sources: bool,
+    /// The highest Java version classes can target. Default is the lowest Java version of the template
+    #[serde(default)]
+    pub max_java_version: Option<u16>,
+}
+
+/// A task run with `mcmod task <name>`.
+/// `{version}`, `{modid}`, `{project-root}`, `{target-root}`, `{output-dir}` and `{run-dir}`
+/// are replaced in the steps and environment variables, and set as `MCMOD_*` environment variables
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TaskDef {
+    /// Tasks to run before this one
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Environment variables for the steps
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Steps to run in order
+    #[serde(default)]
+    pub run: Vec<TaskStep>,
+}
+
+/// A shell command run in the project root, or `gradle: <task and args>` run in the target
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TaskStep {
+    Shell(String),
+    Gradle(GradleStep),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GradleStep {
+    pub gradle: String,
+}
+
+/// Java agents, module arguments and output recording for every run
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunOptions {
+    /// Paths of java agent jars relative to the project root,
+    /// optionally followed by `=<options>`
+    #[serde(default)]
+    pub java_agents: Vec<String>,
+    /// JVM arguments like `--add-opens` that are only added
+    /// when the game runs on Java 9 or newer
+    #[serde(default)]
+    pub jvm_module_args: Vec<String>,
+    /// Record the output of every run into target/mcmod-logs
+    #[serde(default)]
+    pub record_output: bool,
+    /// Number of recorded runs to keep. Default is 10
+    #[serde(default)]
+    pub keep_recordings: Option<usize>,
+    /// Size of the client window, like `1280x720`
+    #[serde(default)]
+    pub window_size: Option<WindowSize>,
+    /// Maximize the client window. Needs wmctrl on Linux with X11
+    #[serde(default)]
+    pub maximized: bool,
+    /// Record the size and position of the client window while it runs, and restore them on
+    /// the next launch. The position needs wmctrl on Linux with X11
+    #[serde(default)]
+    pub remember_window: bool,
+}
+
+/// Dependency and version fields of the generated mcmod.info
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct McmodInfoOptions {
+    /// The mcversion. Default is the MC version of the template
+    #[serde(default)]
+    pub mcversion: Option<String>,
+    /// Mods that must be loaded before this mod
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// Mods that must be present
+    #[serde(default)]
+    pub required_mods: Vec<String>,
+    /// Mods that must be loaded after this mod
+    #[serde(default)]
+    pub dependants: Vec<String>,
+    /// Use the dependencies above instead of the ones in the @Mod annotation
+    #[serde(default)]
+    pub use_dependency_information: bool,
+}
+
+/// Names of the constants in the tags class for the main mod
+const TAGS_CONSTANTS: [&str; 8] = [
+    "MODID",
+    "MODNAME",
+    "VERSION",
+    "ARTIFACT_VERSION",
+    "GROUP",
+    "MC_VERSION",
+    "TEMPLATE",
+    "GIT_COMMIT",
+];
+
+/// Another @Mod container in the same jar
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChildMod {
+    /// Mod id
+    pub modid: String,
+    /// Name of the mod
+    pub name: String,
+    /// Mod description
+    #[serde(default)]
+    pub description: String,
+    /// Mod version. Default is the version of the main mod
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl ChildMod {
+    /// Names of the `<MODID>_MODID`, `<MODID>_MODNAME` and `<MODID>_VERSION` constants
+    /// in the tags class
+    pub fn tags_constants(&self) -> [String; 3] {
+        let prefix = self
+            .modid
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        ["MODID", "MODNAME", "VERSION"].map(|x| format!("{prefix}_{x}"))
+    }
+}
+
+/// Timeouts for downloads, in seconds
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DownloadOptions {
+    /// Timeout for connecting to the server. Default is 30
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    /// Timeout for receiving more data after the last data. Default is 60
+    #[serde(default)]
+    pub read_timeout: Option<u64>,
+    /// Timeout for downloading each file. Default is 600
+    #[serde(default)]
+    pub timeout: Option<u64>,
+}
+
+impl DownloadOptions {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap_or(30))
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout.unwrap_or(60))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(600))
+    }
+}
+
+/// Overrides for the generated pack.mcmeta
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackMcmetaOptions {
+    /// The description. Default is "Resources used for <name>"
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The pack_format. Default is the pack format of the template's MC version
+    #[serde(default)]
+    pub pack_format: Option<u32>,
+    /// The range of pack formats supported, as [min, max]. Only used by MC 1.20.2+
+    #[serde(default)]
+    pub supported_formats: Option<[u32; 2]>,
+}
+
+/// An additional target to build the same source with
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetDef {
+    /// Template used by the target. Defaults to the name of the target
+    #[serde(default)]
+    pub template: Option<Template>,
+    /// Overrides applied when this target is selected
+    #[serde(flatten)]
+    pub overrides: Profile,
+}
+
+/// Overrides applied on top of the top-level config when a profile is selected
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    /// Replaces the mod version
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Replaces the version to use for artifacts
+    #[serde(default)]
+    pub artifact_version: Option<String>,
+    /// Replaces the mods to download
+    #[serde(default)]
+    pub mods: Option<Vec<ModSpec>>,
+    /// Replaces the libraries to download
+    #[serde(default)]
+    pub libs: Option<Vec<LibSpec>>,
+    /// Merged into the top-level gradle properties overrides
+    #[serde(default)]
+    pub gradle_overrides: BTreeMap<String, String>,
+    /// Replaces the extra JVM arguments
+    #[serde(default)]
+    pub jvm_args: Option<Vec<String>>,
+    /// Replaces the run directory
+    #[serde(default)]
+    pub run_dir: Option<String>,
+    /// Run in target/run-<profile> when this profile is selected
+    #[serde(default)]
+    pub isolated_run_dir: Option<bool>,
+}
+
+/// A library to download into libs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LibSpec {
+    Simple(String),
+    Api(ApiLib),
+}
+
+/// A mod jar that only the API of is compiled against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApiLib {
+    /// The mod jar, same as the entries in `libs`
+    pub source: String,
+    /// The mod id of the mod, to find its API packages from the @API annotations
+    pub api_of: String,
+    /// The API package to extract, like `com.example.api`. Found from the @API annotations if empty
+    #[serde(default)]
+    pub api: String,
+}
+
+impl LibSpec {
+    pub fn source(&self) -> &str {
+        match self {
+            Self::Simple(s) => s,
+            Self::Api(x) => &x.source,
+        }
+    }
+
+    /// The mod the lib is the API of, or None if the whole jar is used
+    pub fn api_of(&self) -> Option<&ApiLib> {
+        match self {
+            Self::Simple(_) => None,
+            Self::Api(x) => Some(x),
+        }
+    }
+}
+
+/// A mod to download into the run directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModSpec {
+    Simple(String),
+    Sided(SidedMod),
+}
+
+/// A mod that is only installed for one side
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SidedMod {
+    /// The mod, same as the entries in `mods`
+    pub source: String,
+    /// The side to install the mod for
+    pub side: ModSide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModSide {
+    ClientOnly,
+    ServerOnly,
+}
+
+impl ModSpec {
+    pub fn source(&self) -> &str {
+        match self {
+            Self::Simple(s) => s,
+            Self::Sided(m) => &m.source,
+        }
+    }
+
+    /// The side the mod is only installed for, or None if it's for both
+    pub fn side(&self) -> Option<ModSide> {
+        match self {
+            Self::Simple(_) => None,
+            Self::Sided(m) => Some(m.side),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -88,9 +560,231 @@ pub struct Mcmod {
 pub enum CopySpec {
     Simple(String),
     SourceTarget(String, String),
+    Overlay(CopyOverlay),
+    Delete(CopyDelete),
+    Generate(CopyGenerate),
+}
+
+/// A path to delete in the target before copying
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CopyDelete {
+    /// Target path relative to the target root
+    pub delete: String,
+    /// Names of targets or templates this applies to. Applies to all if empty
+    #[serde(default)]
+    pub only_for: Vec<String>,
+}
+
+/// A copy path that only applies to some targets, or that replaces files from earlier copy paths.
+///
+/// With `overwrite`, files copied by this copy path replace the ones from earlier copy paths,
+/// so this can be used to layer version-specific sources over common sources
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CopyOverlay {
+    /// Source path relative to the project root
+    pub source: String,
+    /// Target path relative to the target root. Defaults to the source path
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Names of targets or templates this applies to. Applies to all if empty
+    #[serde(default)]
+    pub only_for: Vec<String>,
+    /// Replace files copied to the same path by earlier copy paths, instead of failing
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// A command that generates files during sync, which are copied to the target like a source directory
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CopyGenerate {
+    /// Command run with the shell in the project root.
+    /// It writes the files into the directory in the `MCMOD_OUTPUT` environment variable
+    pub generate: String,
+    /// Target directory relative to the target root
+    pub target: String,
+    /// Paths relative to the project root that the command reads. The command only runs
+    /// again when they change. If empty, the command runs on every sync
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Names of targets or templates this applies to. Applies to all if empty
+    #[serde(default)]
+    pub only_for: Vec<String>,
+    /// Replace files copied to the same path by earlier copy paths, instead of failing
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+impl CopySpec {
+    /// Get the source and target paths. None if the target is deleted instead,
+    /// which is also the case for the older `["null", target]` form
+    pub fn source_target(&self) -> Option<(&str, &str)> {
+        let (source, target) = match self {
+            Self::Simple(s) => (s.as_str(), s.as_str()),
+            Self::SourceTarget(s, t) => (s.as_str(), t.as_str()),
+            Self::Overlay(o) => (o.source.as_str(), o.target.as_deref().unwrap_or(&o.source)),
+            Self::Delete(_) | Self::Generate(_) => return None,
+        };
+        if source == "null" {
+            return None;
+        }
+        Some((source, target))
+    }
+
+    /// Get the target path, relative to the target root
+    pub fn target(&self) -> &str {
+        match self {
+            Self::Simple(s) => s,
+            Self::SourceTarget(_, t) => t,
+            Self::Overlay(o) => o.target.as_deref().unwrap_or(&o.source),
+            Self::Delete(d) => &d.delete,
+            Self::Generate(g) => &g.target,
+        }
+    }
+
+    /// Check if the target is deleted before copying, instead of copied to
+    pub fn is_delete(&self) -> bool {
+        match self {
+            Self::Delete(_) => true,
+            Self::Generate(_) => false,
+            _ => self.source_target().is_none(),
+        }
+    }
+
+    /// Get the generator if the files are generated by a command
+    pub fn generator(&self) -> Option<&CopyGenerate> {
+        match self {
+            Self::Generate(g) => Some(g),
+            _ => None,
+        }
+    }
+
+    /// Get the directory the files are copied from: the source in the project,
+    /// or the output of the generator. None if the target is deleted
+    pub fn copy_source(&self, project: &Project) -> Option<PathBuf> {
+        match self.generator() {
+            Some(g) => Some(generate::output_dir(project, g)),
+            None => Some(project.root.join(self.source_target()?.0)),
+        }
+    }
+
+    /// Check if files from this copy path can replace files from earlier copy paths
+    pub fn overwrite(&self) -> bool {
+        match self {
+            Self::Overlay(o) => o.overwrite,
+            Self::Generate(g) => g.overwrite,
+            _ => false,
+        }
+    }
+
+    /// Check if this copy path applies to the target of the project
+    pub fn applies_to(&self, project: &Project, template: &Template) -> bool {
+        let only_for = match self {
+            Self::Overlay(o) => &o.only_for,
+            Self::Delete(d) => &d.only_for,
+            Self::Generate(g) => &g.only_for,
+            _ => return true,
+        };
+        if only_for.is_empty() {
+            return true;
+        }
+        let template = template.to_string();
+        only_for
+            .iter()
+            .any(|x| *x == template || Some(x) == project.target.as_ref())
+    }
+}
+
+/// Where a copy path copies to. Sources in other languages than Java that a copy path copies
+/// into a Java source directory are routed into the source directory of their language
+#[derive(Debug)]
+pub struct CopyRoute<'a> {
+    /// Index of the copy path in copy_paths
+    pub layer: usize,
+    pub copy_path: &'a CopySpec,
+    /// Target path relative to the target root
+    pub target: String,
+    pub filter: LanguageFilter,
+}
+
+/// Which source files a copy route copies
+#[derive(Debug, Default, Clone)]
+pub struct LanguageFilter {
+    /// Only copy the sources in this language
+    pub only: Option<Language>,
+    /// Don't copy the sources in these languages, which are copied by other routes
+    pub skip: Vec<Language>,
+}
+
+impl LanguageFilter {
+    /// Check if the file is copied
+    pub fn copies(&self, path: &Path) -> bool {
+        let language = Language::of(path);
+        match self.only {
+            Some(x) => language == Some(x),
+            None => language.is_none_or(|x| !self.skip.contains(&x)),
+        }
+    }
 }
 
 impl Mcmod {
+    /// Apply the overrides in the profile with the given name
+    pub fn apply_profile(&mut self, name: &str) -> IoResult<()> {
+        let profile = match self.profiles.remove(name) {
+            Some(x) => x,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Profile '{name}' is not defined in mcmod.yaml"),
+            ))?,
+        };
+        self.apply_overrides(profile);
+        Ok(())
+    }
+
+    /// Switch to the target with the given name and apply its overrides
+    pub fn apply_target(&mut self, name: &str) -> IoResult<()> {
+        let target = match self.targets.remove(name) {
+            Some(x) => x,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Target '{name}' is not defined in mcmod.yaml"),
+            ))?,
+        };
+        self.template = match target.template {
+            Some(x) => x,
+            None => name.parse()?,
+        };
+        self.apply_overrides(target.overrides);
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, profile: Profile) {
+        if let Some(version) = profile.version {
+            self.version = version;
+        }
+        if let Some(artifact_version) = profile.artifact_version {
+            self.artifact_version = artifact_version;
+        }
+        if let Some(mods) = profile.mods {
+            self.mods = mods;
+        }
+        if let Some(libs) = profile.libs {
+            self.libs = libs;
+        }
+        self.gradle_overrides.extend(profile.gradle_overrides);
+        if let Some(jvm_args) = profile.jvm_args {
+            self.jvm_args = jvm_args;
+        }
+        if let Some(run_dir) = profile.run_dir {
+            self.run_dir = run_dir;
+        }
+        if let Some(isolated_run_dir) = profile.isolated_run_dir {
+            self.isolated_run_dir = isolated_run_dir;
+        }
+    }
+
     /// Apply defaults to missing fields
     pub async fn apply_defaults(&mut self, project: &Project) -> IoResult<()> {
         if self.update_url.is_empty() && !self.url.is_empty() {
@@ -100,34 +794,363 @@ impl Mcmod {
             self.artifact_version = self.version.clone();
         }
         if self.group.is_empty() {
-            self.group = project.source_group().await?;
+            self.group = self.detect_group(project).await?;
         }
         if self.archives_base_name.is_empty() {
             self.archives_base_name = self.name.replace(' ', "-");
         }
+        if self.api.is_empty() {
+            if let Some(api) = self.sources.iter().find(|x| x.set == SourceSet::Api) {
+                let api_group = util::source_group(&project.root.join(&api.root)).await?;
+                self.api = api_group.unwrap_or_default();
+            }
+        }
+        extends::apply(self, project);
+        self.template.locate_manifest(project).await?;
+        self.apply_sources();
 
         Ok(())
     }
 
-    /// Create the content of the mcmod.info file
-    pub fn create_mcmod_info(&self) -> IoResult<String> {
+    /// Copy the roots in `sources` into the directories of their source sets in the template,
+    /// before the other copy paths
+    fn apply_sources(&mut self) {
+        let handler = self.template.new_handler();
+        let copies = self
+            .sources
+            .iter()
+            .map(|x| {
+                let dir = handler.source_set_dir(x.set, Language::Java);
+                CopySpec::SourceTarget(x.root.clone(), dir)
+            })
+            .collect::<Vec<_>>();
+        self.copy_paths.splice(0..0, copies);
+    }
+
+    /// Detect the group of the main and API roots in `sources` (or `src`), and get the package
+    /// all of them are in. Empty with a warning if it can't be told, see [`Self::require_group`]
+    async fn detect_group(&self, project: &Project) -> IoResult<String> {
+        let roots = self
+            .sources
+            .iter()
+            .filter(|x| x.set != SourceSet::Test)
+            .map(|x| &x.root)
+            .collect::<Vec<_>>();
+        if roots.is_empty() {
+            let group = util::source_group(&project.source_root()).await?;
+            return Ok(group.unwrap_or_default());
+        }
+        let mut groups = Vec::new();
+        for root in roots {
+            let path = project.root.join(root);
+            if !path.is_dir() {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Source root '{root}' in `sources` is not a directory"),
+                )))?;
+            }
+            match util::source_group(&path).await? {
+                Some(group) => groups.push((root, group)),
+                None => return Ok(String::new()),
+            }
+        }
+        let mut common = groups[0].1.split('.').collect::<Vec<_>>();
+        for (_, group) in &groups[1..] {
+            let len = common
+                .iter()
+                .zip(group.split('.'))
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            common.truncate(len);
+        }
+        // different groups only sharing the top-level package, like com.a and com.b
+        let same = groups.iter().all(|(_, x)| *x == groups[0].1);
+        if !same && common.len() < 2 {
+            let groups = groups
+                .iter()
+                .map(|(root, group)| format!("{group} ({root})"))
+                .collect::<Vec<_>>();
+            println!(
+                "warning: the source roots have different groups: {}. Set `group` in mcmod.yaml",
+                groups.join(", ")
+            );
+            return Ok(String::new());
+        }
+        Ok(common.join("."))
+    }
+
+    /// The group, for the places that can't work without one
+    pub fn require_group(&self) -> IoResult<&str> {
+        if self.group.is_empty() {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The group of the mod can't be detected from the sources. Set `group` in mcmod.yaml",
+            )))?;
+        }
+        Ok(&self.group)
+    }
+
+    /// If the project is a resource pack
+    pub fn is_pack(&self) -> bool {
+        self.project_type == ProjectType::Pack
+    }
+
+    /// Validate the config against the features supported by the template
+    pub fn validate(&self) -> IoResult<()> {
+        for copy_path in &self.copy_paths {
+            validate_copy_target(copy_path.target())?;
+        }
+        for (name, task) in &self.tasks {
+            if let Some(depend) = task.depends.iter().find(|x| !self.tasks.contains_key(*x)) {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Task '{name}' depends on '{depend}', which is not in `tasks`"),
+                )))?;
+            }
+        }
+        if self.keep_artifacts == Some(0) {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`keep-artifacts` must be at least 1. Remove it from mcmod.yaml to keep all jars",
+            )))?;
+        }
+        if self.is_pack() {
+            return self.validate_pack();
+        }
+        let template = &self.template;
+        let handler = template.new_handler();
+        let capabilities = handler.capabilities();
+        if !capabilities.mixins && !self.mixins.is_empty() {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Mixins are not supported by template '{template}'. Remove `mixins` from mcmod.yaml"),
+            )))?;
+        }
+        if !capabilities.coremods && !self.coremod.is_empty() {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Coremods are not supported by template '{template}'. Remove `coremod` from mcmod.yaml"),
+            )))?;
+        }
+        if let Some(language) = self
+            .other_languages()
+            .into_iter()
+            .find(|x| !capabilities.languages.contains(x))
+        {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Language '{}' is not supported by template '{template}'. Remove it from `languages` in mcmod.yaml",
+                    language.name()
+                ),
+            )))?;
+        }
+        if capabilities.git_version
+            && (!self.version.is_empty() || !self.artifact_version.is_empty())
+        {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Version is automatically determined from git for template '{template}'. Remove the versions in mcmod.yaml"),
+            )))?;
+        }
+        if !capabilities.git_version && self.version.is_empty() {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Template '{template}' requires `version` in mcmod.yaml"),
+            )))?;
+        }
+        if self.coremod_only {
+            if self.coremod.is_empty() && self.mixins.is_empty() {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`coremod-only` requires `coremod` or `mixins` in mcmod.yaml",
+                )))?;
+            }
+            if !self.child_mods.is_empty() {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`coremod-only` jars cannot have `child-mods`",
+                )))?;
+            }
+        }
+        if !self.tags_class.is_empty() {
+            let valid = self.tags_class.contains('.')
+                && self.tags_class.split('.').all(|x| {
+                    x.chars()
+                        .next()
+                        .is_some_and(|c| c.is_alphabetic() || c == '_')
+                        && x.chars()
+                            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+                });
+            if !valid {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`tags-class` must be a fully qualified class name like `com.example.Tags`, but is '{}'", self.tags_class),
+                )))?;
+            }
+        }
+        for lib in self.api_libs() {
+            if !lib.source.ends_with(".jar") {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The API can only be extracted from a jar, but `api-of` lib is '{}'",
+                        lib.source
+                    ),
+                )))?;
+            }
+        }
+        let java_versions = handler.capabilities().java_versions;
+        for (task, version) in &self.task_java_versions {
+            if !java_versions.contains(version) {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Java {version} for task '{task}' in `task-java-versions` is not supported by the template, which uses {java_versions:?}"),
+                )))?;
+            }
+        }
+        handler
+            .validate(self)
+            .map_err(|e| e.attach_printable(ErrorCode::InvalidConfig))?;
+
+        Ok(())
+    }
+
+    /// Check that the child mod ids are unique and can be used in the constant names
+    /// of the tags class. Done when loading mcmod.yaml, before anything is generated
+    pub fn validate_child_mods(&self) -> IoResult<()> {
+        let mut modids = vec![&self.modid];
+        for child in &self.child_mods {
+            if modids.contains(&&child.modid) {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Mod id '{}' is used more than once in mcmod.yaml",
+                        child.modid
+                    ),
+                )))?;
+            }
+            modids.push(&child.modid);
+        }
+        if self.tags_class.is_empty() {
+            return Ok(());
+        }
+        let mut constants = TAGS_CONSTANTS.map(str::to_owned).to_vec();
+        for child in &self.child_mods {
+            if child.modid.starts_with(|c: char| c.is_ascii_digit()) {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Child mod id '{}' cannot start with a digit, because it's used as a constant name in `tags-class`",
+                        child.modid
+                    ),
+                )))?;
+            }
+            for name in child.tags_constants() {
+                if constants.contains(&name) {
+                    Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Child mod id '{}' creates the constant `{name}` in `tags-class`, which is already used. Rename the child mod",
+                            child.modid
+                        ),
+                    )))?;
+                }
+                constants.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that a pack doesn't use options that need java sources or gradle
+    fn validate_pack(&self) -> IoResult<()> {
+        let mod_options = [
+            ("mixins", !self.mixins.is_empty()),
+            ("coremod", !self.coremod.is_empty()),
+            ("access-transformers", !self.access_transformers.is_empty()),
+            ("tags-class", !self.tags_class.is_empty()),
+            ("api", !self.api.is_empty()),
+            ("libs", !self.libs.is_empty()),
+            ("mods", !self.mods.is_empty()),
+            ("child-mods", !self.child_mods.is_empty()),
+        ];
+        for (name, used) in mod_options {
+            if used {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`{name}` is not supported for packs. Remove it from mcmod.yaml, or remove `type: pack`"),
+                ))?;
+            }
+        }
+        if self.version.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Packs require `version` in mcmod.yaml",
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Create the content of the mcmod.info file.
+    ///
+    /// The fields in `extra` (from mcmod.info.extra.json) are merged into the entry of the mod
+    pub fn create_mcmod_info(&self, extra: Option<&Value>) -> IoResult<String> {
         let handler = self.template.new_handler();
         let version = format!("${{{}}}", handler.mcmod_version_key());
-        let info = json!([{
+        let options = &self.mcmod_info;
+        let mcversion = match &options.mcversion {
+            Some(x) => x.as_str(),
+            None => handler.mc_version(),
+        };
+        let mut entry = json!({
             "modid": self.modid,
             "name": self.name,
             "description": self.description,
             "version": version,
-            "mcversion": handler.mc_version(),
+            "mcversion": mcversion,
             "url": self.url,
             "updateUrl": self.update_url,
             "authorList": self.authors,
             "credits": self.credits,
             "logoFile": self.logo,
             "screenshots": self.screenshots,
-            "dependencies": [],
-        }]);
-        match serde_json::to_string_pretty(&info) {
+            "dependencies": options.dependencies,
+            "requiredMods": options.required_mods,
+            "dependants": options.dependants,
+            "useDependencyInformation": options.use_dependency_information,
+        });
+        match extra {
+            None => {}
+            Some(Value::Object(extra)) => {
+                for (key, value) in extra {
+                    entry[key] = value.clone();
+                }
+            }
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{MCMOD_INFO_EXTRA} must be a JSON object"),
+            ))?,
+        }
+        let mut entries = vec![entry];
+        for child in &self.child_mods {
+            let version = child.version.as_deref().unwrap_or(&version);
+            entries.push(json!({
+                "modid": child.modid,
+                "name": child.name,
+                "description": child.description,
+                "version": version,
+                "mcversion": mcversion,
+                "url": self.url,
+                "updateUrl": self.update_url,
+                "authorList": self.authors,
+                "credits": self.credits,
+                "logoFile": self.logo,
+                "screenshots": self.screenshots,
+                "parent": self.modid,
+                "dependencies": [],
+            }));
+        }
+        match serde_json::to_string_pretty(&entries) {
             Ok(x) => Ok(x),
             Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
         }
@@ -135,58 +1158,224 @@ impl Mcmod {
 
     /// Create the content of the pack.mcmeta file
     pub fn create_pack_mcmeta(&self) -> IoResult<String> {
-        let pack = json!({
-            "pack": {
-                "pack_format": 1,
-                "description": format!("Resources used for {}", self.name),
-            }
+        let options = &self.pack_mcmeta;
+        let pack_format = match options.pack_format {
+            Some(x) => x,
+            None => self.template.new_handler().pack_format(),
+        };
+        let description = match &options.description {
+            Some(x) => x.clone(),
+            None if self.is_pack() => self.description.clone(),
+            None => format!("Resources used for {}", self.name),
+        };
+        let mut pack = json!({
+            "pack_format": pack_format,
+            "description": description,
         });
-        match serde_json::to_string_pretty(&pack) {
+        if let Some([min, max]) = options.supported_formats {
+            if min > max || pack_format < min || pack_format > max {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`supported-formats` in `pack-mcmeta` must be a range [min, max] that includes the pack format {pack_format}"),
+                ))?;
+            }
+            pack["supported_formats"] = json!([min, max]);
+        }
+        match serde_json::to_string_pretty(&json!({ "pack": pack })) {
             Ok(x) => Ok(x),
             Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
         }
     }
 
-    /// Create the content of build.ninja
-    pub async fn create_build_ninja(&self, root: &Path, target_root: &Path) -> IoResult<String> {
-        let ninja = Ninja::new();
-        ninja.comment("Incremental build file for copying source and assets");
-        ninja.comment("Please run `mcmod sync` to update this file when mcmod.yaml, or when the file structure changes");
+    /// Get the path of the tags class relative to the java source root, if it's set
+    pub fn tags_class_path(&self) -> Option<PathBuf> {
+        if self.tags_class.is_empty() {
+            return None;
+        }
+        let mut path = self.tags_class.split('.').collect::<PathBuf>();
+        path.set_extension("java");
+        Some(path)
+    }
 
-        let cp = if cfg!(windows) {
-            Rule::new("cp", "coreutils cp $in $out")
-        } else {
-            Rule::new("cp", "cp $in $out")
-        };
-        let cp = cp.description("Copying $in").add_to(&ninja);
+    /// Create the content of the tags class with the version and build metadata
+    pub fn create_tags_class(&self, version: &str, git_commit: &str) -> String {
+        let (package, class) = self
+            .tags_class
+            .rsplit_once('.')
+            .unwrap_or(("", &self.tags_class));
+        let handler = self.template.new_handler();
+        let constants = [
+            (TAGS_CONSTANTS[0], self.modid.as_str()),
+            (TAGS_CONSTANTS[1], &self.name),
+            (TAGS_CONSTANTS[2], version),
+            (TAGS_CONSTANTS[3], &self.artifact_version),
+            (TAGS_CONSTANTS[4], &self.group),
+            (TAGS_CONSTANTS[5], handler.mc_version()),
+            (TAGS_CONSTANTS[6], &self.template.to_string()),
+            (TAGS_CONSTANTS[7], git_commit),
+        ];
+        let mut constants = constants
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<_>>();
+        for child in &self.child_mods {
+            let [modid, name, child_version] = child.tags_constants();
+            constants.push((modid, child.modid.clone()));
+            constants.push((name, child.name.clone()));
+            let version = child.version.as_deref().unwrap_or(version);
+            constants.push((child_version, version.to_owned()));
+        }
+        let mut content =
+            format!("// generated by mcmod\npackage {package};\n\npublic final class {class} {{\n");
+        for (name, value) in constants {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            content.push_str(&format!(
+                "    public static final String {name} = \"{value}\";\n"
+            ));
+        }
+        content.push_str(&format!("\n    private {class}() {{}}\n}}\n"));
+        content
+    }
 
-        let mut join_set = JoinSet::new();
-        for copy_path in &self.copy_paths {
-            if let CopySpec::SourceTarget(s, t) = copy_path {
-                if s == "null" {
-                    let target = target_root.join(t);
-                    if target.exists() {
-                        if target.is_dir() {
-                            fs::remove_dir_all(&target).await?;
-                        } else {
-                            fs::remove_file(&target).await?;
-                        }
-                    }
+    /// Get the sources of the mods to run the side with, and the mods in the mod set if selected.
+    /// Client-only and server-only mods are only included if the side is given
+    pub fn mods_for(&self, side: Option<ModSide>, mod_set: Option<&str>) -> IoResult<Vec<String>> {
+        let mut mods = self.mods.iter().collect::<Vec<_>>();
+        if let Some(name) = mod_set {
+            match self.mod_sets.get(name) {
+                Some(set) => mods.extend(set),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "Mod set '{name}' is not defined in mcmod.yaml. Available: {}",
+                        self.mod_sets.keys().cloned().collect::<Vec<_>>().join(", ")
+                    ),
+                ))?,
+            }
+        }
+        Ok(mods
+            .into_iter()
+            .filter(|x| x.side().is_none() || x.side() == side)
+            .map(|x| x.source().to_owned())
+            .collect())
+    }
+
+    /// Get the sources of the libs that are used as they are, without the API libs
+    pub fn full_libs(&self) -> Vec<String> {
+        self.libs
+            .iter()
+            .filter(|x| x.api_of().is_none())
+            .map(|x| x.source().to_owned())
+            .collect()
+    }
+
+    /// Get the libs that only the API is extracted from
+    pub fn api_libs(&self) -> Vec<&ApiLib> {
+        self.libs.iter().filter_map(LibSpec::api_of).collect()
+    }
+
+    /// Get the sources of all mods, including sided mods and mods in mod sets
+    pub fn all_mods(&self) -> Vec<String> {
+        let mut mods = Vec::new();
+        for spec in self.mods.iter().chain(self.mod_sets.values().flatten()) {
+            let source = spec.source().to_owned();
+            if !mods.contains(&source) {
+                mods.push(source);
+            }
+        }
+        mods
+    }
+
+    /// The languages of the sources other than Java
+    pub fn other_languages(&self) -> Vec<Language> {
+        let mut languages = Vec::new();
+        for language in &self.languages {
+            if *language != Language::Java && !languages.contains(language) {
+                languages.push(*language);
+            }
+        }
+        languages
+    }
+
+    /// Get the routes of the copy paths. Copy paths into a Java source directory of the
+    /// template are split into a route for each language in `languages`
+    pub fn copy_routes(&self) -> Vec<CopyRoute<'_>> {
+        let languages = self.other_languages();
+        let handler = self.template.new_handler();
+        let java_dirs = [SourceSet::Main, SourceSet::Test]
+            .map(|set| (set, handler.source_set_dir(set, Language::Java)));
+        let mut routes = Vec::new();
+        for (layer, copy_path) in self.copy_paths.iter().enumerate() {
+            let target = copy_path.target().trim_end_matches('/');
+            let routed = java_dirs.iter().find_map(|(set, dir)| {
+                let rest = target.strip_prefix(dir.as_str())?;
+                (rest.is_empty() || rest.starts_with('/')).then_some((*set, rest))
+            });
+            let (set, rest) = match routed {
+                Some(x) if !languages.is_empty() && !copy_path.is_delete() => x,
+                _ => {
+                    routes.push(CopyRoute {
+                        layer,
+                        copy_path,
+                        target: target.to_owned(),
+                        filter: LanguageFilter::default(),
+                    });
+                    continue;
                 }
+            };
+            routes.push(CopyRoute {
+                layer,
+                copy_path,
+                target: target.to_owned(),
+                filter: LanguageFilter {
+                    only: None,
+                    skip: languages.clone(),
+                },
+            });
+            for language in &languages {
+                routes.push(CopyRoute {
+                    layer,
+                    copy_path,
+                    target: format!("{}{rest}", handler.source_set_dir(set, *language)),
+                    filter: LanguageFilter {
+                        only: Some(*language),
+                        skip: Vec::new(),
+                    },
+                });
             }
         }
+        routes
+    }
+
+    /// Collect the files to copy from copy_paths, mapping target paths to source paths
+    pub async fn collect_copy_edges(
+        &self,
+        project: &Project,
+    ) -> IoResult<BTreeMap<PathBuf, PathBuf>> {
+        Ok(self.collect_copy_plan(project, Path::new("")).await?.edges)
+    }
 
+    /// Collect the files to copy into the directory of the target (relative to the target root)
+    /// and everything under it, and the source directories copied into each target directory
+    pub async fn collect_copy_plan(&self, project: &Project, subtree: &Path) -> IoResult<CopyPlan> {
+        let target_root = project.target_root();
+        let mut join_set = JoinSet::new();
         let exclude: Arc<[String]> = Arc::from(self.copy_exclude.as_slice());
+        let walk = Arc::new(CopyWalk::default());
 
-        for copy_path in &self.copy_paths {
-            let (source, target) = match copy_path {
-                CopySpec::Simple(s) => (s, s),
-                CopySpec::SourceTarget(s, t) => (s, t),
+        for route in self.copy_routes() {
+            let copy_path = route.copy_path;
+            if !copy_path.applies_to(project, &self.template) {
+                continue;
+            }
+            let source = match copy_path.copy_source(project) {
+                Some(x) => x,
+                None => continue,
             };
-            if source == "null" {
+            // generators that haven't run yet have nothing to copy
+            if !source.exists() && copy_path.generator().is_some() {
                 continue;
             }
-            let source = root.join(source);
             if !source.exists() {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -196,25 +1385,196 @@ impl Mcmod {
                     ),
                 ))?;
             }
+            let route_target = Path::new(&route.target)
+                .components()
+                .filter(|x| !matches!(x, Component::CurDir))
+                .collect::<PathBuf>();
+            let route_target = route_target.as_path();
+            let (source, target) = if let Ok(rest) = subtree.strip_prefix(route_target) {
+                // only the part of the copy path in the subtree
+                let source = if rest.as_os_str().is_empty() {
+                    source
+                } else {
+                    source.join(rest)
+                };
+                if !source.exists() {
+                    continue;
+                }
+                (source, target_root.join(subtree))
+            } else if route_target.starts_with(subtree) {
+                (source, target_root.join(route_target))
+            } else {
+                continue;
+            };
             let source = Arc::new(source);
-            let target = Arc::new(target_root.join(target));
+            let target = Arc::new(target);
+            let layer = route.layer;
+            let filter = Arc::new(route.filter);
             let exclude = Arc::clone(&exclude);
-            let cp = cp.clone();
+            let walk = Arc::clone(&walk);
             join_set.spawn(async move {
-                add_copy_edge(source, target, cp, PathBuf::new(), exclude).await
+                add_copy_edge(source, target, layer, filter, walk, PathBuf::new(), exclude).await
             });
         }
         join_join_set!(join_set).await?;
 
-        Ok(ninja.to_string())
+        let (mut edges, mut dirs) = match (walk.edges.lock(), walk.dirs.lock()) {
+            (Ok(edges), Ok(dirs)) => (edges, dirs),
+            _ => Err(io::Error::other("Failed to collect copy edges"))?,
+        };
+        let mut result = BTreeMap::new();
+        for (target, mut sources) in std::mem::take(&mut *edges) {
+            sources.sort();
+            let mut sources = sources.into_iter();
+            let (_, mut source) = match sources.next() {
+                Some(x) => x,
+                None => continue,
+            };
+            for (layer, later) in sources {
+                let relative = target.strip_prefix(&target_root).unwrap_or(&target);
+                let overwrite = self.copy_paths[layer].overwrite();
+                check_copy_conflict(project, relative, &source, &later, overwrite)?;
+                source = later;
+            }
+            result.insert(target, source);
+        }
+        Ok(CopyPlan {
+            edges: result,
+            dirs: std::mem::take(&mut *dirs),
+        })
+    }
+}
+
+/// What is copied into a directory of the target and everything under it
+#[derive(Debug, Default)]
+pub struct CopyPlan {
+    /// Target path to source path of the files
+    pub edges: BTreeMap<PathBuf, PathBuf>,
+    /// Target directory to the source directories copied into it
+    pub dirs: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+/// Hand-written fields to merge into the generated mcmod.info, in the project root
+pub const MCMOD_INFO_EXTRA: &str = "mcmod.info.extra.json";
+
+/// Read mcmod.info.extra.json if it exists
+pub async fn read_mcmod_info_extra(project: &Project) -> IoResult<Option<Value>> {
+    let path = project.root.join(MCMOD_INFO_EXTRA);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).await?;
+    match serde_json::from_str(&content) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot parse {MCMOD_INFO_EXTRA}: {e}"),
+        ))?,
+    }
+}
+
+/// If the file should be copied through the preprocessor when it's enabled
+pub fn is_preprocessed(path: &Path) -> bool {
+    Language::of(path).is_some()
+}
+
+/// Directories in the target root that are managed by the tool or gradle, which copy paths can't change.
+/// The state files starting with `.mcmod-` and the files and directories starting with `mcmod-`
+/// (like `mcmod-logs`, `mcmod-run.gradle` and the gradle scripts of the templates) are also managed
+const MANAGED_TARGET_DIRS: &[&str] = &[
+    ".git",
+    ".gradle",
+    "mods-cache",
+    "api-cache",
+    "srg-cache",
+    "pack-mods",
+    "extends",
+    "env-import",
+    "decompiled",
+    "generated",
+    "libs",
+];
+
+/// Check that the target of a copy path is inside the target root and not managed by the tool
+fn validate_copy_target(target: &str) -> IoResult<()> {
+    let mut first = None;
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(x) => {
+                first.get_or_insert(x.to_string_lossy());
+            }
+            Component::CurDir => {}
+            _ => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Target '{target}' in `copy-paths` must be a path inside the target directory, without `..`"),
+            )))?,
+        }
+    }
+    let first = match first {
+        Some(x) => x,
+        None => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Target '{target}' in `copy-paths` is the whole target directory. Use a path inside it"),
+        )))?,
+    };
+    if MANAGED_TARGET_DIRS.contains(&first.as_ref())
+        || first.starts_with(".mcmod-")
+        || first.starts_with("mcmod-")
+    {
+        Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Target '{target}' in `copy-paths` is in '{first}', which is managed by mcmod or gradle"),
+        )))?;
+    }
+    Ok(())
+}
+
+/// Check that a file copied by an earlier copy path can be replaced by the file from a later one.
+/// The target is relative to the target root
+pub fn check_copy_conflict(
+    project: &Project,
+    target: &Path,
+    earlier: &Path,
+    later: &Path,
+    overwrite: bool,
+) -> IoResult<()> {
+    if overwrite || earlier == later {
+        return Ok(());
     }
+    let relative = |path: &Path| {
+        path.strip_prefix(&project.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    Err(ErrorCode::InvalidConfig.error(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "'{}' in the target is copied from both '{}' and '{}'. Remove one of them, or set `overwrite: true` on the later copy path in mcmod.yaml to replace the file",
+            target.display(),
+            relative(earlier),
+            relative(later)
+        ),
+    )))
+}
+
+/// What the copy paths are collected into while walking them
+#[derive(Default)]
+struct CopyWalk {
+    /// Files to copy, mapping target path to the layers (index in copy_paths) and source paths
+    /// copied to it. Conflicts are checked after all layers are collected
+    edges: Mutex<BTreeMap<PathBuf, Vec<(usize, PathBuf)>>>,
+    /// Target directory to the source directories copied into it
+    dirs: Mutex<BTreeMap<PathBuf, BTreeSet<PathBuf>>>,
 }
 
 #[async_recursion]
 async fn add_copy_edge(
     source_root: Arc<PathBuf>,
     target_root: Arc<PathBuf>,
-    cp: RuleRef,
+    layer: usize,
+    filter: Arc<LanguageFilter>,
+    walk: Arc<CopyWalk>,
     path: PathBuf,
     exclude: Arc<[String]>,
 ) -> IoResult<()> {
@@ -228,7 +1588,15 @@ async fn add_copy_edge(
     }
 
     if source_path.is_dir() {
-        if !target_path.exists() {
+        match walk.dirs.lock() {
+            Ok(mut dirs) => {
+                let sources = dirs.entry(target_path.clone()).or_default();
+                sources.insert(source_path.clone());
+            }
+            Err(_) => Err(io::Error::other("Failed to collect copy edges"))?,
+        }
+        // routes of other languages only create the directories they copy files into
+        if !target_path.exists() && filter.only.is_none() {
             fs::create_dir_all(&target_path).await?;
         }
         let mut join_set = JoinSet::new();
@@ -237,17 +1605,91 @@ async fn add_copy_edge(
             let path = path.join(entry.file_name());
             let source_root = Arc::clone(&source_root);
             let target_root = Arc::clone(&target_root);
+            let filter = Arc::clone(&filter);
+            let walk = Arc::clone(&walk);
             let exclude = Arc::clone(&exclude);
-            let cp = cp.clone();
             join_set.spawn(async move {
-                add_copy_edge(source_root, target_root, cp, path, exclude).await
+                add_copy_edge(source_root, target_root, layer, filter, walk, path, exclude).await
             });
         }
         join_join_set!(join_set).await?;
-    } else {
-        cp.build([escape_build(&target_path.display().to_string())])
-            .with([escape_build(&source_path.display().to_string())]);
+    } else if filter.copies(&source_path) {
+        let mut edges = match walk.edges.lock() {
+            Ok(x) => x,
+            Err(_) => Err(io::Error::other("Failed to collect copy edges"))?,
+        };
+        edges
+            .entry(target_path)
+            .or_default()
+            .push((layer, source_path));
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Mcmod {
+        let base = "template: ntmc-1.7.10\nname: Example Mod\nmodid: mymodid\ndescription: ''\nversion: 1.0.0\n";
+        serde_yaml::from_str(&format!("{base}{yaml}")).unwrap()
+    }
+
+    #[test]
+    fn test_validate_child_mods() {
+        let mcmod = parse(
+            "tags-class: com.example.Tags\nchild-mods:\n- modid: mymodid-compat\n  name: Compat\n",
+        );
+        assert!(mcmod.validate_child_mods().is_ok());
+        let tags = mcmod.create_tags_class("1.0.0", "abc");
+        assert!(tags.contains("MYMODID_COMPAT_MODID = \"mymodid-compat\""));
+        assert!(tags.contains("MYMODID_COMPAT_VERSION = \"1.0.0\""));
+    }
+
+    #[test]
+    fn test_validate_child_mods_duplicate() {
+        let mcmod = parse("child-mods:\n- modid: mymodid\n  name: Compat\n");
+        assert!(mcmod.validate_child_mods().is_err());
+    }
+
+    #[test]
+    fn test_validate_child_mods_constant_collision() {
+        let mcmod = parse("tags-class: com.example.Tags\nchild-mods:\n- modid: a-b\n  name: A\n- modid: a_b\n  name: B\n");
+        assert!(mcmod.validate_child_mods().is_err());
+        // ARTIFACT_VERSION is a constant of the main mod
+        let mcmod =
+            parse("tags-class: com.example.Tags\nchild-mods:\n- modid: artifact\n  name: A\n");
+        assert!(mcmod.validate_child_mods().is_err());
+        // the constants are only generated with a tags class
+        let mcmod = parse("child-mods:\n- modid: a-b\n  name: A\n- modid: a_b\n  name: B\n");
+        assert!(mcmod.validate_child_mods().is_ok());
+    }
+
+    #[test]
+    fn test_validate_copy_target() {
+        assert!(validate_copy_target("src/main/java").is_ok());
+        assert!(validate_copy_target("./src/main/resources/assets").is_ok());
+        assert!(validate_copy_target("mcmod.txt").is_ok());
+        // outside the target
+        assert!(validate_copy_target("../src").is_err());
+        assert!(validate_copy_target("src/../../x").is_err());
+        assert!(validate_copy_target("/src").is_err());
+        // the whole target
+        assert!(validate_copy_target("").is_err());
+        assert!(validate_copy_target(".").is_err());
+        // managed by mcmod or gradle
+        assert!(validate_copy_target("libs/a.jar").is_err());
+        assert!(validate_copy_target(".gradle").is_err());
+        assert!(validate_copy_target(".mcmod-ninja/x").is_err());
+        assert!(validate_copy_target("mcmod-logs").is_err());
+        let error = validate_copy_target("../src").unwrap_err();
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::InvalidConfig));
+    }
+
+    #[test]
+    fn test_validate_child_mods_leading_digit() {
+        let mcmod = parse("tags-class: com.example.Tags\nchild-mods:\n- modid: 1mod\n  name: A\n");
+        assert!(mcmod.validate_child_mods().is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Running in the environment of a mod pack, with `mcmod run --pack <name>`
+//!
+//! A pack is defined in `packs/<name>.yaml` in the project, with the mods to add to the mods
+//! in mcmod.yaml. The files in `packs/<name>/`, like `config/` and `options.txt`, are copied
+//! into the run directory on every run. Each pack runs in its own target/run-pack-<name>,
+//! so it doesn't change the main run directory
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::mcmod::{ModSide, ModSpec};
+use crate::run::Side;
+use crate::sync::{self, Downloader, Prune, MODS_CDN_URL};
+use crate::template::TemplateHandler;
+use crate::util::{cd, mkdir, IoResult, Project};
+
+/// A pack in `packs/<name>.yaml`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModPack {
+    /// Mods in the pack, like `mods` in mcmod.yaml. Can also be `modrinth:<project>/<version>/<file>`
+    /// or `curseforge:<file id>/<file>`
+    #[serde(default)]
+    pub mods: Vec<ModSpec>,
+}
+
+impl ModPack {
+    pub fn path(project: &Project, name: &str) -> PathBuf {
+        cd!(project.root.clone(), "packs", format!("{name}.yaml"))
+    }
+
+    pub async fn load(project: &Project, name: &str) -> IoResult<Self> {
+        let path = Self::path(project, name);
+        if !path.exists() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Pack '{name}' is not defined. Create '{}' with the mods of the pack",
+                    path.display()
+                ),
+            ))?;
+        }
+        let content = fs::read_to_string(&path).await?;
+        match serde_yaml::from_str(&content) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cannot parse '{}': {e}", path.display()),
+            ))?,
+        }
+    }
+}
+
+/// Get the url to download a mod in a pack from.
+/// `modrinth:` and `curseforge:` refs are resolved to their CDNs, the others are used as they are
+pub fn resolve_ref(source: &str) -> IoResult<String> {
+    let invalid = |format: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid mod '{source}' in the pack. Use the form `{format}`"),
+        )
+    };
+    if let Some(rest) = source.strip_prefix("modrinth:") {
+        let parts = rest.split('/').collect::<Vec<_>>();
+        return match parts.as_slice() {
+            [project, version, file] => Ok(format!(
+                "https://cdn.modrinth.com/data/{project}/versions/{version}/{file}"
+            )),
+            _ => Err(invalid("modrinth:<project id>/<version id>/<file name>"))?,
+        };
+    }
+    if let Some(rest) = source.strip_prefix("curseforge:") {
+        let id = rest
+            .split_once('/')
+            .and_then(|(id, file)| Some((id.parse::<u64>().ok()?, file)));
+        return match id {
+            Some((id, file)) => Ok(format!(
+                "https://mediafilez.forgecdn.net/files/{}/{}/{file}",
+                id / 1000,
+                id % 1000
+            )),
+            None => Err(invalid("curseforge:<file id>/<file name>"))?,
+        };
+    }
+    Ok(source.to_owned())
+}
+
+/// Directory the mods of the pack are downloaded to
+fn cache_dir(project: &Project, name: &str) -> PathBuf {
+    cd!(project.target_root(), "pack-mods", name)
+}
+
+/// Download the mods of the pack, put them into the mods directory of the pack's run directory,
+/// and copy the files of the pack into the run directory.
+/// The project must have the pack set, so the run directory is the pack's
+pub async fn setup(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    name: &str,
+    side: Option<&Side>,
+) -> IoResult<()> {
+    let side = side.map(|x| match x {
+        Side::Client => ModSide::ClientOnly,
+        Side::Server => ModSide::ServerOnly,
+    });
+    let pack = ModPack::load(project, name).await?;
+    let mut mods = Vec::new();
+    for spec in &pack.mods {
+        if spec.side().is_none() || spec.side() == side {
+            mods.push(resolve_ref(spec.source())?);
+        }
+    }
+    let cache_dir = cache_dir(project, name);
+    let before = file_names(&cache_dir).await?;
+    println!("syncing the mods of pack '{name}'");
+    let mcmod = project.mcmod().await?;
+    let prune = Prune {
+        enabled: true,
+        keep: &[],
+        generated: &[],
+    };
+    sync::sync_downloads(
+        &cache_dir,
+        &mods,
+        &project.root,
+        MODS_CDN_URL,
+        &Downloader::new(&mcmod.downloads)?,
+        &prune,
+        false,
+    )
+    .await?;
+    let after = file_names(&cache_dir).await?;
+
+    let run_dir = handler.run_dir(project)?;
+    let mods_root = cd!(run_dir.clone(), "mods");
+    mkdir!(&mods_root).await?;
+    for removed in before.difference(&after) {
+        let path = mods_root.join(removed);
+        if path.is_file() {
+            fs::remove_file(path).await?;
+        }
+    }
+    for file in &after {
+        let path = mods_root.join(file);
+        let source = cache_dir.join(file);
+        if !path.exists() && fs::hard_link(&source, &path).await.is_err() {
+            fs::copy(&source, &path).await?;
+        }
+    }
+
+    let files_dir = cd!(project.root.clone(), "packs", name);
+    if files_dir.is_dir() {
+        let mut count = 0;
+        for entry in WalkDir::new(&files_dir) {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(e) => Err(io::Error::other(e))?,
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(&files_dir)
+                .unwrap_or(entry.path());
+            let target = run_dir.join(relative);
+            if let Some(parent) = target.parent() {
+                mkdir!(parent).await?;
+            }
+            fs::copy(entry.path(), &target).await?;
+            count += 1;
+        }
+        println!(
+            "copied {count} file(s) of pack '{name}' into '{}'",
+            run_dir.display()
+        );
+    }
+    Ok(())
+}
+
+async fn file_names(dir: &std::path::Path) -> IoResult<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        names.insert(entry.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
@@ -0,0 +1,393 @@
+//! Printing and comparing NBT files, like structures and level.dat
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use tokio::fs;
+
+use crate::util::IoResult;
+
+/// Arrays longer than this are summarized when printing
+const ARRAY_PRINT_LIMIT: usize = 32;
+
+#[derive(Debug, Parser)]
+pub struct NbtCommand {
+    #[clap(subcommand)]
+    pub command: NbtSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NbtSubcommand {
+    /// Print an NBT file as a tree
+    Print(PrintCommand),
+    /// Compare two NBT files, printing the values that are added, removed or changed
+    Diff(DiffCommand),
+}
+
+#[derive(Debug, Parser)]
+pub struct PrintCommand {
+    /// The NBT file, compressed or not
+    pub file: PathBuf,
+
+    /// Print arrays in full instead of summarizing long ones
+    #[arg(long)]
+    pub full: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffCommand {
+    /// The old NBT file
+    pub old: PathBuf,
+    /// The new NBT file
+    pub new: PathBuf,
+}
+
+impl NbtCommand {
+    pub async fn run(self) -> IoResult<()> {
+        match self.command {
+            NbtSubcommand::Print(print) => print.run().await,
+            NbtSubcommand::Diff(diff) => diff.run().await,
+        }
+    }
+}
+
+impl PrintCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let (name, tag) = read_file(&self.file).await?;
+        let mut output = String::new();
+        tag.print(&quote_name(&name), 0, self.full, &mut output);
+        print!("{output}");
+        Ok(())
+    }
+}
+
+impl DiffCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let (old, new) = tokio::join!(read_file(&self.old), read_file(&self.new));
+        let (old, new) = (old?, new?);
+        let mut old_values = Vec::new();
+        old.1.flatten("", &mut old_values);
+        let mut new_values = Vec::new();
+        new.1.flatten("", &mut new_values);
+        let new_map = new_values
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<HashMap<_, _>>();
+        let old_map = old_values
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let (mut added, mut removed, mut changed) = (0, 0, 0);
+        for (path, value) in &old_values {
+            match new_map.get(path.as_str()) {
+                None => {
+                    println!("- {path}: {value}");
+                    removed += 1;
+                }
+                Some(new_value) if new_value != value => {
+                    println!("~ {path}: {value} -> {new_value}");
+                    changed += 1;
+                }
+                _ => {}
+            }
+        }
+        for (path, value) in &new_values {
+            if !old_map.contains_key(path.as_str()) {
+                println!("+ {path}: {value}");
+                added += 1;
+            }
+        }
+        if added + removed + changed == 0 {
+            println!("no differences");
+        } else {
+            println!("{added} added, {removed} removed, {changed} changed");
+        }
+        Ok(())
+    }
+}
+
+/// A named binary tag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    /// Entries in the order of the file
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+/// Read an NBT file, which can be gzip compressed (like level.dat and structures),
+/// zlib compressed or uncompressed. Returns the name and the root tag
+pub async fn read_file(path: &Path) -> IoResult<(String, Tag)> {
+    let data = fs::read(path).await?;
+    let result = decompress(&data).and_then(|data| read_root(&data));
+    match result {
+        Ok(x) => Ok(x),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot read '{}' as NBT: {e}", path.display()),
+        ))?,
+    }
+}
+
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match data {
+        [0x1f, 0x8b, ..] => {
+            GzDecoder::new(data).read_to_end(&mut output)?;
+        }
+        [0x78, ..] => {
+            ZlibDecoder::new(data).read_to_end(&mut output)?;
+        }
+        _ => output.extend_from_slice(data),
+    }
+    Ok(output)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Read the root tag, which is a named compound
+pub fn read_root(data: &[u8]) -> io::Result<(String, Tag)> {
+    let mut reader = Reader { data, pos: 0 };
+    let id = reader.u8()?;
+    if id != 10 {
+        return Err(invalid(format!(
+            "The root tag should be a compound, not {id}"
+        )));
+    }
+    let name = reader.string()?;
+    let tag = reader.payload(id, 0)?;
+    Ok((name, tag))
+}
+
+/// Big-endian reader of the NBT payloads
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid("Unexpected end of NBT data"))?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = i32::from_be_bytes(self.take()?);
+        // each element is at least 1 byte, so this catches corrupted lengths early
+        if len < 0 || len as usize > self.data.len() - self.pos {
+            return Err(invalid(format!("Invalid NBT length {len}")));
+        }
+        Ok(len as usize)
+    }
+
+    /// A modified UTF-8 string. Non-ASCII characters outside of the BMP are rare in
+    /// NBT, so it's read as UTF-8 and invalid sequences are replaced
+    fn string(&mut self) -> io::Result<String> {
+        let len = u16::from_be_bytes(self.take()?) as usize;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("Unexpected end of NBT data"))?;
+        self.pos += len;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> io::Result<Tag> {
+        // the game limits nesting to 512
+        if depth > 512 {
+            return Err(invalid("NBT is nested too deeply"));
+        }
+        let tag = match id {
+            1 => Tag::Byte(i8::from_be_bytes(self.take()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.take()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.take()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.take()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.take()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.take()?)),
+            7 => {
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(i8::from_be_bytes(self.take()?));
+                }
+                Tag::ByteArray(values)
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element_id = self.u8()?;
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(self.payload(element_id, depth + 1)?);
+                }
+                Tag::List(values)
+            }
+            10 => {
+                let mut entries = Vec::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            11 => {
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(i32::from_be_bytes(self.take()?));
+                }
+                Tag::IntArray(values)
+            }
+            12 => {
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(i64::from_be_bytes(self.take()?));
+                }
+                Tag::LongArray(values)
+            }
+            _ => return Err(invalid(format!("Unknown NBT tag type {id}"))),
+        };
+        Ok(tag)
+    }
+}
+
+impl Tag {
+    /// Print the tag with the label, indented by depth. Compounds and lists of
+    /// compounds or lists have their entries on separate lines
+    fn print(&self, label: &str, depth: usize, full: bool, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Tag::Compound(entries) if !entries.is_empty() => {
+                output.push_str(&format!("{indent}{label}: {{\n"));
+                for (key, value) in entries {
+                    value.print(&quote_name(key), depth + 1, full, output);
+                }
+                output.push_str(&format!("{indent}}}\n"));
+            }
+            Tag::List(values)
+                if values
+                    .iter()
+                    .any(|x| matches!(x, Tag::List(_) | Tag::Compound(_))) =>
+            {
+                output.push_str(&format!("{indent}{label}: [\n"));
+                for (i, value) in values.iter().enumerate() {
+                    value.print(&format!("[{i}]"), depth + 1, full, output);
+                }
+                output.push_str(&format!("{indent}]\n"));
+            }
+            _ => {
+                output.push_str(&format!("{indent}{label}: {}\n", self.format_value(full)));
+            }
+        }
+    }
+
+    /// Add the values with their paths, like `blocks[3].pos[0]`. Empty compounds
+    /// and lists are added as values so they show up in diffs
+    fn flatten(&self, path: &str, output: &mut Vec<(String, String)>) {
+        match self {
+            Tag::Compound(entries) if !entries.is_empty() => {
+                for (key, value) in entries {
+                    let key = quote_name(key);
+                    let child = if path.is_empty() {
+                        key
+                    } else {
+                        format!("{path}.{key}")
+                    };
+                    value.flatten(&child, output);
+                }
+            }
+            Tag::List(values) if !values.is_empty() => {
+                for (i, value) in values.iter().enumerate() {
+                    value.flatten(&format!("{path}[{i}]"), output);
+                }
+            }
+            _ => output.push((path.to_owned(), self.format_value(true))),
+        }
+    }
+
+    /// Format a value like SNBT, with the type suffixes on numbers
+    fn format_value(&self, full: bool) -> String {
+        match self {
+            Tag::Byte(x) => format!("{x}b"),
+            Tag::Short(x) => format!("{x}s"),
+            Tag::Int(x) => format!("{x}"),
+            Tag::Long(x) => format!("{x}L"),
+            Tag::Float(x) => format!("{x:?}f"),
+            Tag::Double(x) => format!("{x:?}d"),
+            Tag::String(x) => format!("{x:?}"),
+            Tag::ByteArray(x) => format_array("B", x, full),
+            Tag::IntArray(x) => format_array("I", x, full),
+            Tag::LongArray(x) => format_array("L", x, full),
+            // lists of compounds and lists are printed on separate lines
+            Tag::List(values) => {
+                let values = values
+                    .iter()
+                    .map(|x| x.format_value(full))
+                    .collect::<Vec<_>>();
+                format!("[{}]", values.join(", "))
+            }
+            Tag::Compound(_) => "{}".to_owned(),
+        }
+    }
+}
+
+fn format_array<T: ToString>(prefix: &str, values: &[T], full: bool) -> String {
+    if !full && values.len() > ARRAY_PRINT_LIMIT {
+        let shown = values[..ARRAY_PRINT_LIMIT]
+            .iter()
+            .map(T::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        return format!(
+            "[{prefix}; {shown}, ... ({} more)]",
+            values.len() - ARRAY_PRINT_LIMIT
+        );
+    }
+    let values = values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("[{prefix}; {values}]")
+}
+
+/// Quote names that are not plain identifiers, including the usually empty root name
+fn quote_name(name: &str) -> String {
+    let plain = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'));
+    if plain {
+        name.to_owned()
+    } else {
+        format!("{name:?}")
+    }
+}
@@ -0,0 +1,353 @@
+//! Generating the ninja files for copying sources and assets
+//!
+//! The build.ninja in the project root only has the rules, and a `subninja` for each
+//! directory in the target. Each subninja has the copy edges for the files directly
+//! in that directory. build.ninja depends on the source directories, so when files are
+//! added, removed or renamed, ninja runs `mcmod regen-ninja` first, which only walks the
+//! directories that changed and regenerates the subninjas under them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::Parser;
+use ninja_writer::*;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::mcmod::{is_preprocessed, CopyPlan, Mcmod};
+use crate::preprocess;
+use crate::state::{read_json, write_json};
+use crate::util::{mkdir, write_file, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct RegenNinjaCommand {}
+
+impl RegenNinjaCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let build_ninja = project.build_ninja();
+        let generated = match fs::metadata(&build_ninja).await {
+            Ok(x) => x.modified()?,
+            Err(_) => return write_ninja_files(&project).await,
+        };
+        let mcmod_modified = fs::metadata(project.root.join("mcmod.yaml"))
+            .await?
+            .modified()?;
+        match read_json::<NinjaState>(&state_path(&project)).await {
+            Some(state) if mcmod_modified <= generated => {
+                regen_ninja_files(&project, state, generated).await
+            }
+            _ => write_ninja_files(&project).await,
+        }
+    }
+}
+
+/// The directories in the target, relative to the target root
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NinjaState {
+    /// Target directory to the source directories copied into it
+    dirs: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    /// Target directories with files to copy, which have a subninja
+    subninjas: BTreeSet<PathBuf>,
+}
+
+impl NinjaState {
+    /// Add the directories of the plan, and write the subninjas of the directories with files
+    async fn add_plan(&mut self, project: &Project, mcmod: &Mcmod, plan: CopyPlan) -> IoResult<()> {
+        let root = project.root.as_path();
+        let target_root = project.target_root();
+        let relative = |path: &Path| {
+            let relative = path.strip_prefix(&target_root).unwrap_or(path);
+            relative
+                .components()
+                .filter(|x| !matches!(x, Component::CurDir))
+                .collect::<PathBuf>()
+        };
+        for (dir, sources) in plan.dirs {
+            self.dirs.insert(relative(&dir), sources);
+        }
+        let mut subninjas = BTreeMap::<PathBuf, String>::new();
+        for (target, source) in plan.edges {
+            let dir = target.parent().map(relative).unwrap_or_default();
+            let rule = if mcmod.preprocess && is_preprocessed(&source) {
+                "preprocess"
+            } else {
+                "cp"
+            };
+            let target = ninja_path(root, &target)?;
+            let source = ninja_path(root, &source)?;
+            subninjas
+                .entry(dir)
+                .or_default()
+                .push_str(&edge_line(&target, rule, &source));
+        }
+        for (dir, content) in subninjas {
+            write_if_changed(&subninja_path(project, &dir), &content).await?;
+            self.subninjas.insert(dir);
+        }
+        Ok(())
+    }
+
+    /// Remove the directory and everything under it
+    async fn remove_subtree(&mut self, project: &Project, subtree: &Path) -> IoResult<()> {
+        self.dirs.retain(|dir, _| !dir.starts_with(subtree));
+        let removed = self
+            .subninjas
+            .iter()
+            .filter(|dir| dir.starts_with(subtree))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dir in removed {
+            let path = subninja_path(project, &dir);
+            if path.exists() {
+                fs::remove_file(path).await?;
+            }
+            self.subninjas.remove(&dir);
+        }
+        Ok(())
+    }
+
+    /// Write build.ninja and the state. build.ninja is touched if the content is the same,
+    /// since it has to be newer than the source directories it depends on, or ninja keeps
+    /// regenerating it
+    async fn write_build_ninja(&self, project: &Project, mcmod: &Mcmod) -> IoResult<()> {
+        write_json(&state_path(project), self).await?;
+        let build_ninja = project.build_ninja();
+        let content = create_build_ninja_content(project, mcmod, self)?;
+        if !write_if_changed(&build_ninja, &content).await? {
+            touch(&build_ninja)?;
+        }
+        Ok(())
+    }
+}
+
+fn subninja_root(project: &Project) -> PathBuf {
+    project.target_root().join(".mcmod-ninja")
+}
+
+fn state_path(project: &Project) -> PathBuf {
+    subninja_root(project).join("dirs.json")
+}
+
+/// Path of the subninja for a target directory
+fn subninja_path(project: &Project, dir: &Path) -> PathBuf {
+    let hash = blake3::hash(dir.to_string_lossy().as_bytes()).to_hex();
+    subninja_root(project).join(format!("{}.ninja", &hash[..16]))
+}
+
+/// Generate build.ninja and all subninjas, applying the deletes in copy_paths
+pub async fn create_build_ninja(project: &Project) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let target_root = project.target_root();
+    for copy_path in &mcmod.copy_paths {
+        if !copy_path.applies_to(project, &mcmod.template) {
+            continue;
+        }
+        if copy_path.is_delete() {
+            let target = target_root.join(copy_path.target());
+            if target.exists() {
+                if target.is_dir() {
+                    fs::remove_dir_all(&target).await?;
+                } else {
+                    fs::remove_file(&target).await?;
+                }
+            }
+        }
+    }
+    write_ninja_files(project).await
+}
+
+/// Write build.ninja and the subninjas of all directories.
+///
+/// Only files with changed content are written, and subninjas of directories that
+/// no longer have files to copy are removed
+async fn write_ninja_files(project: &Project) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let subninja_root = subninja_root(project);
+    mkdir!(&subninja_root).await?;
+    let plan = mcmod.collect_copy_plan(project, Path::new("")).await?;
+    let mut state = NinjaState::default();
+    state.add_plan(project, mcmod, plan).await?;
+
+    let paths = state
+        .subninjas
+        .iter()
+        .map(|dir| subninja_path(project, dir))
+        .collect::<BTreeSet<_>>();
+    let mut entries = fs::read_dir(&subninja_root).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|x| x == "ninja") && !paths.contains(&path) {
+            fs::remove_file(path).await?;
+        }
+    }
+    state.write_build_ninja(project, mcmod).await
+}
+
+/// Regenerate the subtrees of the source directories that changed since build.ninja
+/// was generated. build.ninja is only rewritten if directories were added or removed
+async fn regen_ninja_files(
+    project: &Project,
+    mut state: NinjaState,
+    generated: SystemTime,
+) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let mut changed = Vec::<PathBuf>::new();
+    for (dir, sources) in &state.dirs {
+        // sub directories of a changed directory are regenerated with it
+        if changed.iter().any(|x| dir.starts_with(x)) {
+            continue;
+        }
+        for source in sources {
+            let modified = fs::metadata(source).await.and_then(|x| x.modified());
+            // a removed directory changes its parent, which is regenerated
+            if modified.is_ok_and(|x| x > generated) {
+                changed.push(dir.clone());
+                break;
+            }
+        }
+    }
+    for dir in changed {
+        state.remove_subtree(project, &dir).await?;
+        let plan = mcmod.collect_copy_plan(project, &dir).await?;
+        state.add_plan(project, mcmod, plan).await?;
+    }
+    state.write_build_ninja(project, mcmod).await
+}
+
+/// Set the modified time of the file to now
+fn touch(path: &Path) -> IoResult<()> {
+    let file = std::fs::File::options().write(true).open(path)?;
+    file.set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+/// Write the file if the content is different. Returns if it was written
+async fn write_if_changed(path: &Path, content: &str) -> IoResult<bool> {
+    if fs::read_to_string(path).await.ok().as_deref() == Some(content) {
+        return Ok(false);
+    }
+    write_file!(path, content).await?;
+    Ok(true)
+}
+
+/// The build statement copying the source to the target with the rule
+fn edge_line(target: &str, rule: &str, source: &str) -> String {
+    format!("build {target}: {rule} {source}\n")
+}
+
+/// Create the top-level build.ninja that includes the subninjas
+fn create_build_ninja_content(
+    project: &Project,
+    mcmod: &Mcmod,
+    state: &NinjaState,
+) -> IoResult<String> {
+    let root = project.root.as_path();
+    let ninja = Ninja::new();
+    ninja.comment("Incremental build file for copying source and assets");
+    ninja.comment("Please run `mcmod sync` to update this file when mcmod.yaml changes");
+    if project.target.is_some() {
+        // keep ninja's log files in the target root so multiple targets don't share them
+        ninja.variable("builddir", ninja_path(root, &project.target_root())?);
+    }
+
+    let cp = if cfg!(windows) {
+        Rule::new("cp", "coreutils cp $in $out")
+    } else {
+        Rule::new("cp", "cp $in $out")
+    };
+    cp.description("Copying $in").add_to(&ninja);
+    let exe = std::env::current_exe()?;
+    let exe = escape(&exe.display().to_string()).into_owned();
+    if mcmod.preprocess {
+        let mc_version = preprocess::mc_version_number(mcmod.template.new_handler().mc_version());
+        Rule::new(
+            "preprocess",
+            format!("\"{exe}\" preprocess --var MC={mc_version} $in $out"),
+        )
+        .description("Preprocessing $in")
+        .add_to(&ninja);
+    }
+
+    let mut regen_command = format!("\"{exe}\"");
+    if let Some(target) = &project.target {
+        regen_command.push_str(&format!(" --target \"{}\"", escape(target)));
+    }
+    if let Some(profile) = &project.profile {
+        regen_command.push_str(&format!(" --profile \"{}\"", escape(profile)));
+    }
+    regen_command.push_str(" regen-ninja");
+    let regen = Rule::new("regen", regen_command)
+        .description("Regenerating $out")
+        .generator()
+        .add_to(&ninja);
+    let mut inputs = vec!["mcmod.yaml".to_owned()];
+    // adding, removing or renaming files changes the modified time of their directory
+    let sources = state.dirs.values().flatten().collect::<BTreeSet<_>>();
+    for source in sources {
+        inputs.push(ninja_path(root, source)?);
+    }
+    regen
+        .build([ninja_path(root, &project.build_ninja())?])
+        .with_implicit(inputs);
+
+    for dir in &state.subninjas {
+        ninja.subninja(ninja_path(root, &subninja_path(project, dir))?);
+    }
+    Ok(ninja.to_string())
+}
+
+/// Get the path for build.ninja, relative to the project root where ninja runs.
+///
+/// Relative paths avoid the `:` in Windows drive letters, and `/` is used as separator
+/// on all platforms. Spaces, `:` and `$` are escaped
+fn ninja_path(root: &Path, path: &Path) -> IoResult<String> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component.as_os_str().to_str() {
+            Some(x) => parts.push(x),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Path '{}' is not valid UTF-8", path.display()),
+            ))?,
+        }
+    }
+    Ok(escape_build(&parts.join("/")).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(root: &str, path: &str) -> String {
+        ninja_path(Path::new(root), Path::new(path)).unwrap()
+    }
+
+    #[test]
+    fn test_ninja_path() {
+        assert_eq!(path("/p", "/p/src/A.java"), "src/A.java");
+        assert_eq!(path("/p", "/p/my mod/A B.java"), "my$ mod/A$ B.java");
+        assert_eq!(
+            path("/p", "/p/src/Outer$Inner.java"),
+            "src/Outer$$Inner.java"
+        );
+        assert_eq!(path("/p", "C:/mods/A.java"), "C$:/mods/A.java");
+        assert_eq!(
+            path("/p", "/p/assets/lang/日本語.lang"),
+            "assets/lang/日本語.lang"
+        );
+    }
+
+    #[test]
+    fn test_edge_line() {
+        let target = path("/p", "/p/target/src/main/java/My Mod$1.java");
+        let source = path("/p", "/p/src/My Mod$1.java");
+        assert_eq!(
+            edge_line(&target, "cp", &source),
+            "build target/src/main/java/My$ Mod$$1.java: cp src/My$ Mod$$1.java\n"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Building resource packs, for projects with `type: pack`
+//!
+//! The synced resources (pack.mcmeta and the assets) are zipped directly, without gradle
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::hash;
+use crate::util::{cd, mkdir, IoResult, Project};
+
+/// Zip the resources in the target into the output directory. Returns the path of the zip
+pub async fn build_pack(project: &Project, output_dir: &Path) -> IoResult<PathBuf> {
+    let mcmod = project.mcmod().await?;
+    let resources = cd!(project.target_root(), "src", "main", "resources");
+    if !resources.join("pack.mcmeta").exists() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "pack.mcmeta is not synced. Run `mcmod sync` first",
+        ))?;
+    }
+    mkdir!(output_dir).await?;
+    let output = output_dir.join(format!(
+        "{}-{}.zip",
+        mcmod.archives_base_name, mcmod.artifact_version
+    ));
+    let result = {
+        let output = output.clone();
+        tokio::task::spawn_blocking(move || write_zip(&resources, &output)).await
+    };
+    let count = match result {
+        Ok(x) => x?,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    println!("packed {count} file(s) into '{}'", output.display());
+    Ok(output)
+}
+
+/// Zip the files in the directory, with paths relative to it. Returns the number of files
+fn write_zip(dir: &Path, output: &Path) -> IoResult<usize> {
+    let mut writer = ZipWriter::new(std::fs::File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut count = 0;
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = match entry {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::other(e))?,
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = hash::relative_key(dir, entry.path());
+        writer.start_file(name, options).map_err(io::Error::other)?;
+        writer.write_all(&std::fs::read(entry.path())?)?;
+        count += 1;
+    }
+    writer.finish().map_err(io::Error::other)?;
+    Ok(count)
+}
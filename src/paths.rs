@@ -0,0 +1,141 @@
+//! Directories for the mutable state of the tool, so it can run from a read-only install
+//!
+//! - Config, like user templates: `$XDG_CONFIG_HOME/mcmod` (`~/.config/mcmod`) on Linux,
+//!   `%APPDATA%\mcmod` on Windows and `~/Library/Application Support/mcmod` on macOS
+//! - Cache, like downloaded tools: `$XDG_CACHE_HOME/mcmod` (`~/.cache/mcmod`) on Linux,
+//!   `%LOCALAPPDATA%\mcmod\cache` on Windows and `~/Library/Caches/mcmod` on macOS
+//!
+//! - Data that must not be cleared, like relocated run directories: `$XDG_DATA_HOME/mcmod`
+//!   (`~/.local/share/mcmod`) on Linux, `%LOCALAPPDATA%\mcmod\data` on Windows and
+//!   `~/Library/Application Support/mcmod/data` on macOS
+//!
+//! MCMOD_CONFIG_DIR, MCMOD_CACHE_DIR and MCMOD_DATA_DIR override the directories on all platforms
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::util::{self, cd, mkdir, IoResult};
+
+/// Get the directory for user config of the tool
+pub fn config_dir() -> IoResult<PathBuf> {
+    if let Some(x) = env_path("MCMOD_CONFIG_DIR") {
+        return Ok(x);
+    }
+    let base = if cfg!(windows) {
+        env_path("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|x| cd!(x, "Library", "Application Support"))
+    } else {
+        env_path("XDG_CONFIG_HOME").or_else(|| home_dir().map(|x| x.join(".config")))
+    };
+    match base {
+        Some(x) => Ok(x.join("mcmod")),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot find the config directory. Set MCMOD_CONFIG_DIR",
+        ))?,
+    }
+}
+
+/// Get the directory for caches of the tool, moving the cache from the
+/// old location in the tool's target directory if it exists
+pub async fn cache_dir() -> IoResult<PathBuf> {
+    let cache_dir = cache_dir_location()?;
+    migrate_cache(&cache_dir).await?;
+    Ok(cache_dir)
+}
+
+/// Get the directory for caches of the tool, for places that can't await.
+/// Use cache_dir for the files that older versions kept in the tool's target directory
+pub fn cache_dir_location() -> IoResult<PathBuf> {
+    if let Some(x) = env_path("MCMOD_CACHE_DIR") {
+        return Ok(x);
+    }
+    let base = if cfg!(windows) {
+        env_path("LOCALAPPDATA").map(|x| cd!(x, "mcmod", "cache"))
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|x| cd!(x, "Library", "Caches", "mcmod"))
+    } else {
+        env_path("XDG_CACHE_HOME")
+            .or_else(|| home_dir().map(|x| x.join(".cache")))
+            .map(|x| x.join("mcmod"))
+    };
+    match base {
+        Some(x) => Ok(x),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot find the cache directory. Set MCMOD_CACHE_DIR",
+        ))?,
+    }
+}
+
+/// Get the directory for data of the tool that is not a cache, like the saves and configs
+/// in relocated run directories
+pub fn data_dir() -> IoResult<PathBuf> {
+    if let Some(x) = env_path("MCMOD_DATA_DIR") {
+        return Ok(x);
+    }
+    let base = if cfg!(windows) {
+        env_path("LOCALAPPDATA").map(|x| cd!(x, "mcmod", "data"))
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|x| cd!(x, "Library", "Application Support", "mcmod", "data"))
+    } else {
+        env_path("XDG_DATA_HOME")
+            .or_else(|| home_dir().map(|x| cd!(x, ".local", "share")))
+            .map(|x| x.join("mcmod"))
+    };
+    match base {
+        Some(x) => Ok(x),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot find the data directory. Set MCMOD_DATA_DIR",
+        ))?,
+    }
+}
+
+/// Move the files in the cache of older versions, which was in the tool's target directory
+async fn migrate_cache(cache_dir: &Path) -> IoResult<()> {
+    let old_dir = match util::tool_root() {
+        Ok(x) => cd!(x, "target", "cache"),
+        Err(_) => return Ok(()),
+    };
+    if !old_dir.is_dir() || old_dir == cache_dir {
+        return Ok(());
+    }
+    mkdir!(cache_dir).await?;
+    let mut entries = fs::read_dir(&old_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let target = cache_dir.join(entry.file_name());
+        if target.exists() {
+            continue;
+        }
+        println!(
+            "moving '{}' to '{}'",
+            entry.path().display(),
+            target.display()
+        );
+        // the old location can be on another drive, or read-only
+        if fs::rename(entry.path(), &target).await.is_err() {
+            fs::copy(entry.path(), &target).await?;
+            let _ = fs::remove_file(entry.path()).await;
+        }
+    }
+    let _ = fs::remove_dir(&old_dir).await;
+    Ok(())
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    match std::env::var_os(name) {
+        Some(x) if !x.is_empty() => Some(PathBuf::from(x)),
+        _ => None,
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env_path("HOME").or_else(|| env_path("USERPROFILE"))
+}
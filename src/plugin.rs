@@ -0,0 +1,152 @@
+//! Running `mcmod-<name>` executables on PATH as `mcmod <name>`, like cargo does
+//!
+//! The plugin gets the project in environment variables, and the config in mcmod.yaml
+//! (with the profile and target applied) as JSON on stdin. Outside a project, or when mcmod.yaml
+//! can't be loaded, stdin is `null`
+
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use clap::CommandFactory;
+
+use crate::discover::PROJECT_ROOT_ENV;
+use crate::util::{IoResult, Project, ProjectArgs};
+use crate::Cli;
+
+/// Find the executable of the plugin on PATH
+pub fn find_plugin(name: &str) -> Option<PathBuf> {
+    let file_name = format!("mcmod-{name}{}", std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&file_name))
+        .find(|x| x.is_file())
+}
+
+/// Run the plugin for the command line that didn't match a builtin command
+pub async fn run_plugin(args: &ProjectArgs, command_line: Vec<OsString>) -> IoResult<()> {
+    let name = command_line
+        .first()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let exe = match find_plugin(&name) {
+        Some(x) => x,
+        None => {
+            let hint = match closest_builtin(&name) {
+                Some(x) => format!("Did you mean `mcmod {x}`?"),
+                None => "Run `mcmod help` for the builtin commands".to_owned(),
+            };
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Unknown command '{name}'. It's not a builtin command, and there is no `mcmod-{name}` on PATH. {hint}"),
+            ))?
+        }
+    };
+    let mut command = Command::new(&exe);
+    command.args(&command_line[1..]).stdin(Stdio::piped());
+    if let Ok(current_exe) = std::env::current_exe() {
+        command.env("MCMOD", current_exe);
+    }
+    command.env("MCMOD_VERSION", env!("CARGO_PKG_VERSION"));
+    // not in a project is fine, since plugins don't have to work on a project
+    let project = Project::new_in(args).ok();
+    let config = match &project {
+        Some(project) => {
+            command
+                .env(PROJECT_ROOT_ENV, &project.root)
+                .env("MCMOD_TARGET_ROOT", project.target_root());
+            if let Some(profile) = &project.profile {
+                command.env("MCMOD_PROFILE", profile);
+            }
+            if let Some(target) = &project.target {
+                command.env("MCMOD_TARGET", target);
+            }
+            match project.mcmod().await {
+                Ok(mcmod) => serde_json::to_string(mcmod),
+                Err(e) => {
+                    println!(
+                        "warning: failed to load mcmod.yaml, the plugin gets `null` as config: {e}"
+                    );
+                    serde_json::to_string(&())
+                }
+            }
+        }
+        None => serde_json::to_string(&()),
+    };
+    let config = match config {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
+    let mut child = match command.spawn() {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Cannot run '{}': {e}", exe.display()),
+        ))?,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // the plugin can exit without reading stdin
+        let _ = stdin.write_all(config.as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        Err(io::Error::other(format!(
+            "mcmod-{name} failed with {status}"
+        )))?;
+    }
+    Ok(())
+}
+
+/// The builtin command that the mistyped name is closest to, since clap doesn't suggest
+/// commands when unknown ones are run as plugins
+fn closest_builtin(name: &str) -> Option<String> {
+    let command = Cli::command();
+    let (distance, closest) = command
+        .get_subcommands()
+        .map(|x| x.get_name())
+        .map(|x| (edit_distance(name, x), x))
+        .min()?;
+    // allow a typo for every 3 characters
+    (distance <= name.len().div_ceil(3)).then(|| closest.to_owned())
+}
+
+/// Levenshtein distance between the strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitute.min(previous + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("sync", "sync"), 0);
+        assert_eq!(edit_distance("snyc", "sync"), 2);
+        assert_eq!(edit_distance("buld", "build"), 1);
+        assert_eq!(edit_distance("", "run"), 3);
+    }
+
+    #[test]
+    fn test_closest_builtin() {
+        assert_eq!(closest_builtin("buld").as_deref(), Some("build"));
+        assert_eq!(closest_builtin("snyc").as_deref(), Some("sync"));
+        assert_eq!(
+            closest_builtin("upgrade-projet").as_deref(),
+            Some("upgrade-project")
+        );
+        assert_eq!(closest_builtin("publish"), None);
+    }
+}
@@ -0,0 +1,482 @@
+//! Minimal PNG decoding, resizing and encoding for processing the logo
+
+use std::io::{self, Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Length of the signature and the start of IHDR up to the height
+pub const HEADER_LEN: usize = 24;
+
+/// Get the width and height from the start of a PNG file
+pub fn size(header: &[u8]) -> Option<(u32, u32)> {
+    let rest = header.strip_prefix(SIGNATURE)?;
+    if rest.get(4..8)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(rest.get(8..12)?.try_into().ok()?);
+    let height = u32::from_be_bytes(rest.get(12..16)?.try_into().ok()?);
+    Some((width, height))
+}
+
+/// An 8-bit RGBA image
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels, row by row
+    pub pixels: Vec<u8>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl Image {
+    /// Decode a non-interlaced PNG of any color type into RGBA
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut rest = data
+            .strip_prefix(SIGNATURE)
+            .ok_or_else(|| invalid("Not a PNG file"))?;
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut transparency: &[u8] = &[];
+        let mut compressed = Vec::new();
+        while rest.len() >= 12 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let chunk_type = &rest[4..8];
+            let chunk = rest
+                .get(8..8 + len)
+                .ok_or_else(|| invalid("Truncated PNG chunk"))?;
+            match chunk_type {
+                b"IHDR" if chunk.len() >= 13 => header = Some(chunk),
+                b"PLTE" => palette = chunk,
+                b"tRNS" => transparency = chunk,
+                b"IDAT" => compressed.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => {}
+            }
+            rest = &rest[(12 + len).min(rest.len())..];
+        }
+        let header = header.ok_or_else(|| invalid("PNG has no header"))?;
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let bit_depth = header[8] as usize;
+        let color_type = header[9];
+        if header[12] != 0 {
+            return Err(invalid("Interlaced PNGs are not supported"));
+        }
+        let channels = match color_type {
+            0 => 1,
+            2 => 3,
+            3 => 1,
+            4 => 2,
+            6 => 4,
+            _ => return Err(invalid(format!("Unknown PNG color type {color_type}"))),
+        };
+        if !matches!(bit_depth, 1 | 2 | 4 | 8 | 16) || (bit_depth < 8 && channels != 1) {
+            return Err(invalid(format!("Unsupported PNG bit depth {bit_depth}")));
+        }
+
+        let mut raw = Vec::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut raw)?;
+        let bits_per_pixel = channels * bit_depth;
+        let stride = (width as usize * bits_per_pixel).div_ceil(8);
+        // bytes to the corresponding byte of the previous pixel, at least 1
+        let pixel_bytes = bits_per_pixel.div_ceil(8);
+        if raw.len() < (stride + 1) * height as usize {
+            return Err(invalid("PNG image data is too short"));
+        }
+        let mut rows = vec![0u8; stride * height as usize];
+        for y in 0..height as usize {
+            let filter = raw[y * (stride + 1)];
+            let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+            let (before, current) = rows.split_at_mut(y * stride);
+            let previous = if y == 0 {
+                None
+            } else {
+                Some(&before[(y - 1) * stride..])
+            };
+            let current = &mut current[..stride];
+            for x in 0..stride {
+                let a = if x >= pixel_bytes {
+                    current[x - pixel_bytes]
+                } else {
+                    0
+                };
+                let b = previous.map_or(0, |p| p[x]);
+                let c = match previous {
+                    Some(p) if x >= pixel_bytes => p[x - pixel_bytes],
+                    _ => 0,
+                };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    _ => return Err(invalid(format!("Unknown PNG filter {filter}"))),
+                };
+                current[x] = line[x].wrapping_add(predicted);
+            }
+        }
+
+        // sample n of a row, scaled to 8 bits
+        let sample = |row: &[u8], n: usize| -> u8 {
+            match bit_depth {
+                8 => row[n],
+                16 => row[n * 2],
+                _ => {
+                    let bit = n * bit_depth;
+                    let value =
+                        (row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1);
+                    if color_type == 3 {
+                        value
+                    } else {
+                        (value as u32 * 255 / ((1 << bit_depth) - 1)) as u8
+                    }
+                }
+            }
+        };
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height as usize {
+            let row = &rows[y * stride..(y + 1) * stride];
+            for x in 0..width as usize {
+                let n = x * channels;
+                let rgba = match color_type {
+                    0 => {
+                        let v = sample(row, n);
+                        [v, v, v, 255]
+                    }
+                    2 => [sample(row, n), sample(row, n + 1), sample(row, n + 2), 255],
+                    3 => {
+                        let i = sample(row, n) as usize;
+                        let color = palette
+                            .get(i * 3..i * 3 + 3)
+                            .ok_or_else(|| invalid("PNG palette index out of range"))?;
+                        let alpha = transparency.get(i).copied().unwrap_or(255);
+                        [color[0], color[1], color[2], alpha]
+                    }
+                    4 => {
+                        let v = sample(row, n);
+                        [v, v, v, sample(row, n + 1)]
+                    }
+                    _ => [
+                        sample(row, n),
+                        sample(row, n + 1),
+                        sample(row, n + 2),
+                        sample(row, n + 3),
+                    ],
+                };
+                pixels.extend_from_slice(&rgba);
+            }
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Encode the image as an RGBA PNG
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let stride = self.width as usize * 4;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height as usize);
+        for row in self.pixels.chunks(stride) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&raw)?;
+        let compressed = encoder.finish()?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8-bit RGBA, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut output = SIGNATURE.to_vec();
+        for (chunk_type, data) in [
+            (b"IHDR", header.as_slice()),
+            (b"IDAT", compressed.as_slice()),
+            (b"IEND", &[]),
+        ] {
+            output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            output.extend_from_slice(chunk_type);
+            output.extend_from_slice(data);
+            let mut crc = Crc::new();
+            crc.update(chunk_type);
+            crc.update(data);
+            output.extend_from_slice(&crc.sum().to_be_bytes());
+        }
+        Ok(output)
+    }
+
+    /// Resize the image by averaging the pixels each new pixel covers.
+    /// Colors are weighted by alpha so transparent pixels don't darken the edges
+    pub fn resize(&self, width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        let scale_x = self.width as f64 / width as f64;
+        let scale_y = self.height as f64 / height as f64;
+        for y in 0..height {
+            let (y0, y1) = (y as f64 * scale_y, (y + 1) as f64 * scale_y);
+            for x in 0..width {
+                let (x0, x1) = (x as f64 * scale_x, (x + 1) as f64 * scale_x);
+                let mut sum = [0f64; 4];
+                let mut area = 0f64;
+                for sy in y0.floor() as u32..(y1.ceil() as u32).min(self.height) {
+                    let weight_y = (y1.min(sy as f64 + 1.0) - y0.max(sy as f64)).max(0.0);
+                    for sx in x0.floor() as u32..(x1.ceil() as u32).min(self.width) {
+                        let weight_x = (x1.min(sx as f64 + 1.0) - x0.max(sx as f64)).max(0.0);
+                        let weight = weight_x * weight_y;
+                        let i = (sy as usize * self.width as usize + sx as usize) * 4;
+                        let alpha = self.pixels[i + 3] as f64;
+                        for (total, value) in sum.iter_mut().zip(&self.pixels[i..i + 3]) {
+                            *total += *value as f64 * alpha * weight;
+                        }
+                        sum[3] += alpha * weight;
+                        area += weight;
+                    }
+                }
+                let alpha = if area > 0.0 { sum[3] / area } else { 0.0 };
+                for value in &sum[..3] {
+                    let color = if sum[3] > 0.0 { value / sum[3] } else { 0.0 };
+                    pixels.push(color.round().clamp(0.0, 255.0) as u8);
+                }
+                pixels.push(alpha.round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Put the image at the top left of a larger transparent image
+    pub fn pad(&self, width: u32, height: u32) -> Self {
+        let mut pixels = vec![0; width as usize * height as usize * 4];
+        let stride = self.width as usize * 4;
+        for (y, row) in self.pixels.chunks(stride).enumerate() {
+            let start = y * width as usize * 4;
+            pixels[start..start + stride].copy_from_slice(row);
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample of the channel of the pixel, with all values of the bit depth appearing
+    fn test_sample(x: usize, y: usize, channel: usize, bit_depth: usize) -> u16 {
+        let value = (x * 37 + y * 101 + channel * 59 + x * y * 7) as u32;
+        (value % (1 << bit_depth)) as u16
+    }
+
+    /// Encode the samples with the filter, like an encoder would
+    fn encode_test_png(
+        width: usize,
+        height: usize,
+        bit_depth: usize,
+        color_type: u8,
+        filter: u8,
+        extra_chunks: &[(&[u8; 4], Vec<u8>)],
+    ) -> Vec<u8> {
+        let channels = match color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        };
+        let bits_per_pixel = channels * bit_depth;
+        let stride = (width * bits_per_pixel).div_ceil(8);
+        let pixel_bytes = bits_per_pixel.div_ceil(8);
+        let mut rows = vec![vec![0u8; stride]; height];
+        for (y, row) in rows.iter_mut().enumerate() {
+            for x in 0..width {
+                for channel in 0..channels {
+                    let value = test_sample(x, y, channel, bit_depth);
+                    let n = x * channels + channel;
+                    match bit_depth {
+                        16 => row[n * 2..n * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+                        8 => row[n] = value as u8,
+                        _ => {
+                            let bit = n * bit_depth;
+                            row[bit / 8] |= (value as u8) << (8 - bit_depth - bit % 8);
+                        }
+                    }
+                }
+            }
+        }
+        let mut raw = Vec::new();
+        for y in 0..height {
+            raw.push(filter);
+            for x in 0..stride {
+                let a = if x >= pixel_bytes {
+                    rows[y][x - pixel_bytes]
+                } else {
+                    0
+                };
+                let b = if y > 0 { rows[y - 1][x] } else { 0 };
+                let c = if y > 0 && x >= pixel_bytes {
+                    rows[y - 1][x - pixel_bytes]
+                } else {
+                    0
+                };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                raw.push(rows[y][x].wrapping_sub(predicted));
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let mut header = Vec::new();
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        header.extend_from_slice(&[bit_depth as u8, color_type, 0, 0, 0]);
+        let mut chunks = vec![(b"IHDR", header)];
+        chunks.extend(extra_chunks.iter().cloned());
+        chunks.push((b"IDAT", encoder.finish().unwrap()));
+        chunks.push((b"IEND", Vec::new()));
+        let mut output = SIGNATURE.to_vec();
+        for (chunk_type, data) in chunks {
+            output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            output.extend_from_slice(chunk_type);
+            output.extend_from_slice(&data);
+            // the decoder doesn't check the CRC
+            output.extend_from_slice(&[0; 4]);
+        }
+        output
+    }
+
+    /// The 8-bit value of a sample
+    fn scale(value: u16, bit_depth: usize) -> u8 {
+        match bit_depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ => (value as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+        }
+    }
+
+    #[test]
+    fn decodes_every_color_type_and_bit_depth() {
+        let (width, height) = (7, 5);
+        let cases: &[(u8, &[usize])] = &[
+            (0, &[1, 2, 4, 8, 16]),
+            (2, &[8, 16]),
+            (3, &[1, 2, 4, 8]),
+            (4, &[8, 16]),
+            (6, &[8, 16]),
+        ];
+        for (color_type, depths) in cases {
+            for bit_depth in depths.iter().copied() {
+                let entries = 1 << bit_depth;
+                let palette = (0..entries)
+                    .flat_map(|i| [i as u8, (i * 3) as u8, 255 - i as u8])
+                    .collect::<Vec<_>>();
+                let transparency = (0..entries.min(4))
+                    .map(|i| i as u8 * 60)
+                    .collect::<Vec<_>>();
+                let extra = if *color_type == 3 {
+                    vec![(b"PLTE", palette.clone()), (b"tRNS", transparency.clone())]
+                } else {
+                    Vec::new()
+                };
+                for filter in 0..=4 {
+                    let data =
+                        encode_test_png(width, height, bit_depth, *color_type, filter, &extra);
+                    let image = Image::decode(&data).unwrap();
+                    assert_eq!((image.width, image.height), (width as u32, height as u32));
+                    for y in 0..height {
+                        for x in 0..width {
+                            let sample = |channel| test_sample(x, y, channel, bit_depth);
+                            let s = |channel| scale(sample(channel), bit_depth);
+                            let expected = match color_type {
+                                0 => [s(0), s(0), s(0), 255],
+                                2 => [s(0), s(1), s(2), 255],
+                                3 => {
+                                    let i = sample(0) as usize;
+                                    let alpha = transparency.get(i).copied().unwrap_or(255);
+                                    [
+                                        palette[i * 3],
+                                        palette[i * 3 + 1],
+                                        palette[i * 3 + 2],
+                                        alpha,
+                                    ]
+                                }
+                                4 => [s(0), s(0), s(0), s(1)],
+                                _ => [s(0), s(1), s(2), s(3)],
+                            };
+                            let i = (y * width + x) * 4;
+                            assert_eq!(
+                                image.pixels[i..i + 4],
+                                expected,
+                                "color type {color_type}, bit depth {bit_depth}, filter {filter}, pixel ({x}, {y})"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let image = Image {
+            width: 3,
+            height: 2,
+            pixels: (0..24).map(|x| x * 10).collect(),
+        };
+        let decoded = Image::decode(&image.encode().unwrap()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.pixels, image.pixels);
+        assert_eq!(size(&image.encode().unwrap()[..HEADER_LEN]), Some((3, 2)));
+    }
+
+    #[test]
+    fn resize_and_pad() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            pixels: [
+                [255, 0, 0, 255],
+                [0, 0, 255, 255],
+                [255, 0, 0, 255],
+                [0, 0, 0, 0],
+            ]
+            .concat(),
+        };
+        let resized = image.resize(1, 1);
+        // the transparent pixel doesn't darken the color
+        assert_eq!(resized.pixels, [170, 0, 85, 191]);
+        let padded = resized.pad(2, 1);
+        assert_eq!(padded.pixels, [170, 0, 85, 191, 0, 0, 0, 0]);
+    }
+}
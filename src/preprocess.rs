@@ -0,0 +1,318 @@
+//! Preprocessor for sources shared between multiple MC versions
+//!
+//! Lines between `//#if`, `//#elseif`, `//#else` and `//#endif` directives
+//! are kept or commented out with `//$$ ` depending on the condition.
+//! Conditions compare variables (like `MC`) with integers, for example
+//! `//#if MC >= 11202 && MC < 11600`.
+//!
+//! Since the IDE sees the original sources, code for other versions should be
+//! commented out with `//$$ ` in the source. Those lines are uncommented when the
+//! branch is active
+
+use std::collections::BTreeMap;
+use std::io;
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::util::{write_file, IoResult};
+
+const COMMENT_PREFIX: &str = "//$$";
+
+#[derive(Debug, Parser)]
+pub struct PreprocessCommand {
+    /// Variables in the form of NAME=VALUE
+    #[arg(long = "var")]
+    pub vars: Vec<String>,
+    /// Input file
+    pub input: String,
+    /// Output file
+    pub output: String,
+}
+
+impl PreprocessCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let mut vars = BTreeMap::new();
+        for var in &self.vars {
+            let (name, value) = match var.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid variable '{var}', expected NAME=VALUE"),
+                ))?,
+            };
+            let value = match value.parse::<i64>() {
+                Ok(x) => x,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Value of variable '{name}' must be an integer"),
+                ))?,
+            };
+            vars.insert(name.to_owned(), value);
+        }
+        let source = fs::read_to_string(&self.input).await?;
+        let output = match preprocess(&source, &vars) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", self.input),
+            ))?,
+        };
+        write_file!(&self.output, output).await?;
+        Ok(())
+    }
+}
+
+/// Convert a MC version like 1.12.2 to the number used in conditions like 11202
+pub fn mc_version_number(version: &str) -> i64 {
+    let mut parts = version.split('.').map(|x| x.parse::<i64>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = parts.next().unwrap_or(0);
+    let patch = parts.next().unwrap_or(0);
+    major * 10000 + minor * 100 + patch
+}
+
+struct Frame {
+    /// If the enclosing block is active
+    parent_active: bool,
+    /// If any branch in this block has been taken
+    taken: bool,
+    /// If the current branch is active
+    active: bool,
+    /// If #else has been seen
+    seen_else: bool,
+}
+
+/// Preprocess the source with the variables. Errors are reported with line numbers
+pub fn preprocess(source: &str, vars: &BTreeMap<String, i64>) -> Result<String, String> {
+    let mut output = String::with_capacity(source.len());
+    let mut stack: Vec<Frame> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim_start();
+        let active = stack.last().map(|x| x.active).unwrap_or(true);
+        if let Some(directive) = trimmed.strip_prefix("//#") {
+            let (name, rest) = match directive.split_once(char::is_whitespace) {
+                Some((name, rest)) => (name, rest.trim()),
+                None => (directive.trim(), ""),
+            };
+            match name {
+                "if" => {
+                    let cond = eval(rest, vars).map_err(|e| format!("line {line_no}: {e}"))?;
+                    stack.push(Frame {
+                        parent_active: active,
+                        taken: cond,
+                        active: active && cond,
+                        seen_else: false,
+                    });
+                }
+                "elseif" => {
+                    let frame = match stack.last_mut() {
+                        Some(x) if !x.seen_else => x,
+                        _ => return Err(format!("line {line_no}: unexpected //#elseif")),
+                    };
+                    let cond = eval(rest, vars).map_err(|e| format!("line {line_no}: {e}"))?;
+                    frame.active = frame.parent_active && !frame.taken && cond;
+                    frame.taken |= cond;
+                }
+                "else" => {
+                    let frame = match stack.last_mut() {
+                        Some(x) if !x.seen_else => x,
+                        _ => return Err(format!("line {line_no}: unexpected //#else")),
+                    };
+                    frame.active = frame.parent_active && !frame.taken;
+                    frame.taken = true;
+                    frame.seen_else = true;
+                }
+                "endif" => {
+                    if stack.pop().is_none() {
+                        return Err(format!("line {line_no}: unexpected //#endif"));
+                    }
+                }
+                _ => return Err(format!("line {line_no}: unknown directive '//#{name}'")),
+            }
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        if active {
+            match trimmed.strip_prefix(COMMENT_PREFIX) {
+                Some(code) => {
+                    output.push_str(indent);
+                    output.push_str(code.strip_prefix(' ').unwrap_or(code));
+                }
+                None => output.push_str(line),
+            }
+        } else if trimmed.is_empty() || trimmed.starts_with(COMMENT_PREFIX) {
+            output.push_str(line);
+        } else {
+            output.push_str(indent);
+            output.push_str(COMMENT_PREFIX);
+            output.push(' ');
+            output.push_str(trimmed);
+        }
+        output.push('\n');
+    }
+
+    if !stack.is_empty() {
+        return Err("missing //#endif at end of file".to_owned());
+    }
+
+    Ok(output)
+}
+
+/// Evaluate a condition. `&&` binds tighter than `||`
+fn eval(expr: &str, vars: &BTreeMap<String, i64>) -> Result<bool, String> {
+    if expr.is_empty() {
+        return Err("missing condition".to_owned());
+    }
+    for any in expr.split("||") {
+        let mut all = true;
+        for cond in any.split("&&") {
+            if !eval_comparison(cond.trim(), vars)? {
+                all = false;
+                break;
+            }
+        }
+        if all {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn eval_comparison(cond: &str, vars: &BTreeMap<String, i64>) -> Result<bool, String> {
+    for op in [">=", "<=", "==", "!=", ">", "<"] {
+        if let Some((lhs, rhs)) = cond.split_once(op) {
+            let lhs = eval_value(lhs.trim(), vars)?;
+            let rhs = eval_value(rhs.trim(), vars)?;
+            return Ok(match op {
+                ">=" => lhs >= rhs,
+                "<=" => lhs <= rhs,
+                "==" => lhs == rhs,
+                "!=" => lhs != rhs,
+                ">" => lhs > rhs,
+                _ => lhs < rhs,
+            });
+        }
+    }
+    match cond.strip_prefix('!') {
+        Some(x) => Ok(eval_value(x.trim(), vars)? == 0),
+        None => Ok(eval_value(cond, vars)? != 0),
+    }
+}
+
+fn eval_value(value: &str, vars: &BTreeMap<String, i64>) -> Result<i64, String> {
+    if let Ok(x) = value.parse::<i64>() {
+        return Ok(x);
+    }
+    match vars.get(value) {
+        Some(x) => Ok(*x),
+        None => Err(format!("unknown variable '{value}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(mc: i64) -> BTreeMap<String, i64> {
+        BTreeMap::from([("MC".to_owned(), mc)])
+    }
+
+    const SOURCE: &str = "\
+class A {
+    //#if MC >= 11202
+    //$$ int a = 1;
+    //#elseif MC >= 10800
+    //$$ int a = 2;
+    //#else
+    int a = 3;
+    //#endif
+}
+";
+
+    #[test]
+    fn test_mc_version_number() {
+        assert_eq!(mc_version_number("1.7.10"), 10710);
+        assert_eq!(mc_version_number("1.12.2"), 11202);
+        assert_eq!(mc_version_number("1.20"), 12000);
+    }
+
+    #[test]
+    fn test_preprocess_branches() {
+        let expected = |a: [&str; 3]| {
+            format!(
+                "class A {{\n    //#if MC >= 11202\n    {}\n    //#elseif MC >= 10800\n    {}\n    //#else\n    {}\n    //#endif\n}}\n",
+                a[0], a[1], a[2]
+            )
+        };
+        assert_eq!(
+            preprocess(SOURCE, &vars(11202)).unwrap(),
+            expected(["int a = 1;", "//$$ int a = 2;", "//$$ int a = 3;"])
+        );
+        assert_eq!(
+            preprocess(SOURCE, &vars(10800)).unwrap(),
+            expected(["//$$ int a = 1;", "int a = 2;", "//$$ int a = 3;"])
+        );
+        assert_eq!(
+            preprocess(SOURCE, &vars(10710)).unwrap(),
+            expected(["//$$ int a = 1;", "//$$ int a = 2;", "int a = 3;"])
+        );
+    }
+
+    #[test]
+    fn test_preprocess_is_stable() {
+        // the output for the version of the source is the source itself
+        assert_eq!(preprocess(SOURCE, &vars(10710)).unwrap(), SOURCE);
+        let output = preprocess(SOURCE, &vars(11202)).unwrap();
+        assert_eq!(preprocess(&output, &vars(10710)).unwrap(), SOURCE);
+    }
+
+    #[test]
+    fn test_preprocess_nested() {
+        let source =
+            "//#if MC >= 10800\n//#if MC < 11200 || MC == 11202\na();\n//#endif\n//#endif\n";
+        assert_eq!(preprocess(source, &vars(11000)).unwrap(), source);
+        assert_eq!(
+            preprocess(source, &vars(11201)).unwrap(),
+            "//#if MC >= 10800\n//#if MC < 11200 || MC == 11202\n//$$ a();\n//#endif\n//#endif\n"
+        );
+        assert_eq!(
+            preprocess(source, &vars(10710)).unwrap(),
+            "//#if MC >= 10800\n//#if MC < 11200 || MC == 11202\n//$$ a();\n//#endif\n//#endif\n"
+        );
+    }
+
+    #[test]
+    fn test_eval() {
+        let vars = vars(11202);
+        assert_eq!(eval("MC >= 11200 && MC < 11300", &vars), Ok(true));
+        assert_eq!(eval("MC < 10800 || MC == 11202", &vars), Ok(true));
+        assert_eq!(eval("MC != 11202", &vars), Ok(false));
+        assert_eq!(eval("!MC", &vars), Ok(false));
+        assert!(eval("", &vars).is_err());
+        assert!(eval("FORGE > 1", &vars).is_err());
+    }
+
+    #[test]
+    fn test_preprocess_errors() {
+        let vars = vars(11202);
+        assert_eq!(
+            preprocess("//#endif\n", &vars),
+            Err("line 1: unexpected //#endif".to_owned())
+        );
+        assert_eq!(
+            preprocess("//#if MC > 1\n//#else\n//#elseif MC > 2\n", &vars),
+            Err("line 3: unexpected //#elseif".to_owned())
+        );
+        assert_eq!(
+            preprocess("//#ifdef MC\n", &vars),
+            Err("line 1: unknown directive '//#ifdef'".to_owned())
+        );
+        assert!(preprocess("//#if MC > 1\n", &vars).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! Sending commands to the dev server with RCON
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::util::{cd, write_file, IoResult, Project, ProjectArgs};
+
+/// Port used when server.properties doesn't have one
+const DEFAULT_PORT: u16 = 25575;
+
+const PACKET_LOGIN: i32 = 3;
+const PACKET_COMMAND: i32 = 2;
+
+#[derive(Debug, Parser)]
+pub struct RconCommand {
+    /// The command to send, without the leading `/`
+    #[arg(trailing_var_arg = true, required_unless_present = "script")]
+    pub command: Vec<String>,
+
+    /// Send the commands in the file, one per line. Empty lines and lines
+    /// starting with `#` are skipped. Use `-` to read from stdin
+    #[arg(long, conflicts_with = "command")]
+    pub script: Option<PathBuf>,
+}
+
+impl RconCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let run_dir = project
+            .mcmod()
+            .await?
+            .template
+            .new_handler()
+            .run_dir(&project)?;
+        let properties = read_properties(&cd!(run_dir, "server.properties")).await?;
+        let get = |key: &str| {
+            properties
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        if get("enable-rcon") != Some("true") {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "RCON is not enabled in server.properties. Run the server with `mcmod run server --enable-rcon`",
+            ))?;
+        }
+        let port = match get("rcon.port") {
+            Some(x) => x.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid rcon.port '{x}' in server.properties"),
+                )
+            })?,
+            None => DEFAULT_PORT,
+        };
+        let password = get("rcon.password").unwrap_or_default().to_owned();
+
+        let commands = match &self.script {
+            Some(script) => {
+                let content = if script.as_os_str() == "-" {
+                    let mut content = String::new();
+                    io::stdin().read_to_string(&mut content)?;
+                    content
+                } else {
+                    fs::read_to_string(script).await?
+                };
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                    .map(|x| x.trim_start_matches('/').to_owned())
+                    .collect()
+            }
+            None => vec![self.command.join(" ")],
+        };
+
+        let mut client = RconClient::connect(port, &password).await?;
+        for command in commands {
+            let response = client.send(&command).await?;
+            let response = strip_formatting(&response);
+            if !response.is_empty() {
+                println!("{response}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enable RCON in server.properties of the run directory, generating a password if not set
+pub async fn enable_rcon(run_dir: &Path) -> IoResult<()> {
+    let path = run_dir.join("server.properties");
+    let content = if path.exists() {
+        fs::read_to_string(&path).await?
+    } else {
+        String::new()
+    };
+    let properties = parse_properties(&content);
+    let get = |key: &str| {
+        properties
+            .iter()
+            .find(|(k, v)| k == key && !v.is_empty())
+            .map(|(_, v)| v.clone())
+    };
+    let values = [
+        ("enable-rcon", "true".to_owned()),
+        (
+            "rcon.port",
+            get("rcon.port").unwrap_or_else(|| DEFAULT_PORT.to_string()),
+        ),
+        (
+            "rcon.password",
+            get("rcon.password").unwrap_or_else(generate_password),
+        ),
+    ];
+    // keep the other lines as they are
+    let mut output = String::new();
+    let mut written = Vec::new();
+    for line in content.lines() {
+        let key = match line.split_once('=') {
+            Some((k, _)) if !line.trim_start().starts_with('#') => k.trim(),
+            _ => "",
+        };
+        match values.iter().find(|(k, _)| *k == key) {
+            Some((k, v)) => {
+                output.push_str(&format!("{k}={v}\n"));
+                written.push(*k);
+            }
+            None => {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+    }
+    for (k, v) in &values {
+        if !written.contains(k) {
+            output.push_str(&format!("{k}={v}\n"));
+        }
+    }
+    write_file!(&path, output).await?;
+    Ok(())
+}
+
+/// Read the key-value pairs of a properties file
+async fn read_properties(path: &Path) -> IoResult<Vec<(String, String)>> {
+    match fs::read_to_string(path).await {
+        Ok(x) => Ok(parse_properties(&x)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "'{}' does not exist. Run the server with `mcmod run server --enable-rcon` first",
+                path.display()
+            ),
+        ))?,
+        Err(e) => Err(e)?,
+    }
+}
+
+fn parse_properties(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter(|x| !x.trim_start().starts_with('#'))
+        .filter_map(|x| x.split_once('='))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect()
+}
+
+/// A random enough password for a server that only listens locally
+fn generate_password() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&nanos.to_le_bytes());
+    hasher.update(&std::process::id().to_le_bytes());
+    hasher.finalize().to_hex()[..16].to_owned()
+}
+
+/// Remove the `§x` formatting codes in the response
+fn strip_formatting(s: &str) -> String {
+    let mut output = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            output.push(c);
+        }
+    }
+    output.trim_end().to_owned()
+}
+
+struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    async fn connect(port: u16, password: &str) -> IoResult<Self> {
+        let stream = match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("Cannot connect to RCON on port {port}. Is the server running? {e}"),
+            ))?,
+        };
+        let mut client = Self { stream, next_id: 1 };
+        let id = client.write_packet(PACKET_LOGIN, password).await?;
+        let (response_id, _) = client.read_packet().await?;
+        if response_id != id {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "RCON login failed. Check rcon.password in server.properties",
+            ))?;
+        }
+        Ok(client)
+    }
+
+    /// Send the command and return the response
+    async fn send(&mut self, command: &str) -> IoResult<String> {
+        self.write_packet(PACKET_COMMAND, command).await?;
+        let (_, payload) = self.read_packet().await?;
+        Ok(payload)
+    }
+
+    async fn write_packet(&mut self, packet_type: i32, payload: &str) -> IoResult<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&((payload.len() + 10) as i32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&packet_type.to_le_bytes());
+        packet.extend_from_slice(payload.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet).await?;
+        Ok(id)
+    }
+
+    /// Read a packet, returning the id and payload
+    async fn read_packet(&mut self) -> IoResult<(i32, String)> {
+        let len = self.stream.read_i32_le().await?;
+        if !(10..=1024 * 1024).contains(&len) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid RCON packet length {len}"),
+            ))?;
+        }
+        let mut data = vec![0; len as usize];
+        self.stream.read_exact(&mut data).await?;
+        let id = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let payload = String::from_utf8_lossy(&data[8..data.len() - 2]).to_string();
+        Ok((id, payload))
+    }
+}
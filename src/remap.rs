@@ -0,0 +1,470 @@
+//! Remapping jars between SRG and dev (MCP) names
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::paths;
+use crate::util::IoResult;
+
+#[derive(Debug, Parser)]
+pub struct RemapCommand {
+    /// The jar to remap
+    pub jar: PathBuf,
+
+    /// The names to remap to
+    #[arg(long, value_enum)]
+    pub to: MappingTarget,
+
+    /// Directory with fields.csv and methods.csv.
+    ///
+    /// By default, the MCP mappings downloaded by the template into the gradle cache are used
+    #[arg(long)]
+    pub mappings: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum MappingTarget {
+    /// MCP names used in the dev environment
+    Dev,
+    /// SRG names used in released jars
+    Srg,
+}
+
+impl RemapCommand {
+    pub async fn run(self) -> IoResult<()> {
+        let mappings_dir = match self.mappings {
+            Some(x) => x,
+            None => find_mappings_dir()?,
+        };
+        println!("using mappings in '{}'", mappings_dir.display());
+        let names = read_mappings(&mappings_dir).await?;
+        let names = match self.to {
+            MappingTarget::Dev => names,
+            MappingTarget::Srg => {
+                let (names, ambiguous) = reverse(names);
+                if !ambiguous.is_empty() {
+                    println!(
+                        "warning: {} dev names map to more than one SRG name and are not remapped",
+                        ambiguous.len()
+                    );
+                }
+                names
+            }
+        };
+
+        let suffix = match self.to {
+            MappingTarget::Dev => "dev",
+            MappingTarget::Srg => "srg",
+        };
+        let stem = self
+            .jar
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let output = self.jar.with_file_name(format!("{stem}-{suffix}.jar"));
+        let (input, output_path) = (self.jar.clone(), output.clone());
+        let result =
+            tokio::task::spawn_blocking(move || remap_jar(&input, &output_path, &names)).await;
+        let remapped = match result {
+            Ok(x) => x?,
+            Err(e) => Err(io::Error::from(e))?,
+        };
+        println!(
+            "remapped {remapped} names, written to '{}'",
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+/// Find the MCP mappings in the gradle caches, using the most recently downloaded one.
+/// The shared gradle homes in the cache directory are searched too
+pub fn find_mappings_dir() -> IoResult<PathBuf> {
+    let mut gradle_homes = Vec::new();
+    match std::env::var("GRADLE_USER_HOME") {
+        Ok(x) => gradle_homes.push(PathBuf::from(x)),
+        Err(_) => {
+            let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"));
+            if let Ok(x) = home {
+                gradle_homes.push(PathBuf::from(x).join(".gradle"));
+            }
+        }
+    }
+    if let Ok(cache_dir) = paths::cache_dir_location() {
+        if let Ok(entries) = std::fs::read_dir(cache_dir.join("gradle")) {
+            gradle_homes.extend(entries.flatten().map(|x| x.path()));
+        }
+    }
+    if gradle_homes.is_empty() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Cannot find the gradle home. Set GRADLE_USER_HOME or use --mappings",
+        ))?;
+    }
+    let mut found = None;
+    for gradle_home in &gradle_homes {
+        let caches = gradle_home.join("caches");
+        // ForgeGradle and RetroFuturaGradle caches
+        for dir in ["minecraft", "retro_futura_gradle"] {
+            for entry in WalkDir::new(caches.join(dir)).max_depth(8) {
+                let entry = match entry {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                if entry.file_name() != "methods.csv" {
+                    continue;
+                }
+                let dir = match entry.path().parent() {
+                    Some(x) if x.join("fields.csv").exists() => x,
+                    _ => continue,
+                };
+                let modified = entry.metadata().ok().and_then(|x| x.modified().ok());
+                if found.as_ref().is_none_or(|(time, _)| modified > *time) {
+                    found = Some((modified, dir.to_path_buf()));
+                }
+            }
+        }
+    }
+    match found {
+        Some((_, x)) => Ok(x),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Cannot find MCP mappings in the gradle caches of {}. Run `mcmod sync` to set up the template first, or use --mappings",
+                gradle_homes
+                    .iter()
+                    .map(|x| format!("'{}'", x.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ))?,
+    }
+}
+
+/// Read the SRG to dev names of fields and methods in the mappings directory
+pub async fn read_mappings(mappings_dir: &Path) -> IoResult<BTreeMap<String, String>> {
+    let mut names = BTreeMap::new();
+    for file in ["fields.csv", "methods.csv"] {
+        let content = tokio::fs::read_to_string(mappings_dir.join(file)).await?;
+        read_csv(&content, &mut names);
+    }
+    Ok(names)
+}
+
+/// Read `searge,name,...` lines of a MCP csv into the map
+fn read_csv(content: &str, names: &mut BTreeMap<String, String>) {
+    for line in content.lines().skip(1) {
+        let mut parts = line.split(',');
+        if let (Some(srg), Some(name)) = (parts.next(), parts.next()) {
+            if !srg.is_empty() && !name.is_empty() {
+                names.insert(srg.to_owned(), name.to_owned());
+            }
+        }
+    }
+}
+
+/// Reverse the mapping. Names that map from multiple names are returned separately
+fn reverse(names: BTreeMap<String, String>) -> (BTreeMap<String, String>, BTreeSet<String>) {
+    let mut reversed = BTreeMap::new();
+    let mut ambiguous = BTreeSet::new();
+    for (from, to) in names {
+        if ambiguous.contains(&to) {
+            continue;
+        }
+        if reversed.insert(to.clone(), from).is_some() {
+            reversed.remove(&to);
+            ambiguous.insert(to);
+        }
+    }
+    (reversed, ambiguous)
+}
+
+/// Remap the classes in the jar into the output jar, returning the number of names remapped.
+///
+/// Signatures are removed since they are invalid after remapping
+pub fn remap_jar(input: &Path, output: &Path, names: &BTreeMap<String, String>) -> IoResult<usize> {
+    let mut archive = match ZipArchive::new(std::fs::File::open(input)?) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot read '{}' as a jar: {e}", input.display()),
+        ))?,
+    };
+    let mut writer = ZipWriter::new(std::fs::File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut remapped = 0;
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let name = entry.name().to_owned();
+        if entry.is_dir() {
+            writer
+                .add_directory(name, options)
+                .map_err(io::Error::other)?;
+            continue;
+        }
+        if is_signature(&name) {
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        if name.ends_with(".class") {
+            match remap_class(&content, names) {
+                Some((class, count)) => {
+                    content = class;
+                    remapped += count;
+                }
+                None => println!("warning: cannot parse '{name}', copied as-is"),
+            }
+        }
+        writer.start_file(name, options).map_err(io::Error::other)?;
+        writer.write_all(&content)?;
+    }
+    writer.finish().map_err(io::Error::other)?;
+    Ok(remapped)
+}
+
+fn is_signature(name: &str) -> bool {
+    name.starts_with("META-INF/")
+        && !name[9..].contains('/')
+        && (name.ends_with(".SF") || name.ends_with(".RSA") || name.ends_with(".DSA"))
+}
+
+/// Rename the fields and methods the class declares and references.
+///
+/// The new names are added to the end of the constant pool, and only the name slots of
+/// NameAndType entries and of the declared fields and methods point to them. Other uses
+/// of the old names, like string literals that equal a member name, are not changed.
+/// Returns None if the class can't be parsed
+fn remap_class(class: &[u8], names: &BTreeMap<String, String>) -> Option<(Vec<u8>, usize)> {
+    if class.get(..4)? != [0xCA, 0xFE, 0xBA, 0xBE] {
+        return None;
+    }
+    let mut reader = ClassReader {
+        data: class,
+        pos: 8,
+    };
+    let count = reader.u2()? as usize;
+    // (start, end) of each Utf8 entry by index
+    let mut utf8 = BTreeMap::new();
+    // positions of the name indices to redirect
+    let mut name_slots = Vec::new();
+    let mut i = 1;
+    while i < count {
+        let start = reader.pos;
+        let tag = reader.u1()?;
+        let size = match tag {
+            1 => reader.u2()? as usize,
+            3 | 4 | 9 | 10 | 11 | 17 | 18 => 4,
+            5 | 6 => 8,
+            7 | 8 | 16 | 19 | 20 => 2,
+            12 => {
+                name_slots.push(reader.pos);
+                4
+            }
+            15 => 3,
+            _ => return None,
+        };
+        reader.skip(size)?;
+        if tag == 1 {
+            utf8.insert(i as u16, (start + 3, reader.pos));
+        }
+        i += if tag == 5 || tag == 6 { 2 } else { 1 };
+    }
+    let pool_end = reader.pos;
+    // access, this, super
+    reader.skip(6)?;
+    let interfaces = reader.u2()? as usize;
+    reader.skip(interfaces * 2)?;
+    // fields, then methods
+    for _ in 0..2 {
+        for _ in 0..reader.u2()? {
+            reader.skip(2)?;
+            name_slots.push(reader.pos);
+            reader.skip(4)?;
+            for _ in 0..reader.u2()? {
+                reader.skip(2)?;
+                let len = reader.u4()? as usize;
+                reader.skip(len)?;
+            }
+        }
+    }
+
+    // old index -> index of the added entry with the new name
+    let mut redirects = BTreeMap::new();
+    let mut added = Vec::new();
+    for slot in &name_slots {
+        let index = u16::from_be_bytes([class[*slot], class[*slot + 1]]);
+        if redirects.contains_key(&index) {
+            continue;
+        }
+        let new_name = utf8
+            .get(&index)
+            .and_then(|(start, end)| std::str::from_utf8(&class[*start..*end]).ok())
+            .and_then(|x| names.get(x));
+        if let Some(name) = new_name {
+            let new_index = u16::try_from(count + added.len()).ok()?;
+            redirects.insert(index, new_index);
+            added.push(name);
+        }
+    }
+    if added.is_empty() {
+        return Some((class.to_vec(), 0));
+    }
+    let new_count = u16::try_from(count + added.len()).ok()?;
+
+    let mut output = class[..8].to_vec();
+    output.extend_from_slice(&new_count.to_be_bytes());
+    output.extend_from_slice(&class[10..pool_end]);
+    for name in &added {
+        output.push(1);
+        output.extend_from_slice(&u16::try_from(name.len()).ok()?.to_be_bytes());
+        output.extend_from_slice(name.as_bytes());
+    }
+    let shift = output.len() - pool_end;
+    output.extend_from_slice(&class[pool_end..]);
+    for slot in name_slots {
+        let index = u16::from_be_bytes([class[slot], class[slot + 1]]);
+        if let Some(new_index) = redirects.get(&index) {
+            let pos = if slot < pool_end { slot } else { slot + shift };
+            output[pos..pos + 2].copy_from_slice(&new_index.to_be_bytes());
+        }
+    }
+    Some((output, added.len()))
+}
+
+/// Reads the big-endian values of a class file
+pub struct ClassReader<'a> {
+    pub data: &'a [u8],
+    pub pos: usize,
+}
+
+impl ClassReader<'_> {
+    pub fn skip(&mut self, n: usize) -> Option<()> {
+        if self.pos + n > self.data.len() {
+            return None;
+        }
+        self.pos += n;
+        Some(())
+    }
+
+    pub fn u1(&mut self) -> Option<u8> {
+        let x = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(x)
+    }
+
+    pub fn u2(&mut self) -> Option<u16> {
+        let x = self.data.get(self.pos..self.pos + 2)?;
+        self.pos += 2;
+        Some(u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    pub fn u4(&mut self) -> Option<u32> {
+        let x = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf8(s: &str) -> Vec<u8> {
+        let mut x = vec![1];
+        x.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        x.extend_from_slice(s.as_bytes());
+        x
+    }
+
+    fn refs(tag: u8, indices: &[u16]) -> Vec<u8> {
+        let mut x = vec![tag];
+        for i in indices {
+            x.extend_from_slice(&i.to_be_bytes());
+        }
+        x
+    }
+
+    /// A class with a method `func_1_a` it calls, and a string literal with the same text
+    fn fixture() -> Vec<u8> {
+        let pool = [
+            utf8("Test"),
+            refs(7, &[1]),
+            utf8("java/lang/Object"),
+            refs(7, &[3]),
+            utf8("func_1_a"),
+            utf8("()V"),
+            refs(12, &[5, 6]),
+            refs(10, &[2, 7]),
+            refs(8, &[5]),
+        ];
+        let mut class = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52];
+        class.extend_from_slice(&(pool.len() as u16 + 1).to_be_bytes());
+        class.extend(pool.concat());
+        // public class Test extends Object, no interfaces or fields
+        class.extend_from_slice(&[0, 0x21, 0, 2, 0, 4, 0, 0, 0, 0]);
+        // one method: public func_1_a()V, no attributes
+        class.extend_from_slice(&[0, 1, 0, 1, 0, 5, 0, 6, 0, 0]);
+        // no class attributes
+        class.extend_from_slice(&[0, 0]);
+        class
+    }
+
+    /// The constant pool entries by index, and the name index of the method
+    fn parse(class: &[u8]) -> (Vec<Vec<u8>>, u16) {
+        let mut reader = ClassReader {
+            data: class,
+            pos: 8,
+        };
+        let count = reader.u2().unwrap();
+        let mut pool = vec![Vec::new()];
+        for _ in 1..count {
+            let start = reader.pos;
+            let size = match reader.u1().unwrap() {
+                1 => reader.u2().unwrap() as usize,
+                7 | 8 => 2,
+                _ => 4,
+            };
+            reader.skip(size).unwrap();
+            pool.push(class[start..reader.pos].to_vec());
+        }
+        // access, this, super, interface, field and method counts, method access
+        reader.skip(14).unwrap();
+        (pool, reader.u2().unwrap())
+    }
+
+    fn text(pool: &[Vec<u8>], index: u16) -> &str {
+        std::str::from_utf8(&pool[index as usize][3..]).unwrap()
+    }
+
+    fn index_at(entry: &[u8], n: usize) -> u16 {
+        u16::from_be_bytes([entry[1 + n * 2], entry[2 + n * 2]])
+    }
+
+    #[test]
+    fn string_literal_equal_to_a_member_name_is_kept() {
+        let names = BTreeMap::from([("func_1_a".to_owned(), "doThing".to_owned())]);
+        let (class, count) = remap_class(&fixture(), &names).unwrap();
+        assert_eq!(count, 1);
+        let (pool, method_name) = parse(&class);
+        assert_eq!(text(&pool, method_name), "doThing");
+        assert_eq!(text(&pool, index_at(&pool[7], 0)), "doThing");
+        assert_eq!(text(&pool, index_at(&pool[7], 1)), "()V");
+        assert_eq!(text(&pool, index_at(&pool[9], 0)), "func_1_a");
+    }
+
+    #[test]
+    fn class_without_mapped_names_is_unchanged() {
+        let names = BTreeMap::from([("func_2_b".to_owned(), "other".to_owned())]);
+        assert_eq!(remap_class(&fixture(), &names), Some((fixture(), 0)));
+    }
+}
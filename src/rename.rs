@@ -0,0 +1,454 @@
+//! Renaming the mod id and group of the project
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::util::{mkdir, write_file, IoResult, Project, ProjectArgs};
+
+/// Extensions of the files to update the package and mod id in
+const TEXT_EXTENSIONS: &[&str] = &["java", "scala", "kt", "json", "cfg"];
+
+#[derive(Debug, Parser)]
+pub struct RenameCommand {
+    /// The new mod id. Renames assets/<modid> and files named after the mod id
+    #[arg(long, required_unless_present = "group")]
+    pub modid: Option<String>,
+
+    /// The new group. Moves the Java packages and updates package and import statements
+    #[arg(long)]
+    pub group: Option<String>,
+}
+
+impl RenameCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let _lock = project.lock_target().await?;
+        let mcmod = project.mcmod().await?;
+        let old_modid = mcmod.modid.clone();
+        // the packages are moved out of the old group
+        let old_group = match self.group {
+            Some(_) => mcmod.require_group()?.to_owned(),
+            None => mcmod.group.clone(),
+        };
+        let new_modid = self.modid.unwrap_or_else(|| old_modid.clone());
+        let new_group = self.group.unwrap_or_else(|| old_group.clone());
+        if !is_valid_modid(&new_modid) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid mod id '{new_modid}'. Use lowercase letters, digits, `_` and `-`"),
+            ))?;
+        }
+        if !is_valid_package(&new_group) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid group '{new_group}'. It should be a Java package like `com.example.mymod`"),
+            ))?;
+        }
+        if old_group.is_empty() && new_group != old_group {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot find the current group. Set `group` in mcmod.yaml first",
+            ))?;
+        }
+        if new_modid == old_modid && new_group == old_group {
+            println!("nothing to rename");
+            return Ok(());
+        }
+        let rename = Rename {
+            old_modid: &old_modid,
+            new_modid: &new_modid,
+            old_group: &old_group,
+            new_group: &new_group,
+        };
+
+        let sources = mcmod
+            .copy_paths
+            .iter()
+            .filter_map(|x| x.source_target())
+            .map(|(source, target)| (project.root.join(source), target))
+            .filter(|(source, _)| source.is_dir())
+            .collect::<Vec<_>>();
+
+        let mut changed = 0;
+        let mut renamed_files = Vec::new();
+        let mut updated_sources = Vec::new();
+        for (source, target) in &sources {
+            if rename.group_changed() {
+                let old_dir = source.join(old_group.replace('.', "/"));
+                let new_dir = source.join(new_group.replace('.', "/"));
+                if old_dir.is_dir() {
+                    move_dir(&old_dir, &new_dir, source).await?;
+                    changed += 1;
+                }
+            }
+            if rename.modid_changed() && target.ends_with("assets") {
+                let old_dir = source.join(&old_modid);
+                let new_dir = source.join(&new_modid);
+                if old_dir.is_dir() {
+                    move_dir(&old_dir, &new_dir, source).await?;
+                    changed += 1;
+                }
+            }
+            // the same source can be copied to multiple targets
+            if !updated_sources.contains(&source) {
+                changed += rename.update_files(source, &mut renamed_files).await?;
+                updated_sources.push(source);
+            }
+        }
+
+        let mcmod_path = project.root.join("mcmod.yaml");
+        let yaml = fs::read_to_string(&mcmod_path).await?;
+        let new_yaml = rename.update_yaml(&yaml, &renamed_files);
+        if new_yaml != yaml {
+            println!("updating '{}'", mcmod_path.display());
+            write_file!(&mcmod_path, new_yaml).await?;
+            changed += 1;
+        }
+
+        println!("renamed {changed} file(s) and directories");
+        println!("run `mcmod sync` to update the target project");
+        Ok(())
+    }
+}
+
+struct Rename<'a> {
+    old_modid: &'a str,
+    new_modid: &'a str,
+    old_group: &'a str,
+    new_group: &'a str,
+}
+
+impl Rename<'_> {
+    fn modid_changed(&self) -> bool {
+        self.old_modid != self.new_modid
+    }
+
+    fn group_changed(&self) -> bool {
+        self.old_group != self.new_group
+    }
+
+    /// Update the packages and mod id in the text files of the source directory,
+    /// and rename the files named after the mod id, like `mymodid_at.cfg`.
+    ///
+    /// Renamed files are added as (old name, new name). Returns the number of changed files
+    async fn update_files(
+        &self,
+        source: &Path,
+        renamed: &mut Vec<(String, String)>,
+    ) -> IoResult<usize> {
+        let mut changed = 0;
+        let paths = WalkDir::new(source)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|x| x.file_type().is_file())
+            .map(|x| x.into_path())
+            .collect::<Vec<_>>();
+        for mut path in paths {
+            let file_name = path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if self.modid_changed() {
+                let new_name = rename_file_name(&file_name, self.old_modid, self.new_modid);
+                if new_name != file_name {
+                    let new_path = path.with_file_name(&new_name);
+                    println!("renaming '{}' to '{}'", path.display(), new_name);
+                    fs::rename(&path, &new_path).await?;
+                    renamed.push((file_name, new_name));
+                    path = new_path;
+                    changed += 1;
+                }
+            }
+            let is_text = path
+                .extension()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| TEXT_EXTENSIONS.contains(&x));
+            if !is_text {
+                continue;
+            }
+            let content = match fs::read_to_string(&path).await {
+                Ok(x) => x,
+                // not utf-8
+                Err(_) => continue,
+            };
+            let new_content = self.update_text(&content);
+            if new_content != content {
+                println!("updating '{}'", path.display());
+                write_file!(&path, new_content).await?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Update packages and string literals of the mod id, like `"mymodid"` and `"mymodid:textures/..."`
+    fn update_text(&self, content: &str) -> String {
+        let mut content = content.to_owned();
+        if self.group_changed() {
+            content = replace_word(&content, self.old_group, self.new_group, true);
+        }
+        if self.modid_changed() {
+            for quote in ["\"{}\"", "\"{}:"] {
+                content = content.replace(
+                    &quote.replace("{}", self.old_modid),
+                    &quote.replace("{}", self.new_modid),
+                );
+            }
+        }
+        content
+    }
+
+    /// Update the modid line, the packages and the names of renamed files in mcmod.yaml,
+    /// keeping the line endings
+    fn update_yaml(&self, yaml: &str, renamed: &[(String, String)]) -> String {
+        let line_ending = if yaml.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut output = String::new();
+        // the top-level key the line is under
+        let mut key = "";
+        for line in yaml.lines() {
+            if let Some((k, _)) = line.split_once(':') {
+                if !line.starts_with([' ', '-', '#']) {
+                    key = k;
+                }
+            }
+            let mut line = line.to_owned();
+            if self.modid_changed() {
+                if let Some(value) = line.strip_prefix("modid:") {
+                    line = format!(
+                        "modid:{}",
+                        value.replacen(self.old_modid, self.new_modid, 1)
+                    );
+                }
+                if key == "logo" || key == "screenshots" {
+                    // only the value, after the key or the `- ` of the list item
+                    let split = line.find([':', '-']).map_or(0, |i| i + 1);
+                    let (prefix, value) = line.split_at(split);
+                    line = format!("{prefix}{}", self.update_asset_paths(value));
+                }
+                for (old, new) in renamed {
+                    if line.strip_prefix("- ").is_some_and(|x| x.trim() == old) {
+                        line = line.replace(old.as_str(), new);
+                    }
+                }
+            }
+            if self.group_changed() {
+                line = replace_word(&line, self.old_group, self.new_group, true);
+            }
+            output.push_str(&line);
+            output.push_str(line_ending);
+        }
+        if !yaml.ends_with('\n') {
+            output.truncate(output.len() - line_ending.len());
+        }
+        output
+    }
+
+    /// Update the paths in a `logo` or `screenshots` line for the moved `assets/<modid>`
+    /// directory and the renamed files
+    fn update_asset_paths(&self, value: &str) -> String {
+        let is_quote = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '[' | ']' | ',');
+        value
+            .split('/')
+            .map(|part| {
+                let name = part.trim_matches(is_quote);
+                if name.is_empty() {
+                    return part.to_owned();
+                }
+                let new_name = if name == self.old_modid {
+                    self.new_modid.to_owned()
+                } else {
+                    rename_file_name(name, self.old_modid, self.new_modid)
+                };
+                part.replacen(name, &new_name, 1)
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Move a directory, removing the parents of the old directory that become empty
+/// up to the source root
+async fn move_dir(old_dir: &Path, new_dir: &Path, source_root: &Path) -> IoResult<()> {
+    if new_dir.exists() {
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "Cannot move '{}' to '{}', which already exists",
+                old_dir.display(),
+                new_dir.display()
+            ),
+        ))?;
+    }
+    println!("moving '{}' to '{}'", old_dir.display(), new_dir.display());
+    // moving into a sub-directory of itself, like a.b to a.b.c
+    let temp_dir = if new_dir.starts_with(old_dir) {
+        let temp_dir = old_dir.with_extension("mcmod-rename");
+        fs::rename(old_dir, &temp_dir).await?;
+        temp_dir
+    } else {
+        old_dir.to_path_buf()
+    };
+    if let Some(parent) = new_dir.parent() {
+        mkdir!(parent).await?;
+    }
+    fs::rename(&temp_dir, new_dir).await?;
+    let mut current: Option<PathBuf> = old_dir.parent().map(Path::to_path_buf);
+    while let Some(dir) = current {
+        if dir == source_root || !dir.starts_with(source_root) {
+            break;
+        }
+        if fs::read_dir(&dir).await?.next_entry().await?.is_some() {
+            break;
+        }
+        fs::remove_dir(&dir).await?;
+        current = dir.parent().map(Path::to_path_buf);
+    }
+    Ok(())
+}
+
+/// Replace `old` where it's not part of a longer identifier.
+/// For packages, `old` also can't be preceded by `.`, so `com.a.b` is not replaced by `a.b`
+fn replace_word(s: &str, old: &str, new: &str, is_package: bool) -> String {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut output = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find(old) {
+        let before = match i {
+            0 => output.chars().last(),
+            _ => rest[..i].chars().last(),
+        };
+        let after = rest[i + old.len()..].chars().next();
+        let starts = before.is_none_or(|c| !is_ident(c) && (!is_package || c != '.'));
+        let ends = after.is_none_or(|c| !is_ident(c));
+        output.push_str(&rest[..i]);
+        if starts && ends {
+            output.push_str(new);
+        } else {
+            output.push_str(old);
+        }
+        rest = &rest[i + old.len()..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Replace the parts of the file name separated by `.`, `_` or `-` that are `old`
+fn rename_file_name(name: &str, old: &str, new: &str) -> String {
+    name.split_inclusive(['.', '_', '-'])
+        .map(|part| {
+            let (word, separator) = match part.strip_suffix(['.', '_', '-']) {
+                Some(word) => (word, &part[word.len()..]),
+                None => (part, ""),
+            };
+            if word == old {
+                format!("{new}{separator}")
+            } else {
+                part.to_owned()
+            }
+        })
+        .collect()
+}
+
+fn is_valid_modid(modid: &str) -> bool {
+    !modid.is_empty()
+        && modid
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+fn is_valid_package(package: &str) -> bool {
+    package.split('.').all(|x| {
+        x.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && x.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RENAME: Rename<'static> = Rename {
+        old_modid: "mymodid",
+        new_modid: "newmod",
+        old_group: "com.example.mymod",
+        new_group: "com.example.newmod",
+    };
+
+    #[test]
+    fn test_update_yaml() {
+        let yaml = "modid: mymodid\ngroup: com.example.mymod\nlogo: assets/mymodid/logo.png\nscreenshots:\n- \"assets/mymodid/a.png\"\n- mymodid_b.png\naccess-transformers:\n- mymodid_at.cfg\n";
+        let renamed = [("mymodid_at.cfg".to_owned(), "newmod_at.cfg".to_owned())];
+        assert_eq!(
+            RENAME.update_yaml(yaml, &renamed),
+            "modid: newmod\ngroup: com.example.newmod\nlogo: assets/newmod/logo.png\nscreenshots:\n- \"assets/newmod/a.png\"\n- newmod_b.png\naccess-transformers:\n- newmod_at.cfg\n"
+        );
+    }
+
+    #[test]
+    fn test_update_yaml_inline_screenshots() {
+        let yaml = "modid: mymodid\nscreenshots: [assets/mymodid/a.png, \"assets/mymodid/b.png\"]";
+        assert_eq!(
+            RENAME.update_yaml(yaml, &[]),
+            "modid: newmod\nscreenshots: [assets/newmod/a.png, \"assets/newmod/b.png\"]"
+        );
+    }
+
+    #[test]
+    fn test_update_yaml_keeps_crlf() {
+        let yaml = "# mymodid\r\nmodid: mymodid\r\ncredits: assets/mymodid\r\n";
+        assert_eq!(
+            RENAME.update_yaml(yaml, &[]),
+            "# mymodid\r\nmodid: newmod\r\ncredits: assets/mymodid\r\n"
+        );
+    }
+
+    #[test]
+    fn test_update_text() {
+        let text = "package com.example.mymod.item;\nimport com.example.mymodextra.X;\nString a = \"mymodid\";\nString b = \"mymodid:textures/a.png\";\n";
+        assert_eq!(
+            RENAME.update_text(text),
+            "package com.example.newmod.item;\nimport com.example.mymodextra.X;\nString a = \"newmod\";\nString b = \"newmod:textures/a.png\";\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_file_name() {
+        assert_eq!(
+            rename_file_name("mymodid_at.cfg", "mymodid", "newmod"),
+            "newmod_at.cfg"
+        );
+        assert_eq!(
+            rename_file_name("mymodid.png", "mymodid", "newmod"),
+            "newmod.png"
+        );
+        assert_eq!(
+            rename_file_name("mymodidx.png", "mymodid", "newmod"),
+            "mymodidx.png"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_files_once_per_source() {
+        let source = std::env::temp_dir().join(format!("mcmod-test-rename-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&source);
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("mymodid_at.cfg"), "").unwrap();
+        std::fs::write(source.join("A.java"), "String a = \"mymodid\";").unwrap();
+
+        let mut renamed = Vec::new();
+        assert_eq!(RENAME.update_files(&source, &mut renamed).await.unwrap(), 2);
+        assert_eq!(RENAME.update_files(&source, &mut renamed).await.unwrap(), 0);
+        assert_eq!(
+            renamed,
+            vec![("mymodid_at.cfg".to_owned(), "newmod_at.cfg".to_owned())]
+        );
+        assert!(source.join("newmod_at.cfg").exists());
+        std::fs::remove_dir_all(&source).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+//! Bundling the information needed to reproduce a problem into a zip for bug reports
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::fs;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::diagnose;
+use crate::run::{self, Side};
+use crate::template::TemplateHandler;
+use crate::util::{self, cd, mkdir, timestamp, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct ReportCommand {
+    /// The side to include the log and crash report of
+    #[arg(long, value_enum, default_value = "client")]
+    pub side: Side,
+
+    /// Number of lines at the end of the log to include
+    #[arg(long, default_value_t = 2000)]
+    pub log_lines: usize,
+
+    /// Write the zip to this path instead of target/report-<time>.zip
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl ReportCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let handler = mcmod.template.new_handler();
+        let scrubber = Scrubber::new(&project);
+        // (name in the zip, content)
+        let mut files = Vec::new();
+
+        files.push((
+            "environment.txt",
+            environment(handler.as_ref(), &project).await?,
+        ));
+        let yaml = fs::read_to_string(project.root.join("mcmod.yaml")).await?;
+        files.push(("mcmod.yaml", yaml));
+        let lockfile = project.root.join("mcmod.lock");
+        if lockfile.exists() {
+            files.push(("mcmod.lock", fs::read_to_string(&lockfile).await?));
+        }
+
+        let log_path = diagnose::fml_log_path(handler.as_ref(), &project, &self.side)?;
+        if log_path.exists() {
+            let log = diagnose::read_log(&log_path).await?;
+            let lines = log.lines().collect::<Vec<_>>();
+            let start = lines.len().saturating_sub(self.log_lines);
+            let mut trimmed = String::new();
+            if start > 0 {
+                trimmed.push_str(&format!("[{start} earlier lines removed]\n"));
+            }
+            for line in &lines[start..] {
+                trimmed.push_str(line);
+                trimmed.push('\n');
+            }
+            files.push(("latest.log", trimmed));
+        } else {
+            println!("no log of the last run at '{}'", log_path.display());
+        }
+        let crash_dir = cd!(handler.run_dir(&project)?, "crash-reports");
+        match run::latest_crash_report(&crash_dir).await? {
+            Some(report) => {
+                println!("including crash report '{}'", report.display());
+                files.push(("crash-report.txt", diagnose::read_log(&report).await?));
+            }
+            None => println!("no crash report in '{}'", crash_dir.display()),
+        }
+
+        let output = match self.output {
+            Some(x) => x,
+            None => {
+                mkdir!(project.target_root()).await?;
+                project
+                    .target_root()
+                    .join(format!("report-{}.zip", timestamp()))
+            }
+        };
+        let files = files
+            .into_iter()
+            .map(|(name, content)| (name, scrubber.scrub(&content)))
+            .collect::<Vec<_>>();
+        write_zip(&output, &files)?;
+        println!(
+            "wrote '{}' with {} file(s). Paths and the user name are replaced with placeholders",
+            output.display(),
+            files.len()
+        );
+        Ok(())
+    }
+}
+
+/// Versions of the tool, template, MC and java, the OS, and the mods in the run directory
+async fn environment(handler: &dyn TemplateHandler, project: &Project) -> IoResult<String> {
+    let mcmod = project.mcmod().await?;
+    let mut out = String::new();
+    out.push_str(&format!("mcmod: {}\n", env!("CARGO_PKG_VERSION")));
+    out.push_str(&format!(
+        "os: {} {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    ));
+    out.push_str(&format!("template: {}\n", mcmod.template));
+    out.push_str(&format!("mc: {}\n", handler.mc_version()));
+    let commit = util::git_output(&project.target_root(), &["rev-parse", "HEAD"]);
+    if !commit.is_empty() {
+        out.push_str(&format!("template commit: {commit}\n"));
+    }
+    for version in handler.capabilities().java_versions {
+        let home = std::env::var(format!("JDK{version}_HOME")).unwrap_or_default();
+        if home.is_empty() {
+            out.push_str(&format!("java {version}: JDK{version}_HOME is not set\n"));
+            continue;
+        }
+        let java = Path::new(&home).join("bin").join("java");
+        let version_output = match std::process::Command::new(java).arg("-version").output() {
+            // java -version prints to stderr
+            Ok(x) => String::from_utf8_lossy(&x.stderr)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+            Err(e) => format!("cannot run java: {e}"),
+        };
+        out.push_str(&format!("java {version}: {home} ({version_output})\n"));
+    }
+    let mods_root = cd!(handler.run_dir(project)?, "mods");
+    if mods_root.is_dir() {
+        out.push_str("\nmods in the run directory:\n");
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(&mods_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        for name in names {
+            out.push_str(&format!("  {name}\n"));
+        }
+    }
+    Ok(out)
+}
+
+/// Replaces the project path, home directory and user name in the files
+struct Scrubber {
+    replacements: Vec<(String, &'static str)>,
+    /// User names, only replaced as a path component
+    users: Vec<String>,
+}
+
+impl Scrubber {
+    fn new(project: &Project) -> Self {
+        // the project is usually in the home directory, so it's replaced first
+        let mut replacements = vec![(project.root.display().to_string(), "<project>")];
+        for var in ["HOME", "USERPROFILE"] {
+            if let Ok(home) = std::env::var(var) {
+                replacements.push((home, "<home>"));
+            }
+        }
+        let users = ["USER", "USERNAME"]
+            .into_iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .filter(|x| !x.is_empty())
+            .collect();
+        replacements.retain(|(x, _)| !x.is_empty() && x != "/");
+        Self {
+            replacements,
+            users,
+        }
+    }
+
+    fn scrub(&self, content: &str) -> String {
+        let mut content = content.to_owned();
+        for (from, to) in &self.replacements {
+            content = content.replace(from.as_str(), to);
+            // paths in java logs on windows can use either separator
+            if from.contains('\\') {
+                content = content.replace(&from.replace('\\', "/"), to);
+            }
+        }
+        for user in &self.users {
+            content = replace_path_component(&content, user, "<user>");
+        }
+        content
+    }
+}
+
+/// Replace `from` where it's a path component, like in `/tmp/<from>/` or `C:\\<from>`,
+/// but not in other words
+fn replace_path_component(s: &str, from: &str, to: &str) -> String {
+    let is_separator = |c: Option<char>| matches!(c, Some('/' | '\\'));
+    let is_word =
+        |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    let mut output = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find(from) {
+        let before = match i {
+            0 => output.chars().last(),
+            _ => rest[..i].chars().last(),
+        };
+        let after = rest[i + from.len()..].chars().next();
+        let is_path =
+            (is_separator(before) && !is_word(after)) || (is_separator(after) && !is_word(before));
+        output.push_str(&rest[..i]);
+        output.push_str(if is_path { to } else { from });
+        rest = &rest[i + from.len()..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn write_zip(output: &Path, files: &[(&str, String)]) -> IoResult<()> {
+    let mut writer = ZipWriter::new(std::fs::File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        writer
+            .start_file(*name, options)
+            .map_err(io::Error::other)?;
+        writer.write_all(content.as_bytes())?;
+    }
+    writer.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_path_component() {
+        let replace = |s| replace_path_component(s, "bob", "<user>");
+        assert_eq!(replace("/tmp/bob/x.log"), "/tmp/<user>/x.log");
+        assert_eq!(replace("C:\\Users\\bob"), "C:\\Users\\<user>");
+        assert_eq!(replace("at bob/x"), "at <user>/x");
+        assert_eq!(replace("bob is not a path"), "bob is not a path");
+        assert_eq!(replace("/tmp/bobby/x"), "/tmp/bobby/x");
+        assert_eq!(replace("/tmp/jobob/x"), "/tmp/jobob/x");
+    }
+}
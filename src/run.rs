@@ -1,25 +1,146 @@
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
 
 use clap::{Parser, ValueEnum};
 use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
 
-use crate::sync::SyncCommand;
-use crate::template::TemplateHandler;
-use crate::util::{cd, IoResult, Project};
+use crate::diagnose;
+use crate::dump::{self, Dump};
+use crate::explain::ErrorCode;
+use crate::gradle;
+use crate::modpack;
+use crate::rcon;
+use crate::run_env;
+use crate::sync::{self, SyncCommand};
+use crate::template::{self, TemplateHandler};
+use crate::timings;
+use crate::util::{self, cd, mkdir, timestamp, write_file, IoResult, Project, ProjectArgs};
+use crate::window::{WindowGeometry, WindowSize, WindowTracker};
 
 #[derive(Debug, Parser)]
 pub struct RunCommand {
     /// The command to run
     ///
     /// By default, anything starts with "client" or "server" will be
-    /// mapped to "runClient" and "runServer". Other commands are passed to gradle directly
+    /// mapped to "runClient" and "runServer". "data" runs data generation and copies the
+    /// generated resources into the project. "export-env" and "import-env" write and read
+    /// a snapshot of the mods and configs in the run directory. Other commands are passed to gradle directly
     #[arg(default_value = "client")]
     pub command: String,
 
+    /// The environment file for "export-env" and "import-env"
+    pub file: Option<PathBuf>,
+
     /// Whether to fully sync before running
     #[arg(short, long)]
     pub sync: bool,
+
+    /// Run without syncing
+    #[arg(long, conflicts_with = "sync")]
+    pub no_sync: bool,
+
+    /// Run with the mods in this mod set from mcmod.yaml, in addition to `mods`
+    #[arg(long, value_name = "MOD_SET")]
+    pub mods: Option<String>,
+
+    /// Record a profile of the game into target/profiles.
+    ///
+    /// `jfr` uses Java Flight Recorder. `async` uses async-profiler, and requires
+    /// ASYNC_PROFILER_LIB to point to libasyncProfiler
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "jfr")]
+    pub profile_jvm: Option<JvmProfiler>,
+
+    /// Export the classes transformed by mixins into .mixin.out in the run directory,
+    /// and log mixin processing verbosely
+    #[arg(long)]
+    pub mixin_debug: bool,
+
+    /// Enable RCON in server.properties, to send commands with `mcmod rcon`
+    #[arg(long)]
+    pub enable_rcon: bool,
+
+    /// Record the output into target/mcmod-logs. Always enabled by `run.record-output`
+    #[arg(long)]
+    pub record: bool,
+
+    /// Restart the server when it crashes, waiting longer after each crash
+    #[arg(long)]
+    pub restart_on_crash: bool,
+
+    /// Maximum number of restarts with --restart-on-crash. Default is no limit
+    #[arg(long = "max", value_name = "N", requires = "restart_on_crash")]
+    pub max_restarts: Option<u32>,
+
+    /// Enable mixin hot swapping. The mixin jar also needs to be added to `run.java-agents`
+    #[arg(long)]
+    pub mixin_hot_swap: bool,
+
+    /// Collect the dumps written by a dump mod in run/mods during the run into target/dumps.
+    /// The dump mod, like NEI, needs to be in `mods`
+    #[arg(long, value_enum)]
+    pub dump: Option<Dump>,
+
+    /// Run with the mods and configs of the pack in packs/<PACK>.yaml, in its own run directory
+    #[arg(long, value_name = "PACK")]
+    pub pack: Option<String>,
+
+    /// Size of the client window, like `1280x720`.
+    /// Overrides `run.window-size` and the window remembered with `run.remember-window`
+    #[arg(long, value_name = "WxH")]
+    pub size: Option<WindowSize>,
+
+    /// Maximize the client window. Needs wmctrl on Linux with X11
+    #[arg(long)]
+    pub maximized: bool,
+}
+
+/// Number of recorded run logs to keep if `run.keep-recordings` is not set
+const DEFAULT_KEEP_RECORDINGS: usize = 10;
+
+/// JVM arguments for `--mixin-debug`
+const MIXIN_DEBUG_ARGS: &[&str] = &["-Dmixin.debug.export=true", "-Dmixin.debug.verbose=true"];
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum JvmProfiler {
+    /// Java Flight Recorder
+    Jfr,
+    /// async-profiler agent
+    Async,
+}
+
+impl JvmProfiler {
+    /// Get the JVM arguments to record into the output path (without extension)
+    fn jvm_args(self, output: &Path) -> IoResult<(Vec<String>, PathBuf)> {
+        match self {
+            Self::Jfr => {
+                let output = output.with_extension("jfr");
+                let arg = format!(
+                    "-XX:StartFlightRecording=dumponexit=true,settings=profile,filename={}",
+                    util::gradle_path(&output)
+                );
+                Ok((vec![arg], output))
+            }
+            Self::Async => {
+                let lib = match std::env::var("ASYNC_PROFILER_LIB") {
+                    Ok(x) => x,
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "ASYNC_PROFILER_LIB must be set to the path of libasyncProfiler to use async-profiler",
+                    ))?,
+                };
+                let output = output.with_extension("html");
+                let arg = format!(
+                    "-agentpath:{lib}=start,event=cpu,file={}",
+                    util::gradle_path(&output)
+                );
+                Ok((vec![arg], output))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -31,36 +152,484 @@ pub enum Side {
 }
 
 impl RunCommand {
-    pub async fn run(self, dir: &str) -> IoResult<()> {
-        let sync = SyncCommand {
-            incremental: !self.sync,
-            eclipse: false,
-        };
-        sync.run(dir).await?;
-        let project = Project::new_in(dir)?;
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let mut project = Project::new_in(args)?;
+        if project.mcmod().await?.is_pack() {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Packs can't be run. Build the pack with `mcmod build` and add it to the resource packs of the game",
+            ))?;
+        }
+        if self.command == "export-env" || self.command == "import-env" {
+            let file = match &self.file {
+                Some(x) => x,
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Specify the environment file: `mcmod run {} <FILE>`",
+                        self.command
+                    ),
+                ))?,
+            };
+            project.pack = self.pack.clone();
+            let template_handler = project.mcmod().await?.template.new_handler();
+            if self.command == "export-env" {
+                return run_env::export_env(template_handler.as_ref(), &project, file).await;
+            }
+            return run_env::import_env(template_handler.as_ref(), &project, file).await;
+        }
+        if self.no_sync {
+            println!("skipping sync");
+        } else if !self.sync && sync::is_up_to_date(&project).await? {
+            println!("nothing changed since the last sync, skipping sync");
+        } else {
+            let sync = SyncCommand {
+                incremental: !self.sync,
+                eclipse: false,
+                pull_back: false,
+                overwrite: false,
+                ninja_args: Vec::new(),
+                no_prune: false,
+                show_properties_diff: false,
+                fix: false,
+                keep_going: false,
+            };
+            sync.run(args).await?;
+        }
+        project.pack = self.pack.clone();
         let template_handler = project.mcmod().await?.template.new_handler();
-        if let Some(c) = self.command.strip_prefix("client") {
-            template_handler
-                .run_gradlew(&project, &[&format!("runClient{c}")])
-                .await?;
-            return Ok(());
+        let mut jvm_args = project.mcmod().await?.jvm_args.clone();
+        let recording = match self.profile_jvm {
+            Some(profiler) => {
+                let profiles_dir = cd!(project.target_root(), "profiles");
+                mkdir!(&profiles_dir).await?;
+                let name = format!("{}-{}", self.command, timestamp());
+                let (args, output) = profiler.jvm_args(&profiles_dir.join(name))?;
+                jvm_args.extend(args);
+                Some(output)
+            }
+            None => None,
+        };
+        if self.mixin_debug {
+            jvm_args.extend(MIXIN_DEBUG_ARGS.iter().map(|x| x.to_string()));
+        }
+        if self.mixin_hot_swap {
+            jvm_args.push("-Dmixin.hotSwap=true".to_string());
+        }
+        let is_client = self.command.starts_with("client");
+        let window = if is_client {
+            Some(WindowGeometry::for_launch(&project, self.size, self.maximized).await?)
+        } else {
+            None
+        };
+        let client_args = window
+            .as_ref()
+            .map(WindowGeometry::game_args)
+            .unwrap_or_default();
+        let init_script = write_init_script(&project, &jvm_args, &client_args).await?;
+        let init_script = util::gradle_path(&init_script);
+        let (task, side) = if let Some(c) = self.command.strip_prefix("client") {
+            (format!("runClient{c}"), Some(Side::Client))
+        } else if let Some(c) = self.command.strip_prefix("server") {
+            agree_to_eula(template_handler.as_ref(), &project, false).await?;
+            if self.enable_rcon {
+                rcon::enable_rcon(&template_handler.run_dir(&project)?).await?;
+            }
+            (format!("runServer{c}"), Some(Side::Server))
+        } else if self.command == "data" {
+            match template_handler.capabilities().datagen {
+                Some(task) => (task.to_owned(), None),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Template '{}' doesn't have data generation, which is only in MC 1.16+",
+                        project.mcmod().await?.template
+                    ),
+                ))?,
+            }
+        } else {
+            (self.command.clone(), None)
+        };
+
+        sync::link_mods(
+            template_handler.as_ref(),
+            &project,
+            side.as_ref(),
+            self.mods.as_deref(),
+        )
+        .await?;
+        if let Some(pack) = &self.pack {
+            modpack::setup(template_handler.as_ref(), &project, pack, side.as_ref()).await?;
+        }
+        let run_options = &project.mcmod().await?.run;
+        let output_log = if self.record || run_options.record_output {
+            let keep = run_options
+                .keep_recordings
+                .unwrap_or(DEFAULT_KEEP_RECORDINGS);
+            Some(new_output_log(&project, keep).await?)
+        } else {
+            None
+        };
+        if let Some(dump) = self.dump {
+            if side.is_none() {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--dump can only be used to run the client or server",
+                ))?;
+            }
+            dump.print_instructions(template_handler.as_ref(), &project)?;
+        }
+        let window_tracker = match window {
+            Some(window) => {
+                let record = run_options
+                    .remember_window
+                    .then(|| WindowGeometry::path(&project));
+                WindowTracker::start(window, record)
+            }
+            None => None,
+        };
+        let started = SystemTime::now();
+        let gradle_args = [task.as_str(), "--init-script", &init_script];
+        let phase = format!("run: {task}");
+        let result = timings::time(&phase, async {
+            if self.restart_on_crash {
+                if !matches!(side, Some(Side::Server)) {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--restart-on-crash can only be used to run the server",
+                    ))?;
+                }
+                run_restarting(
+                    template_handler.as_ref(),
+                    &project,
+                    &gradle_args,
+                    output_log.as_deref(),
+                    self.max_restarts,
+                )
+                .await
+            } else {
+                run_gradlew_recorded(
+                    template_handler.as_ref(),
+                    &project,
+                    &gradle_args,
+                    output_log.as_deref(),
+                )
+                .await
+            }
+        })
+        .await;
+        if let Some(tracker) = window_tracker {
+            tracker.stop();
+        }
+        if let Some(output_log) = &output_log {
+            println!("output recorded to '{}'", output_log.display());
+        }
+        if let Some(recording) = recording {
+            if recording.exists() {
+                println!("profile recorded to '{}'", recording.display());
+            } else {
+                println!("no profile was recorded");
+            }
+        }
+        if self.mixin_debug {
+            let export_dir = cd!(template_handler.run_dir(&project)?, ".mixin.out", "class");
+            println!("mixin classes exported to '{}'", export_dir.display());
+        }
+        if let (Err(_), Some(side)) = (&result, &side) {
+            diagnose::report_run(template_handler.as_ref(), &project, side).await;
+        }
+        // dumps made before a crash are still useful for debugging it
+        if self.dump.is_some() {
+            dump::collect(template_handler.as_ref(), &project, started).await?;
         }
-        if let Some(c) = self.command.strip_prefix("server") {
-            agree_to_eula(template_handler.as_ref(), &project).await?;
-            template_handler
-                .run_gradlew(&project, &[&format!("runServer{c}")])
-                .await?;
+        if result.is_ok() && self.command == "data" {
+            copy_generated_resources(template_handler.as_ref(), &project).await?;
+        }
+        result
+    }
+}
+
+/// Copy the resources from data generation into the project, to the source of the
+/// copy path that would copy them back. Unchanged files are skipped
+async fn copy_generated_resources(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+) -> IoResult<()> {
+    let output_dir = handler.datagen_output_dir(project)?;
+    if !output_dir.is_dir() {
+        println!("no resources generated in '{}'", output_dir.display());
+        return Ok(());
+    }
+    let mcmod = project.mcmod().await?;
+    let copy_paths = mcmod
+        .copy_paths
+        .iter()
+        .filter(|x| x.applies_to(project, &mcmod.template))
+        .filter_map(|x| x.source_target())
+        .collect::<Vec<_>>();
+    let mut copied = 0;
+    let mut unmapped = 0;
+    // .cache has the hashes of the data generator to skip unchanged files, not resources
+    for entry in walkdir::WalkDir::new(&output_dir)
+        .into_iter()
+        .filter_entry(|x| x.depth() != 1 || x.file_name() != ".cache")
+        .filter_map(Result::ok)
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = match entry.path().strip_prefix(&output_dir) {
+            Ok(x) => x.to_string_lossy().replace('\\', "/"),
+            Err(_) => continue,
+        };
+        // the longest target that contains the file, like src/main/resources/assets
+        let target_path = format!("src/main/resources/{rel_path}");
+        let mapped = copy_paths
+            .iter()
+            .filter_map(|(source, target)| {
+                let target = target.trim_end_matches('/');
+                let rest = target_path.strip_prefix(target)?.strip_prefix('/')?;
+                Some((target.len(), project.root.join(source).join(rest)))
+            })
+            .max_by_key(|(len, _)| *len);
+        let path = match mapped {
+            Some((_, x)) => x,
+            None => {
+                println!("warning: no copy path for generated '{rel_path}'");
+                unmapped += 1;
+                continue;
+            }
+        };
+        let content = fs::read(entry.path()).await?;
+        if fs::read(&path).await.ok().as_ref() == Some(&content) {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            mkdir!(parent).await?;
+        }
+        util::write_atomic(&path, &content).await?;
+        copied += 1;
+    }
+    println!("copied {copied} generated file(s) into the project");
+    if unmapped > 0 {
+        println!("add copy paths for src/main/resources/assets and src/main/resources/data to copy the other {unmapped} file(s)");
+    }
+    Ok(())
+}
+
+/// Run the gradle task again when the game crashes, until it stops without crashing.
+///
+/// A crash is a failed run or a new crash report, since the server can exit normally
+/// after crashing. The delay before restarting doubles after each crash, up to 5 minutes
+async fn run_restarting(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    args: &[&str],
+    output_log: Option<&Path>,
+    max_restarts: Option<u32>,
+) -> IoResult<()> {
+    let crash_dir = cd!(handler.run_dir(project)?, "crash-reports");
+    let mut crashes = 0;
+    loop {
+        let last_report = latest_crash_report(&crash_dir).await?;
+        let result = run_gradlew_recorded(handler, project, args, output_log).await;
+        let report = latest_crash_report(&crash_dir).await?;
+        let new_report = report.filter(|x| Some(x) != last_report.as_ref());
+        if result.is_ok() && new_report.is_none() {
             return Ok(());
         }
+        crashes += 1;
+        if let Some(report) = new_report {
+            println!("crash report: '{}'", report.display());
+        }
+        if max_restarts.is_some_and(|max| crashes > max) {
+            println!("crashed {crashes} times, not restarting");
+            result?;
+            Err(io::Error::other("The server crashed"))?;
+        }
+        let delay = Duration::from_secs((5u64 << (crashes - 1).min(6)).min(300));
+        println!(
+            "crashed {crashes} time(s), restarting in {} seconds",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Create the path of the log to record the run into, removing old logs
+/// so only `keep` logs are left after recording
+async fn new_output_log(project: &Project, keep: usize) -> IoResult<PathBuf> {
+    let dir = cd!(project.target_root(), "mcmod-logs");
+    mkdir!(&dir).await?;
+    let mut logs = Vec::new();
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("run-") && name.ends_with(".log") {
+            logs.push(entry.path());
+        }
+    }
+    // names have the time, so the oldest is first
+    logs.sort();
+    let remove = (logs.len() + 1).saturating_sub(keep.max(1));
+    for log in &logs[..remove] {
+        fs::remove_file(log).await?;
+    }
+    Ok(dir.join(format!("run-{}.log", timestamp())))
+}
+
+/// Send the lines of the output of gradle to the channel. Reads until the pipe is closed,
+/// even if the lines are not UTF-8 or reading fails, so gradle never blocks on a full pipe
+async fn forward_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    is_err: bool,
+    send: mpsc::UnboundedSender<(bool, String)>,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => return,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                let line = line.trim_end_matches(['\n', '\r']).to_owned();
+                let _ = send.send((is_err, line));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => {
+                let _ = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Run gradlew, also writing the output with timestamps into `output_log` if set.
+/// Output of restarted runs is appended to the same log
+async fn run_gradlew_recorded(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    args: &[&str],
+    output_log: Option<&Path>,
+) -> IoResult<()> {
+    let output_log = match output_log {
+        Some(x) => x,
+        None => return handler.run_gradlew(project, args).await,
+    };
+    let mut command = handler.gradlew_command(project, args)?;
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = tokio::process::Command::from(command).spawn()?;
+    let (send, mut recv) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_lines(stdout, false, send.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_lines(stderr, true, send));
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_log)
+        .await?;
+    // stop writing the log on the first error, but keep forwarding the output
+    // until gradle exits, so it doesn't block on a full pipe
+    let mut log_error = None;
+    while let Some((is_err, line)) = recv.recv().await {
+        if is_err {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+        if log_error.is_none() {
+            let record = format!("[{}] {line}\n", timestamp());
+            if let Err(e) = file.write_all(record.as_bytes()).await {
+                log_error = Some(e);
+            }
+        }
+    }
+    if log_error.is_none() {
+        log_error = file.flush().await.err();
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        Err(ErrorCode::GradleFailed.error(io::Error::other("gradlew failed")))?;
+    }
+    if let Some(e) = log_error {
+        Err(e)?;
+    }
+    Ok(())
+}
+
+/// The newest crash report in the directory. Crash reports are named by time
+pub async fn latest_crash_report(crash_dir: &Path) -> IoResult<Option<PathBuf>> {
+    if !crash_dir.is_dir() {
+        return Ok(None);
+    }
+    let mut latest = None;
+    let mut entries = fs::read_dir(crash_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_report = path.extension().is_some_and(|x| x == "txt");
+        if is_report && latest.as_ref().is_none_or(|x| path > *x) {
+            latest = Some(path);
+        }
+    }
+    Ok(latest)
+}
 
-        template_handler
-            .run_gradlew(&project, &[&self.command])
-            .await?;
-        Ok(())
+/// Write the gradle init script that injects the JVM arguments, java agents
+/// and run directory into the run tasks
+pub async fn write_init_script(
+    project: &Project,
+    jvm_args: &[String],
+    client_args: &[String],
+) -> IoResult<PathBuf> {
+    let path = cd!(project.target_root(), "mcmod-run.gradle");
+    let mcmod = project.mcmod().await?;
+    let handler = mcmod.template.new_handler();
+    let run_dir = template::setup_run_dir(handler.as_ref(), project).await?;
+    let mut jvm_args = jvm_args.to_vec();
+    for agent in &mcmod.run.java_agents {
+        let (jar, options) = match agent.split_once('=') {
+            Some((jar, options)) => (jar, Some(options)),
+            None => (agent.as_str(), None),
+        };
+        let jar = project.root.join(jar);
+        if !jar.exists() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Java agent '{}' does not exist", jar.display()),
+            ))?;
+        }
+        let arg = match options {
+            Some(options) => format!("-javaagent:{}={options}", util::gradle_path(&jar)),
+            None => format!("-javaagent:{}", util::gradle_path(&jar)),
+        };
+        jvm_args.push(arg);
     }
+    let working_dir = if run_dir == cd!(project.target_root(), "run") {
+        None
+    } else {
+        Some(run_dir.as_path())
+    };
+    let script = gradle::create_run_init_script(
+        &jvm_args,
+        &mcmod.run.jvm_module_args,
+        working_dir,
+        client_args,
+    );
+    write_file!(&path, script).await?;
+    Ok(path)
 }
 
-async fn agree_to_eula(template_handler: &dyn TemplateHandler, project: &Project) -> IoResult<()> {
+/// Agree to the EULA for the server, asking the user unless `auto_agree` is true
+/// or MCMOD_EULA_AUTO_AGREE is set
+pub async fn agree_to_eula(
+    template_handler: &dyn TemplateHandler,
+    project: &Project,
+    auto_agree: bool,
+) -> IoResult<()> {
     let eula_path = cd!(template_handler.run_dir(project)?, "eula.txt");
     if eula_path.exists() {
         let content = fs::read_to_string(&eula_path).await?;
@@ -72,7 +641,10 @@ async fn agree_to_eula(template_handler: &dyn TemplateHandler, project: &Project
     }
 
     let env = std::env::var("MCMOD_EULA_AUTO_AGREE").unwrap_or_default();
-    if env == "true" || env == "1" {
+    if auto_agree {
+        println!("Automatically agreeing to EULA to run the server");
+        println!("Please read the EULA at https://account.mojang.com/documents/minecraft_eula");
+    } else if env == "true" || env == "1" {
         println!("Automatically agreeing to EULA to run the server (because MCMOD_EULA_AUTO_AGREE is set)");
         println!("Please read the EULA at https://account.mojang.com/documents/minecraft_eula");
     } else {
@@ -85,7 +657,7 @@ async fn agree_to_eula(template_handler: &dyn TemplateHandler, project: &Project
         let stdin = io::stdin();
         stdin.read_line(&mut buffer)?;
         if buffer.trim().to_lowercase() != "y" {
-            Err(io::Error::new(io::ErrorKind::Other, "EULA not agreed"))?;
+            Err(io::Error::other("EULA not agreed"))?;
         }
     }
 
@@ -96,3 +668,21 @@ async fn agree_to_eula(template_handler: &dyn TemplateHandler, project: &Project
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forwards_lines_after_invalid_utf8() {
+        let (send, mut recv) = mpsc::unbounded_channel();
+        let input: &[u8] = b"first\n\xff\xfe bad\r\nlast";
+        forward_lines(input, true, send).await;
+        let mut lines = Vec::new();
+        while let Some((is_err, line)) = recv.recv().await {
+            assert!(is_err);
+            lines.push(line);
+        }
+        assert_eq!(lines, ["first", "\u{fffd}\u{fffd} bad", "last"]);
+    }
+}
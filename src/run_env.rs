@@ -0,0 +1,343 @@
+//! Snapshots of the run environment, with `mcmod run export-env` and `mcmod run import-env`
+//!
+//! The snapshot has the hash of each mod in the mods directory of the run directory, where to
+//! download it from if known, the files in config/, and the template the environment is for
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::hash;
+use crate::modpack::{self, ModPack};
+use crate::state::write_json;
+use crate::sync::{self, Downloader, Prune, MODS_CDN_URL};
+use crate::template::{self, TemplateHandler};
+use crate::util::{self, mkdir, write_file, IoResult, Project};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunEnv {
+    /// The template, which determines the MC and Forge versions
+    pub template: String,
+    pub mc_version: String,
+    /// Commit of the template in the target, since the Forge version is in its build script
+    #[serde(default)]
+    pub template_commit: String,
+    pub mods: Vec<EnvMod>,
+    /// Content of each file in config/, keyed by the path relative to it
+    #[serde(default)]
+    pub config: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EnvMod {
+    pub name: String,
+    pub hash: String,
+    /// Url to download the mod from. None for mods that are not in mcmod.yaml or the pack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// Write the environment of the run directory to the file
+pub async fn export_env(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    file: &Path,
+) -> IoResult<()> {
+    let mcmod = project.mcmod().await?;
+    let run_dir = handler.run_dir(project)?;
+    let mods_root = run_dir.join("mods");
+    if !mods_root.is_dir() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "'{}' doesn't exist. Run the game once before exporting the environment",
+                mods_root.display()
+            ),
+        ))?;
+    }
+    let urls = known_urls(project).await?;
+    let hashes = hash::hash_files(project, &mods_root, mod_files(&mods_root).await?).await?;
+    let mut mods = Vec::new();
+    let mut unknown = 0;
+    for (name, hash) in hashes {
+        let hash = match hash {
+            Some(x) => x,
+            None => continue,
+        };
+        let url = urls.get(&name).cloned();
+        if url.is_none() {
+            unknown += 1;
+        }
+        mods.push(EnvMod { name, hash, url });
+    }
+
+    let mut config = BTreeMap::new();
+    let config_root = run_dir.join("config");
+    for entry in WalkDir::new(&config_root).sort_by_file_name() {
+        let entry = match entry {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let key = hash::relative_key(&config_root, entry.path());
+        match fs::read_to_string(entry.path()).await {
+            Ok(content) => {
+                config.insert(key, content);
+            }
+            Err(_) => println!("skipping the binary config file '{key}'"),
+        }
+    }
+
+    let env = RunEnv {
+        template: mcmod.template.to_string(),
+        mc_version: handler.mc_version().to_owned(),
+        template_commit: util::git_output(&project.target_root(), &["rev-parse", "HEAD"]),
+        mods,
+        config,
+    };
+    write_json(file, &env).await?;
+    println!(
+        "exported {} mod(s) and {} config file(s) to '{}'",
+        env.mods.len(),
+        env.config.len(),
+        file.display()
+    );
+    if unknown > 0 {
+        println!("{unknown} mod(s) are not in mcmod.yaml and have to be added to the mods directory manually when importing");
+    }
+    Ok(())
+}
+
+/// Make the run directory match the environment in the file. Mods are downloaded and checked
+/// against their hashes, mods not in the environment are removed, and config/ is replaced
+pub async fn import_env(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    file: &Path,
+) -> IoResult<()> {
+    let content = match fs::read_to_string(file).await {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Cannot read '{}': {e}", file.display()),
+        ))?,
+    };
+    let env: RunEnv = match serde_json::from_str(&content) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot parse '{}': {e}", file.display()),
+        ))?,
+    };
+    let mcmod = project.mcmod().await?;
+    if env.template != mcmod.template.to_string() {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The environment is for template '{}' (MC {}), but the project uses '{}'",
+                env.template, env.mc_version, mcmod.template
+            ),
+        ))?;
+    }
+    // the file comes from someone else, so its paths must stay in the run directory
+    for env_mod in &env.mods {
+        check_env_path("mod", &env_mod.name, true)?;
+    }
+    for key in env.config.keys() {
+        check_env_path("config file", key, false)?;
+    }
+    let template_commit = util::git_output(&project.target_root(), &["rev-parse", "HEAD"]);
+    if !env.template_commit.is_empty() && env.template_commit != template_commit {
+        println!(
+            "warning: the environment was exported with template commit {}, but the target is at {}",
+            env.template_commit,
+            if template_commit.is_empty() {
+                "an unknown commit"
+            } else {
+                &template_commit
+            }
+        );
+    }
+
+    let run_dir = template::setup_run_dir(handler, project).await?;
+    let mods_root = run_dir.join("mods");
+    mkdir!(&mods_root).await?;
+    let current = hash::hash_files(project, &mods_root, mod_files(&mods_root).await?).await?;
+    for name in current.keys() {
+        if !env.mods.iter().any(|x| &x.name == name) {
+            println!("removing '{name}'");
+            fs::remove_file(mods_root.join(name)).await?;
+        }
+    }
+    let mut downloads = Vec::new();
+    let mut missing = Vec::new();
+    for env_mod in &env.mods {
+        if current
+            .get(&env_mod.name)
+            .is_some_and(|x| x.as_ref() == Some(&env_mod.hash))
+        {
+            continue;
+        }
+        match &env_mod.url {
+            Some(url) => downloads.push(url.clone()),
+            None => missing.push(env_mod.name.as_str()),
+        }
+    }
+    if !downloads.is_empty() {
+        let staging = project.target_root().join("env-import");
+        // don't reuse files from an import that failed
+        if staging.exists() {
+            fs::remove_dir_all(&staging).await?;
+        }
+        let prune = Prune {
+            enabled: true,
+            keep: &[],
+            generated: &[],
+        };
+        sync::sync_downloads(
+            &staging,
+            &downloads,
+            &project.root,
+            MODS_CDN_URL,
+            &Downloader::new(&mcmod.downloads)?,
+            &prune,
+            false,
+        )
+        .await?;
+        let files = env
+            .mods
+            .iter()
+            .map(|x| staging.join(&x.name))
+            .filter(|x| x.exists())
+            .collect::<Vec<_>>();
+        let hashes = hash::hash_files(project, &staging, files).await?;
+        for env_mod in &env.mods {
+            let hash = match hashes.get(&env_mod.name) {
+                Some(x) => x,
+                None => continue,
+            };
+            if hash.as_ref() != Some(&env_mod.hash) {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The downloaded '{}' is different from the one in the environment",
+                        env_mod.name
+                    ),
+                ))?;
+            }
+            fs::rename(staging.join(&env_mod.name), mods_root.join(&env_mod.name)).await?;
+        }
+        fs::remove_dir_all(&staging).await?;
+    }
+
+    let config_root = run_dir.join("config");
+    if config_root.exists() {
+        fs::remove_dir_all(&config_root).await?;
+    }
+    for (key, content) in &env.config {
+        let path = config_root.join(key);
+        if let Some(parent) = path.parent() {
+            mkdir!(parent).await?;
+        }
+        write_file!(&path, content).await?;
+    }
+    println!(
+        "imported {} mod(s) and {} config file(s) into '{}'",
+        env.mods.len() - missing.len(),
+        env.config.len(),
+        run_dir.display()
+    );
+    if !missing.is_empty() {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "These mods are not downloadable and have to be added to '{}' manually: {}",
+                mods_root.display(),
+                missing.join(", ")
+            ),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Check that a path in the environment is relative without `..`, and a single file name
+/// if `file_name` is set
+fn check_env_path(kind: &str, path: &str, file_name: bool) -> IoResult<()> {
+    let components = Path::new(path).components().collect::<Vec<_>>();
+    let valid = !components.is_empty()
+        && components.iter().all(|x| matches!(x, Component::Normal(_)))
+        && (!file_name || components.len() == 1);
+    if !valid {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The environment has an invalid {kind} path '{path}'"),
+        ))?;
+    }
+    Ok(())
+}
+
+/// The mod files in the mods directory
+async fn mod_files(mods_root: &Path) -> IoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dir = fs::read_dir(mods_root).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        if entry.file_type().await?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Urls of the mods in mcmod.yaml, the mod sets and the pack being run, keyed by file name
+async fn known_urls(project: &Project) -> IoResult<BTreeMap<String, String>> {
+    let mcmod = project.mcmod().await?;
+    let mut sources = mcmod
+        .mods
+        .iter()
+        .chain(mcmod.mod_sets.values().flatten())
+        .map(|x| x.source().to_owned())
+        .collect::<Vec<_>>();
+    if let Some(pack) = &project.pack {
+        for spec in ModPack::load(project, pack).await?.mods {
+            sources.push(modpack::resolve_ref(spec.source())?);
+        }
+    }
+    let mut urls = BTreeMap::new();
+    for source in &sources {
+        let name = match sync::download_file_name(source) {
+            Some(x) => x,
+            None => continue,
+        };
+        if let Some(url) = sync::download_url(source, MODS_CDN_URL) {
+            urls.insert(name.to_owned(), url);
+        }
+    }
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_paths_stay_in_the_run_dir() {
+        assert!(check_env_path("config file", "forge.cfg", false).is_ok());
+        assert!(check_env_path("config file", "sub/mod.cfg", false).is_ok());
+        assert!(check_env_path("config file", "../x", false).is_err());
+        assert!(check_env_path("config file", "a/../../x", false).is_err());
+        assert!(check_env_path("config file", "/home/u/.bashrc", false).is_err());
+        assert!(check_env_path("config file", "", false).is_err());
+        assert!(check_env_path("mod", "mod.jar", true).is_ok());
+        assert!(check_env_path("mod", "sub/mod.jar", true).is_err());
+        assert!(check_env_path("mod", "..", true).is_err());
+    }
+}
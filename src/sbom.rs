@@ -0,0 +1,226 @@
+//! Software bill of materials of the libs and mods used by the project
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde_json::{json, Value};
+
+use crate::apijar;
+use crate::devjar;
+use crate::hash;
+use crate::jar::JarInfo;
+use crate::state::write_json;
+use crate::sync;
+use crate::util::{cd, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct SbomCommand {
+    /// Where to write the SBOM. Default is target/sbom.cdx.json
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl SbomCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let handler = mcmod.template.new_handler();
+
+        let mut components = Vec::new();
+        let libs_dir = handler.libs_dir(&project)?;
+        let resolved = devjar::Lockfile::load(&project)
+            .await
+            .apply(&mcmod.full_libs());
+        for lib in &resolved.libs {
+            components.push(component(&project, lib, "lib", &libs_dir, sync::LIBS_CDN_URL).await?);
+        }
+        // the jar with SRG names the dev jar is remapped from
+        let srg_cache_dir = devjar::cache_dir(&project);
+        for lib in &resolved.remap {
+            components
+                .push(component(&project, lib, "lib", &srg_cache_dir, sync::LIBS_CDN_URL).await?);
+        }
+        // the full jar the API is extracted from
+        let api_cache_dir = apijar::cache_dir(&project);
+        for lib in mcmod.api_libs() {
+            components.push(
+                component(
+                    &project,
+                    &lib.source,
+                    "lib",
+                    &api_cache_dir,
+                    sync::LIBS_CDN_URL,
+                )
+                .await?,
+            );
+        }
+        let mods_dir = sync::mods_cache_dir(&project);
+        for mod_source in mcmod.all_mods() {
+            components.push(
+                component(&project, &mod_source, "mod", &mods_dir, sync::MODS_CDN_URL).await?,
+            );
+        }
+
+        let sbom = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "component": {
+                    "type": "application",
+                    "bom-ref": mcmod.modid,
+                    "group": mcmod.group,
+                    "name": mcmod.name,
+                    "version": mcmod.artifact_version,
+                },
+                "tools": [{ "name": "mcmod" }],
+            },
+            "components": components,
+        });
+        let output = match self.output {
+            Some(x) => x,
+            None => cd!(project.target_root(), "sbom.cdx.json"),
+        };
+        write_json(&output, &sbom).await?;
+        println!(
+            "written {} component(s) to '{}'",
+            components.len(),
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+/// Create the component of a lib or mod in mcmod.yaml, reading the downloaded jar if it exists
+async fn component(
+    project: &Project,
+    source: &str,
+    kind: &str,
+    dir: &Path,
+    cdn_url_prefix: &str,
+) -> IoResult<Value> {
+    let file_name = sync::download_file_name(source).unwrap_or(source);
+    let path = dir.join(file_name);
+    let stem = Path::new(file_name)
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut component = json!({
+        "type": "library",
+        "bom-ref": format!("{kind}:{file_name}"),
+        "name": stem,
+        "properties": [
+            { "name": "mcmod:kind", "value": kind },
+            { "name": "mcmod:file", "value": file_name },
+        ],
+    });
+    if let Some(url) = sync::download_url(source, cdn_url_prefix) {
+        component["externalReferences"] = json!([{ "type": "distribution", "url": url }]);
+    }
+    if !path.is_file() {
+        println!(
+            "warning: '{}' is not downloaded, run `mcmod sync` to include its hash and metadata",
+            path.display()
+        );
+        return Ok(component);
+    }
+
+    let hashes = hash::hash_files(project, dir, vec![path.clone()]).await?;
+    if let Some(Some(hash)) = hashes.into_values().next() {
+        component["hashes"] = json!([{ "alg": "BLAKE3", "content": hash }]);
+    }
+    let info = match JarInfo::read(&path).await {
+        Ok(x) => x,
+        Err(_) => {
+            println!("warning: cannot read '{}' as a jar", path.display());
+            return Ok(component);
+        }
+    };
+    let metadata = JarMetadata::read(&info);
+    if let Some(name) = metadata.name {
+        component["name"] = json!(name);
+    }
+    if let Some(version) = metadata.version {
+        component["version"] = json!(version);
+    }
+    if let Some(license) = metadata.license {
+        component["licenses"] = json!([{ "license": { "name": license } }]);
+    }
+    Ok(component)
+}
+
+/// Name, version and license from the mod metadata or manifest of a jar
+#[derive(Default)]
+struct JarMetadata {
+    name: Option<String>,
+    version: Option<String>,
+    license: Option<String>,
+}
+
+impl JarMetadata {
+    fn read(info: &JarInfo) -> Self {
+        let mut metadata = Self {
+            name: info.manifest.get("Implementation-Title").cloned(),
+            version: info.manifest.get("Implementation-Version").cloned(),
+            license: info.manifest.get("Bundle-License").cloned(),
+        };
+        match &info.mod_metadata {
+            Some((name, content)) if name == "mcmod.info" => metadata.read_mcmod_info(content),
+            Some((_, content)) => metadata.read_mods_toml(content),
+            None => {}
+        }
+        metadata
+    }
+
+    /// Read the first mod in mcmod.info, which is either a list of mods or `{"modList": [...]}`
+    fn read_mcmod_info(&mut self, content: &str) {
+        let value: Value = match serde_json::from_str(content) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let entry = match value.get("modList") {
+            Some(list) => list.get(0),
+            None => value.get(0),
+        };
+        let entry = match entry {
+            Some(x) => x,
+            None => return,
+        };
+        if let Some(name) = entry.get("name").and_then(Value::as_str) {
+            self.name = Some(name.to_owned());
+        }
+        // versions not replaced when building look like ${version}
+        if let Some(version) = entry.get("version").and_then(Value::as_str) {
+            if !version.contains("${") {
+                self.version = Some(version.to_owned());
+            }
+        }
+    }
+
+    /// Read the license, and the first display name and version in mods.toml
+    fn read_mods_toml(&mut self, content: &str) {
+        let mut found = (false, false, false);
+        for line in content.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim().trim_matches('"')),
+                None => continue,
+            };
+            match key {
+                "license" if !found.0 => {
+                    self.license = Some(value.to_owned());
+                    found.0 = true;
+                }
+                "displayName" if !found.1 => {
+                    self.name = Some(value.to_owned());
+                    found.1 = true;
+                }
+                "version" if !found.2 && !value.contains("${") => {
+                    self.version = Some(value.to_owned());
+                    found.2 = true;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Check that the game boots with the mod
+
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+
+use crate::run::{self, Side};
+use crate::sync::{self, SyncCommand};
+use crate::util::{self, kill_process_tree, new_process_group, IoResult, Project, ProjectArgs};
+
+/// Logged by the server when it's done starting
+const SERVER_DONE: &str = "Done (";
+/// Logged by FML when all mods are loaded, used for the client
+const CLIENT_DONE: &str = "Forge Mod Loader has successfully loaded";
+/// Lines that mean the game crashed
+const CRASH_MARKERS: &[&str] = &[
+    "---- Minecraft Crash Report ----",
+    "Encountered an unexpected exception",
+    "The state engine was in incorrect state ERRORED",
+];
+
+#[derive(Debug, Parser)]
+pub struct SmoketestCommand {
+    /// Start the client instead of the server, in a virtual framebuffer
+    /// with xvfb-run when it's available
+    #[arg(long)]
+    pub client: bool,
+
+    /// Seconds to wait for the game to start
+    #[arg(long, default_value = "600")]
+    pub timeout: u64,
+}
+
+impl SmoketestCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        if !sync::is_up_to_date(&project).await? {
+            let sync = SyncCommand {
+                incremental: true,
+                eclipse: false,
+                pull_back: false,
+                overwrite: false,
+                ninja_args: Vec::new(),
+                no_prune: false,
+                show_properties_diff: false,
+                fix: false,
+                keep_going: false,
+            };
+            sync.run(args).await?;
+        }
+        let mcmod = project.mcmod().await?;
+        let template_handler = mcmod.template.new_handler();
+        let task = if self.client {
+            "runClient"
+        } else {
+            run::agree_to_eula(template_handler.as_ref(), &project, true).await?;
+            "runServer"
+        };
+        let side = if self.client {
+            Side::Client
+        } else {
+            Side::Server
+        };
+        sync::link_mods(template_handler.as_ref(), &project, Some(&side), None).await?;
+        let init_script = run::write_init_script(&project, &mcmod.jvm_args, &[]).await?;
+        let init_script = util::gradle_path(&init_script);
+        let mut command =
+            template_handler.gradlew_command(&project, &[task, "--init-script", &init_script])?;
+        if self.client && cfg!(unix) && which("xvfb-run") {
+            println!("running client with xvfb-run");
+            let mut xvfb = std::process::Command::new("xvfb-run");
+            xvfb.arg("-a")
+                .arg(command.get_program())
+                .args(command.get_args());
+            if let Some(dir) = command.get_current_dir() {
+                xvfb.current_dir(dir);
+            }
+            for (key, value) in command.get_envs() {
+                if let Some(value) = value {
+                    xvfb.env(key, value);
+                }
+            }
+            command = xvfb;
+        }
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        new_process_group(&mut command);
+        let mut child = Command::from(command).spawn()?;
+
+        let done_marker = if self.client {
+            CLIENT_DONE
+        } else {
+            SERVER_DONE
+        };
+        let timeout = Duration::from_secs(self.timeout);
+        let result = tokio::time::timeout(timeout, wait_for_start(&mut child, done_marker)).await;
+        let result = match result {
+            Ok(x) => x,
+            Err(_) => Err(format!("game did not start in {} seconds", self.timeout)),
+        };
+        stop(&mut child, !self.client && result.is_ok()).await?;
+
+        match result {
+            Ok(()) => {
+                println!("smoke test passed");
+                Ok(())
+            }
+            Err(e) => Err(io::Error::other(format!("Smoke test failed: {e}")))?,
+        }
+    }
+}
+
+/// Echo the output of the game until it's started, crashed or exited
+async fn wait_for_start(child: &mut Child, done_marker: &str) -> Result<(), String> {
+    let stdout = match child.stdout.take() {
+        Some(x) => x,
+        None => return Err("cannot read output of the game".to_owned()),
+    };
+    let mut lines = BufReader::new(stdout).lines();
+    let mut crashed = None;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        println!("{line}");
+        if crashed.is_none() && CRASH_MARKERS.iter().any(|x| line.contains(x)) {
+            crashed = Some(line.trim().to_owned());
+        }
+        if crashed.is_none() && line.contains(done_marker) {
+            // keep echoing the output while the game is stopping
+            tokio::spawn(async move {
+                while let Ok(Some(line)) = lines.next_line().await {
+                    println!("{line}");
+                }
+            });
+            return Ok(());
+        }
+    }
+    match crashed {
+        Some(line) => Err(format!("game crashed: {line}")),
+        None => Err("game exited before it started".to_owned()),
+    }
+}
+
+/// Stop the game. The server is asked to stop first, and killed if it doesn't
+async fn stop(child: &mut Child, graceful: bool) -> IoResult<()> {
+    if graceful {
+        if let Some(stdin) = child.stdin.as_mut() {
+            let _ = stdin.write_all(b"stop\n").await;
+            let _ = stdin.flush().await;
+        }
+        let wait = tokio::time::timeout(Duration::from_secs(30), child.wait()).await;
+        if wait.is_ok() {
+            return Ok(());
+        }
+        println!("server did not stop in time, killing it");
+    }
+    if let Some(pid) = child.id() {
+        kill_process_tree(pid);
+    }
+    let _ = child.kill().await;
+    Ok(())
+}
+
+/// If an executable is in PATH
+fn which(name: &str) -> bool {
+    std::process::Command::new(name)
+        .arg("--help")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
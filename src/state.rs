@@ -0,0 +1,242 @@
+//! State files the tool keeps in the target directory
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io};
+
+use crate::mcmod::MCMOD_INFO_EXTRA;
+use crate::util::{write_file, IoResult, Project};
+
+/// Read a JSON state file, returning None if it doesn't exist or is invalid
+pub async fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let content = fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Write a JSON state file
+pub async fn write_json<T: Serialize>(path: &Path, value: &T) -> IoResult<()> {
+    let content = match serde_json::to_string_pretty(value) {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
+    write_file!(path, content).await?;
+    Ok(())
+}
+
+/// Progress of setting up the template in the target directory, so a failed
+/// setup can resume from the step that failed
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TemplateState {
+    /// Name of the template in the target directory
+    pub template: String,
+    /// The template is cloned and patched
+    pub cloned: bool,
+    /// The gradle setup of the template is done
+    pub gradle_setup: bool,
+    /// The eclipse project is generated
+    pub eclipse: bool,
+    /// Key of the decompiled workspace made by the gradle setup, to keep it in the cache
+    #[serde(default)]
+    pub decomp_key: Option<String>,
+    /// Directories of the decompiled workspace in the target
+    #[serde(default)]
+    pub decomp_dirs: Vec<String>,
+}
+
+impl TemplateState {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-template")
+    }
+
+    /// Load the state of the target directory
+    pub async fn load(project: &Project) -> Self {
+        let path = Self::path(project);
+        if let Some(state) = read_json(&path).await {
+            return state;
+        }
+        // older versions only store the template name after the whole setup is done
+        match fs::read_to_string(&path).await {
+            Ok(name) if !name.trim().is_empty() => Self {
+                template: name.trim().to_owned(),
+                cloned: true,
+                gradle_setup: true,
+                eclipse: true,
+                ..Default::default()
+            },
+            _ => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+
+    /// If the template is fully set up
+    pub fn is_setup(&self) -> bool {
+        self.cloned && self.gradle_setup
+    }
+}
+
+/// Inputs of the last successful build, to skip gradle when nothing changed
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildState {
+    /// Hash of the synced files that affect the build
+    pub inputs: String,
+    /// Version from git, for templates that use it
+    pub git_version: String,
+}
+
+impl BuildState {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-build-state")
+    }
+
+    pub async fn load(project: &Project) -> Option<Self> {
+        read_json(&Self::path(project)).await
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+
+    /// Remove the state, so a failed build is not considered up to date
+    pub async fn remove(project: &Project) -> IoResult<()> {
+        let path = Self::path(project);
+        if path.exists() {
+            fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Config used by the last full sync, to detect when an incremental sync is not enough
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SyncState {
+    /// Hash of the config after applying the target and profile
+    pub config: String,
+}
+
+impl SyncState {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-sync-state")
+    }
+
+    /// Get the state for the current config of the project,
+    /// including mcmod.info.extra.json
+    pub async fn current(project: &Project) -> IoResult<Self> {
+        let config = match serde_json::to_string(project.mcmod().await?) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(config.as_bytes());
+        if let Ok(extra) = fs::read(project.root.join(MCMOD_INFO_EXTRA)).await {
+            hasher.update(&extra);
+        }
+        Ok(Self {
+            config: hasher.finalize().to_hex().to_string(),
+        })
+    }
+
+    pub async fn load(project: &Project) -> Option<Self> {
+        read_json(&Self::path(project)).await
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+}
+
+/// Hashes of the files mcmod manages in the target directory, as of the last sync
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    /// Hash of each file, keyed by the path relative to the target directory
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-manifest")
+    }
+
+    pub async fn load(project: &Project) -> Option<Self> {
+        read_json(&Self::path(project)).await
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+}
+
+/// Hashes of files from previous runs, reused while the size and modified time of the file
+/// stay the same, so change detection doesn't read every file again
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HashCache {
+    /// Cached hash of each file, keyed by the absolute path
+    pub files: BTreeMap<String, CachedHash>,
+    /// If the cache needs to be saved
+    #[serde(skip)]
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CachedHash {
+    pub size: u64,
+    /// Modified time in nanoseconds since the epoch
+    pub modified: u64,
+    pub hash: String,
+}
+
+impl HashCache {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-hash-cache")
+    }
+
+    pub async fn load(project: &Project) -> Self {
+        read_json(&Self::path(project)).await.unwrap_or_default()
+    }
+
+    /// Save the cache if it changed. It's not pretty printed since it can have many files
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let content = match serde_json::to_string(self) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        write_file!(&Self::path(project), content).await?;
+        Ok(())
+    }
+}
+
+/// Hashes of the sources of the workspace members, as of their last successful build
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WorkspaceState {
+    /// Hash of the sources of each member, keyed by the directory relative to the workspace root
+    pub members: BTreeMap<String, String>,
+}
+
+impl WorkspaceState {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-workspace-state")
+    }
+
+    pub async fn load(project: &Project) -> Self {
+        read_json(&Self::path(project)).await.unwrap_or_default()
+    }
+
+    pub async fn save(&self, project: &Project) -> IoResult<()> {
+        write_json(&Self::path(project), self).await
+    }
+}
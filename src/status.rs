@@ -0,0 +1,197 @@
+//! Summary of the workspace state, and what syncing would do
+
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::apijar;
+use crate::build;
+use crate::devjar;
+use crate::gradle;
+use crate::state::{Manifest, SyncState, TemplateState};
+use crate::sync;
+use crate::util::{self, cd, IoResult, Project, ProjectArgs};
+use crate::verify;
+
+#[derive(Debug, Parser)]
+pub struct StatusCommand {}
+
+impl StatusCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        mcmod.validate()?;
+        let handler = mcmod.template.new_handler();
+        let target_root = project.target_root();
+        if let Some(target) = &project.target {
+            println!("target: {target}");
+        }
+
+        let version = match build::git_version(handler.as_ref(), &project) {
+            x if x.is_empty() => mcmod.artifact_version.clone(),
+            x => format!("{x} (from git)"),
+        };
+        println!("version: {version}");
+
+        let state = TemplateState::load(&project).await;
+        let template_name = mcmod.template.to_string();
+        if !state.cloned {
+            println!("template: {template_name}, not cloned");
+            println!();
+            println!("run `mcmod sync` to set up the target directory");
+            return Ok(());
+        }
+        if state.template != template_name {
+            println!(
+                "template: {} is cloned, but mcmod.yaml uses {template_name}",
+                state.template
+            );
+            println!();
+            println!("run `mcmod sync` to set up the target directory with the new template");
+            return Ok(());
+        }
+        // downloaded templates are not git repositories
+        let rev = if target_root.join(".git").exists() {
+            util::git_output(&target_root, &["rev-parse", "--short", "HEAD"])
+        } else {
+            String::new()
+        };
+        let setup = if state.gradle_setup {
+            "set up"
+        } else {
+            "gradle setup not done"
+        };
+        match rev.as_str() {
+            "" => println!("template: {template_name}, {setup}"),
+            rev => println!("template: {template_name} at {rev}, {setup}"),
+        }
+
+        let mut actions = Vec::new();
+        let last_sync = match fs::metadata(Manifest::path(&project)).await {
+            Ok(x) => Some(x.modified()?),
+            Err(_) => None,
+        };
+        match last_sync {
+            Some(time) => println!("last sync: {}", format_ago(time)),
+            None => println!("last sync: never"),
+        }
+        if SyncState::load(&project).await != Some(SyncState::current(&project).await?) {
+            println!("config: changed since the last full sync");
+            actions.push("do a full sync for the config changes");
+        } else {
+            println!("config: unchanged");
+        }
+        let sources_changed = match last_sync {
+            Some(time) => sync::sources_modified_since(&project, time).await?,
+            None => true,
+        };
+        if sources_changed {
+            println!("sources: changed since the last sync");
+            actions.push("copy the changed sources and assets");
+        } else {
+            println!("sources: unchanged");
+        }
+        let edited = verify::edited_copies(&project).await?;
+        if !edited.is_empty() {
+            println!(
+                "edits: {} synced file(s) edited in the target directory",
+                edited.len()
+            );
+            for (target, _) in &edited {
+                println!("  {}", target.display());
+            }
+            actions.push("stop because of the target edits. Use --pull-back or --overwrite");
+        }
+
+        let resolved = devjar::Lockfile::load(&project)
+            .await
+            .apply(&mcmod.full_libs());
+        let mut missing_libs = missing_downloads(&resolved.libs, &handler.libs_dir(&project)?);
+        missing_libs.extend(missing_downloads(
+            &resolved.remap,
+            &devjar::cache_dir(&project),
+        ));
+        let api_sources = mcmod
+            .api_libs()
+            .iter()
+            .map(|x| x.source.clone())
+            .collect::<Vec<_>>();
+        missing_libs.extend(missing_downloads(
+            &api_sources,
+            &apijar::cache_dir(&project),
+        ));
+        let missing_mods = missing_downloads(&mcmod.all_mods(), &sync::mods_cache_dir(&project));
+        if missing_libs.is_empty() && missing_mods.is_empty() {
+            println!("downloads: all downloaded");
+        } else {
+            println!(
+                "downloads: {} lib(s) and {} mod(s) pending",
+                missing_libs.len(),
+                missing_mods.len()
+            );
+            for name in missing_libs.iter().chain(&missing_mods) {
+                println!("  {name}");
+            }
+            actions.push("download the pending libs and mods");
+        }
+
+        let gradle_properties = cd!(target_root.clone(), "gradle.properties");
+        let old = fs::read_to_string(&gradle_properties)
+            .await
+            .unwrap_or_default();
+        let properties = sync::gradle_properties_to_merge(handler.as_ref(), &project).await?;
+        let new = gradle::merge_properties_content(&old, properties);
+        let drift = count_changed_lines(&old, &new);
+        if drift == 0 {
+            println!("gradle.properties: up to date");
+        } else {
+            println!("gradle.properties: {drift} line(s) differ from mcmod.yaml");
+            actions.push("update gradle.properties. See the changes with `mcmod sync --show-properties-diff`");
+        }
+
+        println!();
+        if actions.is_empty() {
+            println!("everything is up to date");
+        } else {
+            println!("`mcmod sync` will:");
+            for action in actions {
+                println!("  - {action}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the file names of the libs or mods that are not in the download directory
+fn missing_downloads(sources: &[String], dir: &Path) -> Vec<String> {
+    sources
+        .iter()
+        .filter_map(|x| sync::download_file_name(x))
+        .filter(|x| !dir.join(x).is_file())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Count the lines that differ, compared by position like `print_properties_diff`
+fn count_changed_lines(old: &str, new: &str) -> usize {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    new.lines()
+        .enumerate()
+        .filter(|(i, line)| old_lines.get(*i) != Some(line))
+        .count()
+}
+
+fn format_ago(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    match secs {
+        0..=59 => format!("{secs}s ago"),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
@@ -1,24 +1,42 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use quick_xml::events::{BytesStart, BytesText, Event};
-use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
+use tokio::fs;
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
 
 use clap::Parser;
 use quick_xml::{Reader, Writer};
 use reqwest::Client;
+use walkdir::WalkDir;
 
+use crate::apijar;
+use crate::assets;
+use crate::build;
+use crate::decompile;
+use crate::devjar;
+use crate::explain::ErrorCode;
+use crate::extends;
+use crate::generate;
 use crate::gradle;
-use crate::template::{self, TemplateHandler};
-use crate::util::{cd, join_join_set, mkdir, write_file, IoResult, Project};
+use crate::mcmod::{is_preprocessed, read_mcmod_info_extra, DownloadOptions, ModSide};
+use crate::ninja;
+use crate::run::Side;
+use crate::state::{Manifest, SyncState, TemplateState};
+use crate::template::{self, Template, TemplateHandler};
+use crate::timings;
+use crate::util::{
+    self, cd, git_output, join_join_set, mkdir, write_file, IoResult, Project, ProjectArgs,
+};
+use crate::verify;
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 pub struct SyncCommand {
     /// If syncing incrementally.
     ///
@@ -30,69 +48,142 @@ pub struct SyncCommand {
     /// Force syncing eclipse project
     #[arg(long)]
     pub eclipse: bool,
+
+    /// Copy files edited in the target directory back to the project before syncing
+    #[arg(long, conflicts_with = "overwrite")]
+    pub pull_back: bool,
+
+    /// Overwrite files edited in the target directory
+    #[arg(long)]
+    pub overwrite: bool,
+
+    /// Extra argument for ninja, like `--ninja-arg=-j4`, `--ninja-arg=-v` or `--ninja-arg=-dexplain`.
+    /// Can be specified multiple times
+    #[arg(long = "ninja-arg", allow_hyphen_values = true)]
+    pub ninja_args: Vec<String>,
+
+    /// Don't remove files in libs and mods that are not in mcmod.yaml
+    #[arg(long)]
+    pub no_prune: bool,
+
+    /// Print the changes to gradle.properties, and if each property is from the template
+    /// or gradle-overrides
+    #[arg(long)]
+    pub show_properties_diff: bool,
+
+    /// Fix the assets directories and resource locations that don't match the mod id
+    #[arg(long)]
+    pub fix: bool,
+
+    /// Finish the other downloads and copies when one fails, and list all failures at the end
+    #[arg(short, long)]
+    pub keep_going: bool,
 }
 
 impl SyncCommand {
-    pub async fn run(mut self, dir: &str) -> IoResult<()> {
-        let project = Project::new_in(dir)?;
+    pub async fn run(mut self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        if let Some(target) = &project.target {
+            println!("syncing target '{target}'");
+        }
+        project.mcmod().await?.validate()?;
+        let _lock = project.lock_target().await?;
+        if !self.overwrite {
+            guard_target_edits(&project, self.pull_back).await?;
+        }
 
-        let template_marker = project.target_root().join(".mcmod-template");
-        if !template_marker.exists() && !self.incremental {
+        let is_pack = project.mcmod().await?.is_pack();
+        let mut state = TemplateState::load(&project).await;
+        // packs don't use the template, so there is nothing to set up
+        if self.incremental && !is_pack && !state.is_setup() {
             println!("forcing non-incremental sync since template has not been setup");
             self.incremental = false;
         }
+        let sync_state = SyncState::current(&project).await?;
+        if self.incremental && SyncState::load(&project).await.as_ref() != Some(&sync_state) {
+            println!(
+                "forcing non-incremental sync since the config has changed since the last sync"
+            );
+            self.incremental = false;
+        }
 
         if self.incremental {
-            sync_source(&project, self.incremental).await?;
+            if self.show_properties_diff {
+                println!("gradle.properties is not changed by incremental sync");
+            }
+            timings::time(
+                "sync: source",
+                sync_source(&project, self.incremental, &self.ninja_args),
+            )
+            .await?;
+            timings::time("sync: manifest", verify::write_manifest(&project)).await?;
+            return Ok(());
+        }
+        timings::time(
+            "sync: check assets",
+            assets::check_assets(&project, self.fix),
+        )
+        .await?;
+        if is_pack {
+            println!("syncing source");
+            timings::time(
+                "sync: source",
+                sync_source(&project, false, &self.ninja_args),
+            )
+            .await?;
+            println!("syncing metadata");
+            timings::time("sync: metadata", sync_metadata(&project)).await?;
+            timings::time("sync: manifest", verify::write_manifest(&project)).await?;
+            sync_state.save(&project).await?;
+            println!("sync done");
             return Ok(());
         }
 
         let template = &project.mcmod().await?.template;
-        let template_handler = template.new_handler();
+        let mut template_handler = template.new_handler();
+        if let Some(java_version) = template_handler.capabilities().java_versions.first() {
+            gradle::java_home(*java_version)?;
+        }
 
         let template_name = template.to_string();
-        let template_marked = match fs::read_to_string(&template_marker).await {
-            Ok(s) => s,
-            Err(_) => String::new(),
-        };
-
-        let template_updated = template_marked.trim() != template_name;
+        let template_updated = state.template != template_name || !state.cloned;
         if template_updated {
             println!(
                 "template is not initialized or has changed. initializing new target directory"
             );
             let target_root = project.target_root();
             if target_root.exists() {
+                // the cache only saves time, so a sync doesn't fail on it
+                if let Err(e) = template::save_decomp_cache(&project).await {
+                    println!("warning: cannot keep the decompiled workspace in the cache: {e:?}");
+                }
                 fs::remove_dir_all(&target_root).await?;
             }
             let templates = template::read_templates().await?;
             let template_def = match templates.get(&template_name) {
                 Some(t) => t,
-                None => Err(io::Error::new(
+                None => Err(ErrorCode::TemplateNotFound.error(io::Error::new(
                     io::ErrorKind::NotFound,
                     format!("Template '{}' not found in templates.json. You either specified an invalid template or this is a bug", template_name),
-                ))?,
+                )))?,
             };
-            {
-                let status = Command::new("git")
-                    .args([
-                        "clone",
-                        "--branch",
-                        &template_def.branch,
-                        "--depth",
-                        "1",
-                        "--recurse-submodules",
-                        "--",
-                        &template_def.url,
-                        target_root.to_str().unwrap(),
-                    ])
-                    .status()?;
-
-                if !status.success() {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Failed to clone template",
-                    ))?;
+            timings::time("sync: fetch template", template_def.fetch(&target_root)).await?;
+            apply_template_patches(&project, &template_name).await?;
+            template_def
+                .run_post_clone(&project, &template_name)
+                .await?;
+            state = TemplateState {
+                template: template_name.clone(),
+                cloned: true,
+                ..Default::default()
+            };
+            state.save(&project).await?;
+            // custom templates are described by the manifest they ship, which is only available now
+            if let Template::Custom(_) = template {
+                template_handler = template.new_handler();
+                template_handler.validate(project.mcmod().await?)?;
+                if let Some(java_version) = template_handler.capabilities().java_versions.first() {
+                    gradle::java_home(*java_version)?;
                 }
             }
         } else {
@@ -100,65 +191,349 @@ impl SyncCommand {
         }
 
         println!("syncing gradle properties");
-        sync_gradle_properties(template_handler.as_ref(), &project).await?;
+        timings::time(
+            "sync: gradle properties",
+            sync_gradle_properties(
+                template_handler.as_ref(),
+                &project,
+                self.show_properties_diff,
+            ),
+        )
+        .await?;
         println!("syncing source");
-        sync_source(&project, self.incremental).await?;
+        timings::time(
+            "sync: source",
+            sync_source(&project, self.incremental, &self.ninja_args),
+        )
+        .await?;
 
         println!("syncing metadata");
-        sync_metadata(&project).await?;
+        timings::time("sync: metadata", async {
+            sync_metadata(&project).await?;
+            template_handler.sync_template(&project).await
+        })
+        .await?;
+        if project.mcmod().await?.extends.is_some() {
+            println!("syncing parent mod");
+            timings::time("sync: parent mod", extends::sync_parent(&project)).await?;
+        }
         println!("syncing libs");
-        let libs_changed = sync_libs(template_handler.as_ref(), &project).await?;
+        let downloader = Downloader::new(&project.mcmod().await?.downloads)?;
+        let prune = Prune {
+            enabled: !self.no_prune,
+            keep: &project.mcmod().await?.keep,
+            generated: &[],
+        };
+        let libs_result = timings::time(
+            "sync: libs",
+            sync_libs(
+                template_handler.as_ref(),
+                &project,
+                &downloader,
+                &prune,
+                self.keep_going,
+            ),
+        )
+        .await;
+        // with --keep-going, the mods are still synced when a lib failed
+        let libs_result = match libs_result {
+            Err(e) if !self.keep_going => return Err(e),
+            x => x,
+        };
         println!("syncing mods");
-        let mods_changed = sync_mods(template_handler.as_ref(), &project).await?;
+        let mods_result = timings::time(
+            "sync: mods",
+            sync_mods(
+                template_handler.as_ref(),
+                &project,
+                &downloader,
+                &prune,
+                self.keep_going,
+            ),
+        )
+        .await;
+        let (libs_changed, mods_changed) = match (libs_result, mods_result) {
+            (Ok(libs), Ok(mods)) => (libs, mods),
+            (Err(mut libs), Err(mods)) => {
+                libs.extend_one(mods);
+                return Err(libs);
+            }
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
 
-        if template_updated {
-            println!("setting up target template '{template_name}'");
-            template_handler.setup_project(&project).await?;
-            write_file!(&template_marker, &template_name).await?;
+        if !state.gradle_setup {
+            let skip_gradle_setup = template::read_templates()
+                .await?
+                .get(&template_name)
+                .is_some_and(|x| x.skip_gradle_setup);
+            if skip_gradle_setup {
+                println!("skipping gradle setup of target template '{template_name}'");
+            } else {
+                let key = template::decomp_cache_key(template_handler.as_ref(), &project).await?;
+                if let Err(e) =
+                    template::restore_decomp_cache(template_handler.as_ref(), &project, &key).await
+                {
+                    println!(
+                        "warning: cannot restore the decompiled workspace from the cache: {e:?}"
+                    );
+                }
+                println!("setting up target template '{template_name}'");
+                timings::time(
+                    "sync: gradle setup",
+                    template_handler.setup_project(&project),
+                )
+                .await?;
+                state.decomp_key = Some(key);
+                state.decomp_dirs = template_handler.decomp_cache_dirs();
+            }
+            state.gradle_setup = true;
+            state.save(&project).await?;
         }
 
-        if self.eclipse || template_updated || libs_changed || mods_changed {
-            println!("syncing eclipse");
-            sync_eclipse_workspace(template_handler.as_ref(), &project).await?;
+        let libs_remapped = timings::time(
+            "sync: remap libs",
+            devjar::remap_jars(&project, &template_handler.libs_dir(&project)?),
+        )
+        .await?;
+
+        if self.eclipse || !state.eclipse || libs_changed || mods_changed || libs_remapped {
+            if project.target.is_none() {
+                println!("syncing eclipse");
+                timings::time(
+                    "sync: eclipse",
+                    sync_eclipse_workspace(template_handler.as_ref(), &project),
+                )
+                .await?;
+                state.eclipse = true;
+                state.save(&project).await?;
+            } else if self.eclipse {
+                println!("skipping eclipse since it's only synced for the top-level template");
+            }
         }
 
+        timings::time("sync: manifest", verify::write_manifest(&project)).await?;
+        sync_state.save(&project).await?;
         println!("sync done");
 
         Ok(())
     }
 }
 
-async fn sync_gradle_properties(handler: &dyn TemplateHandler, project: &Project) -> IoResult<()> {
-    println!("updating gradle.properties");
+/// Check if nothing changed since the last sync, without running ninja.
+///
+/// This is true if the template is set up, the config is the same as the last full sync,
+/// and nothing in the copied paths is newer than the last sync
+pub async fn is_up_to_date(project: &Project) -> IoResult<bool> {
+    if !TemplateState::load(project).await.is_setup()
+        || !project.build_ninja().exists()
+        || SyncState::load(project).await != Some(SyncState::current(project).await?)
+    {
+        return Ok(false);
+    }
+    let last_sync = match fs::metadata(Manifest::path(project)).await {
+        Ok(x) => x.modified()?,
+        Err(_) => return Ok(false),
+    };
+    Ok(!sources_modified_since(project, last_sync).await?)
+}
+
+/// Check if anything in the copied paths is newer than the time
+pub async fn sources_modified_since(project: &Project, time: SystemTime) -> IoResult<bool> {
+    let mcmod = project.mcmod().await?;
+    let mut sources = Vec::new();
+    for copy_path in &mcmod.copy_paths {
+        if !copy_path.applies_to(project, &mcmod.template) {
+            continue;
+        }
+        if let Some((source, _)) = copy_path.source_target() {
+            sources.push(project.root.join(source));
+        }
+        if let Some(generator) = copy_path.generator() {
+            sources.extend(generator.inputs.iter().map(|x| project.root.join(x)));
+        }
+    }
+    let result = tokio::task::spawn_blocking(move || {
+        for source in sources {
+            for entry in WalkDir::new(source) {
+                let modified = match entry.and_then(|x| x.metadata()) {
+                    Ok(x) => x.modified()?,
+                    Err(_) => return Ok(true),
+                };
+                if modified > time {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    })
+    .await;
+    match result {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::from(e))?,
+    }
+}
+
+/// Check for synced files that were edited in the target directory instead of the project,
+/// since syncing would overwrite them. If `pull_back` is true, the edits are copied back
+async fn guard_target_edits(project: &Project, pull_back: bool) -> IoResult<()> {
+    let edited = verify::edited_copies(project).await?;
+    if edited.is_empty() {
+        return Ok(());
+    }
+    let mcmod = project.mcmod().await?;
+    if pull_back {
+        for (target, source) in &edited {
+            if mcmod.preprocess && is_preprocessed(source) {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot pull back '{}' since it is preprocessed. Please move the changes manually",
+                        target.display()
+                    ),
+                ))?;
+            }
+        }
+        for (target, source) in &edited {
+            println!("pulling back '{}'", source.display());
+            fs::copy(target, source).await?;
+        }
+        return Ok(());
+    }
+    for (target, _) in &edited {
+        eprintln!("edited in target: {}", target.display());
+    }
+    Err(ErrorCode::TargetEdited.error(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} synced file(s) were edited in the target directory and would be overwritten. Run with --pull-back to copy the changes to the project, or --overwrite to discard them", edited.len()),
+    )))?
+}
+
+/// Apply patches and overlay files in template-patches/<template> to the freshly cloned template.
+///
+/// `.patch` files are applied with `git apply`. Other files are copied over the template
+async fn apply_template_patches(project: &Project, template_name: &str) -> IoResult<()> {
+    let patches_root = cd!(project.root.clone(), "template-patches", template_name);
+    if !patches_root.exists() {
+        return Ok(());
+    }
+    let target_root = project.target_root();
+    for entry in WalkDir::new(&patches_root).sort_by_file_name() {
+        let entry = match entry {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::NotFound, e))?,
+        };
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let relative = match path.strip_prefix(&patches_root) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if path.extension().is_some_and(|x| x == "patch") {
+            println!("applying patch '{}'", relative.display());
+            let status = Command::new("git")
+                .args(["apply", "--whitespace=nowarn"])
+                .arg(path)
+                .current_dir(&target_root)
+                .status()?;
+            if !status.success() {
+                Err(io::Error::other(format!(
+                    "Failed to apply template patch '{}'",
+                    path.display()
+                )))?;
+            }
+        } else {
+            println!("copying template overlay '{}'", relative.display());
+            let target = target_root.join(relative);
+            if let Some(parent) = target.parent() {
+                mkdir!(parent).await?;
+            }
+            fs::copy(path, target).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The properties from the template and `gradle-overrides` that syncing merges into gradle.properties
+pub async fn gradle_properties_to_merge(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+) -> IoResult<BTreeMap<String, String>> {
     let mut properties = handler.make_gradle_properties(project).await?;
     for (k, v) in project.mcmod().await?.gradle_overrides.iter() {
         properties.insert(k.clone(), v.clone());
     }
+    Ok(properties)
+}
+
+async fn sync_gradle_properties(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    show_diff: bool,
+) -> IoResult<()> {
+    println!("updating gradle.properties");
+    let properties = gradle_properties_to_merge(handler, project).await?;
+    let overrides = &project.mcmod().await?.gradle_overrides;
     let gradle_properties = cd!(project.target_root(), "gradle.properties");
-    gradle::merge_properties(&gradle_properties, properties).await?;
+    if !show_diff {
+        gradle::merge_properties(&gradle_properties, properties).await?;
+        return Ok(());
+    }
+    let old = if gradle_properties.exists() {
+        fs::read_to_string(&gradle_properties).await?
+    } else {
+        String::new()
+    };
+    let new = gradle::merge_properties_content(&old, properties);
+    let changed = gradle::print_properties_diff(&old, &new, |key| {
+        if overrides.contains_key(key) {
+            "gradle-overrides"
+        } else {
+            "template"
+        }
+    });
+    println!("{changed} line(s) changed in gradle.properties");
+    write_file!(&gradle_properties, new).await?;
     Ok(())
 }
 
-async fn sync_source(project: &Project, incremental: bool) -> IoResult<()> {
-    let build_ninja = project.root.join("build.ninja");
+async fn sync_source(project: &Project, incremental: bool, ninja_args: &[String]) -> IoResult<()> {
+    generate::run_generators(project).await?;
+    let build_ninja = project.build_ninja();
     if !build_ninja.exists() || !incremental {
         let mut forge_source_root = project.target_root();
         forge_source_root.push("src");
         if forge_source_root.exists() {
             fs::remove_dir_all(&forge_source_root).await?;
         }
-        let ninja_file = project
-            .mcmod()
-            .await?
-            .create_build_ninja(&project.root, &project.target_root())
-            .await?;
-        write_file!(&build_ninja, ninja_file).await?;
+        ninja::create_build_ninja(project).await?;
     }
 
-    let result = Command::new("ninja").current_dir(&project.root).status()?;
+    // relative to the root, so ninja recognizes the edge that regenerates it
+    let ninja_file = build_ninja
+        .strip_prefix(&project.root)
+        .unwrap_or(&build_ninja);
+    let result = Command::new("ninja")
+        .arg("-f")
+        .arg(ninja_file)
+        .args(&project.mcmod().await?.ninja_args)
+        .args(ninja_args)
+        .current_dir(&project.root)
+        .status()?;
 
     if !result.success() {
-        Err(io::Error::new(io::ErrorKind::Other, "ninja failed"))?;
+        Err(ErrorCode::NinjaFailed.error(io::Error::other(format!(
+            "ninja failed with '{}'. See the ninja output above for the failed step, or run with --ninja-arg=-v to see the commands",
+            build_ninja.display()
+        ))))?;
+    }
+    // the copy of the logo is replaced whenever ninja copies it again
+    assets::process_logo(project).await?;
+    let mcmod = project.mcmod().await?;
+    if !mcmod.is_pack() {
+        let handler = mcmod.template.new_handler();
+        handler.sync_generated_sources(project).await?;
     }
     Ok(())
 }
@@ -168,37 +543,291 @@ async fn sync_metadata(project: &Project) -> IoResult<()> {
     let resource_path = cd!(project.target_root(), "src", "main", "resources");
     mkdir!(&resource_path).await?;
     let mcmod_info_future = async {
-        let info_str = mcmod.create_mcmod_info()?;
+        if mcmod.coremod_only || mcmod.is_pack() {
+            return Ok(());
+        }
+        let extra = read_mcmod_info_extra(project).await?;
+        let info_str = mcmod.create_mcmod_info(extra.as_ref())?;
         write_file!(resource_path.join("mcmod.info"), info_str).await
     };
     let pack_mcmeta_future = async {
         let pack_str = mcmod.create_pack_mcmeta()?;
         write_file!(resource_path.join("pack.mcmeta"), pack_str).await
     };
-    let (r1, r2) = tokio::join!(mcmod_info_future, pack_mcmeta_future);
+    let tags_class_future = async {
+        let path = match mcmod.tags_class_path() {
+            Some(x) => cd!(project.target_root(), "src", "main", "java", x),
+            None => return Ok(()),
+        };
+        let handler = mcmod.template.new_handler();
+        let version = if mcmod.version.is_empty() {
+            build::git_version(handler.as_ref(), project)
+        } else {
+            mcmod.version.clone()
+        };
+        let git_commit = git_output(&project.root, &["rev-parse", "HEAD"]);
+        if let Some(parent) = path.parent() {
+            mkdir!(parent).await?;
+        }
+        write_file!(&path, mcmod.create_tags_class(&version, &git_commit)).await
+    };
+    let (r1, r2, r3) = tokio::join!(mcmod_info_future, pack_mcmeta_future, tags_class_future);
     r1?;
     r2?;
+    r3?;
     Ok(())
 }
 
-async fn sync_libs(template_handler: &dyn TemplateHandler, project: &Project) -> IoResult<bool> {
+async fn sync_libs(
+    template_handler: &dyn TemplateHandler,
+    project: &Project,
+    downloader: &Downloader,
+    prune: &Prune<'_>,
+    keep_going: bool,
+) -> IoResult<bool> {
     let libs_root = template_handler.libs_dir(project)?;
-    let libs = &project.mcmod().await?.libs;
-    let cdn_url_prefix = "https://cdn.pistonite.org/minecraft/devjars/";
-    let changed = sync_downloads(&libs_root, libs, cdn_url_prefix).await?;
+    let mcmod = project.mcmod().await?;
+    let cdn_url_prefix = LIBS_CDN_URL;
+    let resolved = devjar::resolve(project, &mcmod.full_libs(), &mcmod.downloads).await?;
+    let api_libs = mcmod.api_libs();
+    let generated = api_libs
+        .iter()
+        .filter_map(|x| apijar::jar_name(x))
+        .chain(resolved.remap.iter().filter_map(|x| devjar::jar_name(x)))
+        .collect::<Vec<_>>();
+    let mut changed = sync_downloads(
+        &libs_root,
+        &resolved.libs,
+        &project.root,
+        cdn_url_prefix,
+        downloader,
+        &Prune {
+            generated: &generated,
+            ..*prune
+        },
+        keep_going,
+    )
+    .await?;
+    // jars with SRG names are remapped into libs after the template is set up
+    let srg_cache_dir = devjar::cache_dir(project);
+    if !resolved.remap.is_empty() || srg_cache_dir.exists() {
+        changed |= sync_downloads(
+            &srg_cache_dir,
+            &resolved.remap,
+            &project.root,
+            cdn_url_prefix,
+            downloader,
+            &Prune {
+                enabled: true,
+                keep: &[],
+                generated: &[],
+            },
+            keep_going,
+        )
+        .await?;
+    }
+    let api_cache_dir = apijar::cache_dir(project);
+    if api_libs.is_empty() && !api_cache_dir.exists() {
+        return Ok(changed);
+    }
+    // the full jars are only used to extract the API from, so nothing else is kept there
+    let api_sources = api_libs
+        .iter()
+        .map(|x| x.source.clone())
+        .collect::<Vec<_>>();
+    changed |= sync_downloads(
+        &api_cache_dir,
+        &api_sources,
+        &project.root,
+        cdn_url_prefix,
+        downloader,
+        &Prune {
+            enabled: true,
+            keep: &[],
+            generated: &[],
+        },
+        keep_going,
+    )
+    .await?;
+    for lib in api_libs {
+        changed |= apijar::sync_jar(project, &libs_root, lib).await?;
+    }
     Ok(changed)
 }
 
-async fn sync_mods(template_handler: &dyn TemplateHandler, project: &Project) -> IoResult<bool> {
-    let mods_root = cd!(template_handler.run_dir(project)?, "mods");
-    let mods = &project.mcmod().await?.mods;
-    let cdn_url_prefix = "https://cdn.pistonite.org/minecraft/jars/";
-    let changed = sync_downloads(&mods_root, mods, cdn_url_prefix).await?;
+pub async fn sync_mods(
+    template_handler: &dyn TemplateHandler,
+    project: &Project,
+    downloader: &Downloader,
+    prune: &Prune<'_>,
+    keep_going: bool,
+) -> IoResult<bool> {
+    let mcmod = project.mcmod().await?;
+    let cdn_url_prefix = MODS_CDN_URL;
+    let cache_dir = mods_cache_dir(project);
+    // unlink everything first, since mods removed from the cache can't be found after syncing
+    let mods_root = cd!(
+        template::setup_run_dir(template_handler, project).await?,
+        "mods"
+    );
+    if cache_dir.exists() {
+        let mut dir = fs::read_dir(&cache_dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = mods_root.join(entry.file_name());
+            if path.is_file() {
+                fs::remove_file(path).await?;
+            }
+        }
+    }
+    let changed = sync_downloads(
+        &cache_dir,
+        &mcmod.all_mods(),
+        &project.root,
+        cdn_url_prefix,
+        downloader,
+        prune,
+        keep_going,
+    )
+    .await?;
+    link_mods(template_handler, project, None, None).await?;
     Ok(changed)
 }
 
-/// Sync downloads in a directory and return if anything was updated
-async fn sync_downloads(libs_root: &Path, libs: &[String], cdn_url_prefix: &str) -> IoResult<bool> {
+/// Where libs that are not urls or paths are downloaded from
+pub const LIBS_CDN_URL: &str = "https://cdn.pistonite.org/minecraft/devjars/";
+/// Where mods that are not urls or paths are downloaded from
+pub const MODS_CDN_URL: &str = "https://cdn.pistonite.org/minecraft/jars/";
+
+/// The url a lib or mod in mcmod.yaml is downloaded from. None for local paths
+pub fn download_url(lib: &str, cdn_url_prefix: &str) -> Option<String> {
+    if lib.starts_with("./") {
+        None
+    } else if lib.starts_with("http") {
+        Some(lib.to_owned())
+    } else {
+        Some(format!("{cdn_url_prefix}{lib}"))
+    }
+}
+
+/// The file name of a lib or mod in mcmod.yaml in the download directory
+pub fn download_file_name(lib: &str) -> Option<&str> {
+    if lib.starts_with("./") || lib.starts_with("http") {
+        Path::new(lib).file_name().and_then(|x| x.to_str())
+    } else {
+        Some(lib)
+    }
+}
+
+/// Directory all mods are downloaded to. The run directory links to the ones being used
+pub fn mods_cache_dir(project: &Project) -> PathBuf {
+    project.target_root().join("mods-cache")
+}
+
+/// Put the mods for running the side with the mod set into the mods directory,
+/// and remove the other mods from the cache.
+///
+/// Mods are hard linked from the cache when possible, so switching is fast.
+/// Files in the mods directory that are not from the cache are kept
+pub async fn link_mods(
+    template_handler: &dyn TemplateHandler,
+    project: &Project,
+    side: Option<&Side>,
+    mod_set: Option<&str>,
+) -> IoResult<()> {
+    let side = side.map(|x| match x {
+        Side::Client => ModSide::ClientOnly,
+        Side::Server => ModSide::ServerOnly,
+    });
+    let mcmod = project.mcmod().await?;
+    let active = mcmod
+        .mods_for(side, mod_set)?
+        .iter()
+        .filter_map(|x| Path::new(x).file_name().map(|x| x.to_os_string()))
+        .collect::<Vec<_>>();
+    let cache_dir = mods_cache_dir(project);
+    let mods_root = cd!(
+        template::setup_run_dir(template_handler, project).await?,
+        "mods"
+    );
+    mkdir!(&mods_root).await?;
+    mkdir!(&cache_dir).await?;
+    let mut dir = fs::read_dir(&cache_dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name();
+        let path = mods_root.join(&name);
+        if !active.contains(&name) {
+            if path.is_file() {
+                fs::remove_file(&path).await?;
+            }
+        } else if !path.exists() && fs::hard_link(entry.path(), &path).await.is_err() {
+            fs::copy(entry.path(), &path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// How files that are not in mcmod.yaml are removed from download directories
+pub struct Prune<'a> {
+    /// If files are removed. Set to false with --no-prune
+    pub enabled: bool,
+    /// Patterns of file names to not remove
+    pub keep: &'a [String],
+    /// Names of files the tool generates in the directory, which are not removed
+    pub generated: &'a [String],
+}
+
+/// If the copy of a local lib or mod is older than the file it's copied from,
+/// like the jar of another project in the workspace that was built again
+fn is_stale_copy(project_root: &Path, lib: &str, copy: &Path) -> bool {
+    if !lib.starts_with("./") {
+        return false;
+    }
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|x| x.modified()).ok();
+    match (modified(&project_root.join(lib)), modified(copy)) {
+        (Some(source), Some(copy)) => source > copy,
+        _ => false,
+    }
+}
+
+/// The HTTP client for downloads with the timeouts from mcmod.yaml. It's reused for all
+/// downloads of a sync, and kept by the daemon between syncs
+pub struct Downloader {
+    client: Arc<Client>,
+    read_timeout: Duration,
+    timeout: Duration,
+}
+
+impl Downloader {
+    pub fn new(options: &DownloadOptions) -> IoResult<Self> {
+        let client = match Client::builder()
+            .connect_timeout(options.connect_timeout())
+            .build()
+        {
+            Ok(x) => Arc::new(x),
+            Err(e) => Err(io::Error::other(e))?,
+        };
+        Ok(Self {
+            client,
+            read_timeout: options.read_timeout(),
+            timeout: options.timeout(),
+        })
+    }
+}
+
+/// Sync downloads in a directory and return if anything was updated.
+/// Local paths (`./...`) are relative to the project root.
+///
+/// If keep_going, the other downloads and copies continue when one fails,
+/// and all failures are listed at the end
+pub async fn sync_downloads(
+    libs_root: &Path,
+    libs: &[String],
+    project_root: &Path,
+    cdn_url_prefix: &str,
+    downloader: &Downloader,
+    prune: &Prune<'_>,
+    keep_going: bool,
+) -> IoResult<bool> {
     let mut changed = false;
     let mut needs_download = libs.iter().map(|lib| lib.as_str()).collect::<Vec<_>>();
     mkdir!(libs_root).await?;
@@ -220,12 +849,26 @@ async fn sync_downloads(libs_root: &Path, libs: &[String], cdn_url_prefix: &str)
                 lib == &name
             }
         }) {
+            Some(i) if is_stale_copy(project_root, needs_download[i], &entry.path()) => {
+                // copied again below
+            }
             Some(i) => {
                 // up to date
                 needs_download.swap_remove(i);
             }
             None => {
                 let path = entry.path();
+                if prune.generated.iter().any(|x| x == name) {
+                    continue;
+                }
+                if !prune.enabled {
+                    println!("not removing '{}' (--no-prune)", path.display());
+                    continue;
+                }
+                if prune.keep.iter().any(|x| util::wildcard_match(x, name)) {
+                    println!("keeping '{}'", path.display());
+                    continue;
+                }
                 changed = true;
                 println!("removing '{}'", path.display());
                 if path.is_dir() {
@@ -236,45 +879,62 @@ async fn sync_downloads(libs_root: &Path, libs: &[String], cdn_url_prefix: &str)
             }
         }
     }
-    let mut join_set = JoinSet::new();
-    let (send, mut recv) = mpsc::channel::<IoResult<String>>(100);
-    let client = Arc::new(Client::new());
-    join_set.spawn(async move {
-        let mut error = None;
-        while let Some(result) = recv.recv().await {
-            if error.is_some() {
-                continue;
-            }
+    let mut join_set = JoinSet::<IoResult<()>>::new();
+    // (lib, result) of each download, and of each failed copy with keep_going
+    let (send, mut recv) = mpsc::channel::<(String, IoResult<()>)>(100);
+    let client = &downloader.client;
+    let (read_timeout, timeout) = (downloader.read_timeout, downloader.timeout);
+    let receiver = tokio::spawn(async move {
+        let mut failures = Vec::new();
+        while let Some((lib, result)) = recv.recv().await {
             match result {
-                Ok(url) => {
-                    println!("downloaded '{}'", url);
+                Ok(()) => {
+                    if failures.is_empty() || keep_going {
+                        println!("downloaded '{lib}'");
+                    }
                 }
                 Err(e) => {
-                    error = Some(e);
-                    recv.close();
+                    if keep_going {
+                        eprintln!("failed '{lib}', continuing with the others");
+                    } else if failures.is_empty() {
+                        recv.close();
+                    }
+                    failures.push((lib, e));
                 }
             }
         }
-        match error {
-            Some(e) => Err(e),
-            None => Ok(()),
-        }
+        failures
     });
     if !needs_download.is_empty() {
         changed = true;
     }
+    let total = needs_download.len();
     for lib in needs_download {
         if lib.starts_with("./") {
-            let file_name = match Path::new(lib).file_name() {
-                Some(name) => name,
-                None => Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Cannot find file name in path '{lib}'"),
-                ))?,
-            };
             println!("copying '{lib}'");
-            let path = libs_root.join(file_name);
-            fs::copy(lib, path).await?;
+            let result: IoResult<()> = async {
+                let file_name = match Path::new(lib).file_name() {
+                    Some(name) => name,
+                    None => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Cannot find file name in path '{lib}'"),
+                    ))?,
+                };
+                if let Err(e) = fs::copy(project_root.join(lib), libs_root.join(file_name)).await {
+                    Err(io::Error::new(
+                        e.kind(),
+                        format!("Cannot copy '{lib}': {e}"),
+                    ))?;
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(e) = result {
+                if !keep_going {
+                    return Err(e.attach_printable(ErrorCode::DownloadFailed));
+                }
+                let _ = send.send((lib.to_owned(), Err(e))).await;
+            }
             continue;
         }
         let (url, path) = if lib.starts_with("http") {
@@ -289,34 +949,132 @@ async fn sync_downloads(libs_root: &Path, libs: &[String], cdn_url_prefix: &str)
             let path = libs_root.join(file_name);
             (url, path)
         } else {
-            // let url = format!("https://cdn.pistonite.org/minecraft/devjars/{lib}");
             let url = format!("{cdn_url_prefix}{lib}");
             let path = libs_root.join(lib);
             (url, path)
         };
         println!("downloading '{url}'");
-        let client = Arc::clone(&client);
+        let client = Arc::clone(client);
         let send = send.clone();
         join_set.spawn(async move {
-            let result = download_binary(client, &url, &path).await.map(|_| url);
-            let _ = send.send(result).await;
+            let result: IoResult<()> = async {
+                let start = Instant::now();
+                let download = download_binary(client, &url, &path, read_timeout);
+                match tokio::time::timeout(timeout, download).await {
+                    Ok(x) => x?,
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "Timed out downloading '{url}' after {}s",
+                            start.elapsed().as_secs()
+                        ),
+                    ))?,
+                }
+                Ok(())
+            }
+            .await;
+            let _ = send.send((url, result)).await;
             Ok(())
         });
     }
     drop(send);
     join_join_set!(join_set).await?;
-    Ok(changed)
+    let mut failures = match receiver.await {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::from(e))?,
+    };
+    if failures.is_empty() {
+        return Ok(changed);
+    }
+    if !keep_going {
+        let (_, e) = failures.swap_remove(0);
+        return Err(e.attach_printable(ErrorCode::DownloadFailed));
+    }
+    eprintln!();
+    eprintln!("{} of {total} download(s) failed:", failures.len());
+    for (lib, e) in &failures {
+        eprintln!("  {lib}");
+        eprintln!("    {}", e.current_context());
+    }
+    Err(ErrorCode::DownloadFailed.error(io::Error::other(format!(
+        "{} of {total} download(s) failed",
+        failures.len()
+    ))))?
 }
 
-async fn download_binary(client: Arc<Client>, url: &str, path: &Path) -> IoResult<()> {
-    let bytes_result = async { client.get(url).send().await?.bytes().await }.await;
-
-    let bytes = match bytes_result {
-        Ok(response) => response,
-        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e))?,
+/// Download the url into the path. The file is only written after the download is complete
+/// and the content is checked
+async fn download_binary(
+    client: Arc<Client>,
+    url: &str,
+    path: &Path,
+    read_timeout: Duration,
+) -> IoResult<()> {
+    let start = Instant::now();
+    let timed_out = || {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "Timed out downloading '{url}' after {}s, no data received in {}s",
+                start.elapsed().as_secs(),
+                read_timeout.as_secs()
+            ),
+        )
+    };
+    let mut response = match tokio::time::timeout(read_timeout, client.get(url).send()).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(e)) => Err(io::Error::other(e))?,
+        Err(_) => Err(timed_out())?,
     };
+    let status = response.status();
+    if !status.is_success() {
+        Err(io::Error::other(format!(
+            "Failed to download '{url}': server returned {status}"
+        )))?;
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/html") {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to download '{url}': server returned a web page instead of a file"),
+        ))?;
+    }
+    let expected_len = response.content_length();
+    let mut bytes = Vec::new();
+    loop {
+        match tokio::time::timeout(read_timeout, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => bytes.extend_from_slice(&chunk),
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => Err(io::Error::other(e))?,
+            Err(_) => Err(timed_out())?,
+        }
+    }
 
-    File::create(path).await?.write_all(&bytes).await?;
+    if let Some(expected_len) = expected_len {
+        if bytes.len() as u64 != expected_len {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Failed to download '{url}': expected {expected_len} bytes, but got {}",
+                    bytes.len()
+                ),
+            ))?;
+        }
+    }
+    let is_archive = path.extension().is_some_and(|x| x == "jar" || x == "zip");
+    // local file header, or end of central directory for an empty zip
+    if is_archive && !bytes.starts_with(b"PK\x03\x04") && !bytes.starts_with(b"PK\x05\x06") {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to download '{url}': the downloaded file is not a jar or zip"),
+        ))?;
+    }
+
+    util::write_atomic(path, &bytes).await?;
 
     Ok(())
 }
@@ -328,14 +1086,14 @@ async fn sync_eclipse_workspace(
     template_handler.setup_eclipse(project).await?;
     println!("remapping .classpath");
     let output_file = project.root.join(".classpath");
-    let writer = std::io::BufWriter::new(std::fs::File::create(&output_file)?);
+    let mut output = Vec::new();
     let classpath_file = project.target_root().join(".classpath");
     let input = fs::read_to_string(&classpath_file)
         .await?
         .replace("\r\n", "\n");
     let result = async {
         let mut reader = Reader::from_str(&input);
-        let mut writer = Writer::new_with_indent(writer, b' ', 4);
+        let mut writer = Writer::new_with_indent(&mut output, b' ', 4);
         let mut buf = Vec::new();
 
         loop {
@@ -406,6 +1164,7 @@ async fn sync_eclipse_workspace(
     if let Err(e) = result {
         Err(io::Error::new(io::ErrorKind::InvalidData, e))?;
     }
+    util::write_atomic(&output_file, &output).await?;
 
     fs::remove_file(classpath_file).await?;
 
@@ -417,14 +1176,14 @@ async fn sync_eclipse_workspace(
             "Cannot determine project name from root path",
         ))?,
     };
-    let writer = std::io::BufWriter::new(std::fs::File::create(&output_file)?);
+    let mut output = Vec::new();
     let project_file = project.target_root().join(".project");
     let input = fs::read_to_string(&project_file)
         .await?
         .replace("\r\n", "\n");
     let result = async {
         let mut reader = Reader::from_str(&input);
-        let mut writer = Writer::new_with_indent(writer, b' ', 4);
+        let mut writer = Writer::new_with_indent(&mut output, b' ', 4);
         let mut buf = Vec::new();
 
         let mut level = 0;
@@ -466,6 +1225,10 @@ async fn sync_eclipse_workspace(
     if let Err(e) = result {
         Err(io::Error::new(io::ErrorKind::InvalidData, e))?;
     }
+    util::write_atomic(&output_file, &output).await?;
+
+    // keep the sources of decompiled libs attached
+    decompile::attach_sources(project).await?;
 
     Ok(())
 }
@@ -0,0 +1,214 @@
+//! Running the tasks in `tasks` in mcmod.yaml
+
+use std::collections::BTreeMap;
+use std::io;
+
+use clap::Parser;
+
+use crate::build;
+use crate::explain::ErrorCode;
+use crate::mcmod::{Mcmod, TaskStep};
+use crate::util::{self, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct TaskCommand {
+    /// The task to run, after the tasks it depends on. Lists the tasks if not specified
+    pub name: Option<String>,
+}
+
+impl TaskCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let name = match self.name {
+            Some(x) => x,
+            None => {
+                if mcmod.tasks.is_empty() {
+                    println!("no tasks in mcmod.yaml");
+                }
+                for (name, task) in &mcmod.tasks {
+                    if task.depends.is_empty() {
+                        println!("{name}");
+                    } else {
+                        println!("{name} (after {})", task.depends.join(", "));
+                    }
+                }
+                return Ok(());
+            }
+        };
+        let order = task_order(mcmod, &name)?;
+        let variables = variables(&project).await?;
+        let tasks = order
+            .into_iter()
+            .map(|name| expand_task(mcmod, name, &variables))
+            .collect::<IoResult<Vec<_>>>()?;
+        for task in &tasks {
+            run_task(&project, mcmod, task).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the tasks to run for the task, with dependencies first and each task only once
+fn task_order<'a>(mcmod: &'a Mcmod, name: &'a str) -> IoResult<Vec<&'a str>> {
+    let mut order = Vec::new();
+    let mut visiting = Vec::new();
+    visit(mcmod, name, &mut visiting, &mut order)?;
+    Ok(order)
+}
+
+fn visit<'a>(
+    mcmod: &'a Mcmod,
+    name: &'a str,
+    visiting: &mut Vec<&'a str>,
+    order: &mut Vec<&'a str>,
+) -> IoResult<()> {
+    if order.contains(&name) {
+        return Ok(());
+    }
+    if visiting.contains(&name) {
+        visiting.push(name);
+        Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Tasks depend on each other: {}", visiting.join(" -> ")),
+        )))?;
+    }
+    let task = match mcmod.tasks.get(name) {
+        Some(x) => x,
+        None => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Task '{name}' is not defined in mcmod.yaml. Available: {}",
+                mcmod.tasks.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        )))?,
+    };
+    visiting.push(name);
+    for depend in &task.depends {
+        visit(mcmod, depend, visiting, order)?;
+    }
+    visiting.pop();
+    order.push(name);
+    Ok(())
+}
+
+/// Variables that can be used as `{name}` in tasks, as (name, environment variable, value)
+async fn variables(project: &Project) -> IoResult<Vec<(&'static str, &'static str, String)>> {
+    let mcmod = project.mcmod().await?;
+    let handler = mcmod.template.new_handler();
+    let version = if mcmod.version.is_empty() {
+        build::git_version(handler.as_ref(), project)
+    } else {
+        mcmod.version.clone()
+    };
+    let path = |x: std::path::PathBuf| x.display().to_string();
+    Ok(vec![
+        ("version", "MCMOD_MOD_VERSION", version),
+        ("modid", "MCMOD_MODID", mcmod.modid.clone()),
+        (
+            "project-root",
+            "MCMOD_PROJECT_ROOT",
+            path(project.root.clone()),
+        ),
+        (
+            "target-root",
+            "MCMOD_TARGET_ROOT",
+            path(project.target_root()),
+        ),
+        (
+            "output-dir",
+            "MCMOD_OUTPUT_DIR",
+            path(handler.output_dir(project)?),
+        ),
+        ("run-dir", "MCMOD_RUN_DIR", path(handler.run_dir(project)?)),
+    ])
+}
+
+/// A task with the variables expanded, so typos fail before anything runs
+struct ExpandedTask<'a> {
+    name: &'a str,
+    env: BTreeMap<String, String>,
+    steps: Vec<ExpandedStep>,
+}
+
+enum ExpandedStep {
+    Shell(String),
+    Gradle(Vec<String>),
+}
+
+fn expand_task<'a>(
+    mcmod: &Mcmod,
+    name: &'a str,
+    variables: &[(&'static str, &'static str, String)],
+) -> IoResult<ExpandedTask<'a>> {
+    let task = &mcmod.tasks[name];
+    let context = format!("task '{name}'");
+    let mut env = variables
+        .iter()
+        .map(|(_, env, value)| (env.to_string(), value.clone()))
+        .collect::<BTreeMap<_, _>>();
+    for (key, value) in &task.env {
+        env.insert(
+            key.clone(),
+            util::expand_variables(value, variables, &context)?,
+        );
+    }
+    // the shell reads the values from the environment, so they are never parsed as commands
+    let shell_variables = variables
+        .iter()
+        .map(|(name, env, _)| (*name, *env, util::shell_env_ref(env)))
+        .collect::<Vec<_>>();
+    let mut steps = Vec::new();
+    for step in &task.run {
+        let step = match step {
+            TaskStep::Shell(command) => {
+                ExpandedStep::Shell(util::expand_variables(command, &shell_variables, &context)?)
+            }
+            // split before expanding, so a value with spaces stays one argument
+            TaskStep::Gradle(step) => ExpandedStep::Gradle(
+                step.gradle
+                    .split_whitespace()
+                    .map(|x| util::expand_variables(x, variables, &context))
+                    .collect::<IoResult<_>>()?,
+            ),
+        };
+        steps.push(step);
+    }
+    Ok(ExpandedTask { name, env, steps })
+}
+
+async fn run_task(project: &Project, mcmod: &Mcmod, task: &ExpandedTask<'_>) -> IoResult<()> {
+    let name = task.name;
+    for step in &task.steps {
+        match step {
+            ExpandedStep::Shell(command) => {
+                println!("[{name}] {command}");
+                let status = util::shell_command(command)
+                    .current_dir(&project.root)
+                    .envs(&task.env)
+                    .status()?;
+                if !status.success() {
+                    Err(io::Error::other(format!(
+                        "Task '{name}' failed with {status} at `{command}`"
+                    )))?;
+                }
+            }
+            ExpandedStep::Gradle(args) => {
+                let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+                println!("[{name}] gradlew {}", args.join(" "));
+                let handler = mcmod.template.new_handler();
+                let status = handler
+                    .gradlew_command(project, &args)?
+                    .envs(&task.env)
+                    .status()?;
+                if !status.success() {
+                    Err(ErrorCode::GradleFailed.error(io::Error::other(format!(
+                        "Task '{name}' failed with {status} at `gradlew {}`",
+                        args.join(" ")
+                    ))))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Keeping the decompiled workspace across re-initializations of the target directory
+//!
+//! The gradle setup of a template decompiles MC, which is the slowest step of a sync.
+//! Before the target is wiped, the directories listed by `decomp_cache_dirs` of the template
+//! are moved into the cache, keyed by the template, MC version and access transformers.
+//! A fresh clone of the template with the same key gets a copy of them before the setup
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::cache;
+use crate::extends;
+use crate::paths;
+use crate::state::TemplateState;
+use crate::util::{mkdir, IoResult, Project};
+
+use super::TemplateHandler;
+
+/// Key of the decompiled workspace of the template for the project
+pub async fn decomp_cache_key(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+) -> IoResult<String> {
+    let mcmod = project.mcmod().await?;
+    // access transformers change the decompiled sources, which includes the ones of the parent
+    let mut ats = mcmod.access_transformers.clone();
+    if let Some(parent) = &mcmod.extends {
+        ats.push(extends::at_file_name(parent.modid()));
+    }
+    let ats = blake3::hash(ats.join("\n").as_bytes()).to_hex();
+    Ok(format!(
+        "{}-{}-{}",
+        mcmod.template,
+        handler.mc_version(),
+        &ats[..16]
+    ))
+}
+
+async fn cache_dir(key: &str) -> IoResult<PathBuf> {
+    Ok(paths::cache_dir().await?.join("decomp").join(key))
+}
+
+/// Move the decompiled workspace of the target into the cache, before the target is removed.
+/// The key and directories are the ones recorded by the setup, since the template can be changed
+pub async fn save_decomp_cache(project: &Project) -> IoResult<()> {
+    let state = TemplateState::load(project).await;
+    let key = match &state.decomp_key {
+        Some(x) if state.gradle_setup => x,
+        _ => return Ok(()),
+    };
+    let cache_dir = cache_dir(key).await?;
+    let target_root = project.target_root();
+    let mut saved = false;
+    for dir in &state.decomp_dirs {
+        let source = target_root.join(dir);
+        if !source.is_dir() {
+            continue;
+        }
+        let cached = cache_dir.join(dir);
+        if cached.exists() {
+            fs::remove_dir_all(&cached).await?;
+        }
+        if let Some(parent) = cached.parent() {
+            mkdir!(parent).await?;
+        }
+        // the cache can be on another file system, where it has to be copied
+        if fs::rename(&source, &cached).await.is_err() {
+            copy_dir(&source, &cached)?;
+        }
+        saved = true;
+    }
+    if saved {
+        let checksum_dir = cache_dir.clone();
+        let checksum =
+            tokio::task::spawn_blocking(move || cache::checksum_path(&checksum_dir)).await;
+        let checksum = match checksum {
+            Ok(x) => x?,
+            Err(e) => Err(io::Error::from(e))?,
+        };
+        cache::record_use(&format!("decomp/{key}"), Some(checksum));
+        println!("kept the decompiled workspace in '{}'", cache_dir.display());
+    }
+    Ok(())
+}
+
+/// Copy the cached decompiled workspace into the target, before the gradle setup.
+/// Returns if the cache has the workspace
+pub async fn restore_decomp_cache(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+    key: &str,
+) -> IoResult<bool> {
+    let cache_dir = cache_dir(key).await?;
+    if !cache_dir.is_dir() {
+        return Ok(false);
+    }
+    let target_root = project.target_root();
+    let mut restored = false;
+    // the cache was saved by a setup of the same template, so it has the same directories
+    for dir in handler.decomp_cache_dirs() {
+        let cached = cache_dir.join(&dir);
+        if !cached.is_dir() {
+            continue;
+        }
+        let target = target_root.join(&dir);
+        if target.exists() {
+            fs::remove_dir_all(&target).await?;
+        }
+        if let Some(parent) = target.parent() {
+            mkdir!(parent).await?;
+        }
+        copy_dir(&cached, &target)?;
+        restored = true;
+    }
+    if restored {
+        cache::record_use(&format!("decomp/{key}"), None);
+        println!(
+            "restored the decompiled workspace from '{}'",
+            cache_dir.display()
+        );
+    }
+    Ok(restored)
+}
+
+fn copy_dir(source: &Path, target: &Path) -> IoResult<()> {
+    let errors = copy_dir::copy_dir(source, target)?;
+    if let Some(e) = errors.into_iter().next() {
+        Err(io::Error::new(
+            e.kind(),
+            format!(
+                "Failed to copy '{}' to '{}': {e}",
+                source.display(),
+                target.display()
+            ),
+        ))?;
+    }
+    Ok(())
+}
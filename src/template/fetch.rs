@@ -0,0 +1,321 @@
+//! Fetching templates into the target directory
+
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use flate2::read::GzDecoder;
+use reqwest::Client;
+
+use crate::explain::ErrorCode;
+use crate::util::{self, IoResult, Project};
+
+use super::TemplateDef;
+
+impl TemplateDef {
+    /// Get the local directory if the template is not a git remote
+    pub fn local_path(&self) -> IoResult<Option<PathBuf>> {
+        let path = match self.url.strip_prefix("file://") {
+            Some(x) => x,
+            None => {
+                let is_remote =
+                    self.url.contains("://") || (self.url.contains('@') && self.url.contains(':'));
+                if is_remote {
+                    return Ok(None);
+                }
+                &self.url
+            }
+        };
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return Ok(Some(path.to_path_buf()));
+        }
+        Ok(Some(self.base_dir.join(path)))
+    }
+
+    /// Get the url to download a tar.gz archive of the branch, for known git hosts
+    fn archive_url(&self) -> Option<String> {
+        let url = self.url.trim_end_matches('/');
+        let url = url.strip_suffix(".git").unwrap_or(url);
+        let git_ref = if self.branch.is_empty() {
+            "HEAD"
+        } else {
+            &self.branch
+        };
+        let path = url.strip_prefix("https://")?;
+        let (host, path) = path.split_once('/')?;
+        if host == "github.com" {
+            return Some(format!("{url}/archive/{git_ref}.tar.gz"));
+        }
+        if host == "gitlab.com" || host.starts_with("gitlab.") {
+            let name = path.rsplit('/').next()?;
+            return Some(format!("{url}/-/archive/{git_ref}/{name}-{git_ref}.tar.gz"));
+        }
+        None
+    }
+
+    /// Clone, download or copy the template into the target directory
+    pub async fn fetch(&self, target_root: &Path) -> IoResult<()> {
+        if let Some(local) = self.local_path()? {
+            println!("copying template from '{}'", local.display());
+            if !local.is_dir() {
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Template directory '{}' does not exist", local.display()),
+                ))?;
+            }
+            let errors = copy_dir::copy_dir(&local, target_root)?;
+            if !errors.is_empty() {
+                for e in errors {
+                    eprintln!("  {}", e);
+                }
+                Err(io::Error::other("Failed to copy template"))?;
+            }
+            return Ok(());
+        }
+
+        if !self.use_git && self.clone_command.is_empty() {
+            let url = match self.archive_url() {
+                Some(x) => x,
+                None => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Cannot download template from '{}'. Set `use-git` or `clone-command` for the template in templates.json", self.url),
+                ))?,
+            };
+            download_archive(&url, target_root).await?;
+            // archives don't have the content of submodules
+            if !target_root.join(".gitmodules").exists() {
+                return Ok(());
+            }
+            println!("template has submodules, cloning it with git instead");
+            std::fs::remove_dir_all(target_root)?;
+        }
+
+        let dir = target_root.display().to_string();
+        let status = if self.clone_command.is_empty() {
+            let mut command = Command::new("git");
+            command.arg("clone");
+            if !self.branch.is_empty() {
+                command.args(["--branch", &self.branch]);
+            }
+            command
+                .args([
+                    "--depth",
+                    "1",
+                    "--recurse-submodules",
+                    "--",
+                    &self.url,
+                    &dir,
+                ])
+                .status()?
+        } else {
+            let args = self
+                .clone_command
+                .iter()
+                .map(|x| {
+                    x.replace("{url}", &self.url)
+                        .replace("{branch}", &self.branch)
+                        .replace("{dir}", &dir)
+                })
+                .collect::<Vec<_>>();
+            Command::new(&args[0]).args(&args[1..]).status()?
+        };
+        if !status.success() {
+            Err(ErrorCode::TemplateFetchFailed.error(io::Error::other("Failed to clone template")))?;
+        }
+        Ok(())
+    }
+}
+
+impl TemplateDef {
+    /// Run the `post-clone` commands in the target directory
+    pub async fn run_post_clone(&self, project: &Project, template_name: &str) -> IoResult<()> {
+        if self.post_clone.is_empty() {
+            return Ok(());
+        }
+        let mcmod = project.mcmod().await?;
+        let target_root = project.target_root();
+        // (name in arguments, environment variable, value)
+        let variables = [
+            ("modid", "MCMOD_MODID", mcmod.modid.clone()),
+            ("name", "MCMOD_MOD_NAME", mcmod.name.clone()),
+            ("version", "MCMOD_MOD_VERSION", mcmod.version.clone()),
+            ("group", "MCMOD_MOD_GROUP", mcmod.group.clone()),
+            ("template", "MCMOD_TEMPLATE", template_name.to_owned()),
+            (
+                "project-root",
+                "MCMOD_PROJECT_ROOT",
+                project.root.display().to_string(),
+            ),
+            (
+                "target-root",
+                "MCMOD_TARGET_ROOT",
+                target_root.display().to_string(),
+            ),
+        ];
+        // expand all commands first, so a typo fails before anything runs
+        let commands = self
+            .post_clone
+            .iter()
+            .map(|command| {
+                command
+                    .iter()
+                    .map(|x| util::expand_variables(x, &variables, "post-clone command"))
+                    .collect::<IoResult<Vec<_>>>()
+            })
+            .collect::<IoResult<Vec<_>>>()?;
+        for args in commands {
+            let (program, rest) = match args.split_first() {
+                Some(x) => x,
+                None => continue,
+            };
+            println!("running post-clone command: {}", args.join(" "));
+            let mut process = Command::new(program);
+            process.args(rest).current_dir(&target_root);
+            for (_, env, value) in &variables {
+                process.env(env, value);
+            }
+            let status = match process.status() {
+                Ok(x) => x,
+                Err(e) => Err(ErrorCode::TemplateFetchFailed.error(io::Error::new(
+                    e.kind(),
+                    format!("Cannot run post-clone command '{program}': {e}"),
+                )))?,
+            };
+            if !status.success() {
+                Err(
+                    ErrorCode::TemplateFetchFailed.error(io::Error::other(format!(
+                        "Post-clone command `{}` failed with {status}",
+                        args.join(" ")
+                    ))),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Download a tar.gz archive and extract it into the target directory,
+/// stripping the top-level directory in the archive
+async fn download_archive(url: &str, target_root: &Path) -> IoResult<()> {
+    println!("downloading template from '{url}'");
+    let result = async {
+        let mut response = Client::new().get(url).send().await?.error_for_status()?;
+        let total = response.content_length();
+        let mut bytes = Vec::new();
+        let mut last_reported = 0;
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            let downloaded = bytes.len() / 1024 / 1024;
+            if downloaded != last_reported {
+                last_reported = downloaded;
+                match total {
+                    Some(total) => print!("\r  {downloaded} MiB / {} MiB", total / 1024 / 1024),
+                    None => print!("\r  {downloaded} MiB"),
+                }
+                let _ = io::stdout().flush();
+            }
+        }
+        Ok::<_, reqwest::Error>(bytes)
+    }
+    .await;
+    let bytes = match result {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::other(e))?,
+    };
+    println!("\r  downloaded {} KiB", bytes.len() / 1024);
+
+    let target_root = target_root.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || extract_archive(&bytes, &target_root)).await;
+    match result {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::from(e))?,
+    }
+}
+
+fn extract_archive(bytes: &[u8], target_root: &Path) -> IoResult<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        // links could point outside the target, and later entries would be written through them
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Template archive has a link at '{}'. Set `use-git` for the template in templates.json",
+                    path.display()
+                ),
+            ))?;
+        }
+        // strip the top-level directory and refuse anything that escapes the target
+        let mut components = path.components();
+        components.next();
+        let relative = components.as_path();
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        if relative
+            .components()
+            .any(|x| !matches!(x, Component::Normal(_)))
+        {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid path '{}' in template archive", path.display()),
+            ))?;
+        }
+        let target = target_root.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn archive(links: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "template-main/a/b.txt", &b"hello"[..])
+            .unwrap();
+        if links {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder
+                .append_link(&mut header, "template-main/etc", "/etc")
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn extracts_without_the_top_level_directory() {
+        let dir = std::env::temp_dir().join(format!("mcmod-test-extract-{}", std::process::id()));
+        extract_archive(&archive(false), &dir).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a/b.txt")).unwrap(),
+            "hello"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_links() {
+        let dir = std::env::temp_dir().join(format!("mcmod-test-links-{}", std::process::id()));
+        assert!(extract_archive(&archive(true), &dir).is_err());
+        assert!(!dir.join("etc").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,11 +1,75 @@
 use std::{collections::BTreeMap, io};
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
-use crate::gradle;
+use crate::extends;
+use crate::mcmod::{Language, Mcmod};
 use crate::util::{IoResult, Project};
 
-use super::TemplateHandler;
+use super::{child_mod_properties, Capabilities, TemplateHandler};
+
+/// Options for the GTNH buildscript. Unset options use the value in the template
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GtnhOptions {
+    /// Use Jabel to allow Java 17 syntax while targeting Java 8 (enableModernJavaSyntax)
+    #[serde(default)]
+    pub modern_java_syntax: Option<bool>,
+    /// Allow generic type injection in the Java 17 syntax (enableGenericInjection)
+    #[serde(default)]
+    pub generic_injection: Option<bool>,
+    /// Shade dependencies in the shadowImplementation configuration (usesShadowedDependencies)
+    #[serde(default)]
+    pub shadowed_dependencies: Option<bool>,
+    /// Remove unused classes from shaded dependencies (minimizeShadowedDependencies)
+    #[serde(default)]
+    pub minimize_shadowed_dependencies: Option<bool>,
+    /// Relocate shaded dependencies into the mod's package (relocateShadowedDependencies)
+    #[serde(default)]
+    pub relocate_shadowed_dependencies: Option<bool>,
+    /// Enable mixins even if the mod has no mixins of its own (forceEnableMixins)
+    #[serde(default)]
+    pub force_enable_mixins: Option<bool>,
+    /// The jar only contains mixins and/or a coremod, no @Mod (containsMixinsAndOrCoreModOnly)
+    #[serde(default)]
+    pub mixins_and_or_coremod_only: Option<bool>,
+    /// Put mixins in a separate source set (separateMixinSourceSet)
+    #[serde(default)]
+    pub separate_mixin_source_set: Option<String>,
+}
+
+impl GtnhOptions {
+    /// Insert the options that are set into the gradle properties
+    fn insert_into(&self, map: &mut BTreeMap<String, String>) {
+        let bools = [
+            ("enableModernJavaSyntax", self.modern_java_syntax),
+            ("enableGenericInjection", self.generic_injection),
+            ("usesShadowedDependencies", self.shadowed_dependencies),
+            (
+                "minimizeShadowedDependencies",
+                self.minimize_shadowed_dependencies,
+            ),
+            (
+                "relocateShadowedDependencies",
+                self.relocate_shadowed_dependencies,
+            ),
+            ("forceEnableMixins", self.force_enable_mixins),
+            (
+                "containsMixinsAndOrCoreModOnly",
+                self.mixins_and_or_coremod_only,
+            ),
+        ];
+        for (key, value) in bools {
+            if let Some(value) = value {
+                map.insert(key.to_owned(), value.to_string());
+            }
+        }
+        if let Some(source_set) = &self.separate_mixin_source_set {
+            map.insert("separateMixinSourceSet".to_owned(), source_set.clone());
+        }
+    }
+}
 
 pub struct Gtnh1710Handler;
 #[async_trait(?Send)]
@@ -14,18 +78,99 @@ impl TemplateHandler for Gtnh1710Handler {
         "1.7.10"
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            mixins: true,
+            coremods: true,
+            java_versions: vec![8, 17],
+            git_version: true,
+            datagen: None,
+            // the buildscript applies the plugins when src/main/scala or src/main/kotlin exist
+            languages: vec![Language::Scala, Language::Kotlin],
+        }
+    }
+
+    fn validate(&self, mcmod: &Mcmod) -> IoResult<()> {
+        let options = &mcmod.gtnh;
+        let shadowed = options.shadowed_dependencies.unwrap_or(false);
+        if !shadowed
+            && (options.minimize_shadowed_dependencies == Some(true)
+                || options.relocate_shadowed_dependencies == Some(true))
+        {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gtnh.minimize-shadowed-dependencies and gtnh.relocate-shadowed-dependencies require gtnh.shadowed-dependencies",
+            ))?;
+        }
+        if options.generic_injection == Some(true) && options.modern_java_syntax != Some(true) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gtnh.generic-injection requires gtnh.modern-java-syntax",
+            ))?;
+        }
+        if options.mixins_and_or_coremod_only == Some(true)
+            && mcmod.mixins.is_empty()
+            && mcmod.coremod.is_empty()
+        {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gtnh.mixins-and-or-coremod-only requires mixins or coremod",
+            ))?;
+        }
+        if mcmod.coremod_only && options.mixins_and_or_coremod_only == Some(false) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gtnh.mixins-and-or-coremod-only cannot be false with coremod-only",
+            ))?;
+        }
+        if options.separate_mixin_source_set.is_some() && mcmod.mixins.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gtnh.separate-mixin-source-set requires mixins",
+            ))?;
+        }
+        Ok(())
+    }
+
     fn mcmod_version_key(&self) -> &'static str {
         "modVersion"
     }
 
-    async fn run_gradlew(&self, project: &Project, args: &[&str]) -> IoResult<()> {
-        let mut java_version = 8;
-        if let Some(arg) = args.first() {
-            if arg.ends_with("17") {
-                java_version = 17;
-            }
-        }
-        gradle::run_gradlew(&project.target_root(), java_version, args).await
+    fn gitignore_entries(&self) -> &'static [&'static str] {
+        // the GTNH buildscript is usually imported into IntelliJ
+        &["# intellij", "/.idea", "/*.iml", "/out"]
+    }
+
+    fn decomp_cache_dirs(&self) -> Vec<String> {
+        // RetroFuturaGradle decompiles into the build directory when the mod has access transformers,
+        // and into the gradle home otherwise
+        vec!["build/rfg".to_owned()]
+    }
+
+    async fn sync_template(&self, project: &Project) -> IoResult<()> {
+        let mcmod = project.mcmod().await?;
+        let script = extends::dev_gradle_script(self, project, mcmod);
+        super::sync_gradle_scripts(project, &[(extends::GRADLE_SCRIPT, script)]).await
+    }
+
+    fn dev_access_transformer_gradle(&self, at_file: &str) -> Option<String> {
+        // the deobfuscation task takes extra files, while accessTransformersFile also goes
+        // into the jar manifest
+        Some(format!(
+            "tasks.named('deobfuscateMergedJarToSrg').configure {{\n    accessTransformerFiles.from(file('{at_file}'))\n}}\n"
+        ))
+    }
+
+    fn task_java_versions(&self) -> Vec<(String, u32)> {
+        [
+            "runClient17",
+            "runServer17",
+            "spotlessApply",
+            "spotlessCheck",
+        ]
+        .into_iter()
+        .map(|task| (task.to_owned(), 17))
+        .collect()
     }
 
     async fn make_gradle_properties(
@@ -33,10 +178,8 @@ impl TemplateHandler for Gtnh1710Handler {
         project: &Project,
     ) -> IoResult<BTreeMap<String, String>> {
         let mcmod = project.mcmod().await?;
-
-        if !mcmod.version.is_empty() || !mcmod.artifact_version.is_empty() {
-            Err(io::Error::new(io::ErrorKind::Other, "Version is automatically determined from git for this template. Remove the versions in mcmod.yaml"))?;
-        }
+        // the GTNH buildscript puts the token class and packages into the group
+        mcmod.require_group()?;
 
         let mut map = BTreeMap::new();
         map.insert("modName".to_owned(), mcmod.name.clone());
@@ -60,13 +203,10 @@ impl TemplateHandler for Gtnh1710Handler {
                 Some(x) => {
                     map.insert("apiPackage".to_owned(), x.to_owned());
                 }
-                None => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "api package must be in the same group as the mod ('{}')",
-                        mcmod.group
-                    ),
-                ))?,
+                None => Err(io::Error::other(format!(
+                    "api package must be in the same group as the mod ('{}')",
+                    mcmod.group
+                )))?,
             }
         }
 
@@ -83,18 +223,13 @@ impl TemplateHandler for Gtnh1710Handler {
                 Some(x) => {
                     map.insert("mixinsPackage".to_owned(), x.to_owned());
                 }
-                None => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "mixins package must be in the same group as the mod ('{}')",
-                        mcmod.group
-                    ),
-                ))?,
+                None => Err(io::Error::other(format!(
+                    "mixins package must be in the same group as the mod ('{}')",
+                    mcmod.group
+                )))?,
             }
             if mcmod.coremod.is_empty() {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "coremod class must be specified (and implement IMixinConfigPlugin) if mixins are used",
+                Err(io::Error::other("coremod class must be specified (and implement IMixinConfigPlugin) if mixins are used",
                 ))?;
             }
         }
@@ -109,19 +244,25 @@ impl TemplateHandler for Gtnh1710Handler {
                         map.insert("mixinPlugin".to_owned(), x.to_owned());
                     }
                 }
-                None => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "coremod class must be in the same group as the mod ('{}')",
-                        mcmod.group
-                    ),
-                ))?,
+                None => Err(io::Error::other(format!(
+                    "coremod class must be in the same group as the mod ('{}')",
+                    mcmod.group
+                )))?,
             }
         }
 
         // no good way to apply spotless fix to our source for now
         map.insert("disableSpotless".to_owned(), "true".to_owned());
 
+        map.extend(child_mod_properties(mcmod));
+        mcmod.gtnh.insert_into(&mut map);
+        if mcmod.coremod_only {
+            map.insert(
+                "containsMixinsAndOrCoreModOnly".to_owned(),
+                "true".to_owned(),
+            );
+        }
+
         Ok(map)
     }
 }
@@ -0,0 +1,326 @@
+//! Templates that are not built in, driven by the mcmod-template.yaml they ship
+//!
+//! The manifest maps fields in mcmod.yaml to the gradle properties the build script reads,
+//! and describes the tasks, directories and features of the template
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::explain::ErrorCode;
+use crate::mcmod::{Language, Mcmod, SourceSet};
+use crate::state::TemplateState;
+use crate::util::{IoResult, Project};
+
+use super::{
+    default_source_set_dir, language_dir, languages_property, Capabilities, TemplateHandler,
+};
+
+/// Name of the manifest in the template
+pub const MANIFEST_FILE: &str = "mcmod-template.yaml";
+
+/// A template from templates.json that is not built in
+#[derive(Debug)]
+pub struct CustomTemplate {
+    /// Name in templates.json
+    pub name: String,
+    /// Target directory the template is fetched into
+    pub target_root: Option<PathBuf>,
+    /// Directory of a local template
+    pub local_dir: Option<PathBuf>,
+}
+
+impl CustomTemplate {
+    /// Path of the manifest, in the target if the template is fetched there
+    fn manifest_path(&self) -> Option<PathBuf> {
+        if let Some(target_root) = &self.target_root {
+            let state = std::fs::read_to_string(target_root.join(".mcmod-template"))
+                .ok()
+                .and_then(|x| serde_json::from_str::<TemplateState>(&x).ok());
+            if state.is_some_and(|x| x.cloned && x.template == self.name) {
+                return Some(target_root.join(MANIFEST_FILE));
+            }
+        }
+        self.local_dir.as_ref().map(|x| x.join(MANIFEST_FILE))
+    }
+}
+
+/// The contract between a template and mcmod, in mcmod-template.yaml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TemplateManifest {
+    /// The MC version of the template
+    pub mc_version: String,
+    /// Java versions used by the template. The first one is the main version
+    pub java_versions: Vec<u32>,
+    /// Java version for gradle tasks that don't use the main java version
+    pub task_java_versions: BTreeMap<String, u32>,
+    /// Gradle properties to set in gradle.properties. `${modid}`, `${name}`, `${version}`
+    /// and other fields of mcmod.yaml in the values are replaced. `${languages}` is the
+    /// languages of the sources, like `java,scala`
+    pub properties: BTreeMap<String, String>,
+    /// Gradle tasks to set up the template after it's fetched
+    pub setup_tasks: Vec<String>,
+    /// Gradle tasks to generate the eclipse project
+    pub eclipse_tasks: Vec<String>,
+    /// Gradle tasks to build the jar
+    pub build_tasks: Vec<String>,
+    /// Gradle tasks to compile and run the tests. Arguments of `mcmod test` are added to the last one
+    pub test_tasks: Vec<String>,
+    /// Directories of the Java sources of the source sets relative to the template, when not
+    /// the gradle defaults. The sources in other languages are in the directory next to it
+    pub source_set_dirs: BTreeMap<SourceSet, String>,
+    /// Languages besides Java that the build script compiles, when they are in `${languages}`
+    pub languages: Vec<Language>,
+    /// Directory the jar is built into, relative to the template
+    pub output_dir: String,
+    /// Directory of the dependency jars, relative to the template
+    pub libs_dir: String,
+    /// Directory the game runs in, relative to the template
+    pub run_dir: String,
+    /// Directories in the template with the decompiled workspace made by the setup tasks, kept
+    /// in the cache when the target is re-initialized. None by default, since most gradle
+    /// plugins decompile into the gradle home
+    pub decomp_cache_dirs: Vec<String>,
+    /// The key of the version in mcmod.info
+    pub version_key: String,
+    /// If mixins can be used
+    pub mixins: bool,
+    /// If a coremod can be used
+    pub coremods: bool,
+    /// If the build script determines the version from git
+    pub git_version: bool,
+    /// The gradle task that runs data generation
+    pub datagen_task: Option<String>,
+    /// Directory data generation writes to, relative to the template
+    pub datagen_output_dir: String,
+}
+
+impl Default for TemplateManifest {
+    fn default() -> Self {
+        Self {
+            mc_version: String::new(),
+            java_versions: vec![8],
+            task_java_versions: BTreeMap::new(),
+            properties: BTreeMap::new(),
+            setup_tasks: vec!["setupDecompWorkspace".to_owned()],
+            eclipse_tasks: vec!["eclipse".to_owned()],
+            build_tasks: vec!["build".to_owned()],
+            test_tasks: vec!["test".to_owned()],
+            source_set_dirs: BTreeMap::new(),
+            languages: Vec::new(),
+            output_dir: "build/libs".to_owned(),
+            libs_dir: "libs".to_owned(),
+            run_dir: "run".to_owned(),
+            decomp_cache_dirs: Vec::new(),
+            version_key: "version".to_owned(),
+            mixins: false,
+            coremods: false,
+            git_version: false,
+            datagen_task: None,
+            datagen_output_dir: "src/generated/resources".to_owned(),
+        }
+    }
+}
+
+impl TemplateManifest {
+    /// Values of the variables that can be used in `properties`
+    pub fn variables(mcmod: &Mcmod) -> BTreeMap<&'static str, String> {
+        BTreeMap::from([
+            ("name", mcmod.name.clone()),
+            ("modid", mcmod.modid.clone()),
+            ("description", mcmod.description.clone()),
+            ("version", mcmod.version.clone()),
+            ("artifact-version", mcmod.artifact_version.clone()),
+            ("group", mcmod.group.clone()),
+            ("group-internal", mcmod.group.replace('.', "/")),
+            ("archives-base-name", mcmod.archives_base_name.clone()),
+            ("access-transformers", mcmod.access_transformers.join(" ")),
+            ("coremod", mcmod.coremod.clone()),
+            ("coremod-only", mcmod.coremod_only.to_string()),
+            ("mixins", mcmod.mixins.clone()),
+            ("api", mcmod.api.clone()),
+            ("languages", languages_property(mcmod)),
+        ])
+    }
+}
+
+/// Handler for custom templates, which does what the manifest describes
+pub struct ManifestTemplateHandler {
+    name: String,
+    manifest: TemplateManifest,
+    /// If the manifest is read from the template, instead of the defaults before it's fetched
+    loaded: bool,
+    /// Why the manifest cannot be used, reported when validating
+    error: Option<String>,
+}
+
+impl ManifestTemplateHandler {
+    /// Load the manifest of the template. Before a remote template is fetched,
+    /// the defaults are used until the manifest is available
+    pub fn load(template: &CustomTemplate) -> Self {
+        let mut handler = Self {
+            name: template.name.clone(),
+            manifest: TemplateManifest::default(),
+            loaded: false,
+            error: None,
+        };
+        let path = match template.manifest_path() {
+            Some(x) => x,
+            None => return handler,
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_yaml::from_str(&content) {
+                Ok(x) => {
+                    handler.manifest = x;
+                    handler.loaded = true;
+                }
+                Err(e) => handler.error = Some(format!("Cannot parse '{}': {e}", path.display())),
+            },
+            Err(_) => {
+                handler.error = Some(format!(
+                "Template '{}' is not built in, and doesn't have {MANIFEST_FILE} to describe it",
+                template.name
+            ))
+            }
+        }
+        handler
+    }
+
+    async fn run_tasks(&self, project: &Project, tasks: &[String]) -> IoResult<()> {
+        for task in tasks {
+            let args = task.split_whitespace().collect::<Vec<_>>();
+            self.run_gradlew(project, &args).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl TemplateHandler for ManifestTemplateHandler {
+    fn mc_version(&self) -> &str {
+        &self.manifest.mc_version
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            mixins: self.manifest.mixins,
+            coremods: self.manifest.coremods,
+            java_versions: self.manifest.java_versions.clone(),
+            git_version: self.manifest.git_version,
+            datagen: self.manifest.datagen_task.clone(),
+            languages: self.manifest.languages.clone(),
+        }
+    }
+
+    fn validate(&self, _mcmod: &Mcmod) -> IoResult<()> {
+        if let Some(error) = &self.error {
+            Err(ErrorCode::TemplateNotFound
+                .error(io::Error::new(io::ErrorKind::InvalidData, error.clone())))?;
+        }
+        if !self.loaded {
+            return Ok(());
+        }
+        let missing = if self.manifest.mc_version.is_empty() {
+            Some("mc-version")
+        } else if self.manifest.java_versions.is_empty() {
+            Some("java-versions")
+        } else {
+            None
+        };
+        if let Some(key) = missing {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "`{key}` is missing in {MANIFEST_FILE} of template '{}'",
+                    self.name
+                ),
+            )))?;
+        }
+        Ok(())
+    }
+
+    fn mcmod_version_key(&self) -> &str {
+        &self.manifest.version_key
+    }
+
+    async fn setup_project(&self, project: &Project) -> IoResult<()> {
+        self.run_tasks(project, &self.manifest.setup_tasks).await
+    }
+
+    async fn setup_eclipse(&self, project: &Project) -> IoResult<()> {
+        self.run_tasks(project, &self.manifest.eclipse_tasks).await
+    }
+
+    async fn build(&self, project: &Project) -> IoResult<()> {
+        self.run_tasks(project, &self.manifest.build_tasks).await
+    }
+
+    async fn test(&self, project: &Project, args: &[&str]) -> IoResult<()> {
+        let (last, tasks) = match self.manifest.test_tasks.split_last() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        self.run_tasks(project, tasks).await?;
+        let mut last = last.split_whitespace().collect::<Vec<_>>();
+        last.extend(args);
+        self.run_gradlew(project, &last).await
+    }
+
+    fn source_set_dir(&self, set: SourceSet, language: Language) -> String {
+        let java_dir = match self.manifest.source_set_dirs.get(&set) {
+            Some(x) => x.as_str(),
+            None => default_source_set_dir(set),
+        };
+        language_dir(java_dir, language)
+    }
+
+    fn task_java_versions(&self) -> Vec<(String, u32)> {
+        self.manifest
+            .task_java_versions
+            .iter()
+            .map(|(task, version)| (task.clone(), *version))
+            .collect()
+    }
+
+    fn datagen_output_dir(&self, project: &Project) -> IoResult<PathBuf> {
+        Ok(project
+            .target_root()
+            .join(&self.manifest.datagen_output_dir))
+    }
+
+    fn output_dir(&self, project: &Project) -> IoResult<PathBuf> {
+        Ok(project.target_root().join(&self.manifest.output_dir))
+    }
+
+    fn libs_dir(&self, project: &Project) -> IoResult<PathBuf> {
+        Ok(project.target_root().join(&self.manifest.libs_dir))
+    }
+
+    fn default_run_dir(&self) -> &str {
+        &self.manifest.run_dir
+    }
+
+    fn decomp_cache_dirs(&self) -> Vec<String> {
+        self.manifest.decomp_cache_dirs.clone()
+    }
+
+    async fn make_gradle_properties(
+        &self,
+        project: &Project,
+    ) -> IoResult<BTreeMap<String, String>> {
+        let variables = TemplateManifest::variables(project.mcmod().await?);
+        let mut map = BTreeMap::new();
+        for (key, value) in &self.manifest.properties {
+            let mut value = value.clone();
+            for (name, replacement) in &variables {
+                value = value.replace(&format!("${{{name}}}"), replacement);
+            }
+            map.insert(key.clone(), value);
+        }
+        Ok(map)
+    }
+}
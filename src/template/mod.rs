@@ -1,53 +1,191 @@
 use std::collections::BTreeMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use tokio::{fs, io};
 
-use crate::util::{self, cd, IoResult, Project};
+use crate::explain::ErrorCode;
+use crate::gradle;
+use crate::mcmod::{Language, Mcmod, SourceSet};
+use crate::paths;
+use crate::util::{self, cd, mkdir, write_file, IoResult, Project};
 
+mod decomp_cache;
+mod fetch;
 mod gtnh;
+mod manifest;
 mod ntmc;
+mod registry;
+
+pub use decomp_cache::{decomp_cache_key, restore_decomp_cache, save_decomp_cache};
+pub use gtnh::GtnhOptions;
+pub use manifest::{CustomTemplate, ManifestTemplateHandler, TemplateManifest, MANIFEST_FILE};
+pub use registry::sync_registry;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct TemplateDef {
+    /// Git url, or a local directory (plain path or file:// url) to copy the template from.
+    /// Relative paths are relative to the templates.json the template is in
     pub url: String,
+    /// Branch to clone. Uses the default branch if empty
+    #[serde(default)]
     pub branch: String,
+    /// Clone with the `git` command instead of downloading an archive of the branch.
+    /// Needed for git hosts other than GitHub and GitLab, or for submodules
+    #[serde(default)]
+    pub use_git: bool,
+    /// Command to clone the template instead of `git clone`, for example to use
+    /// a different git binary or credentials for a private host.
+    ///
+    /// `{url}`, `{branch}` and `{dir}` in the arguments are replaced
+    #[serde(default)]
+    pub clone_command: Vec<String>,
+    /// Commands to run in the target directory after the template is fetched and patched,
+    /// like `["./setup.sh"]` or `["git", "lfs", "pull"]`. Each command is a list of arguments
+    /// run without a shell.
+    ///
+    /// `{modid}`, `{name}`, `{version}`, `{group}`, `{template}`, `{project-root}` and
+    /// `{target-root}` in the arguments are replaced, and are also set as `MCMOD_*` environment variables
+    #[serde(default)]
+    pub post_clone: Vec<Vec<String>>,
+    /// Don't run the gradle setup of the template (like `setupDecompWorkspace`),
+    /// for templates that are set up by `post-clone`
+    #[serde(default)]
+    pub skip_gradle_setup: bool,
+    /// Directory of the templates.json the template is from
+    #[serde(skip)]
+    pub base_dir: PathBuf,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub enum Template {
-    #[serde(rename = "ntmc-1.7.10")]
     Ntmc1710,
-    #[serde(rename = "gtnh-1.7.10")]
     Gtnh1710,
+    /// A template in templates.json that is not built in, driven by its mcmod-template.yaml
+    Custom(CustomTemplate),
 }
 
+/// Names of the built-in templates
+const BUILTIN_TEMPLATES: &[&str] = &["ntmc-1.7.10", "gtnh-1.7.10"];
+
 impl fmt::Display for Template {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = serde_json::to_string(self).unwrap();
-        write!(f, "{}", s.trim_matches('"'))
+        let s = match self {
+            Self::Ntmc1710 => "ntmc-1.7.10",
+            Self::Gtnh1710 => "gtnh-1.7.10",
+            Self::Custom(x) => &x.name,
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Parse the name of a built-in template
+impl FromStr for Template {
+    type Err = error_stack::Report<io::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::from_name(s) {
+            Self::Custom(_) => Err(ErrorCode::TemplateNotFound.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown template '{s}'. The built-in templates are {}",
+                    BUILTIN_TEMPLATES.join(", ")
+                ),
+            )))?,
+            x => Ok(x),
+        }
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Self::from_name(&name))
     }
 }
 
 impl Template {
+    /// Get the built-in template with the name, or a custom template
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "ntmc-1.7.10" => Self::Ntmc1710,
+            "gtnh-1.7.10" => Self::Gtnh1710,
+            _ => Self::Custom(CustomTemplate {
+                name: name.to_owned(),
+                target_root: None,
+                local_dir: None,
+            }),
+        }
+    }
+
     pub fn new_handler(&self) -> Box<dyn TemplateHandler> {
         match self {
             Self::Ntmc1710 => Box::new(ntmc::Ntmc1710Handler),
             Self::Gtnh1710 => Box::new(gtnh::Gtnh1710Handler),
+            Self::Custom(x) => Box::new(ManifestTemplateHandler::load(x)),
         }
     }
+
+    /// Set where to find the mcmod-template.yaml of a custom template: the target directory
+    /// once the template is fetched, or the template directory for local templates
+    pub async fn locate_manifest(&mut self, project: &Project) -> IoResult<()> {
+        let custom = match self {
+            Self::Custom(x) => x,
+            _ => return Ok(()),
+        };
+        custom.target_root = Some(project.target_root());
+        if let Some(def) = read_templates().await?.get(&custom.name) {
+            custom.local_dir = def.local_path()?;
+        }
+        Ok(())
+    }
+}
+
+/// Features supported by a template, used to validate mcmod.yaml before syncing
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// If mixins can be used
+    pub mixins: bool,
+    /// If a coremod can be used
+    pub coremods: bool,
+    /// Java versions used by the template. The first one is the main version
+    pub java_versions: Vec<u32>,
+    /// If the version is determined from git instead of mcmod.yaml
+    pub git_version: bool,
+    /// The gradle task that runs data generation, for MC 1.16+ templates
+    pub datagen: Option<String>,
+    /// Languages besides Java that the build script can compile
+    pub languages: Vec<Language>,
 }
 
 #[async_trait(?Send)]
 pub trait TemplateHandler {
     /// Get the MC version this template is for
-    fn mc_version(&self) -> &'static str;
+    fn mc_version(&self) -> &str;
+    /// Get the features supported by this template
+    fn capabilities(&self) -> Capabilities;
+    /// The pack_format in pack.mcmeta for resources of the MC version
+    fn pack_format(&self) -> u32 {
+        pack_format_for(self.mc_version())
+    }
+    /// Validate template-specific options in mcmod.yaml
+    fn validate(&self, _mcmod: &Mcmod) -> IoResult<()> {
+        Ok(())
+    }
     /// The version key to use in mcmod.info. This is needed because different templates
     /// have different build scripts
-    fn mcmod_version_key(&self) -> &'static str;
+    fn mcmod_version_key(&self) -> &str;
     /// Called to setup the template after cloning.
     ///
     /// Templates usually run "setupDecompWorkspace" here, but there can be extra setup steps.
@@ -65,38 +203,345 @@ pub trait TemplateHandler {
         self.run_gradlew(project, &["build"]).await?;
         Ok(())
     }
-    /// Run gradlew with args. Should set java version and call gradle::run_gradlew
-    async fn run_gradlew(&self, project: &Project, args: &[&str]) -> IoResult<()>;
+    /// Called by `mcmod test` to compile and run the tests, with the extra args for gradle
+    async fn test(&self, project: &Project, args: &[&str]) -> IoResult<()> {
+        let args = ["test"].iter().chain(args).copied().collect::<Vec<_>>();
+        self.run_gradlew(project, &args).await?;
+        Ok(())
+    }
+    /// Directory in the template the sources of the source set in the language are copied to
+    fn source_set_dir(&self, set: SourceSet, language: Language) -> String {
+        language_dir(default_source_set_dir(set), language)
+    }
+    /// Called to generate javadoc
+    async fn javadoc(&self, project: &Project) -> IoResult<()> {
+        self.run_gradlew(project, &["javadoc"]).await?;
+        Ok(())
+    }
+    /// Extra lines for the .gitignore generated by `mcmod init`
+    fn gitignore_entries(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Java versions for gradle tasks that don't use the main java version
+    fn task_java_versions(&self) -> Vec<(String, u32)> {
+        Vec::new()
+    }
+    /// The java version to run gradlew with for the task in args.
+    /// `task-java-versions` in mcmod.yaml takes priority over the template's mapping
+    fn java_version_for(&self, project: &Project, args: &[&str]) -> IoResult<u32> {
+        let main_version = self.capabilities().java_versions.first().copied();
+        let task = match args.iter().find(|x| !x.starts_with('-')) {
+            Some(x) => x.trim_start_matches(':'),
+            None => return Ok(main_version.unwrap_or(8)),
+        };
+        if let Some(version) = project.loaded_mcmod()?.task_java_versions.get(task) {
+            return Ok(*version);
+        }
+        let version = self
+            .task_java_versions()
+            .into_iter()
+            .find(|(name, _)| name == task)
+            .map(|(_, version)| version);
+        Ok(version.or(main_version).unwrap_or(8))
+    }
+    /// Create the command to run gradlew with args, with the java version for the task
+    fn gradlew_command(&self, project: &Project, args: &[&str]) -> IoResult<Command> {
+        let java_version = self.java_version_for(project, args)?;
+        let template = project.loaded_mcmod()?.template.to_string();
+        let gradle_home = gradle::shared_gradle_home(&template)?;
+        gradle::gradlew_command(
+            &project.target_root(),
+            java_version,
+            gradle_home.as_deref(),
+            args,
+        )
+    }
+    /// Run gradlew with args
+    async fn run_gradlew(&self, project: &Project, args: &[&str]) -> IoResult<()> {
+        let status = self.gradlew_command(project, args)?.status()?;
+        if !status.success() {
+            Err(ErrorCode::GradleFailed.error(io::Error::other("gradlew failed")))?;
+        }
+        Ok(())
+    }
+    /// The dir data generation writes the generated resources to
+    fn datagen_output_dir(&self, project: &Project) -> IoResult<PathBuf> {
+        Ok(cd!(project.target_root(), "src", "generated", "resources"))
+    }
     /// The build output dir
     fn output_dir(&self, project: &Project) -> IoResult<PathBuf> {
         Ok(cd!(project.target_root(), "build", "libs"))
     }
+    /// The javadoc output dir
+    fn javadoc_dir(&self, project: &Project) -> IoResult<PathBuf> {
+        Ok(cd!(project.target_root(), "build", "docs", "javadoc"))
+    }
+    /// The run dir in the target when mcmod.yaml doesn't set one
+    fn default_run_dir(&self) -> &str {
+        "run"
+    }
+    /// Directories in the target with the decompiled workspace made by the gradle setup,
+    /// which are kept in the cache when the target is re-initialized. Workspaces decompiled
+    /// into the gradle home are kept with it, so they are not listed
+    fn decomp_cache_dirs(&self) -> Vec<String> {
+        Vec::new()
+    }
     /// The dependency libs dir
     fn libs_dir(&self, project: &Project) -> IoResult<PathBuf> {
         Ok(cd!(project.target_root(), "libs"))
     }
-    /// The runtime minecraft dir
+    /// The runtime minecraft dir. Uses a directory for the pack when running with a pack,
+    /// `run-dir` in mcmod.yaml if set, or a directory for the profile if `isolated-run-dir` is set.
+    /// With `short-run-dir`, this is the short directory the run directory is linked to
     fn run_dir(&self, project: &Project) -> IoResult<PathBuf> {
-        Ok(cd!(project.target_root(), "run"))
+        let run_dir = configured_run_dir(project, self.default_run_dir())?;
+        if project.loaded_mcmod()?.short_run_dir {
+            return short_run_dir(&run_dir);
+        }
+        Ok(run_dir)
+    }
+    /// Called after sources and metadata are synced to write template-specific files
+    async fn sync_template(&self, _project: &Project) -> IoResult<()> {
+        Ok(())
+    }
+    /// Called after the sources are copied, also by incremental sync, to write the files
+    /// generated from the sources
+    async fn sync_generated_sources(&self, _project: &Project) -> IoResult<()> {
+        Ok(())
+    }
+    /// Gradle script that applies the access transformer file to the decompiled workspace,
+    /// without putting it into the release jar. Used for the access transformers of the
+    /// parent mod in `extends`. None if the template can't
+    fn dev_access_transformer_gradle(&self, _at_file: &str) -> Option<String> {
+        None
     }
     /// Make a map of gradle properties to combine with gradle.properties in the template
     async fn make_gradle_properties(&self, project: &Project)
         -> IoResult<BTreeMap<String, String>>;
 }
 
+/// Write the gradle scripts (name, content) into the target, and apply them from the
+/// build.gradle of the template if they are not applied yet
+pub async fn sync_gradle_scripts(project: &Project, scripts: &[(&str, String)]) -> IoResult<()> {
+    let target_root = project.target_root();
+    let build_gradle = target_root.join("build.gradle");
+    let mut content = fs::read_to_string(&build_gradle).await?;
+    let mut changed = false;
+    for (script, script_content) in scripts {
+        write_file!(target_root.join(script), script_content).await?;
+        let apply_line = format!("apply from: '{script}'");
+        if !content.contains(&apply_line) {
+            content.push_str(&format!("\n// added by mcmod\n{apply_line}\n"));
+            changed = true;
+        }
+    }
+    if changed {
+        write_file!(&build_gradle, content).await?;
+    }
+    Ok(())
+}
+
+/// The gradle directory of the source set. The API is a package in the main sources,
+/// which the build scripts put into the API jar
+pub fn default_source_set_dir(set: SourceSet) -> &'static str {
+    match set {
+        SourceSet::Main | SourceSet::Api => "src/main/java",
+        SourceSet::Test => "src/test/java",
+    }
+}
+
+/// The directory of the sources in the language, next to the directory of the Java sources,
+/// like `src/main/scala` for `src/main/java`
+pub fn language_dir(java_dir: &str, language: Language) -> String {
+    if language == Language::Java {
+        return java_dir.to_owned();
+    }
+    match java_dir.rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/{}", language.name()),
+        None => language.name().to_owned(),
+    }
+}
+
+/// The languages of the sources for the gradle properties, like `java,scala`
+pub fn languages_property(mcmod: &Mcmod) -> String {
+    let mut languages = vec![Language::Java.name()];
+    languages.extend(mcmod.other_languages().into_iter().map(Language::name));
+    languages.join(",")
+}
+
+/// Gradle properties for the `child-mods`: `modChildIds` with the mod ids separated by `,`,
+/// and `modChildName_<modid>` and `modChildVersion_<modid>` for each of them
+pub fn child_mod_properties(mcmod: &Mcmod) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    let ids = mcmod.child_mods.iter().map(|x| x.modid.as_str());
+    map.insert("modChildIds".to_owned(), ids.collect::<Vec<_>>().join(","));
+    for child in &mcmod.child_mods {
+        let version = child.version.as_ref().unwrap_or(&mcmod.version);
+        map.insert(format!("modChildName_{}", child.modid), child.name.clone());
+        map.insert(format!("modChildVersion_{}", child.modid), version.clone());
+    }
+    map
+}
+
+/// The run directory from mcmod.yaml, before relocating it with `short-run-dir`
+fn configured_run_dir(project: &Project, default_run_dir: &str) -> IoResult<PathBuf> {
+    let mcmod = project.loaded_mcmod()?;
+    if let Some(pack) = &project.pack {
+        return Ok(cd!(project.target_root(), format!("run-pack-{pack}")));
+    }
+    if !mcmod.run_dir.is_empty() {
+        return Ok(project.root.join(&mcmod.run_dir));
+    }
+    if let (true, Some(profile)) = (mcmod.isolated_run_dir, &project.profile) {
+        return Ok(cd!(project.target_root(), format!("run-{profile}")));
+    }
+    Ok(project.target_root().join(default_run_dir))
+}
+
+/// The short directory a run directory is relocated to with `short-run-dir`,
+/// in `C:\mcmodruns` on Windows and the data directory of the tool on other platforms,
+/// since it has the saves and configs. MCMOD_SHORT_RUN_ROOT can be set to use another root
+pub fn short_run_dir(run_dir: &Path) -> IoResult<PathBuf> {
+    let root = match std::env::var("MCMOD_SHORT_RUN_ROOT") {
+        Ok(x) => PathBuf::from(x),
+        Err(_) if cfg!(windows) => PathBuf::from(r"C:\mcmodruns"),
+        Err(_) => paths::data_dir()?.join("runs"),
+    };
+    let hash = blake3::hash(run_dir.to_string_lossy().as_bytes()).to_hex();
+    Ok(root.join(&hash[..12]))
+}
+
+/// Create the run directory and return it.
+///
+/// With `short-run-dir`, the short directory is linked from where the run directory
+/// would be otherwise, with a junction on Windows and a symlink on other platforms
+pub async fn setup_run_dir(handler: &dyn TemplateHandler, project: &Project) -> IoResult<PathBuf> {
+    let run_dir = handler.run_dir(project)?;
+    mkdir!(&run_dir).await?;
+    if !project.loaded_mcmod()?.short_run_dir {
+        return Ok(run_dir);
+    }
+    let link = configured_run_dir(project, handler.default_run_dir())?;
+    if let Ok(metadata) = fs::symlink_metadata(&link).await {
+        if metadata.is_symlink() {
+            if fs::read_link(&link).await.ok().as_ref() == Some(&run_dir) {
+                return Ok(run_dir);
+            }
+            if cfg!(windows) {
+                fs::remove_dir(&link).await?;
+            } else {
+                fs::remove_file(&link).await?;
+            }
+        } else if fs::read_dir(&link).await?.next_entry().await?.is_some() {
+            Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Cannot link '{}' to the short run directory because it's not empty. Move its content to '{}' first",
+                    link.display(),
+                    run_dir.display()
+                ),
+            ))?;
+        } else {
+            fs::remove_dir(&link).await?;
+        }
+    }
+    if let Some(parent) = link.parent() {
+        mkdir!(parent).await?;
+    }
+    println!("linking '{}' to '{}'", link.display(), run_dir.display());
+    link_dir(&link, &run_dir).await?;
+    Ok(run_dir)
+}
+
+#[cfg(windows)]
+async fn link_dir(link: &Path, target: &Path) -> IoResult<()> {
+    // junctions don't need admin or developer mode like symlinks
+    let status = Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .status()?;
+    if !status.success() {
+        Err(io::Error::other(format!(
+            "Failed to create junction '{}'",
+            link.display()
+        )))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+async fn link_dir(link: &Path, target: &Path) -> IoResult<()> {
+    fs::symlink(target, link).await?;
+    Ok(())
+}
+
+/// Get the resource pack format of a MC version. Versions before 1.9 use 1
+pub fn pack_format_for(mc_version: &str) -> u32 {
+    let mut parts = mc_version.split('.').map(|x| x.parse::<u32>().unwrap_or(0));
+    let minor = parts.nth(1).unwrap_or(0);
+    let patch = parts.next().unwrap_or(0);
+    match (minor, patch) {
+        (0..=8, _) => 1,
+        (9..=10, _) => 2,
+        (11..=12, _) => 3,
+        (13..=14, _) => 4,
+        (15, _) | (16, 0..=1) => 5,
+        (16, _) => 6,
+        (17, _) => 7,
+        (18, _) => 8,
+        (19, 0..=2) => 9,
+        (19, 3) => 12,
+        (19, _) => 13,
+        (20, 0..=1) => 15,
+        (20, 2) => 18,
+        (20, 3..=4) => 22,
+        (20, _) => 32,
+        _ => 34,
+    }
+}
+
+/// Read the templates shipped with the tool in templates.json, the templates pinned from the
+/// registry, and the user templates in templates.json in the config directory.
+/// Later ones replace earlier ones with the same name
 pub async fn read_templates() -> IoResult<BTreeMap<String, TemplateDef>> {
-    let templates_json_path = templates_path()?;
-    let templates_json = fs::read_to_string(templates_json_path).await?;
-    let templates: BTreeMap<String, TemplateDef> =
-        serde_json::from_str(&templates_json).map_err(|e| {
+    let mut templates = read_templates_json(&templates_path()?).await?;
+    if let Some(registry_templates) = registry::read_registry_templates().await? {
+        templates.extend(registry_templates);
+    }
+    let user_path = paths::config_dir()?.join("templates.json");
+    if user_path.exists() {
+        templates.extend(read_templates_json(&user_path).await?);
+    }
+    Ok(templates)
+}
+
+async fn read_templates_json(path: &Path) -> IoResult<BTreeMap<String, TemplateDef>> {
+    let templates_json = fs::read_to_string(path).await?;
+    let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    parse_templates_json(&templates_json, &path.display().to_string(), base_dir)
+}
+
+/// Parse templates.json, with relative local templates relative to base_dir
+fn parse_templates_json(
+    content: &str,
+    source: &str,
+    base_dir: PathBuf,
+) -> IoResult<BTreeMap<String, TemplateDef>> {
+    let mut templates: BTreeMap<String, TemplateDef> =
+        serde_json::from_str(content).map_err(|e| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("Failed to parse templates.json: {}", e),
+                format!("Failed to parse '{}': {}", source, e),
             )
         })?;
+    for template in templates.values_mut() {
+        template.base_dir = base_dir.clone();
+    }
     Ok(templates)
 }
 
+/// The templates.json shipped with the tool
 pub fn templates_path() -> IoResult<PathBuf> {
     Ok(cd!(util::tool_root()?, "templates.json"))
 }
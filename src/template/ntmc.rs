@@ -1,13 +1,121 @@
-use std::{collections::BTreeMap, io};
+use std::collections::BTreeMap;
+use std::io;
 
 use async_trait::async_trait;
+use serde_json::json;
+use tokio::fs;
+use walkdir::WalkDir;
 
-use crate::{
-    gradle,
-    util::{IoResult, Project},
-};
+use crate::extends;
+use crate::mcmod::Language;
+use crate::util::{cd, mkdir, write_file, IoResult, Project};
 
-use super::TemplateHandler;
+use super::{child_mod_properties, languages_property, Capabilities, TemplateHandler};
+
+/// The build script for mixins, applied from the template's build.gradle
+const MIXINS_GRADLE: &str = "mcmod-mixins.gradle";
+const MIXINS_GRADLE_CONTENT: &str = r#"// generated by mcmod
+// wires up the mixin annotation processor, refmap and manifest when modMixins is set,
+// and keeps FML from looking for @Mod in coremod-only jars
+if (project.modMixins) {
+    repositories {
+        maven {
+            name = "mixin"
+            url = project.mixinRepository
+        }
+    }
+    dependencies {
+        compile(project.mixinDependency) {
+            transitive = false
+        }
+    }
+    def mixinTmpDir = "${buildDir}/tmp/mixins"
+    def mixinSrg = "${mixinTmpDir}/mixins.srg"
+    def mixinRefMap = "${mixinTmpDir}/${project.modMixinsRefmap}"
+    compileJava {
+        doFirst {
+            new File(mixinTmpDir).mkdirs()
+        }
+        options.compilerArgs += [
+            "-AreobfSrgFile=${tasks.reobf.srg}",
+            "-AoutSrgFile=${mixinSrg}",
+            "-AoutRefMapFile=${mixinRefMap}",
+        ]
+    }
+    reobf {
+        addExtraSrgFile mixinSrg
+    }
+    jar {
+        from mixinRefMap
+        manifest {
+            attributes(
+                "TweakClass": "org.spongepowered.asm.launch.MixinTweaker",
+                "MixinConfigs": project.modMixinsConfig,
+                "ForceLoadAsMod": true,
+            )
+        }
+    }
+    [runClient, runServer].each {
+        it.args "--tweakClass", "org.spongepowered.asm.launch.MixinTweaker", "--mixin", project.modMixinsConfig
+    }
+}
+if (project.modCoremodOnly.toBoolean()) {
+    jar {
+        manifest {
+            attributes(
+                "FMLCorePluginContainsFMLMod": false,
+                "ForceLoadAsMod": false,
+            )
+        }
+    }
+}
+"#;
+
+/// The build script for scala and kotlin sources, applied from the template's build.gradle
+const LANGUAGES_GRADLE: &str = "mcmod-languages.gradle";
+const LANGUAGES_GRADLE_CONTENT: &str = r#"// generated by mcmod
+// applies the plugins of the languages in modLanguages. scalac compiles the java sources
+// together with src/main/scala, so they can use each other. src/main/kotlin is compiled
+// before the java sources, which can use it
+buildscript {
+    repositories {
+        mavenCentral()
+    }
+    dependencies {
+        if (project.modLanguages.split(",").contains("kotlin")) {
+            classpath "org.jetbrains.kotlin:kotlin-gradle-plugin:${project.kotlinVersion}"
+        }
+    }
+}
+def languages = project.modLanguages.split(",")
+if (languages.contains("scala")) {
+    apply plugin: "scala"
+    // javac runs before scalac and can't see the scala classes, so scalac compiles both
+    sourceSets.main.scala.srcDir "src/main/java"
+    sourceSets.main.java.srcDirs = []
+    sourceSets.test.scala.srcDir "src/test/java"
+    sourceSets.test.java.srcDirs = []
+    dependencies {
+        compile "org.scala-lang:scala-library:${project.scalaVersion}"
+    }
+}
+if (languages.contains("kotlin")) {
+    // the plugin is on the classpath of this script, not of the project, so it's applied by class
+    apply plugin: buildscript.classLoader.loadClass("org.jetbrains.kotlin.gradle.plugin.KotlinPluginWrapper")
+    dependencies {
+        compile "org.jetbrains.kotlin:kotlin-stdlib:${project.kotlinVersion}"
+    }
+}
+"#;
+
+/// The build script for the tests of `mcmod test`, applied from the template's build.gradle
+const TESTS_GRADLE: &str = "mcmod-tests.gradle";
+const TESTS_GRADLE_CONTENT: &str = r#"// generated by mcmod
+// adds JUnit for the test sources, which the template doesn't have
+dependencies {
+    testCompile "junit:junit:${project.junitVersion}"
+}
+"#;
 
 pub struct Ntmc1710Handler;
 #[async_trait(?Send)]
@@ -16,12 +124,25 @@ impl TemplateHandler for Ntmc1710Handler {
         "1.7.10"
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            mixins: true,
+            coremods: true,
+            java_versions: vec![8],
+            git_version: false,
+            datagen: None,
+            languages: vec![Language::Scala, Language::Kotlin],
+        }
+    }
+
     fn mcmod_version_key(&self) -> &'static str {
         "version"
     }
 
-    async fn run_gradlew(&self, project: &Project, args: &[&str]) -> IoResult<()> {
-        gradle::run_gradlew(&project.target_root(), 8, args).await
+    fn decomp_cache_dirs(&self) -> Vec<String> {
+        // ForgeGradle decompiles into caches/minecraft of the gradle home, or into the
+        // build directory when the mod has access transformers
+        vec!["build/dirtyArtifacts".to_owned()]
     }
 
     async fn make_gradle_properties(
@@ -30,13 +151,6 @@ impl TemplateHandler for Ntmc1710Handler {
     ) -> IoResult<BTreeMap<String, String>> {
         let mcmod = project.mcmod().await?;
 
-        if !mcmod.mixins.is_empty() {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Mixins are not supported by this template",
-            ))?;
-        }
-
         let mut map = BTreeMap::new();
         map.insert("modName".to_owned(), mcmod.name.clone());
         map.insert("modId".to_owned(), mcmod.modid.clone());
@@ -54,6 +168,7 @@ impl TemplateHandler for Ntmc1710Handler {
         let ats = mcmod.access_transformers.join(" ");
         map.insert("modAccessTransformer".to_owned(), ats);
         map.insert("modCoremod".to_owned(), mcmod.coremod.clone());
+        map.insert("modCoremodOnly".to_owned(), mcmod.coremod_only.to_string());
         if mcmod.api.is_empty() {
             map.insert("modApiPattern".to_owned(), "".to_owned());
         } else {
@@ -64,7 +179,153 @@ impl TemplateHandler for Ntmc1710Handler {
             api_pattern.push_str("**");
             map.insert("modApiPattern".to_owned(), api_pattern);
         }
+        map.insert("modMixins".to_owned(), mcmod.mixins.clone());
+        map.insert(
+            "modMixinsConfig".to_owned(),
+            format!("mixins.{}.json", mcmod.modid),
+        );
+        map.insert(
+            "modMixinsRefmap".to_owned(),
+            format!("mixins.{}.refmap.json", mcmod.modid),
+        );
+        map.insert(
+            "mixinRepository".to_owned(),
+            "https://repo.spongepowered.org/maven/".to_owned(),
+        );
+        map.insert(
+            "mixinDependency".to_owned(),
+            "org.spongepowered:mixin:0.7.11-SNAPSHOT".to_owned(),
+        );
+        map.insert("modLanguages".to_owned(), languages_property(mcmod));
+        // the scala version forge ships with
+        map.insert("scalaVersion".to_owned(), "2.11.1".to_owned());
+        map.insert("kotlinVersion".to_owned(), "1.2.71".to_owned());
+        map.insert("junitVersion".to_owned(), "4.13.2".to_owned());
+        map.extend(child_mod_properties(mcmod));
 
         Ok(map)
     }
+
+    async fn sync_template(&self, project: &Project) -> IoResult<()> {
+        let mcmod = project.mcmod().await?;
+        let scripts = [
+            (MIXINS_GRADLE, MIXINS_GRADLE_CONTENT.to_owned()),
+            (LANGUAGES_GRADLE, LANGUAGES_GRADLE_CONTENT.to_owned()),
+            (TESTS_GRADLE, TESTS_GRADLE_CONTENT.to_owned()),
+            (
+                extends::GRADLE_SCRIPT,
+                extends::dev_gradle_script(self, project, mcmod),
+            ),
+        ];
+        super::sync_gradle_scripts(project, &scripts).await
+    }
+
+    fn dev_access_transformer_gradle(&self, at_file: &str) -> Option<String> {
+        // ForgeGradle applies the access transformers of the extension when decompiling,
+        // while the jar only gets the ones in the resources
+        Some(format!("minecraft {{\n    accessT '{at_file}'\n}}\n"))
+    }
+
+    async fn sync_generated_sources(&self, project: &Project) -> IoResult<()> {
+        let target_root = project.target_root();
+        let mcmod = project.mcmod().await?;
+        if mcmod.mixins.is_empty() {
+            return Ok(());
+        }
+        let mut mixins = Vec::new();
+        let mut client = Vec::new();
+        let mut server = Vec::new();
+        let java_root = cd!(target_root.clone(), "src", "main", "java");
+        let mixins_root = java_root.join(mcmod.mixins.replace('.', "/"));
+        if !mixins_root.exists() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Mixins package '{}' not found in the source", mcmod.mixins),
+            ))?;
+        }
+        for entry in WalkDir::new(&mixins_root).sort_by_file_name() {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(e) => Err(io::Error::new(io::ErrorKind::NotFound, e))?,
+            };
+            let path = entry.path();
+            if path.extension().is_none_or(|x| x != "java") {
+                continue;
+            }
+            // helpers in the mixin package are not mixins
+            if !is_mixin(&fs::read_to_string(path).await?) {
+                continue;
+            }
+            let relative = match path.strip_prefix(&mixins_root) {
+                Ok(x) => x.with_extension(""),
+                Err(_) => continue,
+            };
+            let class_name = relative
+                .components()
+                .map(|x| x.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(".");
+            if class_name.starts_with("client.") {
+                client.push(class_name);
+            } else if class_name.starts_with("server.") {
+                server.push(class_name);
+            } else {
+                mixins.push(class_name);
+            }
+        }
+        let config = json!({
+            "required": true,
+            "minVersion": "0.7.11",
+            "package": mcmod.mixins,
+            "refmap": format!("mixins.{}.refmap.json", mcmod.modid),
+            "target": "@env(DEFAULT)",
+            "compatibilityLevel": "JAVA_8",
+            "mixins": mixins,
+            "client": client,
+            "server": server,
+        });
+        let config = match serde_json::to_string_pretty(&config) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let resource_path = cd!(target_root, "src", "main", "resources");
+        let config_path = resource_path.join(format!("mixins.{}.json", mcmod.modid));
+        // unchanged files are kept, so gradle doesn't process the resources again
+        if fs::read_to_string(&config_path).await.ok().as_ref() == Some(&config) {
+            return Ok(());
+        }
+        mkdir!(&resource_path).await?;
+        write_file!(config_path, config).await?;
+
+        Ok(())
+    }
+}
+
+/// Check if the java source has a class annotated with `@Mixin`, or the qualified name of it
+fn is_mixin(source: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    source.match_indices("Mixin").any(|(i, x)| {
+        let ends = source[i + x.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| !is_ident(c));
+        let before = &source[..i];
+        let package = before.trim_end_matches(|c: char| is_ident(c) || c == '.');
+        let qualifier = &before[package.len()..];
+        ends && package.ends_with('@') && (qualifier.is_empty() || qualifier.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_mixin_annotations() {
+        assert!(is_mixin("@Mixin(Foo.class)\npublic class MixinFoo {}"));
+        assert!(is_mixin("@Mixin (value = Foo.class)"));
+        assert!(is_mixin("@org.spongepowered.asm.mixin.Mixin(Foo.class)"));
+        assert!(!is_mixin("@MixinHelper\npublic class Helper {}"));
+        assert!(!is_mixin("public final class Helpers {}"));
+    }
 }
@@ -0,0 +1,170 @@
+//! Templates distributed from a remote registry set in config.json
+//!
+//! `mcmod template sync-registry` downloads the templates.json of the registry, verifies it
+//! against the blake3 checksum pinned in config.json and pins the verified copy in the config
+//! directory. Templates are always read from the pinned copy, which is checked against the
+//! checksum in config.json again, since registry templates can run `post-clone` commands.
+//!
+//! The checksum is never downloaded from the registry, since a compromised server could
+//! serve a matching one
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::config::{GlobalConfig, RegistryConfig};
+use crate::explain::ErrorCode;
+use crate::paths;
+use crate::state::{read_json, write_json};
+use crate::util::{mkdir, timestamp, write_file, IoResult};
+
+use super::{parse_templates_json, TemplateDef};
+
+/// What was pinned by the last sync
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RegistryState {
+    url: String,
+    checksum: String,
+    synced_at: String,
+}
+
+fn registry_dir() -> IoResult<PathBuf> {
+    Ok(paths::config_dir()?.join("registry"))
+}
+
+/// Download the templates.json of the registry, verify it and replace the pinned copy
+pub async fn sync_registry() -> IoResult<()> {
+    let registry = match GlobalConfig::get()?.template_registry.clone() {
+        Some(x) => x,
+        None => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No `template-registry` in '{}'",
+                paths::config_dir()?.join("config.json").display()
+            ),
+        )))?,
+    };
+    let expected = pinned_checksum(&registry)?;
+    println!("downloading templates from '{}'", registry.url);
+    let content = download_text(&registry.url).await?;
+    let checksum = blake3::hash(content.as_bytes()).to_hex().to_string();
+    if checksum != expected {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Checksum of the templates.json from the registry doesn't match. Expected {expected}, got {checksum}. The pinned copy is not changed"
+            ),
+        ))?;
+    }
+
+    let dir = registry_dir()?;
+    let templates_path = dir.join("templates.json");
+    // make sure the registry is usable before replacing the pinned copy
+    let templates = parse_templates_json(&content, &registry.url, dir.clone())?;
+    let old = read_pinned(&registry)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    mkdir!(&dir).await?;
+    write_file!(&templates_path, content).await?;
+    let state = RegistryState {
+        url: registry.url.clone(),
+        checksum,
+        synced_at: timestamp(),
+    };
+    write_json(&dir.join("registry.json"), &state).await?;
+
+    for name in templates.keys().filter(|x| !old.contains_key(*x)) {
+        println!("  added {name}");
+    }
+    for name in old.keys().filter(|x| !templates.contains_key(*x)) {
+        println!("  removed {name}");
+    }
+    println!(
+        "pinned {} template(s) from the registry in '{}'",
+        templates.len(),
+        templates_path.display()
+    );
+    Ok(())
+}
+
+/// Read the templates pinned from the registry in config.json. None if no registry is
+/// configured or it's not synced yet
+pub async fn read_registry_templates() -> IoResult<Option<BTreeMap<String, TemplateDef>>> {
+    match &GlobalConfig::get()?.template_registry {
+        Some(registry) => read_pinned(registry).await,
+        None => Ok(None),
+    }
+}
+
+/// The checksum of templates.json pinned in config.json, which is required to use the registry
+fn pinned_checksum(registry: &RegistryConfig) -> IoResult<String> {
+    match &registry.checksum {
+        Some(x) => Ok(x.trim().to_ascii_lowercase()),
+        None => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "`template-registry` in '{}' needs the blake3 `checksum` of the templates.json, like the output of `b3sum`, to verify it",
+                paths::config_dir()?.join("config.json").display()
+            ),
+        )))?,
+    }
+}
+
+async fn read_pinned(registry: &RegistryConfig) -> IoResult<Option<BTreeMap<String, TemplateDef>>> {
+    let expected = pinned_checksum(registry)?;
+    let dir = registry_dir()?;
+    let state = match read_json::<RegistryState>(&dir.join("registry.json")).await {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    if state.url != registry.url {
+        Err(ErrorCode::TemplateNotFound.error(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The pinned templates are from '{}', but the registry is now '{}'. Run `mcmod template sync-registry`",
+                state.url, registry.url
+            ),
+        )))?;
+    }
+    let path = dir.join("templates.json");
+    let content = fs::read_to_string(&path).await?;
+    let checksum = blake3::hash(content.as_bytes()).to_hex().to_string();
+    if checksum != state.checksum || checksum != expected {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' doesn't match the checksum in config.json. Run `mcmod template sync-registry` to sync it again",
+                path.display()
+            ),
+        ))?;
+    }
+    let templates = parse_templates_json(&content, &path.display().to_string(), dir)?;
+    Ok(Some(templates))
+}
+
+async fn download_text(url: &str) -> IoResult<String> {
+    let result = async {
+        let response = Client::new().get(url).send().await?.error_for_status()?;
+        response.bytes().await
+    }
+    .await;
+    // the checksum is of the exact bytes, so the text is not decoded with the charset in the headers
+    let bytes = match result {
+        Ok(x) => x,
+        Err(e) => Err(io::Error::other(format!("Failed to download '{url}': {e}")))?,
+    };
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{url}' is not UTF-8: {e}"),
+        ))?,
+    }
+}
@@ -0,0 +1,448 @@
+//! Tools for authoring templates: `mcmod template scaffold` and `mcmod template test`
+//!
+//! A template that is not built in ships a `mcmod-template.yaml`, which tells the manifest
+//! handler the gradle properties to write, the gradle tasks to run, and where the outputs are.
+//! The scaffold can prefill it from a built-in template the new template is similar to
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use tokio::fs;
+
+use crate::mcmod::SourceSet;
+use crate::state::write_json;
+use crate::template::{
+    self, default_source_set_dir, Template, TemplateHandler, TemplateManifest, MANIFEST_FILE,
+};
+use crate::util::{cd, mkdir, timestamp, write_file, IoResult, Project, ProjectArgs};
+
+/// Name of the template in the sample project when testing a template with its manifest
+const TEST_TEMPLATE_NAME: &str = "template-test";
+
+/// Values in mcmod.yaml of the sample project, and the variables they are replaced with
+/// when prefilling the properties in the manifest. Longer values are replaced first
+const SAMPLE_VALUES: &[(&str, &str)] = &[
+    ("Sample mod for testing the template", "${description}"),
+    ("mcmodtest.templatetest", "${group}"),
+    ("mcmodtest/templatetest", "${group-internal}"),
+    ("Template Test", "${name}"),
+    ("Template-Test", "${archives-base-name}"),
+    ("templatetest", "${modid}"),
+    ("1.0.0", "${version}"),
+];
+
+#[derive(Debug, Parser)]
+pub struct TemplateCommand {
+    #[clap(subcommand)]
+    pub command: TemplateSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TemplateSubcommand {
+    /// Create the files of a new template, with a mcmod-template.yaml describing it
+    Scaffold(ScaffoldTemplate),
+    /// Check that a template works: fetch it into a sample project, set it up and build it
+    Test(TestTemplate),
+    /// Download the templates.json of the registry in config.json, verify it and pin it
+    SyncRegistry,
+}
+
+#[derive(Debug, Parser)]
+pub struct ScaffoldTemplate {
+    /// Directory of the new template
+    #[arg(value_name = "DIR")]
+    pub template_dir: PathBuf,
+
+    /// A built-in template to prefill mcmod-template.yaml from, like ntmc-1.7.10
+    #[arg(long)]
+    pub like: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct TestTemplate {
+    /// Directory or git url of the template
+    pub template: String,
+
+    /// Test the template as a replacement of this built-in template, instead of
+    /// with its mcmod-template.yaml
+    #[arg(long)]
+    pub like: Option<String>,
+
+    /// Branch to test for git templates
+    #[arg(long)]
+    pub branch: Option<String>,
+
+    /// Keep the sample project instead of removing it after the test
+    #[arg(long)]
+    pub keep: bool,
+}
+
+impl TemplateCommand {
+    pub async fn run(self, _args: &ProjectArgs) -> IoResult<()> {
+        match self.command {
+            TemplateSubcommand::Scaffold(scaffold) => scaffold.run().await,
+            TemplateSubcommand::Test(test) => test.run().await,
+            TemplateSubcommand::SyncRegistry => template::sync_registry().await,
+        }
+    }
+}
+
+impl ScaffoldTemplate {
+    async fn run(self) -> IoResult<()> {
+        let manifest = match &self.like {
+            Some(like) => {
+                let template = like.parse::<Template>()?;
+                let sample_dir = sample_project_dir();
+                let result = async {
+                    let git_version = template.new_handler().capabilities().git_version;
+                    let project =
+                        create_sample_project(&sample_dir, &template, git_version).await?;
+                    manifest_like(template.new_handler().as_ref(), &project).await
+                }
+                .await;
+                if sample_dir.exists() {
+                    fs::remove_dir_all(&sample_dir).await?;
+                }
+                result?
+            }
+            None => TemplateManifest {
+                mc_version: "1.7.10".to_owned(),
+                properties: [
+                    ("modName", "${name}"),
+                    ("modId", "${modid}"),
+                    ("modVersion", "${version}"),
+                    ("modGroup", "${group}"),
+                    ("modArchivesBaseName", "${archives-base-name}"),
+                ]
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+                ..Default::default()
+            },
+        };
+
+        mkdir!(&self.template_dir).await?;
+        let mut gradle_properties = String::from(
+            "# These properties are set by mcmod from mcmod.yaml when syncing, as described\n\
+             # in mcmod-template.yaml. The build script should read them instead of hard coding the values\n",
+        );
+        for (key, value) in &manifest.properties {
+            gradle_properties.push_str(&format!("{key}={value}\n"));
+        }
+        let mut tasks = vec![
+            format!(
+                "- `{}`: run once after the template is fetched",
+                manifest.setup_tasks.join(" ")
+            ),
+            format!(
+                "- `{}`: builds the jar into `{}`",
+                manifest.build_tasks.join(" "),
+                manifest.output_dir
+            ),
+            format!(
+                "- `{}`: with `mcmod sync --eclipse`",
+                manifest.eclipse_tasks.join(" ")
+            ),
+            format!(
+                "- `{}`: with `mcmod test`, for the tests in `{}`",
+                manifest.test_tasks.join(" "),
+                manifest
+                    .source_set_dirs
+                    .get(&SourceSet::Test)
+                    .map(String::as_str)
+                    .unwrap_or(default_source_set_dir(SourceSet::Test))
+            ),
+            format!(
+                "- `runClient` and `runServer`: with `mcmod run`, in the `{}` directory",
+                manifest.run_dir
+            ),
+        ];
+        if let Some(task) = &manifest.datagen_task {
+            tasks.push(format!("- `{task}`: with `mcmod run data`"));
+        }
+        let readme = format!(
+            "# Template for mcmod\n\n\
+             This template is for MC {} with Java {}. `{MANIFEST_FILE}` describes it to mcmod.\n\n\
+             ## Gradle properties\n\
+             mcmod merges these properties into `gradle.properties`: {}\n\n\
+             ## Gradle tasks\n\
+             mcmod runs these tasks with `gradlew`:\n{}\n\n\
+             The sources in the project are copied to `src/main/java` and `src/main/resources`.\n\
+             Scala and Kotlin sources go to `src/main/scala` and `src/main/kotlin` if they are in `languages`.\n\
+             Run `mcmod template test <this directory>` to check that the template works.\n",
+            manifest.mc_version,
+            manifest
+                .java_versions
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            manifest
+                .properties
+                .keys()
+                .map(|x| format!("`{x}`"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            tasks.join("\n")
+        );
+        let manifest = match serde_yaml::to_string(&manifest) {
+            Ok(x) => x,
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        let files = [
+            (MANIFEST_FILE, manifest),
+            ("gradle.properties", gradle_properties),
+            ("TEMPLATE.md", readme),
+        ];
+        for (name, content) in files {
+            let path = self.template_dir.join(name);
+            if path.exists() {
+                println!("'{}' already exists, skipping", path.display());
+                continue;
+            }
+            write_file!(&path, content).await?;
+            println!("created '{}'", path.display());
+        }
+        for dir in [
+            cd!(self.template_dir.clone(), "src", "main", "java"),
+            cd!(self.template_dir.clone(), "src", "main", "resources"),
+        ] {
+            mkdir!(&dir).await?;
+        }
+        println!();
+        println!("next steps:");
+        println!("  1. add the gradle wrapper and a build.gradle that uses the properties in gradle.properties");
+        println!("  2. adjust {MANIFEST_FILE} to the tasks and directories of the build script");
+        println!("  3. mcmod template test {}", self.template_dir.display());
+        Ok(())
+    }
+}
+
+/// Describe the built-in template with a manifest, replacing the values of the sample project
+/// in the gradle properties with variables
+async fn manifest_like(
+    handler: &dyn TemplateHandler,
+    project: &Project,
+) -> IoResult<TemplateManifest> {
+    let target_root = project.target_root();
+    let relative = |x: PathBuf| {
+        x.strip_prefix(&target_root)
+            .map(|x| x.display().to_string().replace('\\', "/"))
+            .unwrap_or_else(|_| x.display().to_string())
+    };
+    let capabilities = handler.capabilities();
+    let mut properties = handler.make_gradle_properties(project).await?;
+    for value in properties.values_mut() {
+        for (sample, variable) in SAMPLE_VALUES {
+            *value = value.replace(sample, variable);
+        }
+        // the sample only has java sources
+        if value == "java" {
+            *value = "${languages}".to_owned();
+        }
+    }
+    Ok(TemplateManifest {
+        mc_version: handler.mc_version().to_owned(),
+        java_versions: capabilities.java_versions,
+        task_java_versions: handler.task_java_versions().into_iter().collect(),
+        properties,
+        output_dir: relative(handler.output_dir(project)?),
+        libs_dir: relative(handler.libs_dir(project)?),
+        run_dir: handler.default_run_dir().to_owned(),
+        decomp_cache_dirs: handler.decomp_cache_dirs(),
+        version_key: handler.mcmod_version_key().to_owned(),
+        mixins: capabilities.mixins,
+        coremods: capabilities.coremods,
+        git_version: capabilities.git_version,
+        datagen_task: capabilities.datagen,
+        languages: capabilities.languages,
+        datagen_output_dir: relative(handler.datagen_output_dir(project)?),
+        ..Default::default()
+    })
+}
+
+impl TestTemplate {
+    async fn run(self) -> IoResult<()> {
+        let local = Path::new(&self.template);
+        let is_local = local.is_dir();
+        let (template, git_version) = match &self.like {
+            Some(like) => {
+                let template = like.parse::<Template>()?;
+                let git_version = template.new_handler().capabilities().git_version;
+                (template, git_version)
+            }
+            None => {
+                // the manifest of a remote template is only available after fetching,
+                // and the sample project gets a version either way
+                let manifest = if is_local {
+                    let path = local.join(MANIFEST_FILE);
+                    let content = match fs::read_to_string(&path).await {
+                        Ok(x) => x,
+                        Err(e) => Err(io::Error::new(
+                            e.kind(),
+                            format!("Cannot read '{}'. Create it with `mcmod template scaffold`, or test the template as a built-in one with --like", path.display()),
+                        ))?,
+                    };
+                    match serde_yaml::from_str::<TemplateManifest>(&content) {
+                        Ok(x) => x,
+                        Err(e) => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Cannot parse '{}': {e}", path.display()),
+                        ))?,
+                    }
+                } else {
+                    TemplateManifest::default()
+                };
+                (
+                    Template::from_name(TEST_TEMPLATE_NAME),
+                    manifest.git_version,
+                )
+            }
+        };
+        let url = if is_local {
+            std::fs::canonicalize(local)?.display().to_string()
+        } else {
+            self.template.clone()
+        };
+
+        let sample_dir = sample_project_dir();
+        println!("creating sample project in '{}'", sample_dir.display());
+        create_sample_project(&sample_dir, &template, git_version).await?;
+        let config_dir = sample_dir.join(".mcmod-config");
+        mkdir!(&config_dir).await?;
+        let mut def = json!({ "url": url });
+        if let Some(branch) = &self.branch {
+            def["branch"] = json!(branch);
+        }
+        if !is_local
+            && !url.starts_with("https://github.com/")
+            && !url.starts_with("https://gitlab.")
+        {
+            def["use-git"] = json!(true);
+        }
+        write_json(
+            &config_dir.join("templates.json"),
+            &json!({ template.to_string(): def }),
+        )
+        .await?;
+
+        let result = run_checks(&sample_dir, &config_dir).await;
+        if self.keep || result.is_err() {
+            println!("the sample project is kept in '{}'", sample_dir.display());
+        } else {
+            fs::remove_dir_all(&sample_dir).await?;
+        }
+        result?;
+        println!("template passed all checks");
+        Ok(())
+    }
+}
+
+async fn run_checks(sample_dir: &Path, config_dir: &Path) -> IoResult<()> {
+    let steps: [(&str, &[&str]); 2] = [
+        ("fetch and set up the template", &["sync"]),
+        ("build the sample mod", &["build"]),
+    ];
+    let exe = std::env::current_exe()?;
+    for (name, args) in steps {
+        println!("==> {name}: mcmod {}", args.join(" "));
+        let status = Command::new(&exe)
+            .arg("--project-root")
+            .arg(sample_dir)
+            .args(args)
+            .env("MCMOD_CONFIG_DIR", config_dir)
+            .env_remove(crate::discover::PROJECT_ROOT_ENV)
+            .status()?;
+        if !status.success() {
+            Err(io::Error::other(format!(
+                "Check failed: {name}. See the output above"
+            )))?;
+        }
+    }
+    let project = Project::new_root(sample_dir.to_path_buf());
+    let output_dir = project
+        .mcmod()
+        .await?
+        .template
+        .new_handler()
+        .output_dir(&project)?;
+    let has_jar = std::fs::read_dir(&output_dir)
+        .map(|x| {
+            x.flatten()
+                .any(|x| x.path().extension().is_some_and(|x| x == "jar"))
+        })
+        .unwrap_or(false);
+    if !has_jar {
+        Err(io::Error::other(format!(
+            "Check failed: the build didn't output a jar into '{}'",
+            output_dir.display()
+        )))?;
+    }
+    println!("==> found the jar in '{}'", output_dir.display());
+    Ok(())
+}
+
+fn sample_project_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("mcmod-template-test-{}", timestamp()))
+}
+
+/// Create a project with a minimal mod for the template
+async fn create_sample_project(
+    dir: &Path,
+    template: &Template,
+    git_version: bool,
+) -> IoResult<Project> {
+    let version = if git_version { "" } else { "1.0.0" };
+    let mcmod = format!(
+        "template: {template}\n\
+         name: Template Test\n\
+         modid: templatetest\n\
+         description: Sample mod for testing the template\n\
+         version: \"{version}\"\n\
+         copy-paths:\n\
+         - [src, src/main/java]\n"
+    );
+    mkdir!(dir).await?;
+    write_file!(dir.join("mcmod.yaml"), mcmod).await?;
+    let java = "package mcmodtest.templatetest;\n\n\
+         import cpw.mods.fml.common.Mod;\n\n\
+         @Mod(modid = \"templatetest\")\n\
+         public class ModMain {}\n";
+    let source_dir = cd!(dir.to_path_buf(), "src", "mcmodtest", "templatetest");
+    mkdir!(&source_dir).await?;
+    write_file!(source_dir.join("ModMain.java"), java).await?;
+    // templates that take the version from git need a commit
+    if git_version {
+        let dir_str = dir.display().to_string();
+        for args in [
+            vec!["init", "-q"],
+            vec!["add", "-A"],
+            vec![
+                "-c",
+                "user.name=mcmod",
+                "-c",
+                "user.email=mcmod@localhost",
+                "commit",
+                "-qm",
+                "sample",
+            ],
+            vec!["tag", "1.0.0"],
+        ] {
+            let status = Command::new("git")
+                .args(["-C", &dir_str])
+                .args(&args)
+                .status()?;
+            if !status.success() {
+                Err(io::Error::other(
+                    "Failed to create the git repository of the sample project",
+                ))?;
+            }
+        }
+    }
+    let project = Project::new_root(dir.to_path_buf());
+    project.mcmod().await?;
+    Ok(project)
+}
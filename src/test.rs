@@ -0,0 +1,57 @@
+//! Compiling and running the tests in the `test` sources
+
+use std::io;
+
+use clap::Parser;
+
+use crate::explain::ErrorCode;
+use crate::mcmod::SourceSet;
+use crate::sync::{self, SyncCommand};
+use crate::timings;
+use crate::util::{IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct TestCommand {
+    /// Arguments for the test task after `--`, like `-- --tests com.example.MyTest`
+    #[arg(last = true)]
+    pub gradle_args: Vec<String>,
+}
+
+impl TestCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        if !mcmod.sources.iter().any(|x| x.set == SourceSet::Test) {
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No test sources in mcmod.yaml. Add `{ set: test, root: <dir> }` to `sources`",
+            )))?;
+        }
+        if !sync::is_up_to_date(&project).await? {
+            let sync = SyncCommand {
+                incremental: true,
+                eclipse: false,
+                pull_back: false,
+                overwrite: false,
+                ninja_args: Vec::new(),
+                no_prune: false,
+                show_properties_diff: false,
+                fix: false,
+                keep_going: false,
+            };
+            sync.run(args).await?;
+        }
+        let _lock = project.lock_target().await?;
+        let template_handler = mcmod.template.new_handler();
+        let gradle_args = self
+            .gradle_args
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        timings::time(
+            "test: gradle",
+            template_handler.test(&project, &gradle_args),
+        )
+        .await
+    }
+}
@@ -0,0 +1,115 @@
+//! Timing the phases of sync, build and run with `--timings`. Nothing leaves the machine
+
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::explain::ErrorCode;
+use crate::state::write_json;
+use crate::util::{cd, mkdir, timestamp, IoResult, Project};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum TimingsOutput {
+    /// Print the breakdown at the end
+    Print,
+    /// Also write it as JSON into target/mcmod-logs
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Phase {
+    name: String,
+    /// Milliseconds
+    duration: u128,
+}
+
+/// Recorded phases, None if timings are not enabled
+static PHASES: Mutex<Option<Vec<Phase>>> = Mutex::new(None);
+
+fn phases() -> MutexGuard<'static, Option<Vec<Phase>>> {
+    PHASES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Start recording the phases
+pub fn enable() {
+    *phases() = Some(Vec::new());
+}
+
+/// Run the future as a phase, recording how long it took if timings are enabled
+pub async fn time<F: Future>(name: &str, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    record(name, start.elapsed());
+    output
+}
+
+fn record(name: &str, duration: Duration) {
+    if let Some(phases) = phases().as_mut() {
+        phases.push(Phase {
+            name: name.to_owned(),
+            duration: duration.as_millis(),
+        });
+    }
+}
+
+/// Print the breakdown of the phases and the total time of the command,
+/// and write the JSON into target/mcmod-logs of the project if there is one,
+/// with the code of the error if the command failed
+pub async fn report(
+    output: TimingsOutput,
+    total: Duration,
+    project: Option<&Project>,
+    error_code: Option<ErrorCode>,
+) -> IoResult<()> {
+    let phases = phases().take().unwrap_or_default();
+    let total = total.as_millis();
+    println!();
+    println!("timings:");
+    let width = phases.iter().map(|x| x.name.len()).max().unwrap_or(0);
+    for phase in &phases {
+        let percent = (phase.duration * 100).checked_div(total).unwrap_or(0);
+        println!(
+            "  {:<width$}  {:>8}  {percent:>3}%",
+            phase.name,
+            format_millis(phase.duration)
+        );
+    }
+    println!("  {:<width$}  {:>8}", "total", format_millis(total));
+    if output != TimingsOutput::Json {
+        return Ok(());
+    }
+    let project = match project {
+        Some(x) => x,
+        None => {
+            println!("not writing the timings since there is no project");
+            return Ok(());
+        }
+    };
+    let dir = cd!(project.target_root(), "mcmod-logs");
+    mkdir!(&dir).await?;
+    let path = dir.join(format!("timings-{}.json", timestamp()));
+    write_json(
+        &path,
+        &serde_json::json!({
+            "total": total,
+            "phases": phases,
+            "error-code": error_code.map(ErrorCode::code),
+        }),
+    )
+    .await?;
+    println!("timings written to '{}'", path.display());
+    Ok(())
+}
+
+fn format_millis(millis: u128) -> String {
+    if millis < 1000 {
+        format!("{millis}ms")
+    } else {
+        format!("{}.{:02}s", millis / 1000, millis % 1000 / 10)
+    }
+}
@@ -0,0 +1,128 @@
+//! Regenerating the files the tool owns in an existing project, after the tool is updated
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::init::{GITATTRIBUTES, GITIGNORE};
+use crate::run;
+use crate::state::TemplateState;
+use crate::sync::SyncCommand;
+use crate::util::{cd, write_file, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct UpgradeProjectCommand {}
+
+impl UpgradeProjectCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let _lock = project.lock_target().await?;
+        let mcmod = project.mcmod().await?;
+        mcmod.validate()?;
+        let handler = mcmod.template.new_handler();
+
+        let files = tool_owned_files(&project).await?;
+        let mut before = Vec::with_capacity(files.len());
+        for path in &files {
+            before.push(fs::read(path).await.ok());
+        }
+
+        let gitignore_entries = GITIGNORE
+            .iter()
+            .chain(handler.gitignore_entries())
+            .copied()
+            .collect::<Vec<_>>();
+        upgrade_gitignore(&project, &gitignore_entries).await?;
+        let gitattributes = project.root.join(".gitattributes");
+        if !gitattributes.exists() {
+            write_file!(&gitattributes, GITATTRIBUTES.to_owned()).await?;
+        }
+        // older versions store only the template name
+        let state = TemplateState::load(&project).await;
+        if state.cloned {
+            state.save(&project).await?;
+        }
+
+        let sync = SyncCommand {
+            incremental: false,
+            eclipse: true,
+            pull_back: false,
+            overwrite: false,
+            ninja_args: Vec::new(),
+            no_prune: false,
+            show_properties_diff: false,
+            fix: false,
+            keep_going: false,
+        };
+        sync.run(args).await?;
+        run::write_init_script(&project, &mcmod.jvm_args, &[]).await?;
+
+        println!();
+        let mut unchanged = 0;
+        for (path, before) in files.iter().zip(before) {
+            let after = fs::read(path).await.ok();
+            let rel_path = path.strip_prefix(&project.root).unwrap_or(path);
+            match (before, after) {
+                (before, after) if before == after => unchanged += 1,
+                (None, Some(_)) => println!("created: {}", rel_path.display()),
+                (Some(_), None) => println!("removed: {}", rel_path.display()),
+                _ => println!("updated: {}", rel_path.display()),
+            }
+        }
+        println!("{unchanged} generated file(s) unchanged");
+        Ok(())
+    }
+}
+
+/// The files that the tool generates and owns, in the project and the target directory
+async fn tool_owned_files(project: &Project) -> IoResult<Vec<PathBuf>> {
+    let mcmod = project.mcmod().await?;
+    let target_root = project.target_root();
+    let resources = cd!(target_root.clone(), "src", "main", "resources");
+    let mut files = vec![
+        project.build_ninja(),
+        project.root.join(".gitignore"),
+        project.root.join(".gitattributes"),
+        project.root.join(".classpath"),
+        project.root.join(".project"),
+        target_root.join(".classpath"),
+        target_root.join(".project"),
+        target_root.join("gradle.properties"),
+        target_root.join("mcmod-run.gradle"),
+        target_root.join("mcmod-mixins.gradle"),
+        TemplateState::path(project),
+        resources.join("mcmod.info"),
+        resources.join("pack.mcmeta"),
+    ];
+    if let Some(path) = mcmod.tags_class_path() {
+        files.push(cd!(target_root, "src", "main", "java", path));
+    }
+    Ok(files)
+}
+
+/// Add the lines the tool would generate now that are missing from .gitignore,
+/// keeping the user's lines
+async fn upgrade_gitignore(project: &Project, entries: &[&str]) -> IoResult<()> {
+    let path = project.root.join(".gitignore");
+    let old = fs::read_to_string(&path).await.unwrap_or_default();
+    let existing = old.lines().map(str::trim).collect::<Vec<_>>();
+    let missing = entries
+        .iter()
+        .filter(|x| !x.starts_with('#') && !existing.contains(x))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let mut content = old.clone();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str("# added by mcmod upgrade-project\n");
+    for line in missing {
+        content.push_str(line);
+        content.push('\n');
+    }
+    write_file!(&path, content).await?;
+    Ok(())
+}
@@ -1,11 +1,15 @@
 use std::cell::OnceCell;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
 
 use tokio::fs;
 
+use crate::explain::ErrorCode;
 use crate::mcmod::Mcmod;
 
+pub use crate::discover::ProjectArgs;
+
 pub type IoResult<T> = error_stack::Result<T, io::Error>;
 
 macro_rules! cd {
@@ -38,13 +42,9 @@ pub(crate) use mkdir;
 macro_rules! write_file {
     ($path:expr, $content:expr) => {
         async {
-            use tokio::io::AsyncWriteExt;
             let path = $path;
             let content = $content;
-            tokio::fs::File::create(path)
-                .await?
-                .write_all(content.as_bytes())
-                .await?;
+            $crate::util::write_atomic(path.as_ref(), content.as_bytes()).await?;
             Ok::<(), error_stack::Report<tokio::io::Error>>(())
         }
     };
@@ -83,6 +83,267 @@ pub fn confirm_yn() -> IoResult<bool> {
     }
 }
 
+/// Format a size in bytes for people
+pub fn format_size(size: u64) -> String {
+    if size >= 1024 * 1024 * 1024 {
+        format!("{:.1} GiB", size as f64 / 1024.0 / 1024.0 / 1024.0)
+    } else if size >= 1024 * 1024 {
+        format!("{:.1} MiB", size as f64 / 1024.0 / 1024.0)
+    } else if size >= 1024 {
+        format!("{:.1} KiB", size as f64 / 1024.0)
+    } else {
+        format!("{size} B")
+    }
+}
+
+/// Current UTC time as `YYYYMMDD-HHMMSS`, for file names
+pub fn timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // convert days since epoch to a civil date
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Put the command in a new process group, so it can be stopped with everything it starts
+pub fn new_process_group(command: &mut std::process::Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = command;
+    }
+}
+
+/// Kill the process and its children, like gradle and the game it started
+pub fn kill_process_tree(pid: u32) {
+    #[cfg(unix)]
+    let _ = std::process::Command::new("kill")
+        .arg("--")
+        .arg(format!("-{pid}"))
+        .status();
+    #[cfg(windows)]
+    let _ = std::process::Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &pid.to_string()])
+        .status();
+}
+
+/// Match a name with a pattern where `*` matches any characters and `?` matches one character
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it matched up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Write the file by writing a temporary file next to it and renaming it into place,
+/// so the file is never left half-written if mcmod is interrupted
+pub async fn write_atomic(path: &Path, content: &[u8]) -> IoResult<()> {
+    let temp_path = atomic_temp_path(path);
+    if let Err(e) = fs::write(&temp_path, content).await {
+        let _ = fs::remove_file(&temp_path).await;
+        Err(e)?;
+    }
+    fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// [`write_atomic`] for code that can't await
+pub fn write_atomic_blocking(path: &Path, content: &[u8]) -> IoResult<()> {
+    let temp_path = atomic_temp_path(path);
+    if let Err(e) = std::fs::write(&temp_path, content) {
+        let _ = std::fs::remove_file(&temp_path);
+        Err(e)?;
+    }
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// The temporary file for writing the file, unique to the process so concurrent
+/// writers don't write into each other's temporary file
+fn atomic_temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(temp_name)
+}
+
+/// Replace `{name}` of the variables (name, environment variable, value) in the string. The
+/// values are only substituted and never parsed, so they can't inject arguments or commands.
+/// Unknown names are errors, so typos don't pass silently. Braces without a name in them, like
+/// `{}` or `{ }`, and shell variables like `${HOME}` are kept
+pub fn expand_variables(
+    s: &str,
+    variables: &[(&str, &str, String)],
+    context: &str,
+) -> IoResult<String> {
+    let mut output = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find('}');
+        let name = end.map(|x| &after[..x]);
+        let is_name = name.is_some_and(|x| {
+            !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+        let known = variables.iter().find(|(x, _, _)| Some(*x) == name);
+        match (name, end) {
+            (Some(name), Some(_)) if output.ends_with('$') && known.is_some() => {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`${{{name}}}` in {context} is a shell variable. Use `{{{name}}}` for the mcmod variable"),
+                )))?;
+            }
+            (Some(name), Some(end)) if is_name && !output.ends_with('$') => {
+                match known {
+                    Some((_, _, value)) => output.push_str(value),
+                    None => Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Unknown variable '{{{name}}}' in {context}. Available: {}",
+                            variables
+                                .iter()
+                                .map(|(x, _, _)| format!("{{{x}}}"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    )))?,
+                }
+                rest = &after[end + 1..];
+            }
+            _ => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// A reference to the environment variable in a command line of [`shell_command`], quoted
+/// so the shell doesn't parse the value
+pub fn shell_env_ref(env: &str) -> String {
+    if cfg!(windows) {
+        format!("\"%{env}%\"")
+    } else {
+        format!("\"${env}\"")
+    }
+}
+
+/// Create the command to run the command line with the shell of the platform
+pub fn shell_command(command: &str) -> std::process::Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Run git in the directory and get the trimmed output, or empty if git fails
+pub fn git_output(dir: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_owned()
+        }
+        _ => String::new(),
+    }
+}
+
+/// If mcmod runs in WSL and runs gradle on the Windows side, set with MCMOD_WSL_INTEROP
+pub fn wsl_interop() -> bool {
+    let env = std::env::var("MCMOD_WSL_INTEROP").unwrap_or_default();
+    env == "true" || env == "1"
+}
+
+/// Convert a path on a Windows drive in WSL (`/mnt/c/...`) to the Windows path (`C:\...`)
+pub fn to_windows_path(path: &Path) -> Option<String> {
+    let rest = path.to_str()?.strip_prefix("/mnt/")?;
+    let (drive, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let mut chars = drive.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(format!(
+            "{}:\\{}",
+            c.to_ascii_uppercase(),
+            rest.replace('/', "\\")
+        )),
+        _ => None,
+    }
+}
+
+/// The path as seen by gradle and the tools it runs, for paths passed to gradle
+/// or written into files it reads. Translated to a Windows path in WSL interop mode
+pub fn gradle_path(path: &Path) -> String {
+    if !wsl_interop() {
+        return path.display().to_string();
+    }
+    if let Some(x) = to_windows_path(path) {
+        return x;
+    }
+    // paths in the WSL file system, like \\wsl$\<distro>\...
+    let output = std::process::Command::new("wslpath")
+        .arg("-w")
+        .arg(path)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_owned()
+        }
+        _ => path.display().to_string(),
+    }
+}
+
 /// Root of mcmod repo
 pub fn tool_root() -> IoResult<PathBuf> {
     let exe = std::env::current_exe()?;
@@ -99,35 +360,57 @@ pub fn tool_root() -> IoResult<PathBuf> {
     }
 }
 
+/// Lock files held by this process
+static HELD_LOCKS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn held_locks() -> MutexGuard<'static, Vec<PathBuf>> {
+    HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lock on a target directory, released when dropped
+pub struct TargetLock {
+    path: PathBuf,
+    /// None if the lock was already held by this process
+    file: Option<std::fs::File>,
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            held_locks().retain(|x| x != &self.path);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Project {
     /// Root directory of the project
     pub root: PathBuf,
+    /// The profile to apply when loading mcmod.yaml
+    pub profile: Option<String>,
+    /// The target to use instead of the top-level template
+    pub target: Option<String>,
+    /// The pack in packs/ to run with, which has its own run directory
+    pub pack: Option<String>,
     /// The mcmod.yaml file
     mcmod: OnceCell<Mcmod>,
 }
 
 impl Project {
-    /// Initialize a new project context in the given directory
-    pub fn new_in(dir: &str) -> IoResult<Self> {
-        let path = dunce::canonicalize(Path::new(dir))?;
-        let mut cur_path = path.as_ref();
-        while !path.join("mcmod.yaml").exists() {
-            if let Some(parent) = path.parent() {
-                cur_path = parent;
-            } else {
-                Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "Could not find project root",
-                ))?;
-            }
-        }
-        Ok(Self::new_root(cur_path.to_path_buf()))
+    /// Initialize a new project context from the command line arguments
+    pub fn new_in(args: &ProjectArgs) -> IoResult<Self> {
+        let mut project = Self::new_root(args.find_root()?);
+        project.profile = args.profile.clone();
+        project.target = args.target.clone();
+        Ok(project)
     }
 
     pub fn new_root(root: PathBuf) -> Self {
         Self {
             root,
+            profile: None,
+            target: None,
+            pack: None,
             mcmod: OnceCell::new(),
         }
     }
@@ -141,57 +424,265 @@ impl Project {
         let mcmod = fs::read_to_string(mcmod_path).await?;
         let mut mcmod: Mcmod = match serde_yaml::from_str(&mcmod) {
             Ok(mcmod) => mcmod,
-            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) => {
+                Err(ErrorCode::InvalidConfig.error(io::Error::new(io::ErrorKind::InvalidData, e)))?
+            }
         };
+        if let Some(target) = &self.target {
+            mcmod.apply_target(target)?;
+        }
+        if let Some(profile) = &self.profile {
+            mcmod.apply_profile(profile)?;
+        }
         mcmod.apply_defaults(self).await?;
+        mcmod.validate_child_mods()?;
         Ok(self.mcmod.get_or_init(|| mcmod))
     }
 
+    /// Get the mcmod.yaml data that is already loaded, for places that can't await
+    pub fn loaded_mcmod(&self) -> IoResult<&Mcmod> {
+        match self.mcmod.get() {
+            Some(x) => Ok(x),
+            None => Err(io::Error::other(
+                "mcmod.yaml is not loaded yet. This is a bug",
+            ))?,
+        }
+    }
+
     pub fn source_root(&self) -> PathBuf {
         self.root.join("src")
     }
 
-    /// Detect the group from the src directory
-    pub async fn source_group(&self) -> IoResult<String> {
-        let mut current = self.source_root();
-        let mut source_group = String::new();
-        // if current contains a single entry and is dir, continue go into it
-        while current.is_dir() {
-            let mut dir = fs::read_dir(&current).await?;
-            let entry = match dir.next_entry().await? {
-                None => break,
-                Some(x) => x,
-            };
-            if dir.next_entry().await?.is_some() {
-                break;
-            }
-            if entry.file_type().await?.is_dir() {
-                if !source_group.is_empty() {
-                    source_group.push('.');
+    pub fn target_root(&self) -> PathBuf {
+        match &self.target {
+            Some(name) => self.root.join(format!("target-{name}")),
+            None => self.root.join("target"),
+        }
+    }
+
+    /// The lock file of the target directory. It's next to the target directory,
+    /// since the target directory can be deleted while locked
+    pub fn lock_file(&self) -> PathBuf {
+        match &self.target {
+            Some(name) => self.root.join(format!("target-{name}.lock")),
+            None => self.root.join("target.lock"),
+        }
+    }
+
+    /// Lock the target directory, so other mcmod processes can't sync or build it until
+    /// the lock is dropped. Waits for the other process if it's locked.
+    ///
+    /// Locking again in the same process does nothing, since commands like build sync first
+    pub async fn lock_target(&self) -> IoResult<TargetLock> {
+        let path = self.lock_file();
+        {
+            let mut held = held_locks();
+            if held.contains(&path) {
+                return Ok(TargetLock { path, file: None });
+            }
+            held.push(path.clone());
+        }
+        let file = match std::fs::File::create(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                held_locks().retain(|x| x != &path);
+                Err(e)?
+            }
+        };
+        let result = match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(std::fs::TryLockError::WouldBlock) => {
+                println!(
+                    "waiting for another mcmod process using '{}' (lock file '{}')",
+                    self.target_root().display(),
+                    path.display()
+                );
+                let result = tokio::task::spawn_blocking(move || file.lock().map(|_| file)).await;
+                match result {
+                    Ok(x) => x,
+                    Err(e) => Err(io::Error::from(e)),
                 }
-                let file_name = entry.file_name();
-                let name = match file_name.to_str() {
-                    Some(x) => x,
-                    None => Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Invalid source group name",
-                    ))?,
-                };
-                source_group.push_str(name);
-            } else {
-                break;
             }
-            current = entry.path();
+            Err(std::fs::TryLockError::Error(e)) => Err(e),
+        };
+        match result {
+            Ok(file) => Ok(TargetLock {
+                path,
+                file: Some(file),
+            }),
+            Err(e) => {
+                held_locks().retain(|x| x != &path);
+                Err(ErrorCode::TargetLocked.error(io::Error::new(
+                    e.kind(),
+                    format!("Cannot lock '{}': {e}", path.display()),
+                )))?
+            }
         }
-
-        Ok(source_group)
     }
 
-    pub fn target_root(&self) -> PathBuf {
-        self.root.join("target")
+    /// The ninja file for copying files into the target
+    pub fn build_ninja(&self) -> PathBuf {
+        match &self.target {
+            Some(name) => self.root.join(format!("build-{name}.ninja")),
+            None => self.root.join("build.ninja"),
+        }
     }
 
     pub fn assets_root(&self) -> PathBuf {
         self.root.join("assets")
     }
 }
+
+/// Detect the group from a source root, as the packages down to the first directory
+/// that doesn't only contain a single directory.
+///
+/// None with a warning when the group has less than two packages and there are multiple
+/// packages to go into, like `com.a` and `com.b`, since any of them could be the group
+pub async fn source_group(root: &Path) -> IoResult<Option<String>> {
+    let mut current = root.to_path_buf();
+    let mut packages = Vec::new();
+    while current.is_dir() {
+        let mut dirs = Vec::new();
+        let mut has_files = false;
+        let mut dir = fs::read_dir(&current).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                has_files = true;
+                continue;
+            }
+            match entry.file_name().to_str() {
+                Some(x) => dirs.push(x.to_owned()),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid source group name",
+                ))?,
+            }
+        }
+        match dirs.as_slice() {
+            [name] if !has_files => {
+                current.push(name);
+                packages.push(name.clone());
+            }
+            [_, _, ..] if packages.len() < 2 => {
+                dirs.sort();
+                let candidates = dirs
+                    .iter()
+                    .map(|x| {
+                        packages
+                            .iter()
+                            .chain([x])
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join(".")
+                    })
+                    .collect::<Vec<_>>();
+                println!(
+                    "warning: cannot detect the group from '{}', which has more than one top-level package ({}). Set `group` in mcmod.yaml",
+                    root.display(),
+                    candidates.join(", ")
+                );
+                return Ok(None);
+            }
+            _ => break,
+        }
+    }
+    Ok(Some(packages.join(".")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Vec<(&'static str, &'static str, String)> {
+        vec![
+            ("modid", "MCMOD_MODID", "mymodid".to_owned()),
+            ("mod-version", "MCMOD_MOD_VERSION", "1.0.0".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let expand = |s| expand_variables(s, &variables(), "test");
+        assert_eq!(
+            expand("build/libs/{modid}-{mod-version}.jar").unwrap(),
+            "build/libs/mymodid-1.0.0.jar"
+        );
+        // values are not expanded again
+        let variables = [("a", "A", "{b}".to_owned()), ("b", "B", "x".to_owned())];
+        assert_eq!(expand_variables("{a}", &variables, "test").unwrap(), "{b}");
+    }
+
+    #[test]
+    fn test_expand_variables_keeps_other_braces() {
+        let expand = |s| expand_variables(s, &variables(), "test");
+        assert_eq!(
+            expand("find . -exec rm {} ;").unwrap(),
+            "find . -exec rm {} ;"
+        );
+        assert_eq!(expand("{ echo; }").unwrap(), "{ echo; }");
+        assert_eq!(expand("echo ${HOME} {").unwrap(), "echo ${HOME} {");
+    }
+
+    #[test]
+    fn test_expand_variables_errors() {
+        let expand = |s| expand_variables(s, &variables(), "test");
+        // typos
+        assert!(expand("{mod-id}").is_err());
+        // shell syntax for mcmod variables
+        assert!(expand("${modid}").is_err());
+    }
+
+    async fn source_group_of(name: &str, dirs: &[&str], files: &[&str]) -> Option<String> {
+        let root =
+            std::env::temp_dir().join(format!("mcmod-test-group-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in dirs {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in files {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let group = source_group(&root).await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        group
+    }
+
+    #[tokio::test]
+    async fn test_source_group() {
+        let group = source_group_of(
+            "single",
+            &["com/example/mymod/item", "com/example/mymod/block"],
+            &["com/example/mymod/ModMain.java"],
+        )
+        .await;
+        assert_eq!(group.as_deref(), Some("com.example.mymod"));
+        // stops at the first package with files
+        let group = source_group_of(
+            "files",
+            &["com/example/mymod/item"],
+            &["com/example/Main.java"],
+        )
+        .await;
+        assert_eq!(group.as_deref(), Some("com.example"));
+        // sub packages of a group with two packages are part of the mod
+        let group = source_group_of("deep", &["com/example/a", "com/example/b"], &[]).await;
+        assert_eq!(group.as_deref(), Some("com.example"));
+    }
+
+    #[tokio::test]
+    async fn test_source_group_ambiguous() {
+        // src/com and src/net could both be the group
+        let group = source_group_of("top", &["com/example/a", "net/example/b"], &[]).await;
+        assert_eq!(group, None);
+        let group = source_group_of("second", &["com/a/x", "com/b/y"], &[]).await;
+        assert_eq!(group, None);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.jar", "mod-1.0.jar"));
+        assert!(wildcard_match("mod-?.jar", "mod-1.jar"));
+        assert!(!wildcard_match("mod-?.jar", "mod-10.jar"));
+        assert!(!wildcard_match("*.jar", "mod.zip"));
+    }
+}
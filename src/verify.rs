@@ -0,0 +1,159 @@
+//! Checking files mcmod manages in the target directory for manual edits
+
+use std::io;
+use std::path::PathBuf;
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::extends;
+use crate::hash;
+use crate::state::Manifest;
+use crate::util::{cd, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Clone, Parser)]
+pub struct VerifyCommand {}
+
+impl VerifyCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        if let Some(target) = &project.target {
+            println!("verifying target '{target}'");
+        }
+        let manifest = match Manifest::load(&project).await {
+            Some(x) => x,
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No manifest found in the target directory. Please run `mcmod sync` first",
+            ))?,
+        };
+        let target_root = project.target_root();
+        let files = manifest
+            .files
+            .keys()
+            .map(|x| target_root.join(x))
+            .collect::<Vec<_>>();
+        let hashes = hash::hash_files(&project, &target_root, files).await?;
+
+        let mut drifted = 0;
+        for (path, expected) in &manifest.files {
+            match hashes.get(path) {
+                Some(Some(hash)) if hash == expected => {}
+                Some(Some(_)) => {
+                    println!("modified: {path}");
+                    drifted += 1;
+                }
+                _ => {
+                    println!("missing:  {path}");
+                    drifted += 1;
+                }
+            }
+        }
+        if drifted > 0 {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{drifted} file(s) in the target directory changed since the last sync and will be overwritten by the next sync"),
+            ))?;
+        }
+        println!("{} file(s) verified", manifest.files.len());
+        Ok(())
+    }
+}
+
+/// Get the files mcmod manages in the target directory: copied sources,
+/// metadata files and downloaded jars
+async fn managed_files(project: &Project) -> IoResult<Vec<PathBuf>> {
+    let mcmod = project.mcmod().await?;
+    let handler = mcmod.template.new_handler();
+    let mut files = mcmod
+        .collect_copy_edges(project)
+        .await?
+        .into_keys()
+        .collect::<Vec<_>>();
+    let resource_path = cd!(project.target_root(), "src", "main", "resources");
+    if !mcmod.coremod_only {
+        files.push(resource_path.join("mcmod.info"));
+    }
+    files.push(resource_path.join("pack.mcmeta"));
+    if let Some(path) = extends::at_file_path(project, mcmod) {
+        files.push(path);
+    }
+    if let Some(path) = mcmod.tags_class_path() {
+        files.push(cd!(project.target_root(), "src", "main", "java", path));
+    }
+    for dir in [
+        handler.libs_dir(project)?,
+        cd!(handler.run_dir(project)?, "mods"),
+    ] {
+        if !dir.exists() {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Get the copied files that were edited in the target directory since the last sync,
+/// as (target, source) pairs. Files not newer than the manifest are assumed unchanged
+pub async fn edited_copies(project: &Project) -> IoResult<Vec<(PathBuf, PathBuf)>> {
+    let manifest_path = Manifest::path(project);
+    let manifest = match Manifest::load(project).await {
+        Some(x) => x,
+        None => return Ok(Vec::new()),
+    };
+    let manifest_modified = fs::metadata(&manifest_path).await?.modified()?;
+    let mut candidates = Vec::new();
+    for (target, source) in project.mcmod().await?.collect_copy_edges(project).await? {
+        let modified = match fs::metadata(&target).await {
+            Ok(x) => x.modified()?,
+            Err(_) => continue,
+        };
+        if modified > manifest_modified {
+            candidates.push((target, source));
+        }
+    }
+    let target_root = project.target_root();
+    let files = candidates.iter().map(|(x, _)| x.clone()).collect();
+    let hashes = hash::hash_files(project, &target_root, files).await?;
+    let edited = candidates
+        .into_iter()
+        .filter(|(target, _)| {
+            let key = hash::relative_key(&target_root, target);
+            match (manifest.files.get(&key), hashes.get(&key)) {
+                (Some(expected), Some(Some(hash))) => expected != hash,
+                _ => false,
+            }
+        })
+        .collect();
+    Ok(edited)
+}
+
+/// Record the hashes of all managed files in the manifest
+pub async fn write_manifest(project: &Project) -> IoResult<()> {
+    let files = managed_files(project).await?;
+    let hashes = hash::hash_files(project, &project.target_root(), files).await?;
+    let manifest = Manifest {
+        files: hashes
+            .into_iter()
+            .filter_map(|(path, hash)| Some((path, hash?)))
+            .collect(),
+    };
+    manifest.save(project).await
+}
+
+/// Update the hashes of some files in the manifest after they are synced
+pub async fn update_manifest(project: &Project, files: Vec<PathBuf>) -> IoResult<()> {
+    let mut manifest = Manifest::load(project).await.unwrap_or_default();
+    for (path, hash) in hash::hash_files(project, &project.target_root(), files).await? {
+        match hash {
+            Some(hash) => manifest.files.insert(path, hash),
+            None => manifest.files.remove(&path),
+        };
+    }
+    manifest.save(project).await
+}
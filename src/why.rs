@@ -0,0 +1,358 @@
+//! Explaining where a file in the target directory came from
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::fs;
+
+use crate::apijar;
+use crate::devjar::{self, Flavor, Lockfile};
+use crate::hash;
+use crate::mcmod::{is_preprocessed, Mcmod};
+use crate::state::{Manifest, TemplateState};
+use crate::sync;
+use crate::template::TemplateHandler;
+use crate::util::{self, cd, IoResult, Project, ProjectArgs};
+
+#[derive(Debug, Parser)]
+pub struct WhyCommand {
+    /// The file in the target directory, relative to the current directory or the target directory
+    pub file: PathBuf,
+}
+
+impl WhyCommand {
+    pub async fn run(self, args: &ProjectArgs) -> IoResult<()> {
+        let project = Project::new_in(args)?;
+        let mcmod = project.mcmod().await?;
+        let handler = mcmod.template.new_handler();
+        let target_root = project.target_root();
+        let path = match std::path::absolute(&self.file) {
+            Ok(x) if x.starts_with(&target_root) => x,
+            _ => target_root.join(&self.file),
+        };
+        let relative = hash::relative_key(&target_root, &path);
+        println!("{relative}");
+        if !path.exists() {
+            println!("  does not exist in the target directory");
+        }
+
+        let mut found = explain_copy(&project, mcmod, &path).await?;
+        found |= explain_generated(&project, mcmod, &path);
+        found |= explain_download(&project, mcmod, handler.as_ref(), &path).await?;
+        found |= explain_template(&project, &relative).await?;
+        if !found {
+            println!("  not created by mcmod or the template. It's probably an output of gradle or the game");
+        }
+        Ok(())
+    }
+}
+
+/// Print the copy paths that copy to the file, and if it was edited since the last sync
+async fn explain_copy(project: &Project, mcmod: &Mcmod, path: &Path) -> IoResult<bool> {
+    let target_root = project.target_root();
+    let mut copied_from = Vec::new();
+    for route in mcmod.copy_routes() {
+        let (i, copy_path) = (route.layer, route.copy_path);
+        let target = copy_path.target();
+        let relative = match path.strip_prefix(target_root.join(&route.target)) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if !route.filter.copies(path) {
+            continue;
+        }
+        let mut spec = match (copy_path.source_target(), copy_path.generator()) {
+            (Some((source, _)), _) => format!("copy-paths[{i}] ('{source}' -> '{target}')"),
+            (None, Some(g)) => format!("copy-paths[{i}] (`{}` -> '{target}')", g.generate),
+            (None, None) => format!("copy-paths[{i}] (delete '{target}')"),
+        };
+        if let Some(language) = route.filter.only {
+            spec.push_str(&format!(" into '{}' for {}", route.target, language.name()));
+        }
+        if !copy_path.applies_to(project, &mcmod.template) {
+            println!("  {spec} would apply to it, but not to this target");
+            continue;
+        }
+        let source_root = match copy_path.copy_source(project) {
+            Some(x) => x,
+            None => {
+                println!("  removed by {spec} before copying, on every full sync");
+                continue;
+            }
+        };
+        let source_path = if relative.as_os_str().is_empty() {
+            source_root.clone()
+        } else {
+            source_root.join(relative)
+        };
+        if !source_path.is_file() {
+            continue;
+        }
+        // the copy is skipped when the source or a directory it's in is excluded
+        let excluded = source_path
+            .ancestors()
+            .take_while(|x| x.starts_with(&source_root))
+            .find_map(|x| {
+                let x = x.display().to_string();
+                mcmod.copy_exclude.iter().find(|e| x.ends_with(e.as_str()))
+            });
+        let source_relative = hash::relative_key(&project.root, &source_path);
+        match excluded {
+            Some(pattern) => println!(
+                "  not copied from '{source_relative}' by {spec}, since it matches copy-exclude '{pattern}'"
+            ),
+            None => copied_from.push((spec, source_relative, copy_path.overwrite())),
+        }
+    }
+    let (spec, source, _) = match copied_from.last() {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let preprocessed = if mcmod.preprocess && is_preprocessed(Path::new(source)) {
+        " and preprocessed"
+    } else {
+        ""
+    };
+    println!("  copied from '{source}' by {spec}{preprocessed}");
+    for pair in copied_from.windows(2).rev() {
+        let ((earlier_spec, earlier, _), (later_spec, later, overwrite)) = (&pair[0], &pair[1]);
+        if earlier == later {
+            continue;
+        }
+        if *overwrite {
+            println!("  {later_spec} replaces '{earlier}' from {earlier_spec}");
+        } else {
+            println!("  conflicts with '{earlier}' from {earlier_spec}. Syncing fails until `overwrite: true` is set on {later_spec}");
+        }
+    }
+    let key = hash::relative_key(&target_root, path);
+    let expected = Manifest::load(project)
+        .await
+        .and_then(|x| x.files.get(&key).cloned());
+    if let Some(expected) = expected {
+        let hashes = hash::hash_files(project, &target_root, vec![path.to_path_buf()]).await?;
+        match hashes.get(&key) {
+            Some(Some(hash)) if *hash == expected => println!("  unchanged since the last sync"),
+            Some(Some(_)) => println!(
+                "  edited in the target directory since the last sync. The next sync refuses to overwrite it"
+            ),
+            _ => println!("  missing since the last sync"),
+        }
+    } else {
+        println!("  not synced yet");
+    }
+    Ok(true)
+}
+
+/// Print the sync step that generates the file, if it's one
+fn explain_generated(project: &Project, mcmod: &Mcmod, path: &Path) -> bool {
+    let target_root = project.target_root();
+    let resources = cd!(target_root.clone(), "src", "main", "resources");
+    let mut generated = vec![
+        (
+            resources.join("mcmod.info"),
+            "generated from mcmod.yaml and mcmod.info.extra.json by the metadata step of sync",
+        ),
+        (
+            resources.join("pack.mcmeta"),
+            "generated from `pack-mcmeta` in mcmod.yaml by the metadata step of sync",
+        ),
+        (
+            target_root.join("gradle.properties"),
+            "the template's gradle.properties, with the properties from mcmod.yaml and `gradle-overrides` merged in by sync",
+        ),
+        (
+            target_root.join("mcmod-run.gradle"),
+            "gradle init script written by `mcmod run` for `jvm-args` and `run` in mcmod.yaml",
+        ),
+        (
+            target_root.join("mcmod-mixins.gradle"),
+            "written by the template when syncing, and applied from build.gradle for `mixins` in mcmod.yaml",
+        ),
+        (
+            target_root.join(".classpath"),
+            "written by the eclipse task of gradle when syncing, and remapped into the project",
+        ),
+        (
+            target_root.join(".project"),
+            "written by the eclipse task of gradle when syncing, and remapped into the project",
+        ),
+    ];
+    if let Some(tags) = mcmod.tags_class_path() {
+        generated.push((
+            cd!(target_root.clone(), "src", "main", "java", tags),
+            "generated for `tags-class` in mcmod.yaml by the metadata step of sync",
+        ));
+    }
+    if let Some((_, description)) = generated.iter().find(|(x, _)| x == path) {
+        println!("  {description}");
+        return true;
+    }
+    let is_state = path.parent() == Some(target_root.as_path())
+        && path
+            .file_name()
+            .is_some_and(|x| x.to_string_lossy().starts_with(".mcmod-"));
+    if is_state {
+        println!("  state of mcmod, like what was synced and built");
+    }
+    is_state
+}
+
+/// Print the lib or mod in mcmod.yaml the file was downloaded for
+async fn explain_download(
+    project: &Project,
+    mcmod: &Mcmod,
+    handler: &dyn TemplateHandler,
+    path: &Path,
+) -> IoResult<bool> {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy().into_owned()),
+        _ => return Ok(false),
+    };
+    let is_name = |source: &str| sync::download_file_name(source) == Some(name.as_str());
+    let lockfile = Lockfile::load(project).await;
+    let full_libs = mcmod.full_libs();
+    let locked = |url: &str| {
+        lockfile
+            .dev_jars
+            .iter()
+            .find(|(_, x)| x.url == url)
+            .map(|(lib, x)| (lib.clone(), x.flavor))
+    };
+
+    if dir == handler.libs_dir(project)? {
+        for lib in mcmod.api_libs() {
+            if apijar::jar_name(lib).as_deref() == Some(name.as_str()) {
+                println!(
+                    "  API of '{}' extracted from '{}' (lib in mcmod.yaml with `api-of`)",
+                    lib.api_of, lib.source
+                );
+                return Ok(true);
+            }
+        }
+        let resolved = lockfile.apply(&full_libs);
+        for url in &resolved.remap {
+            if devjar::jar_name(url).as_deref() == Some(name.as_str()) {
+                println!("  remapped from SRG names of '{url}' (lib in mcmod.yaml without a dev jar, recorded in mcmod.lock)");
+                return Ok(true);
+            }
+        }
+        for source in &resolved.libs {
+            if !is_name(source) {
+                continue;
+            }
+            match locked(source) {
+                Some((lib, flavor)) => println!(
+                    "  downloaded from '{source}', the {} jar of lib '{lib}' in mcmod.yaml (recorded in mcmod.lock)",
+                    match flavor {
+                        Flavor::Dev => "-dev",
+                        Flavor::Deobf => "-deobf",
+                        Flavor::Remap => "original",
+                    }
+                ),
+                None => explain_source(source, "lib"),
+            }
+            return Ok(true);
+        }
+        explain_unlisted(mcmod, &name, "libs");
+        return Ok(true);
+    }
+    if dir == apijar::cache_dir(project) || dir == devjar::cache_dir(project) {
+        let sources = mcmod
+            .api_libs()
+            .iter()
+            .map(|x| x.source.clone())
+            .chain(lockfile.apply(&full_libs).remap)
+            .collect::<Vec<_>>();
+        match sources.iter().find(|x| is_name(x)) {
+            Some(source) => explain_source(source, "lib"),
+            None => {
+                println!("  not used by a lib in mcmod.yaml anymore. It's removed on the next sync")
+            }
+        }
+        return Ok(true);
+    }
+    let mods_dir = cd!(handler.run_dir(project)?, "mods");
+    if dir == sync::mods_cache_dir(project) || dir == mods_dir {
+        match mcmod.all_mods().iter().find(|x| is_name(x)) {
+            Some(source) => {
+                explain_source(source, "mod");
+                if dir == mods_dir {
+                    println!(
+                        "  linked from target/mods-cache for the side and mod set of the last run"
+                    );
+                }
+            }
+            None if dir == mods_dir => {
+                println!("  not a mod from mcmod.yaml. Files put into run/mods by hand are kept")
+            }
+            None => explain_unlisted(mcmod, &name, "mods"),
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn explain_source(source: &str, kind: &str) {
+    if source.starts_with("./") {
+        println!("  copied from '{source}' ({kind} in mcmod.yaml)");
+    } else if let Some(url) = sync::download_url(source, "") {
+        if source.starts_with("http") {
+            println!("  downloaded from '{url}' ({kind} in mcmod.yaml)");
+        } else {
+            println!("  downloaded from the CDN ({kind} '{source}' in mcmod.yaml)");
+        }
+    }
+}
+
+fn explain_unlisted(mcmod: &Mcmod, name: &str, list: &str) {
+    match mcmod.keep.iter().find(|x| util::wildcard_match(x, name)) {
+        Some(pattern) => {
+            println!("  not in `{list}` in mcmod.yaml, kept by `keep` pattern '{pattern}'")
+        }
+        None => println!("  not in `{list}` in mcmod.yaml. It's removed on the next full sync"),
+    }
+}
+
+/// Print if the file is from the template, and if a template patch changed it
+async fn explain_template(project: &Project, relative: &str) -> IoResult<bool> {
+    let target_root = project.target_root();
+    let template = TemplateState::load(project).await.template;
+    let patches_root = cd!(project.root.clone(), "template-patches", &template);
+    let mut found = false;
+    if patches_root.join(relative).is_file() {
+        println!("  copied from the template overlay 'template-patches/{template}/{relative}' when the template was set up");
+        found = true;
+    }
+    if patches_root.is_dir() {
+        let mut entries = fs::read_dir(&patches_root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|x| x != "patch") {
+                continue;
+            }
+            let content = fs::read_to_string(&path).await.unwrap_or_default();
+            if content.lines().any(|x| x == format!("+++ b/{relative}")) {
+                println!(
+                    "  patched by 'template-patches/{template}/{}' when the template was set up",
+                    entry.file_name().to_string_lossy()
+                );
+                found = true;
+            }
+        }
+    }
+    if !target_root.join(".git").exists() {
+        return Ok(found);
+    }
+    let tracked = util::git_output(&target_root, &["ls-files", "--", relative]);
+    if tracked.is_empty() {
+        return Ok(found);
+    }
+    let diff = util::git_output(&target_root, &["diff", "--stat", "--", relative]);
+    if diff.is_empty() {
+        println!("  from the template '{template}'");
+    } else {
+        println!("  from the template '{template}', and changed since the template was cloned");
+    }
+    Ok(true)
+}
@@ -0,0 +1,312 @@
+//! Size and position of the dev client window
+//!
+//! The size is passed to the game with `--width` and `--height`. The game can't be told where
+//! to put its window or to maximize it, so on Linux with X11 and `wmctrl`, a thread moves or
+//! maximizes the window once it opens. With `run.remember-window`, the thread also records
+//! where the window is while the game runs, and the next launch restores it
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::read_json;
+use crate::util::{self, IoResult, Project};
+
+/// Size of the window, like `1280x720`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for WindowSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for WindowSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = s
+            .split_once(['x', 'X'])
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+        match size {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Self { width, height }),
+            _ => Err(format!(
+                "Invalid window size '{s}'. Use <width>x<height>, like 1280x720"
+            )),
+        }
+    }
+}
+
+impl Serialize for WindowSize {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for WindowSize {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where the client window is, recorded in the target directory
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WindowGeometry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<WindowSize>,
+    /// Position of the top left corner on the screen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+    #[serde(default)]
+    pub maximized: bool,
+}
+
+impl WindowGeometry {
+    pub fn path(project: &Project) -> PathBuf {
+        project.target_root().join(".mcmod-window")
+    }
+
+    /// The geometry to launch the client with: the size and maximized from the command line,
+    /// then the window from the last launch with `run.remember-window`, then `run` in mcmod.yaml
+    pub async fn for_launch(
+        project: &Project,
+        size: Option<WindowSize>,
+        maximized: bool,
+    ) -> IoResult<Self> {
+        let options = &project.mcmod().await?.run;
+        let remembered = if options.remember_window {
+            read_json::<Self>(&Self::path(project)).await
+        } else {
+            None
+        };
+        if size.is_some() || maximized {
+            // the remembered size is kept for when the window is restored from maximized
+            let remembered = remembered.unwrap_or_default();
+            return Ok(Self {
+                size: size.or(remembered.size),
+                position: remembered.position,
+                maximized,
+            });
+        }
+        Ok(remembered.unwrap_or(Self {
+            size: options.window_size,
+            position: None,
+            maximized: options.maximized,
+        }))
+    }
+
+    /// Arguments for the game to open the window with the size
+    pub fn game_args(&self) -> Vec<String> {
+        match self.size {
+            Some(size) => vec![
+                "--width".to_owned(),
+                size.width.to_string(),
+                "--height".to_owned(),
+                size.height.to_string(),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    fn save(&self, path: &Path) {
+        if let Ok(content) = serde_json::to_string_pretty(self) {
+            let _ = util::write_atomic_blocking(path, content.as_bytes());
+        }
+    }
+}
+
+/// Thread that places the client window once it opens, and records where it is
+pub struct WindowTracker {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl WindowTracker {
+    /// Start tracking the window the game is about to open. Records the geometry into
+    /// the record path if set. None if the window can't be placed on this system
+    pub fn start(geometry: WindowGeometry, record: Option<PathBuf>) -> Option<Self> {
+        if let Some(path) = &record {
+            geometry.save(path);
+        }
+        let needs_placing = geometry.position.is_some() || geometry.maximized;
+        if !needs_placing && record.is_none() {
+            return None;
+        }
+        if !cfg!(target_os = "linux")
+            || std::env::var_os("DISPLAY").is_none()
+            || wmctrl(&["-m"]).is_none()
+        {
+            if geometry.maximized {
+                println!("warning: maximizing the window needs wmctrl on Linux with X11");
+            }
+            return None;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || track(geometry, record, &stop))
+        };
+        Some(Self { stop, thread })
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+fn track(mut geometry: WindowGeometry, record: Option<PathBuf>, stop: &AtomicBool) {
+    let existing = windows().into_iter().map(|x| x.id).collect::<Vec<_>>();
+    let mut placed: Option<String> = None;
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_secs(1));
+        let windows = windows();
+        let id = match &placed {
+            Some(id) => id.clone(),
+            None => {
+                let window = windows
+                    .iter()
+                    .find(|x| !existing.contains(&x.id) && x.title.starts_with("Minecraft"));
+                let id = match window {
+                    Some(x) => x.id.clone(),
+                    None => continue,
+                };
+                if let Some((x, y)) = geometry.position {
+                    wmctrl(&["-i", "-r", &id, "-e", &format!("0,{x},{y},-1,-1")]);
+                }
+                if geometry.maximized {
+                    wmctrl(&["-i", "-r", &id, "-b", "add,maximized_vert,maximized_horz"]);
+                }
+                placed = Some(id.clone());
+                id
+            }
+        };
+        let path = match &record {
+            Some(x) => x,
+            None => return,
+        };
+        // the window is gone when the game is closing
+        let window = match windows.into_iter().find(|x| x.id == id) {
+            Some(x) => x,
+            None => continue,
+        };
+        let mut current = geometry.clone();
+        current.maximized = is_maximized(&id);
+        // wmctrl lists where the window content is, but moves the window by its frame,
+        // so the position is recorded for the frame to not move down by the title bar every launch
+        let (left, top) = frame_extents(&id);
+        current.position = Some((window.position.0 - left, window.position.1 - top));
+        // the size of a maximized window is the screen, so the size from before is kept
+        if !current.maximized {
+            current.size = Some(window.size);
+        }
+        if current != geometry {
+            current.save(path);
+            geometry = current;
+        }
+    }
+}
+
+struct Window {
+    id: String,
+    position: (i32, i32),
+    size: WindowSize,
+    title: String,
+}
+
+/// The windows on the screen, from `wmctrl -lG`
+fn windows() -> Vec<Window> {
+    let output = wmctrl(&["-lG"]).unwrap_or_default();
+    output
+        .lines()
+        .filter_map(|line| {
+            // id, desktop, x, y, width, height, host, title
+            let mut parts = line.split_whitespace();
+            let id = parts.next()?.to_owned();
+            let _desktop = parts.next()?;
+            let mut number = || parts.next()?.parse::<i32>().ok();
+            let position = (number()?, number()?);
+            let size = WindowSize {
+                width: u32::try_from(number()?).ok()?,
+                height: u32::try_from(number()?).ok()?,
+            };
+            let _host = parts.next()?;
+            let title = parts.collect::<Vec<_>>().join(" ");
+            Some(Window {
+                id,
+                position,
+                size,
+                title,
+            })
+        })
+        .collect()
+}
+
+fn is_maximized(id: &str) -> bool {
+    let output = std::process::Command::new("xprop")
+        .args(["-id", id, "_NET_WM_STATE"])
+        .output();
+    match output {
+        Ok(x) => String::from_utf8_lossy(&x.stdout).contains("_NET_WM_STATE_MAXIMIZED_VERT"),
+        Err(_) => false,
+    }
+}
+
+/// The left and top size of the window frame the window manager draws around the window
+fn frame_extents(id: &str) -> (i32, i32) {
+    let output = std::process::Command::new("xprop")
+        .args(["-id", id, "_NET_FRAME_EXTENTS"])
+        .output();
+    match output {
+        Ok(x) => parse_frame_extents(&String::from_utf8_lossy(&x.stdout)).unwrap_or_default(),
+        Err(_) => (0, 0),
+    }
+}
+
+/// Parse the output of xprop, like `_NET_FRAME_EXTENTS(CARDINAL) = 1, 1, 28, 1`
+/// (left, right, top, bottom)
+fn parse_frame_extents(output: &str) -> Option<(i32, i32)> {
+    let (_, values) = output.split_once('=')?;
+    let mut values = values.split(',').map(|x| x.trim().parse::<i32>().ok());
+    let left = values.next()??;
+    let _right = values.next()??;
+    let top = values.next()??;
+    Some((left, top))
+}
+
+/// Run wmctrl, returning the output if it succeeded
+fn wmctrl(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("wmctrl")
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frame_extents() {
+        let output = "_NET_FRAME_EXTENTS(CARDINAL) = 2, 3, 28, 4\n";
+        assert_eq!(parse_frame_extents(output), Some((2, 28)));
+        let output = "_NET_FRAME_EXTENTS:  not found.\n";
+        assert_eq!(parse_frame_extents(output), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Projects built together in a workspace
+//!
+//! A workspace is a project with `workspace-members` in mcmod.yaml, and the projects in the
+//! directories listed there. A member depends on another member when one of its local libs or
+//! mods (`./...`) is in the directory of the other member, like the jar of the core mod that an
+//! addon compiles against, or when it `extends` the other member. Members are built after the
+//! members they depend on.
+//!
+//! The hash of the sources of each member is recorded after it's built, so
+//! `mcmod build --changed` only builds the members with changed sources and their dependents
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::explain::ErrorCode;
+use crate::extends;
+use crate::hash;
+use crate::mcmod::Mcmod;
+use crate::util::{IoResult, Project, ProjectArgs};
+
+/// A project in the workspace
+#[derive(Debug)]
+pub struct Member {
+    /// Directory of the member relative to the workspace root. `.` for the root project
+    pub name: String,
+    pub project: Project,
+    /// Indices of the members this member depends on
+    pub depends: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct Workspace {
+    /// The members, with the root project first
+    pub members: Vec<Member>,
+}
+
+impl Workspace {
+    /// Load the workspace of the project. The profile and target apply to every member
+    pub async fn load(args: &ProjectArgs) -> IoResult<Self> {
+        let root = Project::new_in(args)?;
+        let mut members = Vec::new();
+        for dir in &root.mcmod().await?.workspace_members {
+            let path = root.root.join(dir);
+            if !path.join("mcmod.yaml").exists() {
+                Err(ErrorCode::ProjectNotFound.error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "Workspace member '{dir}' in mcmod.yaml has no mcmod.yaml at '{}'",
+                        path.display()
+                    ),
+                )))?;
+            }
+            let mut project = Project::new_root(dunce::canonicalize(&path)?);
+            project.profile = args.profile.clone();
+            project.target = args.target.clone();
+            project.mcmod().await?;
+            members.push(Member {
+                name: dir.trim_end_matches('/').to_owned(),
+                project,
+                depends: Vec::new(),
+            });
+        }
+        members.insert(
+            0,
+            Member {
+                name: ".".to_owned(),
+                project: root,
+                depends: Vec::new(),
+            },
+        );
+
+        let mut workspace = Self { members };
+        for i in 0..workspace.members.len() {
+            let project = &workspace.members[i].project;
+            let mut depends = Vec::new();
+            let mcmod = project.loaded_mcmod()?;
+            let paths = local_sources(mcmod)
+                .map(|x| project.root.join(x))
+                .chain(extends::project_dir(project, mcmod).await?);
+            for path in paths {
+                match workspace.member_of(&path) {
+                    Some(x) if x != i && !depends.contains(&x) => depends.push(x),
+                    _ => {}
+                }
+            }
+            workspace.members[i].depends = depends;
+        }
+        Ok(workspace)
+    }
+
+    /// Index of the member the path is in. The innermost one, since members can be nested
+    /// in the root project
+    pub fn member_of(&self, path: &Path) -> Option<usize> {
+        let path = normalize(path);
+        self.members
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| path.starts_with(&x.project.root))
+            .max_by_key(|(_, x)| x.project.root.components().count())
+            .map(|(i, _)| i)
+    }
+
+    /// Hash the sources of the member: mcmod.yaml, src, assets, the copy paths
+    /// and the local libs and mods that are not from other members
+    pub async fn hash_sources(&self, i: usize) -> IoResult<String> {
+        let project = &self.members[i].project;
+        let mcmod = project.mcmod().await?;
+        let mut inputs = ["mcmod.yaml", "src", "assets"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let copied = mcmod
+            .copy_paths
+            .iter()
+            .filter_map(|x| x.source_target())
+            .map(|(source, _)| source.to_owned());
+        // jars of other members change when they are built, which is tracked by the dependencies.
+        // So do the jars of the parent mod, which are copied into the target
+        let target_root = project.target_root();
+        let local = local_sources(mcmod)
+            .filter(|x| !normalize(&project.root.join(x)).starts_with(&target_root))
+            .filter(|x| self.member_of(&project.root.join(x)).is_none_or(|x| x == i));
+        for path in copied.chain(local).map(PathBuf::from) {
+            if !inputs.contains(&path) {
+                inputs.push(path);
+            }
+        }
+        hash::hash_paths(project, &project.root, &inputs).await
+    }
+
+    /// Indices of the members with the dependencies of each member before it
+    pub fn build_order(&self) -> IoResult<Vec<usize>> {
+        let mut order = Vec::new();
+        let mut visiting = Vec::new();
+        for i in 0..self.members.len() {
+            self.visit(i, &mut visiting, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(&self, i: usize, visiting: &mut Vec<usize>, order: &mut Vec<usize>) -> IoResult<()> {
+        if order.contains(&i) {
+            return Ok(());
+        }
+        if visiting.contains(&i) {
+            visiting.push(i);
+            let names = visiting
+                .iter()
+                .map(|x| self.members[*x].name.as_str())
+                .collect::<Vec<_>>();
+            Err(ErrorCode::InvalidConfig.error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Workspace members depend on each other: {}",
+                    names.join(" -> ")
+                ),
+            )))?;
+        }
+        visiting.push(i);
+        for depend in &self.members[i].depends {
+            self.visit(*depend, visiting, order)?;
+        }
+        visiting.pop();
+        order.push(i);
+        Ok(())
+    }
+}
+
+/// The local libs and mods (`./...`) of the project
+pub fn local_sources(mcmod: &Mcmod) -> impl Iterator<Item = String> + '_ {
+    mcmod
+        .libs
+        .iter()
+        .map(|x| x.source().to_owned())
+        .chain(mcmod.all_mods())
+        .filter(|x| x.starts_with("./"))
+}
+
+/// Remove `.` and `..` from the path without accessing the file system,
+/// since the jars of members that are not built yet don't exist
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}